@@ -0,0 +1,35 @@
+/* The C ABI of evm-executor, built with the `ffi` feature, see src/ffi.rs. */
+#ifndef EVM_EXECUTOR_H
+#define EVM_EXECUTOR_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define EVM_EXECUTOR_OK 0
+#define EVM_EXECUTOR_ERR_NULL_POINTER 1
+#define EVM_EXECUTOR_ERR_DECODE_POB 2
+#define EVM_EXECUTOR_ERR_MISSING_EXPECTED 3
+#define EVM_EXECUTOR_ERR_EXECUTE 4
+/* the required size is written to the out length, call again with a larger buffer */
+#define EVM_EXECUTOR_ERR_BUFFER_TOO_SMALL 5
+#define EVM_EXECUTOR_ERR_PANIC 6
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* writes the 32 bytes state hash of the pob(Pob::to_bytes) to `out` and its
+ * length to `out_len` */
+int32_t evm_executor_pob_state_hash(const uint8_t *pob, size_t pob_len, uint8_t *out,
+                                    size_t out_cap, size_t *out_len);
+
+/* verify and execute the block of the pob, the unsigned poe(Poe::encode) is
+ * written to `poe_out` and its length to `poe_len` */
+int32_t evm_executor_verify_block(const uint8_t *pob, size_t pob_len, uint8_t *poe_out,
+                                  size_t poe_cap, size_t *poe_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
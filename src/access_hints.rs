@@ -0,0 +1,80 @@
+use std::prelude::v1::*;
+
+use eth_types::{TransactionAccessTuple, SH160, SH256};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Remembers the state touched by the txs calling a contract, so the next tx
+// to the same contract can be prefetched in one round before the execution
+// asks for the slots one by one. Backed by memory by default, implement it
+// over a persistent store to keep the hints across restarts.
+pub trait AccessHintStore {
+    // the accounts and slots touched by the earlier txs to `contract`
+    fn hints(&self, contract: &SH160) -> Vec<TransactionAccessTuple>;
+    fn record(&mut self, contract: &SH160, touched: &[TransactionAccessTuple]);
+}
+
+pub const DEFAULT_MAX_HINT_SLOTS: usize = 1024;
+
+// The union of the touched slots per contract. A contract stops learning
+// once `max_slots` slots are known, the popular contracts touch a stable set
+// and the rest is noise from the calldata dependent keys.
+#[derive(Debug)]
+pub struct MemoryAccessHints {
+    contracts: BTreeMap<SH160, BTreeMap<SH160, BTreeSet<SH256>>>,
+    max_slots: usize,
+}
+
+impl Default for MemoryAccessHints {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_HINT_SLOTS)
+    }
+}
+
+impl MemoryAccessHints {
+    pub fn new(max_slots: usize) -> Self {
+        Self {
+            contracts: BTreeMap::new(),
+            max_slots,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.contracts.clear();
+    }
+}
+
+impl AccessHintStore for MemoryAccessHints {
+    fn hints(&self, contract: &SH160) -> Vec<TransactionAccessTuple> {
+        let accounts = match self.contracts.get(contract) {
+            Some(accounts) => accounts,
+            None => return Vec::new(),
+        };
+        accounts
+            .iter()
+            .map(|(address, slots)| TransactionAccessTuple {
+                address: *address,
+                storage_keys: slots.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    fn record(&mut self, contract: &SH160, touched: &[TransactionAccessTuple]) {
+        let accounts = self.contracts.entry(*contract).or_default();
+        let mut slots: usize = accounts.values().map(|slots| slots.len()).sum();
+        for item in touched {
+            let known = accounts.entry(item.address).or_default();
+            for key in &item.storage_keys {
+                if slots >= self.max_slots {
+                    return;
+                }
+                if known.insert(*key) {
+                    slots += 1;
+                }
+            }
+        }
+    }
+}
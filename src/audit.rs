@@ -0,0 +1,99 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{H160, SH160, SH256, U256};
+
+use crate::{keccak_encode, keccak_hash, ExecuteResult};
+
+const RECORD_ACCOUNT: u8 = 1;
+const RECORD_EXISTS: u8 = 2;
+const RECORD_CODE: u8 = 3;
+const RECORD_STORAGE: u8 = 4;
+const RECORD_BLOCK_HASH: u8 = 5;
+const RECORD_TX_RESULT: u8 = 6;
+
+// A running digest over every state read and tx result of the execution,
+// in the order the evm consumed them:
+//
+//     digest = keccak(digest ++ tag ++ fixed size fields)
+//
+// Two executions of the same block with the same inputs give the same digest,
+// so the digest bound in the Poe (v5) pins the exact inputs of the enclave.
+#[derive(Debug, Default)]
+pub struct ExecutionAudit {
+    digest: RefCell<SH256>,
+    records: RefCell<u64>,
+}
+
+fn u256_bytes(val: &U256) -> [u8; 32] {
+    let mut buf = [0_u8; 32];
+    val.to_big_endian(&mut buf);
+    buf
+}
+
+impl ExecutionAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, tag: u8, parts: &[&[u8]]) {
+        let mut digest = self.digest.borrow_mut();
+        let prev = *digest;
+        *digest = keccak_encode(|hash| {
+            hash(prev.0.as_bytes());
+            hash(&[tag]);
+            for part in parts {
+                hash(part);
+            }
+        })
+        .into();
+        *self.records.borrow_mut() += 1;
+    }
+
+    pub fn record_account(&self, address: &H160, balance: &U256, nonce: &U256) {
+        self.record(
+            RECORD_ACCOUNT,
+            &[address.as_bytes(), &u256_bytes(balance), &u256_bytes(nonce)],
+        );
+    }
+
+    pub fn record_exists(&self, address: &H160, exists: bool) {
+        self.record(RECORD_EXISTS, &[address.as_bytes(), &[exists as u8]]);
+    }
+
+    pub fn record_code(&self, address: &SH160, hash: &SH256) {
+        self.record(RECORD_CODE, &[address.0.as_bytes(), hash.0.as_bytes()]);
+    }
+
+    pub fn record_storage(&self, address: &H160, index: &[u8; 32], value: &[u8; 32]) {
+        self.record(RECORD_STORAGE, &[address.as_bytes(), index, value]);
+    }
+
+    pub fn record_block_hash(&self, number: u64, hash: &SH256) {
+        self.record(
+            RECORD_BLOCK_HASH,
+            &[&number.to_be_bytes(), hash.0.as_bytes()],
+        );
+    }
+
+    pub fn record_result(&self, tx_hash: &SH256, result: &ExecuteResult) {
+        self.record(
+            RECORD_TX_RESULT,
+            &[
+                tx_hash.0.as_bytes(),
+                &[result.success as u8],
+                &result.used_gas.to_be_bytes(),
+                &keccak_hash(&result.return_data),
+                &result.logs_bloom.0,
+            ],
+        );
+    }
+
+    pub fn digest(&self) -> SH256 {
+        *self.digest.borrow()
+    }
+
+    pub fn len(&self) -> u64 {
+        *self.records.borrow()
+    }
+}
@@ -0,0 +1,423 @@
+use std::prelude::v1::*;
+
+use eth_types::{Block, BlockHeader, ReceiptTrait, TransactionInner, Withdrawal, SH256};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::{
+    keccak_encode, mpt, BlockBuilder, CommitError, Engine, Ethereum, Pob, PobBatch, PobBlock,
+    PobError, PobExpected, PobStateDB, PobVerifyError, Poe, PoeError, WithdrawalSummary,
+};
+
+// Everything the executor can't derive from a Pob: the engine of the block,
+// the header to build, the txs decoded from the block and a StateDB backed by
+// the mpt nodes of the Pob.
+pub trait BatchSource<B: PobBlock> {
+    type Engine: Engine;
+    type StateDB: StateDB;
+    fn engine(&self, pob: &Pob<B>) -> Result<Self::Engine, String>;
+    fn header(&self, pob: &Pob<B>) -> Result<<Self::Engine as Engine>::BlockHeader, String>;
+    fn transactions(
+        &self,
+        pob: &Pob<B>,
+    ) -> Result<Vec<Arc<<Self::Engine as Engine>::Transaction>>, String>;
+    fn statedb(&self, pob: &Pob<B>) -> Result<Self::StateDB, String>;
+    fn withdrawals(
+        &self,
+        _pob: &Pob<B>,
+    ) -> Result<Option<Vec<<Self::Engine as Engine>::Withdrawal>>, String> {
+        Ok(None)
+    }
+    // the results the execution is checked against, engines can derive them
+    // from the block header when the Pob doesn't embed them.
+    fn expected(&self, pob: &Pob<B>) -> Option<PobExpected> {
+        pob.data.expected.clone()
+    }
+    // the withdrawal root bound by the Poe, read from the post state or
+    // taken from the engine's WithdrawalSummary by default
+    fn withdrawal_root(
+        &self,
+        _pob: &Pob<B>,
+        _statedb: &mut Self::StateDB,
+        summary: Option<&WithdrawalSummary>,
+    ) -> Result<SH256, String> {
+        Ok(summary.and_then(|summary| summary.root).unwrap_or_default())
+    }
+}
+
+// The ethereum blocks, everything is read from the pob itself. A tx which
+// can't be converted to a TransactionInner is a source error. The results
+// are checked against the block header, the embedded ones and the
+// withdrawals must agree with it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EthereumPobSource;
+
+impl BatchSource<Block> for EthereumPobSource {
+    type Engine = Ethereum;
+    type StateDB = PobStateDB;
+
+    fn engine(&self, pob: &Pob<Block>) -> Result<Ethereum, String> {
+        Ok(Ethereum::new(pob.data.chain_id.into()))
+    }
+
+    fn header(&self, pob: &Pob<Block>) -> Result<BlockHeader, String> {
+        let header = &pob.block.header;
+        if let Some(expected) = &pob.data.expected {
+            expected
+                .check(
+                    &header.state_root,
+                    &header.receipts_root,
+                    header.gas_used.as_u64(),
+                    &header.logs_bloom,
+                )
+                .map_err(|err| format!("embedded results differ from the header: {:?}", err))?;
+        }
+        Ok(header.clone())
+    }
+
+    fn transactions(&self, pob: &Pob<Block>) -> Result<Vec<Arc<TransactionInner>>, String> {
+        pob.block
+            .transactions
+            .iter()
+            .map(|tx| {
+                tx.inner()
+                    .map(Arc::new)
+                    .ok_or_else(|| format!("unsupported tx: {:?}", tx.hash))
+            })
+            .collect()
+    }
+
+    fn statedb(&self, pob: &Pob<Block>) -> Result<PobStateDB, String> {
+        Ok(PobStateDB::new(&pob.data))
+    }
+
+    fn withdrawals(&self, pob: &Pob<Block>) -> Result<Option<Vec<Withdrawal>>, String> {
+        let withdrawals = pob.data.withdrawals.clone();
+        let root = withdrawals.as_ref().map(|withdrawals| {
+            let engine = Ethereum::new(pob.data.chain_id.into());
+            let encoded: Vec<Vec<u8>> = withdrawals
+                .iter()
+                .map(|withdrawal| engine.encode_withdrawal(withdrawal))
+                .collect();
+            mpt::ordered_trie_root(&encoded)
+        });
+        if root != pob.block.header.withdrawals_root {
+            return Err(format!(
+                "withdrawals root mismatch: header {:?}, got {:?}",
+                pob.block.header.withdrawals_root, root
+            ));
+        }
+        Ok(withdrawals)
+    }
+
+    // always the header's, the embedded receipts are kept
+    fn expected(&self, pob: &Pob<Block>) -> Option<PobExpected> {
+        let receipts = pob
+            .data
+            .expected
+            .as_ref()
+            .and_then(|expected| expected.receipts.clone());
+        Some(PobExpected::from_header(&pob.block.header, receipts))
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    Empty,
+    Pob(usize, PobError),
+    Verify(usize, PobVerifyError),
+    Source(usize, String),
+    Commit {
+        idx: usize,
+        tx_idx: usize,
+        err: CommitError,
+    },
+    State(usize, statedb::Error),
+    Unlinked(PoeError),
+    Batch(String),
+}
+
+// The unsigned Poes of the blocks and the batch Poe over them. The state
+// roots of the batch Poe are the ones of the batch chain, the state hash
+// covers the blocks of all the chains.
+#[derive(Debug, Clone)]
+pub struct BatchOutput {
+    pub block_poes: Vec<Poe>,
+    // the chain of each block Poe
+    pub chain_ids: Vec<u64>,
+    pub batch_poe: Poe,
+}
+
+pub struct BatchExecutor<S> {
+    source: S,
+}
+
+impl<S> BatchExecutor<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn execute_batch<B: PobBlock>(
+        &self,
+        batch_hash: SH256,
+        batch: PobBatch<B>,
+    ) -> Result<BatchOutput, BatchError>
+    where
+        S: BatchSource<B>,
+    {
+        let pobs = batch.into_pobs().map_err(|err| BatchError::Pob(0, err))?;
+        self.execute(batch_hash, pobs)
+    }
+
+    // execute the pobs in order, every block should start from the state
+    // root of the previous one on the same chain. BatchSource::engine picks
+    // the engine by the chain id of the pob.
+    pub fn execute<B: PobBlock>(
+        &self,
+        batch_hash: SH256,
+        mut pobs: Vec<Pob<B>>,
+    ) -> Result<BatchOutput, BatchError>
+    where
+        S: BatchSource<B>,
+    {
+        if pobs.is_empty() {
+            return Err(BatchError::Empty);
+        }
+        let batch_chain_id = pobs[0].data.chain_id;
+        let mut block_poes: Vec<Poe> = Vec::with_capacity(pobs.len());
+        let mut chain_ids: Vec<u64> = Vec::with_capacity(pobs.len());
+        // the index of the last block per chain
+        let mut last: BTreeMap<u64, usize> = BTreeMap::new();
+        for (idx, pob) in pobs.iter_mut().enumerate() {
+            let chain_id = pob.data.chain_id;
+            if let Some(prev) = last.get(&chain_id).map(|prev| &block_poes[*prev]) {
+                if prev.new_state_root != pob.data.prev_state_root {
+                    return Err(BatchError::Unlinked(PoeError::UnlinkedStateRoot {
+                        idx,
+                        expect: prev.new_state_root,
+                        got: pob.data.prev_state_root,
+                    }));
+                }
+            }
+            let poe = execute_pob(&self.source, idx, pob)?;
+            last.insert(chain_id, idx);
+            block_poes.push(poe);
+            chain_ids.push(chain_id);
+        }
+
+        let mut batch_poes: Vec<Poe> = Vec::with_capacity(block_poes.len());
+        for chain_id in last.keys() {
+            let poes: Vec<Poe> = block_poes
+                .iter()
+                .zip(&chain_ids)
+                .filter(|(_, id)| *id == chain_id)
+                .map(|(poe, _)| poe.clone())
+                .collect();
+            Poe::verify_chain(&poes).map_err(BatchError::Unlinked)?;
+            if *chain_id == batch_chain_id {
+                batch_poes = poes;
+            }
+        }
+        let mut batch_poe = Poe::batch(batch_hash, &batch_poes).map_err(BatchError::Batch)?;
+        if batch_poes.len() != block_poes.len() {
+            batch_poe.state_hash = keccak_encode(|hash| {
+                for poe in &block_poes {
+                    hash(&poe.state_hash.0);
+                }
+            })
+            .into();
+        }
+        Ok(BatchOutput {
+            block_poes,
+            chain_ids,
+            batch_poe,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    MissingExpected,
+    Execute(BatchError),
+}
+
+// The stateless verification of a single block: check the witness, execute
+// the block on the StateDB built from the Pob and compare the results with
+// the expected ones. Returns the unsigned Poe of the block.
+pub fn verify_block<S, B>(source: &S, mut pob: Pob<B>) -> Result<Poe, VerifyError>
+where
+    S: BatchSource<B>,
+    B: PobBlock,
+{
+    if source.expected(&pob).is_none() {
+        return Err(VerifyError::MissingExpected);
+    }
+    execute_pob(source, 0, &mut pob).map_err(VerifyError::Execute)
+}
+
+fn execute_pob<S, B>(source: &S, idx: usize, pob: &mut Pob<B>) -> Result<Poe, BatchError>
+where
+    S: BatchSource<B>,
+    B: PobBlock,
+{
+    let source_err = |err: String| BatchError::Source(idx, err);
+    pob.data
+        .check_version()
+        .map_err(|err| BatchError::Pob(idx, err))?;
+    pob.verify().map_err(|err| BatchError::Verify(idx, err))?;
+
+    let engine = source.engine(pob).map_err(source_err)?;
+    let header = source.header(pob).map_err(source_err)?;
+    let txs = source.transactions(pob).map_err(source_err)?;
+    let withdrawals = source.withdrawals(pob).map_err(source_err)?;
+    let statedb = source.statedb(pob).map_err(source_err)?;
+
+    let mut builder = BlockBuilder::new(engine, statedb, pob.data.block_hashes.clone(), header)
+        .map_err(source_err)?;
+    for (tx_idx, tx) in txs.into_iter().enumerate() {
+        builder
+            .commit(tx)
+            .map_err(|err| BatchError::Commit { idx, tx_idx, err })?;
+    }
+    if let Some(withdrawals) = withdrawals {
+        builder
+            .withdrawal(withdrawals)
+            .map_err(|err| BatchError::State(idx, err))?;
+    }
+    let state_root = builder
+        .flush_state()
+        .map_err(|err| BatchError::State(idx, err))?;
+
+    if let Some(expected) = source.expected(pob) {
+        let gas_used = builder
+            .receipts()
+            .iter()
+            .map(|receipt| receipt.gas_used().as_u64())
+            .sum();
+        expected
+            .check(
+                &state_root,
+                &builder.receipts_root(),
+                gas_used,
+                &builder.logs_bloom().to_hex(),
+            )
+            .map_err(|err| BatchError::Verify(idx, err))?;
+    }
+
+    let summary = builder.withdrawal_summary().cloned();
+    let withdrawal_root = source
+        .withdrawal_root(pob, builder.statedb(), summary.as_ref())
+        .map_err(source_err)?;
+    Ok(Poe::single_block(
+        pob.state_hash(),
+        pob.data.prev_state_root,
+        state_root,
+        withdrawal_root,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PobData;
+    use eth_types::H256;
+
+    fn pob_with_header() -> Pob<Block> {
+        let mut block = Block::default();
+        block.header.state_root.0 .0[0] = 1;
+        block.header.gas_used = 21000_u64.into();
+        let mut data = PobData::default();
+        data.expected = Some(PobExpected::from_header(&block.header, None));
+        Pob::new(block, data)
+    }
+
+    // a shanghai block with two withdrawals, see src/testdata/regression
+    fn fixture() -> Pob<Block> {
+        let pob = std::fs::read("src/testdata/regression/17100000.pob").unwrap();
+        Pob::from_bytes(&pob).unwrap()
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let executor = BatchExecutor::new(EthereumPobSource);
+        let batch_hash: SH256 = H256::repeat_byte(0x42).into();
+        let pob = fixture();
+        let (prev_root, post_root) = (pob.data.prev_state_root, pob.block.header.state_root);
+
+        let output = executor
+            .execute_batch(batch_hash, PobBatch::new(vec![pob]).unwrap())
+            .unwrap();
+        assert_eq!(output.chain_ids, vec![1]);
+        assert_eq!(output.block_poes.len(), 1);
+        assert_eq!(output.block_poes[0].prev_state_root, prev_root);
+        assert_eq!(output.block_poes[0].new_state_root, post_root);
+        assert_eq!(output.batch_poe.batch_hash, batch_hash);
+        assert_eq!(output.batch_poe.prev_state_root, prev_root);
+        assert_eq!(output.batch_poe.new_state_root, post_root);
+
+        assert!(matches!(
+            executor.execute::<Block>(batch_hash, Vec::new()),
+            Err(BatchError::Empty)
+        ));
+        // the second block doesn't start from the state of the first one
+        assert!(matches!(
+            executor.execute(batch_hash, vec![fixture(), fixture()]),
+            Err(BatchError::Unlinked(PoeError::UnlinkedStateRoot {
+                idx: 1,
+                ..
+            }))
+        ));
+
+        let mut tampered = fixture();
+        tampered.block.header.state_root = prev_root;
+        assert!(matches!(
+            executor.execute(batch_hash, vec![tampered]),
+            Err(BatchError::Verify(0, PobVerifyError::ResultMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_expected_from_header() {
+        let pob = pob_with_header();
+        assert!(EthereumPobSource.header(&pob).is_ok());
+        let expected = EthereumPobSource.expected(&pob).unwrap();
+        assert_eq!(expected.state_root, pob.block.header.state_root);
+
+        // the prover can't claim other results than the header's
+        let mut tampered = pob_with_header();
+        if let Some(expected) = &mut tampered.data.expected {
+            expected.gas_used += 1;
+        }
+        assert!(EthereumPobSource.header(&tampered).is_err());
+        assert!(matches!(
+            verify_block(&EthereumPobSource, tampered),
+            Err(VerifyError::Execute(_))
+        ));
+    }
+
+    #[test]
+    fn test_withdrawals_root() {
+        let mut pob = pob_with_header();
+        assert!(matches!(EthereumPobSource.withdrawals(&pob), Ok(None)));
+
+        let withdrawal = Withdrawal {
+            amount: 5_u64.into(),
+            ..Default::default()
+        };
+        pob.data.withdrawals = Some(vec![withdrawal.clone()]);
+        assert!(EthereumPobSource.withdrawals(&pob).is_err());
+
+        let encoded = Ethereum::new(pob.data.chain_id.into()).encode_withdrawal(&withdrawal);
+        pob.block.header.withdrawals_root = Some(mpt::ordered_trie_root(&[encoded]));
+        assert!(EthereumPobSource.withdrawals(&pob).is_ok());
+
+        // the withdrawals can't be dropped either
+        pob.data.withdrawals = None;
+        assert!(EthereumPobSource.withdrawals(&pob).is_err());
+    }
+}
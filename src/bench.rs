@@ -0,0 +1,107 @@
+use std::prelude::v1::*;
+
+use eth_types::{ReceiptTrait, SH256};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{BlockBuilder, Engine, Pob, PobBlock};
+
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    pub iterations: usize,
+    pub txs: u64,
+    pub gas: u64,
+    pub setup: Duration,    // building the StateDB
+    pub execute: Duration,  // committing the txs
+    pub finalize: Duration, // flushing the state root
+}
+
+impl BenchReport {
+    pub fn total(&self) -> Duration {
+        self.setup + self.execute + self.finalize
+    }
+
+    pub fn tx_per_sec(&self) -> f64 {
+        self.txs as f64 / self.execute.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn gas_per_sec(&self) -> f64 {
+        self.gas as f64 / self.execute.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn merge(&mut self, other: &BenchReport) {
+        self.iterations += other.iterations;
+        self.txs += other.txs;
+        self.gas += other.gas;
+        self.setup += other.setup;
+        self.execute += other.execute;
+        self.finalize += other.finalize;
+    }
+}
+
+// Replays one block a number of times, the StateDB is rebuilt for every
+// iteration by `new_statedb` (e.g. from the mpt nodes of a Pob).
+pub struct BlockBench<E: Engine> {
+    pub engine: E,
+    pub header: E::BlockHeader,
+    pub txs: Vec<Arc<E::Transaction>>,
+    pub block_hashes: BTreeMap<u64, SH256>,
+}
+
+impl<E> BlockBench<E>
+where
+    E: Engine + Clone,
+    E::BlockHeader: Clone,
+{
+    pub fn from_pob<B: PobBlock>(
+        engine: E,
+        header: E::BlockHeader,
+        txs: Vec<Arc<E::Transaction>>,
+        pob: &Pob<B>,
+    ) -> Self {
+        Self {
+            engine,
+            header,
+            txs,
+            block_hashes: pob.data.block_hashes.clone(),
+        }
+    }
+
+    pub fn run<D, F>(&self, iterations: usize, mut new_statedb: F) -> Result<BenchReport, String>
+    where
+        D: StateDB,
+        F: FnMut() -> Result<D, String>,
+    {
+        let mut report = BenchReport::default();
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let statedb = new_statedb()?;
+            report.setup += start.elapsed();
+
+            let mut builder = BlockBuilder::new(
+                self.engine.clone(),
+                statedb,
+                self.block_hashes.clone(),
+                self.header.clone(),
+            )?;
+            let start = Instant::now();
+            for (idx, tx) in self.txs.iter().enumerate() {
+                let receipt = builder
+                    .commit(tx.clone())
+                    .map_err(|err| format!("tx[{}] commit fail: {}", idx, err))?;
+                report.gas += receipt.gas_used().as_u64();
+            }
+            report.execute += start.elapsed();
+
+            let start = Instant::now();
+            builder.finalize_header()?;
+            report.finalize += start.elapsed();
+
+            report.txs += self.txs.len() as u64;
+            report.iterations += 1;
+        }
+        Ok(report)
+    }
+}
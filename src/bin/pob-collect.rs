@@ -0,0 +1,100 @@
+// Collects the witness of a block from a node and writes the Pob:
+//
+//     pob-collect --rpc-url http://localhost:8545 --block 19000000 [--out pob.bin]
+//
+// The local execution and Poe signing need a StateDB backed by the Pob nodes,
+// which lives outside of this crate, so the Pob is verified for completeness
+// and written for the prover to pick up.
+use eth_types::{Block, SH256};
+use evm_executor::{DebugExecutionWitness, Pob, PobData};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+struct Args {
+    rpc_url: String,
+    block: u64,
+    out: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rpc_url = None;
+    let mut block = None;
+    let mut out = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value of {}", arg))
+        };
+        match arg.as_str() {
+            "--rpc-url" => rpc_url = Some(value()?),
+            "--block" => {
+                let val = value()?;
+                block = Some(val.parse().map_err(|_| format!("invalid block: {}", val))?);
+            }
+            "--out" => out = Some(value()?),
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(Args {
+        rpc_url: rpc_url.ok_or("--rpc-url is required")?,
+        block: block.ok_or("--block is required")?,
+        out,
+    })
+}
+
+fn call<T: DeserializeOwned>(url: &str, method: &str, params: Value) -> Result<T, String> {
+    let req = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    let resp: Value = ureq::post(url)
+        .send_json(req)
+        .map_err(|err| format!("{}: {}", method, err))?
+        .into_json()
+        .map_err(|err| format!("{}: {}", method, err))?;
+    if let Some(err) = resp.get("error") {
+        return Err(format!("{}: {}", method, err));
+    }
+    serde_json::from_value(resp["result"].clone()).map_err(|err| format!("{}: {}", method, err))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let number = format!("0x{:x}", args.block);
+    let chain_id: String = call(&args.rpc_url, "eth_chainId", json!([]))?;
+    let chain_id = u64::from_str_radix(chain_id.trim_start_matches("0x"), 16)
+        .map_err(|err| format!("invalid chain id: {}", err))?;
+    let block: Block = call(&args.rpc_url, "eth_getBlockByNumber", json!([number, true]))?;
+    let witness: DebugExecutionWitness =
+        call(&args.rpc_url, "debug_executionWitness", json!([number]))?;
+
+    let mut data =
+        PobData::from_execution_witness(chain_id, witness).map_err(|err| format!("{:?}", err))?;
+    data.withdrawals = block.withdrawals.clone();
+    let mut pob = Pob::new(block, data);
+    pob.verify()
+        .map_err(|err| format!("incomplete witness: {:?}", err))?;
+
+    let state_hash: SH256 = pob.state_hash();
+    eprintln!(
+        "block #{} {:?}: {} nodes, {} codes, state_hash: {:?}",
+        args.block,
+        pob.block_hash(),
+        pob.data.mpt_nodes.len(),
+        pob.data.codes.len(),
+        state_hash
+    );
+    match args.out {
+        Some(out) => {
+            std::fs::write(&out, pob.to_bytes()).map_err(|err| format!("{}: {}", out, err))
+        }
+        None => {
+            println!("{}", String::from_utf8_lossy(&pob.encode()));
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = parse_args().and_then(run) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
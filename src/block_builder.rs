@@ -3,14 +3,28 @@ use std::prelude::v1::*;
 use base::format::debug;
 use eth_types::{
     BlockHeaderTrait, FetchState, FetchStateResult, ReceiptTrait, Signer, TransactionAccessTuple,
-    TxTrait, SH160, SH256,
+    TxTrait, SH160, SH256, SU256,
 };
 use statedb::StateDB;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+#[cfg(feature = "std")]
 use std::time::Instant;
 
-use crate::{BlockHashGetter, ExecuteError, ExecuteResult, PrecompileSet, TxContext, TxExecutor};
+#[cfg(feature = "testing")]
+use crate::Cheatcodes;
+#[cfg(feature = "opcode-stats")]
+use crate::OpcodeGasStats;
+use crate::{
+    block_context_hash, block_hash_window, effective_tip, metrics, mpt, AccessHintStore,
+    BlockHashGetter, CachedExecution, CodeCache, DeterminismCheck, DeterminismRecord,
+    DeterminismReport, Divergence, ExecuteError, ExecuteResult, ExecutionAudit, ExecutionCache,
+    ExecutionKey, ExecutionWitness, FeePolicy, FeeVaults, KeccakCache, LogsBloom, PrecompileSet,
+    ReceiptProof, RecoveryCache, SignerError, SignerSchedule, StateFetchStats, StateMisses,
+    StateSnapshot, TxContext, TxExecutor, TxPolicy, TxRejection, TxTrace, WithdrawalOutcome,
+    WithdrawalSummary,
+};
 
 pub trait Engine {
     type Transaction: TxTrait;
@@ -20,31 +34,91 @@ pub trait Engine {
     type Block;
     type NewBlockContext;
     fn signer(&self) -> Signer;
+    // the fork aware signer for the historical blocks, `signer` is used for
+    // all the blocks if None
+    fn signer_schedule(&self) -> Option<SignerSchedule> {
+        None
+    }
+    // the txs from this address(0xffff...fffe on the op stack) are executed
+    // with FeePolicy::NoFee, they don't buy gas, don't take the block gas and
+    // skip the base fee check and the TxPolicy, e.g. the l1 attributes tx.
+    fn system_address(&self) -> Option<SH160> {
+        None
+    }
+    // route the burned base fee and the tips to the vault contracts
+    fn fee_vaults(&self, _header: &Self::BlockHeader) -> FeeVaults {
+        FeeVaults::default()
+    }
     fn evm_config(&self) -> evm::Config;
-    fn precompile(&self) -> PrecompileSet;
+    // the config of a historical block, e.g. the refund rules before london
+    fn evm_config_at(&self, _header: &Self::BlockHeader) -> evm::Config {
+        self.evm_config()
+    }
+    // shared across the blocks, the set is immutable after construction
+    fn precompile(&self) -> Arc<PrecompileSet>;
+    // the precompiles of the fork of `header`
+    fn precompile_at(&self, _header: &Self::BlockHeader) -> Arc<PrecompileSet> {
+        self.precompile()
+    }
     fn new_block_header(
         &self,
         prev_header: &Self::BlockHeader,
         ctx: Self::NewBlockContext,
     ) -> Self::BlockHeader;
+    // the result is moved into the receipt so the logs are not copied
     fn build_receipt(
         &self,
         cumulative_gas_used: u64,
-        result: &ExecuteResult,
+        result: ExecuteResult,
         tx_idx: usize,
         tx: &Self::Transaction,
         header: &Self::BlockHeader,
     ) -> Self::Receipt;
+    // fill the chain specific receipt fields which are not derived from the
+    // ExecuteResult, e.g. the deposit nonce and the l1 fee of the op stack,
+    // see DepositReceiptFields. the statedb is the post state of the tx, an
+    // error reverts the tx.
+    fn extend_receipt<D: StateDB>(
+        &self,
+        _statedb: &mut D,
+        _receipt: &mut Self::Receipt,
+        _ctx: &ReceiptContext,
+    ) -> Result<(), statedb::Error> {
+        Ok(())
+    }
+    // the value in the receipts trie, see ReceiptProof
+    fn encode_receipt(&self, receipt: &Self::Receipt) -> Vec<u8>;
+    // the value in the withdrawals trie, see BlockBuilder::withdrawal_root
+    fn encode_withdrawal(&self, withdrawal: &Self::Withdrawal) -> Vec<u8>;
+    // the account credited by the withdrawal, it's recorded by the
+    // ExecutionWitness as the withdrawals are not executed by the evm
+    fn withdrawal_address(&self, _withdrawal: &Self::Withdrawal) -> Option<SH160> {
+        None
+    }
     fn author(&self, header: &Self::BlockHeader) -> Result<Option<SH160>, String>;
     fn tx_context<'a, H: BlockHashGetter>(
         &self,
         ctx: &mut TxContext<'a, Self::Transaction, Self::BlockHeader, H>,
     );
+    // credit one withdrawal, engines with their own semantics (skipping the
+    // zero amounts, crediting a fee token) report what they did
+    fn process_withdrawal<D: StateDB>(
+        &mut self,
+        statedb: &mut D,
+        withdrawal: &Self::Withdrawal,
+    ) -> Result<WithdrawalOutcome, statedb::Error>;
+    // override it to set WithdrawalSummary::root
     fn process_withdrawals<D: StateDB>(
         &mut self,
         statedb: &mut D,
         withdrawals: &[Self::Withdrawal],
-    ) -> Result<(), statedb::Error>;
+    ) -> Result<WithdrawalSummary, statedb::Error> {
+        let mut summary = WithdrawalSummary::default();
+        for withdrawal in withdrawals {
+            summary.record(self.process_withdrawal(statedb, withdrawal)?);
+        }
+        Ok(summary)
+    }
     fn finalize_block<D: StateDB>(
         &mut self,
         statedb: &mut D,
@@ -55,22 +129,57 @@ pub trait Engine {
     ) -> Result<Self::Block, String>;
 }
 
+// the inputs of Engine::extend_receipt which are gone after the execution
+#[derive(Debug, Clone)]
+pub struct ReceiptContext {
+    pub caller: SH160,
+    // the sender nonce before the execution
+    pub nonce: u64,
+    // set by Engine::tx_context, see FeePolicy::ExtraFee
+    pub extra_fee: Option<SU256>,
+}
+
 pub struct BlockBuilder<E: Engine, D: StateDB, P: BlockHashGetter> {
     engine: E,
     header: E::BlockHeader,
     statedb: D,
     signer: Signer,
+    signer_schedule: Option<SignerSchedule>,
+    system_address: Option<SH160>,
     miner: Option<SH160>,
 
     evm_cfg: evm::Config,
-    precompile: PrecompileSet,
+    precompile: Arc<PrecompileSet>,
 
     cumulative_gas_used: u64,
     prefetcher: P,
+    // the BLOCKHASH window resolved once in `new`
+    block_hashes: BTreeMap<u64, SH256>,
+    code_cache: CodeCache,
+    keccak_cache: KeccakCache,
+    witness: Option<ExecutionWitness>,
+    audit: Option<ExecutionAudit>,
+    determinism: Option<DeterminismCheck>,
+    intermediate_roots: Option<Vec<SH256>>,
+    traces: Option<Vec<TxTrace>>,
+    savepoints: BTreeMap<String, Savepoint>,
+    policy: Option<Box<dyn TxPolicy<E::Transaction>>>,
+    execution_cache: Option<ExecutionCache>,
+    access_hints: Option<Box<dyn AccessHintStore>>,
+    recovery_cache: Option<RecoveryCache>,
+    min_tip: Option<SU256>,
+    fetch_stats: StateFetchStats,
+    #[cfg(feature = "testing")]
+    cheatcodes: Option<Cheatcodes>,
+    #[cfg(feature = "opcode-stats")]
+    opcode_stats: Option<OpcodeGasStats>,
 
     txs: Vec<Arc<E::Transaction>>,
     receipts: Vec<E::Receipt>,
+    blooms: Vec<LogsBloom>,
+    logs_bloom: LogsBloom,
     withdrawals: Option<Vec<E::Withdrawal>>,
+    withdrawal_summary: Option<WithdrawalSummary>,
 }
 
 impl<E, D, P> BlockBuilder<E, D, P>
@@ -86,20 +195,50 @@ where
         header: E::BlockHeader,
     ) -> Result<BlockBuilder<E, D, P>, String> {
         let miner = engine.author(&header)?;
+        let number = header.number().as_u64();
+        let block_hashes = prefetcher.get_hashes(number, block_hash_window(number));
+        let signer_schedule = engine.signer_schedule();
         Ok(BlockBuilder {
-            signer: engine.signer(),
-            evm_cfg: engine.evm_config(),
+            signer: match &signer_schedule {
+                Some(schedule) => schedule.signer_at(number),
+                None => engine.signer(),
+            },
+            signer_schedule,
+            system_address: engine.system_address(),
+            evm_cfg: engine.evm_config_at(&header),
             miner,
             statedb,
-            precompile: engine.precompile(),
+            precompile: engine.precompile_at(&header),
             engine,
             header,
             cumulative_gas_used: 0,
             prefetcher,
+            block_hashes,
+            code_cache: CodeCache::new(),
+            keccak_cache: KeccakCache::default(),
+            witness: None,
+            audit: None,
+            determinism: None,
+            intermediate_roots: None,
+            traces: None,
+            savepoints: BTreeMap::new(),
+            policy: None,
+            execution_cache: None,
+            access_hints: None,
+            recovery_cache: None,
+            min_tip: None,
+            fetch_stats: StateFetchStats::default(),
+            #[cfg(feature = "testing")]
+            cheatcodes: None,
+            #[cfg(feature = "opcode-stats")]
+            opcode_stats: None,
 
             txs: Vec::new(),
             receipts: Vec::new(),
+            blooms: Vec::new(),
+            logs_bloom: LogsBloom::default(),
             withdrawals: None,
+            withdrawal_summary: None,
         })
     }
 
@@ -111,6 +250,183 @@ where
         &self.receipts
     }
 
+    // the bloom of all the committed receipts
+    pub fn logs_bloom(&self) -> &LogsBloom {
+        &self.logs_bloom
+    }
+
+    pub fn code_cache(&self) -> &CodeCache {
+        &self.code_cache
+    }
+
+    pub fn keccak_cache(&self) -> &KeccakCache {
+        &self.keccak_cache
+    }
+
+    // the prefetch statistics since the builder was created
+    pub fn fetch_stats(&self) -> &StateFetchStats {
+        &self.fetch_stats
+    }
+
+    // record the touched state for building a minimal Pob, see PobData::from_witness
+    pub fn record_witness(&mut self) {
+        if self.witness.is_none() {
+            self.witness = Some(ExecutionWitness::new());
+        }
+    }
+
+    pub fn witness(&self) -> Option<&ExecutionWitness> {
+        self.witness.as_ref()
+    }
+
+    pub fn take_witness(&mut self) -> Option<ExecutionWitness> {
+        self.witness.take()
+    }
+
+    // hash the consumed inputs of the following txs, see Poe::with_audit_digest
+    pub fn record_audit(&mut self) {
+        if self.audit.is_none() {
+            self.audit = Some(ExecutionAudit::new());
+        }
+    }
+
+    pub fn audit(&self) -> Option<&ExecutionAudit> {
+        self.audit.as_ref()
+    }
+
+    // record the audit digest after each of the following txs and reject the
+    // txs whose digest differs from `expected`, the record of an independent
+    // run of the block, see DeterminismCheck. it records the audit too.
+    pub fn check_determinism(&mut self, expected: Option<DeterminismRecord>) {
+        self.record_audit();
+        self.determinism = Some(match expected {
+            Some(expected) => DeterminismCheck::against(expected),
+            None => DeterminismCheck::new(),
+        });
+    }
+
+    // the reference of the runs on the other machines
+    pub fn determinism_record(&self) -> Option<&DeterminismRecord> {
+        self.determinism.as_ref().map(|check| check.record())
+    }
+
+    pub fn determinism_report(&self) -> Option<DeterminismReport> {
+        self.determinism.as_ref().map(|check| check.report())
+    }
+
+    // consult the policy before executing the following txs
+    pub fn set_policy(&mut self, policy: Option<Box<dyn TxPolicy<E::Transaction>>>) {
+        self.policy = policy;
+    }
+
+    // reject the txs paying the miner less than `min_tip` per gas before the
+    // execution, see CommitError::TipTooLow. The system txs are exempted.
+    pub fn set_min_tip(&mut self, min_tip: Option<SU256>) {
+        self.min_tip = min_tip;
+    }
+
+    // reuse the results of an earlier run of the same txs, see ExecutionCache.
+    // take_execution_cache hands it over to the builder of the next attempt.
+    pub fn set_execution_cache(&mut self, cache: Option<ExecutionCache>) {
+        self.execution_cache = cache;
+    }
+
+    pub fn execution_cache(&self) -> Option<&ExecutionCache> {
+        self.execution_cache.as_ref()
+    }
+
+    pub fn take_execution_cache(&mut self) -> Option<ExecutionCache> {
+        self.execution_cache.take()
+    }
+
+    // learn the touched state of the committed txs per contract, see
+    // prefetch_hints
+    pub fn set_access_hints(&mut self, store: Option<Box<dyn AccessHintStore>>) {
+        self.access_hints = store;
+    }
+
+    pub fn take_access_hints(&mut self) -> Option<Box<dyn AccessHintStore>> {
+        self.access_hints.take()
+    }
+
+    // skip the sender recovery of the txs seen by an earlier stage, the same
+    // cache can be passed to Poe::try_recover_cached.
+    pub fn set_recovery_cache(&mut self, cache: Option<RecoveryCache>) {
+        self.recovery_cache = cache;
+    }
+
+    pub fn take_recovery_cache(&mut self) -> Option<RecoveryCache> {
+        self.recovery_cache.take()
+    }
+
+    fn sender(&self, tx: &E::Transaction) -> SH160 {
+        match &self.recovery_cache {
+            Some(cache) => cache.sender(tx, &self.signer),
+            None => tx.sender(&self.signer),
+        }
+    }
+
+    // flush the state after each of the following txs, the roots are needed
+    // by the pre-byzantium receipts and for bisecting a disputed block.
+    pub fn record_intermediate_roots(&mut self) {
+        if self.intermediate_roots.is_none() {
+            self.intermediate_roots = Some(Vec::new());
+        }
+    }
+
+    // the state root after each tx since record_intermediate_roots
+    pub fn intermediate_roots(&self) -> Option<&[SH256]> {
+        self.intermediate_roots.as_deref()
+    }
+
+    // trace each of the following txs, see TxTrace
+    pub fn record_traces(&mut self) {
+        if self.traces.is_none() {
+            self.traces = Some(Vec::new());
+        }
+    }
+
+    // the traces of the txs since record_traces
+    pub fn traces(&self) -> Option<&[TxTrace]> {
+        self.traces.as_deref()
+    }
+
+    // serve the calls to CHEATCODE_ADDRESS in the following txs
+    #[cfg(feature = "testing")]
+    pub fn enable_cheatcodes(&mut self) -> &Cheatcodes {
+        self.cheatcodes.get_or_insert_with(Cheatcodes::default)
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn cheatcodes(&self) -> Option<&Cheatcodes> {
+        self.cheatcodes.as_ref()
+    }
+
+    // tally the gas per opcode of the following txs
+    #[cfg(feature = "opcode-stats")]
+    pub fn record_opcode_stats(&mut self) {
+        if self.opcode_stats.is_none() {
+            self.opcode_stats = Some(OpcodeGasStats::new());
+        }
+    }
+
+    #[cfg(feature = "opcode-stats")]
+    pub fn opcode_stats(&self) -> Option<&OpcodeGasStats> {
+        self.opcode_stats.as_ref()
+    }
+
+    #[cfg(feature = "opcode-stats")]
+    pub fn take_opcode_stats(&mut self) -> Option<OpcodeGasStats> {
+        self.opcode_stats.take()
+    }
+
+    // the post-execution state of the touched accounts in the anvil format,
+    // requires record_witness before the txs are committed.
+    pub fn state_snapshot(&mut self) -> Option<Result<StateSnapshot, statedb::Error>> {
+        let witness = self.witness.as_ref()?;
+        Some(StateSnapshot::capture(&mut self.statedb, witness))
+    }
+
     pub fn truncate_and_revert(&mut self, tx_len: usize, state_root: SH256) {
         let refund_gases: Vec<_> = self.receipts[tx_len..]
             .iter()
@@ -120,25 +436,129 @@ where
             self.refund_gas(gas);
         }
         self.txs.truncate(tx_len);
+        if let Some(roots) = &mut self.intermediate_roots {
+            let recorded = roots.len().saturating_sub(self.receipts.len() - tx_len);
+            roots.truncate(recorded);
+        }
+        if let Some(traces) = &mut self.traces {
+            let recorded = traces.len().saturating_sub(self.receipts.len() - tx_len);
+            traces.truncate(recorded);
+        }
         self.receipts.truncate(tx_len);
+        self.blooms.truncate(tx_len);
+        self.savepoints
+            .retain(|_, savepoint| savepoint.tx_len <= tx_len);
+        self.logs_bloom = LogsBloom::default();
+        for bloom in &self.blooms {
+            self.logs_bloom.accrue_bloom(bloom);
+        }
         self.statedb.revert(state_root);
+        self.code_cache.reset_addresses();
+    }
+
+    // Flush the state and remember where the block is, an existing savepoint
+    // with the same label is replaced. Returns the state root.
+    pub fn savepoint(&mut self, label: &str) -> Result<SH256, statedb::Error> {
+        let state_root = self.flush_state()?;
+        self.savepoints.insert(
+            label.into(),
+            Savepoint {
+                state_root,
+                tx_len: self.txs.len(),
+                cumulative_gas_used: self.cumulative_gas_used,
+                withdrawn: self.withdrawals.is_some(),
+            },
+        );
+        Ok(state_root)
+    }
+
+    // Revert the txs and the state committed after the savepoint, the later
+    // savepoints are dropped by truncate_and_revert. The withdrawals processed
+    // after the savepoint are reverted with the state.
+    pub fn rollback(&mut self, label: &str) -> Result<(), String> {
+        let savepoint = match self.savepoints.get(label) {
+            Some(savepoint) => savepoint.clone(),
+            None => return Err(format!("unknown savepoint: {}", label)),
+        };
+        self.truncate_and_revert(savepoint.tx_len, savepoint.state_root);
+        self.cumulative_gas_used = savepoint.cumulative_gas_used;
+        if !savepoint.withdrawn {
+            self.withdrawals = None;
+            self.withdrawal_summary = None;
+            self.savepoints.retain(|_, savepoint| !savepoint.withdrawn);
+        }
+        Ok(())
+    }
+
+    pub fn release_savepoint(&mut self, label: &str) -> bool {
+        self.savepoints.remove(label).is_some()
     }
 
     pub fn flush_state(&mut self) -> Result<SH256, statedb::Error> {
         self.statedb.flush()
     }
 
+    // The state is flushed before the tx, a tx failing after the execution
+    // (in extend_receipt or the determinism check) is reverted to that root
+    // and leaves nothing in the access hints, the execution cache or the
+    // determinism record.
     pub fn commit(&mut self, tx: Arc<E::Transaction>) -> Result<&E::Receipt, CommitError> {
-        let receipt = match self.execute_tx(&tx) {
-            Ok(execute_result) => {
-                let receipt = self.engine.build_receipt(
+        let pre_root = self
+            .flush_state()
+            .map_err(|err| CommitError::Execute(ExecuteError::StateError(err)))?;
+        let receipt = match self.execute_tx(&tx, pre_root) {
+            Ok((execute_result, receipt_ctx, cached)) => {
+                let touched = match (&self.access_hints, tx.to()) {
+                    (Some(_), Some(to)) => Some((to, execute_result.touched.clone())),
+                    _ => None,
+                };
+                let trace = match &self.traces {
+                    Some(_) => Some(TxTrace::new(&*tx, receipt_ctx.caller, &execute_result)),
+                    None => None,
+                };
+                let used_gas = execute_result.used_gas;
+                let logs_bloom = execute_result.logs_bloom;
+                let mut receipt = self.engine.build_receipt(
                     self.cumulative_gas_used,
-                    &execute_result,
+                    execute_result,
                     self.txs.len(),
                     &tx,
                     &self.header,
                 );
-                self.cost_gas(execute_result.used_gas);
+                if let Err(err) =
+                    self.engine
+                        .extend_receipt(&mut self.statedb, &mut receipt, &receipt_ctx)
+                {
+                    self.revert_tx(pre_root);
+                    return Err(CommitError::Execute(ExecuteError::StateError(err)));
+                }
+                if let (Some(check), Some(audit)) = (&mut self.determinism, &self.audit) {
+                    let digest = audit.digest();
+                    if let Some(divergence) = check.check(self.txs.len(), tx.hash(), digest) {
+                        error!("nondeterministic execution: {:?}", divergence);
+                        self.revert_tx(pre_root);
+                        return Err(CommitError::Nondeterministic(divergence));
+                    }
+                }
+                if let (Some(hints), Some((to, touched))) = (&mut self.access_hints, touched) {
+                    hints.record(&to.into(), &touched);
+                }
+                if let (Some(cache), Some((key, cached))) = (&mut self.execution_cache, cached) {
+                    cache.insert(key, cached);
+                }
+                if let Some(roots) = &mut self.intermediate_roots {
+                    let root = self
+                        .statedb
+                        .flush()
+                        .map_err(|err| CommitError::Execute(ExecuteError::StateError(err)))?;
+                    roots.push(root);
+                }
+                if let (Some(traces), Some(trace)) = (&mut self.traces, trace) {
+                    traces.push(trace);
+                }
+                self.cost_gas(used_gas);
+                self.logs_bloom.accrue_bloom(&logs_bloom);
+                self.blooms.push(logs_bloom);
                 self.receipts.push(receipt);
                 self.txs.push(tx.clone());
                 self.receipts.last().unwrap()
@@ -148,6 +568,12 @@ where
         Ok(receipt)
     }
 
+    fn revert_tx(&mut self, pre_root: SH256) {
+        self.statedb.revert(pre_root);
+        // the tx may have deployed code
+        self.code_cache.reset_addresses();
+    }
+
     fn refund_gas(&mut self, gas: u64) {
         self.cumulative_gas_used -= gas;
     }
@@ -156,6 +582,41 @@ where
         self.cumulative_gas_used += gas;
     }
 
+    fn encoded_receipts(&self) -> Vec<Vec<u8>> {
+        self.receipts
+            .iter()
+            .map(|receipt| self.engine.encode_receipt(receipt))
+            .collect()
+    }
+
+    // The receipts are final after the last commit, the proof can be taken
+    // before `finalize` consumes the builder. None if out of range.
+    pub fn receipt_proof(&self, tx_index: usize) -> Option<ReceiptProof> {
+        if tx_index >= self.receipts.len() {
+            return None;
+        }
+        Some(ReceiptProof::new(&self.encoded_receipts(), tx_index))
+    }
+
+    pub fn receipts_root(&self) -> SH256 {
+        ReceiptProof::root(&self.encoded_receipts())
+    }
+
+    // the EIP-4895 withdrawals root of the processed withdrawals, None if
+    // `withdrawal` is not called for this block.
+    pub fn withdrawal_root(&self) -> Option<SH256> {
+        let withdrawals = self.withdrawals.as_ref()?;
+        let encoded: Vec<Vec<u8>> = withdrawals
+            .iter()
+            .map(|withdrawal| self.engine.encode_withdrawal(withdrawal))
+            .collect();
+        Some(mpt::ordered_trie_root(&encoded))
+    }
+
+    pub fn statedb(&mut self) -> &mut D {
+        &mut self.statedb
+    }
+
     pub fn finalize_header(&mut self) -> Result<&E::BlockHeader, String> {
         let state_root = self.flush_state().map_err(debug)?;
         self.header.set_state_root(state_root);
@@ -175,52 +636,191 @@ where
         Ok(blk)
     }
 
-    fn execute_tx(&mut self, tx: &E::Transaction) -> Result<ExecuteResult, CommitError> {
-        let caller = tx.sender(&self.signer);
+    // the sender of the signer schedule, the prank of the cheatcodes replaces it
+    fn caller(&self, tx: &E::Transaction) -> Result<SH160, CommitError> {
+        let caller = match &self.signer_schedule {
+            Some(schedule) => {
+                let number = self.header.number().as_u64();
+                match &self.recovery_cache {
+                    Some(cache) => schedule.sender_cached(tx, number, cache),
+                    None => schedule.sender(tx, number),
+                }
+                .map_err(|err| CommitError::Signature {
+                    tx_hash: tx.hash(),
+                    err,
+                })?
+            }
+            None => self.sender(tx),
+        };
+        #[cfg(feature = "testing")]
+        let caller = match self.cheatcodes.as_ref().and_then(|c| c.prank()) {
+            Some(prank) => prank,
+            None => caller,
+        };
+        Ok(caller)
+    }
+
+    fn execute_tx(
+        &mut self,
+        tx: &E::Transaction,
+        pre_root: SH256,
+    ) -> Result<ExecutedTx, CommitError> {
+        let caller = self.caller(tx)?;
+        let system_tx = self.system_address == Some(caller);
+        if let (Some(policy), false) = (&self.policy, system_tx) {
+            policy
+                .check(tx, &caller)
+                .map_err(|reason| CommitError::Rejected {
+                    tx_hash: tx.hash(),
+                    reason,
+                })?;
+        }
         let mut ctx = TxContext {
             chain_id: self.signer.chain_id,
             caller,
             cfg: &self.evm_cfg,
-            precompile: &self.precompile,
+            precompile: &*self.precompile,
             tx,
             header: &self.header,
-            block_hash_getter: &self.prefetcher,
-            no_gas_fee: false,
-            extra_fee: None,
-            gas_overcommit: false,
+            block_hash_getter: &self.block_hashes,
+            code_cache: Some(&self.code_cache),
+            keccak_cache: Some(&self.keccak_cache),
+            witness: self.witness.as_ref(),
+            audit: self.audit.as_ref(),
+            state_misses: None,
+            #[cfg(feature = "testing")]
+            cheatcodes: self.cheatcodes.as_ref(),
+            #[cfg(feature = "opcode-stats")]
+            opcode_stats: self.opcode_stats.as_ref(),
+            fee_policy: match system_tx {
+                true => FeePolicy::NoFee,
+                false => FeePolicy::Normal,
+            },
+            fee_vaults: self.engine.fee_vaults(&self.header),
             miner: self.miner,
-            block_base_fee: 0.into(),
+            block_base_fee: None,
             difficulty: 0.into(),
+            prev_randao: None,
+            blob_hashes: Vec::new(),
+            blob_base_fee: None,
+            eof: false,
         };
         self.engine.tx_context(&mut ctx);
 
         let gas_limit = tx.gas_limit();
-        if !ctx.no_gas_fee {
+        if ctx.fee_policy.charges_gas() {
             let block_gas_limit = self.header.gas_limit();
             let gas_pool = block_gas_limit
                 .as_u64()
                 .saturating_sub(self.cumulative_gas_used);
             if gas_pool < gas_limit {
                 return Err(CommitError::NotEnoughGasLimit {
+                    tx_hash: tx.hash(),
                     gas_pool,
                     gas_limit,
                 });
             }
+            if let Some(min_tip) = self.min_tip {
+                let tip = effective_tip(tx, ctx.block_base_fee.as_ref());
+                if tip < min_tip {
+                    return Err(CommitError::TipTooLow {
+                        tx_hash: tx.hash(),
+                        tip,
+                        min_tip,
+                    });
+                }
+            }
         }
 
-        let state_db = &mut self.statedb;
-        let result = TxExecutor::new(ctx, state_db)
-            .execute()
-            .map_err(|err| CommitError::Execute(err))?;
-        Ok(result)
+        let receipt_ctx = ReceiptContext {
+            caller,
+            nonce: self
+                .statedb
+                .get_nonce(&caller)
+                .map_err(|err| CommitError::Execute(ExecuteError::StateError(err)))?,
+            extra_fee: ctx.fee_policy.extra_fee(),
+        };
+        // the witness and the audit digest have to see the reads of every tx
+        let cacheable = self.witness.is_none() && self.audit.is_none();
+        match &mut self.execution_cache {
+            Some(cache) if cacheable => {
+                let key = ExecutionKey {
+                    tx_hash: tx.hash(),
+                    pre_root,
+                    block_context: block_context_hash(&ctx),
+                };
+                let cached = match cache.get(&key) {
+                    Some(cached) => {
+                        self.statedb.revert(cached.post_root);
+                        // the cached tx may have deployed code
+                        self.code_cache.reset_addresses();
+                        match resolves(&mut self.statedb, &cached) {
+                            Ok(true) => Some(cached),
+                            Ok(false) => {
+                                debug!(
+                                    "cached post root {:?} is not in the statedb",
+                                    cached.post_root
+                                );
+                                cache.unusable(&key);
+                                self.statedb.revert(pre_root);
+                                None
+                            }
+                            Err(err) => {
+                                self.statedb.revert(pre_root);
+                                return Err(CommitError::Execute(ExecuteError::StateError(err)));
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                match cached {
+                    Some(cached) => Ok((cached.result, cached.receipt_ctx, None)),
+                    None => {
+                        let result = TxExecutor::new(ctx, &mut self.statedb)
+                            .execute()
+                            .map_err(|err| CommitError::Execute(err))?;
+                        let post_root = self
+                            .statedb
+                            .flush()
+                            .map_err(|err| CommitError::Execute(ExecuteError::StateError(err)))?;
+                        let cached = CachedExecution {
+                            result: result.clone(),
+                            receipt_ctx: receipt_ctx.clone(),
+                            post_root,
+                        };
+                        Ok((result, receipt_ctx, Some((key, cached))))
+                    }
+                }
+            }
+            _ => {
+                let result = TxExecutor::new(ctx, &mut self.statedb)
+                    .execute()
+                    .map_err(|err| CommitError::Execute(err))?;
+                Ok((result, receipt_ctx, None))
+            }
+        }
     }
 
     pub fn withdrawal(&mut self, withdrawals: Vec<E::Withdrawal>) -> Result<(), statedb::Error> {
-        self.engine
+        if let Some(witness) = &self.witness {
+            for withdrawal in &withdrawals {
+                if let Some(address) = self.engine.withdrawal_address(withdrawal) {
+                    witness.add_account(&address);
+                }
+            }
+        }
+        let summary = self
+            .engine
             .process_withdrawals(&mut self.statedb, &withdrawals)?;
         self.withdrawals = Some(withdrawals);
+        self.withdrawal_summary = Some(summary);
         Ok(())
     }
+
+    // what the engine did with the withdrawals, None before `withdrawal`
+    pub fn withdrawal_summary(&self) -> Option<&WithdrawalSummary> {
+        self.withdrawal_summary.as_ref()
+    }
 }
 
 impl<E, D, P> BlockBuilder<E, D, P>
@@ -233,8 +833,90 @@ where
     where
         I: Iterator<Item = &'a TransactionAccessTuple>,
     {
-        let mut out = Vec::new();
-        let _start = Instant::now();
+        let out = self.missing_states(list)?;
+        if out.len() > 0 {
+            #[cfg(feature = "std")]
+            let start = Instant::now();
+            let result = self.prefetcher.prefetch(&out)?;
+            #[cfg(feature = "std")]
+            self.observe_fetch(start.elapsed());
+            self.fetch_stats.record_fetched(&result);
+            self.statedb.apply_states(result)?;
+        }
+        Ok(out.len())
+    }
+
+    // The two phase prefetch: execute the txs speculatively to discover the
+    // missing state, fetch it in one round and roll the state back, then the
+    // txs are committed against the complete state. The speculative results
+    // are thrown away, a tx depending on a fetched value may discover more so
+    // it can be repeated until it returns 0.
+    pub fn prefetch_speculative(
+        &mut self,
+        txs: &[Arc<E::Transaction>],
+    ) -> Result<usize, statedb::Error> {
+        let root = self.statedb.flush()?;
+        let misses = StateMisses::new();
+        for tx in txs {
+            // the commit reports it
+            let caller = match self.caller(tx) {
+                Ok(caller) => caller,
+                Err(err) => {
+                    debug!("speculative tx {:?} skipped: {}", tx.hash(), err);
+                    continue;
+                }
+            };
+            let mut ctx = TxContext::builder()
+                .chain_id(self.signer.chain_id)
+                .caller(caller)
+                .cfg(&self.evm_cfg)
+                .precompile(&self.precompile)
+                .tx(tx.as_ref())
+                .header(&self.header)
+                .block_hash_getter(&self.block_hashes)
+                .miner(self.miner)
+                .state_misses(Some(&misses))
+                .build()
+                .expect("all the required fields are set");
+            self.engine.tx_context(&mut ctx);
+            // the balance may come from a missing state, the system txs are
+            // not charged either
+            ctx.fee_policy = FeePolicy::NoFee;
+            if let Err(err) = TxExecutor::new(ctx, &mut self.statedb).execute() {
+                debug!("speculative tx {:?} failed: {:?}", tx.hash(), err);
+            }
+        }
+        self.statedb.revert(root);
+        self.code_cache.reset_addresses();
+        let list = misses.access_list();
+        self.prefetch(list.iter())
+    }
+
+    // prefetch what the earlier txs to the same contract touched, call it
+    // before commit. The creations have nothing to learn from.
+    pub fn prefetch_hints(&mut self, tx: &E::Transaction) -> Result<usize, statedb::Error> {
+        let to: SH160 = match tx.to() {
+            Some(to) => to.into(),
+            None => return Ok(0),
+        };
+        let hints = match &self.access_hints {
+            Some(store) => store.hints(&to),
+            None => return Ok(0),
+        };
+        self.prefetch(hints.iter())
+    }
+
+    #[cfg(feature = "std")]
+    fn observe_fetch(&mut self, elapsed: core::time::Duration) {
+        metrics::observe_state_fetch(elapsed);
+        self.fetch_stats.latency.observe(elapsed);
+    }
+
+    fn missing_states<'a, I>(&mut self, list: I) -> Result<Vec<FetchState<'a>>, statedb::Error>
+    where
+        I: Iterator<Item = &'a TransactionAccessTuple>,
+    {
+        let mut out: Vec<FetchState<'a>> = Vec::new();
         for item in list {
             let mut fetch = FetchState {
                 access_list: None,
@@ -243,11 +925,19 @@ where
             let missing_state = self
                 .statedb
                 .check_missing_state(&item.address, &item.storage_keys)?;
+            let stats = &mut self.fetch_stats;
+            stats.accounts_requested += 1;
+            stats.slots_requested += item.storage_keys.len() as u64;
             if missing_state.account {
+                stats.accounts_missed += 1;
+                stats.slots_missed += item.storage_keys.len() as u64;
+                stats.codes_missed += 1;
                 fetch.code = Some(item.address);
                 fetch.access_list = Some(Cow::Borrowed(item));
             } else {
+                stats.slots_missed += missing_state.storages.len() as u64;
                 if missing_state.code {
+                    stats.codes_missed += 1;
                     fetch.code = Some(item.address);
                 }
                 let mut item = Cow::Borrowed(item);
@@ -261,20 +951,464 @@ where
                 }
             }
         }
-        if out.len() > 0 {
-            let result = self.prefetcher.prefetch(&out)?;
+        Ok(out)
+    }
+}
+
+impl<E, D, P> BlockBuilder<E, D, P>
+where
+    E: Engine,
+    P: BlockHashGetter + StatePrefetcher + Clone + Send + 'static,
+    D: StateDB,
+{
+    // Same as prefetch but shards the requests across at most `concurrency`
+    // prefetcher calls, the latency of each call dominates the witness
+    // collection rather than the bandwidth.
+    pub fn prefetch_concurrent<'a, I>(
+        &mut self,
+        list: I,
+        concurrency: usize,
+    ) -> Result<usize, statedb::Error>
+    where
+        I: Iterator<Item = &'a TransactionAccessTuple>,
+    {
+        let out = self.missing_states(list)?;
+        let total = out.len();
+        if total == 0 {
+            return Ok(0);
+        }
+        let concurrency = concurrency.max(1);
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        let shard_size = (total + concurrency - 1) / concurrency;
+
+        #[cfg(feature = "std")]
+        let results: Vec<_> = {
+            let mut shards: Vec<Vec<FetchState<'static>>> = Vec::new();
+            for (idx, fetch) in out.into_iter().enumerate() {
+                if idx % shard_size == 0 {
+                    shards.push(Vec::with_capacity(shard_size));
+                }
+                shards.last_mut().unwrap().push(FetchState {
+                    access_list: fetch.access_list.map(|item| Cow::Owned(item.into_owned())),
+                    code: fetch.code,
+                });
+            }
+            let handles: Vec<_> = shards
+                .into_iter()
+                .map(|shard| {
+                    let prefetcher = self.prefetcher.clone();
+                    std::thread::spawn(move || prefetcher.prefetch(&shard))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("prefetch thread panicked"))
+                .collect()
+        };
+        #[cfg(not(feature = "std"))]
+        let results: Vec<_> = out
+            .chunks(shard_size)
+            .map(|shard| self.prefetcher.prefetch(shard))
+            .collect();
+
+        #[cfg(feature = "std")]
+        self.observe_fetch(start.elapsed());
+        for result in results {
+            let result = result?;
+            self.fetch_stats.record_fetched(&result);
             self.statedb.apply_states(result)?;
         }
-        Ok(out.len())
+        Ok(total)
+    }
+}
+
+// every account, code and slot the cached tx touched can be read under the
+// current root, the statedb may not hold the nodes of a root it didn't write
+fn resolves<D: StateDB>(statedb: &mut D, cached: &CachedExecution) -> Result<bool, statedb::Error> {
+    // the sender and the miner are in `touched` too
+    for item in &cached.result.touched {
+        let missing = statedb.check_missing_state(&item.address, &item.storage_keys)?;
+        if missing.account || missing.code || !missing.storages.is_empty() {
+            return Ok(false);
+        }
     }
+    Ok(true)
 }
 
 pub trait StatePrefetcher {
     fn prefetch(&self, req: &[FetchState]) -> Result<Vec<FetchStateResult>, statedb::Error>;
 }
 
+// the result of BlockBuilder::execute_tx, a new execution is returned for the
+// ExecutionCache, it's only inserted once the tx is committed
+type ExecutedTx = (
+    ExecuteResult,
+    ReceiptContext,
+    Option<(ExecutionKey, CachedExecution)>,
+);
+
+#[derive(Debug, Clone)]
+struct Savepoint {
+    state_root: SH256,
+    tx_len: usize,
+    cumulative_gas_used: u64,
+    // the withdrawals were processed before the savepoint
+    withdrawn: bool,
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CommitError {
-    NotEnoughGasLimit { gas_pool: u64, gas_limit: u64 },
+    NotEnoughGasLimit {
+        tx_hash: SH256,
+        gas_pool: u64,
+        gas_limit: u64,
+    },
+    Rejected {
+        tx_hash: SH256,
+        reason: TxRejection,
+    },
+    Signature {
+        tx_hash: SH256,
+        err: SignerError,
+    },
+    TipTooLow {
+        tx_hash: SH256,
+        tip: SU256,
+        min_tip: SU256,
+    },
+    // the tx gave another audit digest than the recorded run, see DeterminismCheck
+    Nondeterministic(Divergence),
     Execute(ExecuteError),
 }
+
+impl core::fmt::Display for CommitError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotEnoughGasLimit {
+                tx_hash,
+                gas_pool,
+                gas_limit,
+            } => write!(
+                f,
+                "gas limit reached: tx {:?}, gas pool {}, tx gas limit {}",
+                tx_hash, gas_pool, gas_limit
+            ),
+            Self::Rejected { tx_hash, reason } => {
+                write!(f, "tx {:?} rejected by the policy: {}", tx_hash, reason)
+            }
+            Self::Signature { tx_hash, err } => {
+                write!(f, "tx {:?} signature not accepted: {:?}", tx_hash, err)
+            }
+            Self::TipTooLow {
+                tx_hash,
+                tip,
+                min_tip,
+            } => write!(
+                f,
+                "tx {:?} tip too low: {} < minimum {}",
+                tx_hash, tip, min_tip
+            ),
+            Self::Nondeterministic(divergence) => write!(
+                f,
+                "tx {:?} is nondeterministic: {} differs from the recorded run",
+                divergence.tx_hash, divergence.field
+            ),
+            Self::Execute(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+impl std::error::Error for CommitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Execute(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keccak_hash, Ethereum, ForkSchedule, MemoryAccessHints, PobData, PobStateDB};
+    use eth_types::{
+        Block, BlockHeader, HexBytes, Receipt, Transaction, TransactionInner, Withdrawal, H160,
+        H256,
+    };
+    use rlp::RlpStream;
+    use std::collections::BTreeSet;
+
+    // the txs signed by 0x6f4c950442e1af093bcff730381e63ae9171b87a, the
+    // fees are zero so the sender doesn't need a balance
+    fn tx(name: &str) -> Arc<TransactionInner> {
+        let txs: BTreeMap<String, Transaction> =
+            serde_json::from_str(include_str!("testdata/block_builder.json")).unwrap();
+        Arc::new(txs[name].inner().unwrap())
+    }
+
+    // the contracts are the only accounts, the others are proven empty
+    fn statedb(contracts: &[(SH160, Vec<u8>)]) -> PobStateDB {
+        let items = contracts
+            .iter()
+            .map(|(address, code)| {
+                let storage_root: &[u8] = &mpt::EMPTY_ROOT;
+                let mut account = RlpStream::new_list(4);
+                account.append(&0_u64);
+                account.append(&0_u64);
+                account.append(&storage_root);
+                account.append(&keccak_hash(code).as_slice());
+                (
+                    keccak_hash(address.0.as_bytes()).to_vec(),
+                    account.out().to_vec(),
+                )
+            })
+            .collect();
+        let mut data = PobData::default();
+        let mut nodes = BTreeSet::new();
+        for (address, code) in contracts {
+            let (root, proof) = mpt::build_proof(&items, &keccak_hash(address.0.as_bytes()));
+            data.prev_state_root = root;
+            nodes.extend(proof);
+            data.codes.push(code.clone().into());
+        }
+        data.mpt_nodes = nodes.into_iter().map(HexBytes::from).collect();
+        PobStateDB::new(&data)
+    }
+
+    fn word(val: u64) -> SH256 {
+        H256::from_low_u64_be(val).into()
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            number: 1_u64.into(),
+            gas_limit: 30_000_000_u64.into(),
+            ..Default::default()
+        }
+    }
+
+    // Ethereum with a receipt extension which always fails
+    struct FailingReceipts(Ethereum);
+
+    impl Engine for FailingReceipts {
+        type Transaction = TransactionInner;
+        type BlockHeader = BlockHeader;
+        type Receipt = Receipt;
+        type Withdrawal = Withdrawal;
+        type Block = Block;
+        type NewBlockContext = ();
+
+        fn signer(&self) -> Signer {
+            self.0.signer()
+        }
+
+        fn evm_config(&self) -> evm::Config {
+            self.0.evm_config()
+        }
+
+        fn precompile(&self) -> Arc<PrecompileSet> {
+            self.0.precompile()
+        }
+
+        fn new_block_header(&self, _prev_header: &BlockHeader, _ctx: ()) -> BlockHeader {
+            header()
+        }
+
+        fn build_receipt(
+            &self,
+            cumulative_gas_used: u64,
+            result: ExecuteResult,
+            tx_idx: usize,
+            tx: &TransactionInner,
+            header: &BlockHeader,
+        ) -> Receipt {
+            self.0
+                .build_receipt(cumulative_gas_used, result, tx_idx, tx, header)
+        }
+
+        fn extend_receipt<S: StateDB>(
+            &self,
+            _statedb: &mut S,
+            _receipt: &mut Receipt,
+            _ctx: &ReceiptContext,
+        ) -> Result<(), statedb::Error> {
+            Err(statedb::Error::WithKey("no l1 block info".into()))
+        }
+
+        fn encode_receipt(&self, receipt: &Receipt) -> Vec<u8> {
+            self.0.encode_receipt(receipt)
+        }
+
+        fn encode_withdrawal(&self, withdrawal: &Withdrawal) -> Vec<u8> {
+            self.0.encode_withdrawal(withdrawal)
+        }
+
+        fn author(&self, header: &BlockHeader) -> Result<Option<SH160>, String> {
+            self.0.author(header)
+        }
+
+        fn tx_context<'a, H: BlockHashGetter>(
+            &self,
+            ctx: &mut TxContext<'a, TransactionInner, BlockHeader, H>,
+        ) {
+            self.0.tx_context(ctx)
+        }
+
+        fn process_withdrawal<S: StateDB>(
+            &mut self,
+            statedb: &mut S,
+            withdrawal: &Withdrawal,
+        ) -> Result<WithdrawalOutcome, statedb::Error> {
+            self.0.process_withdrawal(statedb, withdrawal)
+        }
+
+        fn finalize_block<S: StateDB>(
+            &mut self,
+            statedb: &mut S,
+            header: BlockHeader,
+            txs: Vec<Arc<TransactionInner>>,
+            receipts: Vec<Receipt>,
+            withdrawals: Option<Vec<Withdrawal>>,
+        ) -> Result<Block, String> {
+            self.0
+                .finalize_block(statedb, header, txs, receipts, withdrawals)
+        }
+    }
+
+    #[test]
+    fn test_blob_opcodes() {
+        let contract: SH160 = H160::repeat_byte(0x11).into();
+        // BLOBHASH(0), BLOBHASH(1) and BLOBBASEFEE into the slots 0, 1 and 2
+        let code = vec![
+            0x60, 0x00, 0x49, 0x60, 0x00, 0x55, 0x60, 0x01, 0x49, 0x60, 0x01, 0x55, 0x4a, 0x60,
+            0x02, 0x55, 0x00,
+        ];
+        let engine =
+            Ethereum::new(1_u64.into()).with_fork_schedule(ForkSchedule::new().with_cancun(0));
+        let header = BlockHeader {
+            number: 1_u64.into(),
+            gas_limit: 30_000_000_u64.into(),
+            // e^10 times the minimum blob base fee
+            excess_blob_gas: Some(33_384_770_u64.into()),
+            ..Default::default()
+        };
+        let statedb = statedb(&[(contract, code)]);
+        let mut builder = BlockBuilder::new(engine, statedb, BTreeMap::new(), header).unwrap();
+        assert_eq!(builder.commit(tx("blob")).unwrap().status.as_u64(), 1);
+
+        let versioned_hash = |byte: u8| {
+            let mut hash = H256::repeat_byte(byte);
+            hash.0[0] = 0x01;
+            SH256::from(hash)
+        };
+        let statedb = builder.statedb();
+        assert_eq!(
+            statedb.get_state(&contract, &word(0)).unwrap(),
+            versioned_hash(0xaa)
+        );
+        assert_eq!(
+            statedb.get_state(&contract, &word(1)).unwrap(),
+            versioned_hash(0xbb)
+        );
+        assert_eq!(statedb.get_state(&contract, &word(2)).unwrap(), word(22026));
+    }
+
+    #[test]
+    fn test_prev_randao() {
+        let contract: SH160 = H160::repeat_byte(0x11).into();
+        let mix_hash: SH256 = H256::repeat_byte(0x77).into();
+        // DIFFICULTY into the slot 0
+        let code = vec![0x44, 0x60, 0x00, 0x55, 0x00];
+        let difficulty = |difficulty: u64| {
+            let header = BlockHeader {
+                number: 1_u64.into(),
+                gas_limit: 30_000_000_u64.into(),
+                difficulty: difficulty.into(),
+                mix_hash,
+                ..Default::default()
+            };
+            let engine = Ethereum::new(1_u64.into());
+            let statedb = statedb(&[(contract, code.clone())]);
+            let mut builder = BlockBuilder::new(engine, statedb, BTreeMap::new(), header).unwrap();
+            assert_eq!(builder.commit(tx("call")).unwrap().status.as_u64(), 1);
+            builder.statedb().get_state(&contract, &word(0)).unwrap()
+        };
+        // the mix hash is not the randomness before the merge
+        assert_eq!(difficulty(1_000), word(1_000));
+        assert_eq!(difficulty(0), mix_hash);
+    }
+
+    #[test]
+    fn test_extend_receipt_failure() {
+        let contract: SH160 = H160::repeat_byte(0x11).into();
+        // 1 into the slot 0
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x00];
+        let builder = || {
+            let engine = FailingReceipts(Ethereum::new(1_u64.into()));
+            let statedb = statedb(&[(contract, code.clone())]);
+            BlockBuilder::new(engine, statedb, BTreeMap::new(), header()).unwrap()
+        };
+        let reverted = |builder: &mut BlockBuilder<FailingReceipts, PobStateDB, _>| {
+            let sender = builder.sender(&tx("call"));
+            assert!(matches!(
+                builder.commit(tx("call")),
+                Err(CommitError::Execute(ExecuteError::StateError(_)))
+            ));
+            assert!(builder.txs().is_empty());
+            let statedb = builder.statedb();
+            assert_eq!(statedb.get_state(&contract, &word(0)).unwrap(), word(0));
+            assert_eq!(statedb.get_nonce(&sender).unwrap(), 0);
+        };
+
+        let mut cached = builder();
+        cached.set_execution_cache(Some(ExecutionCache::new()));
+        cached.set_access_hints(Some(Box::new(MemoryAccessHints::default())));
+        reverted(&mut cached);
+        assert_eq!(cached.execution_cache().unwrap().len(), 0);
+        let hints = cached.take_access_hints().unwrap();
+        assert!(hints.hints(&contract).is_empty());
+
+        let mut checked = builder();
+        checked.check_determinism(None);
+        reverted(&mut checked);
+        assert!(checked.determinism_record().unwrap().txs.is_empty());
+    }
+
+    #[test]
+    fn test_rollback_withdrawals() {
+        let contract: SH160 = H160::repeat_byte(0x11).into();
+        let engine = Ethereum::new(1_u64.into());
+        let statedb = statedb(&[(contract, vec![0x00])]);
+        let mut builder = BlockBuilder::new(engine, statedb, BTreeMap::new(), header()).unwrap();
+        let withdrawal = Withdrawal {
+            address: contract,
+            amount: 5_u64.into(),
+            ..Default::default()
+        };
+        let gwei = |n: u64| SU256::from(eth_types::gwei() * n);
+
+        builder.savepoint("before").unwrap();
+        builder.withdrawal(vec![withdrawal.clone()]).unwrap();
+        builder.savepoint("after").unwrap();
+        assert_eq!(builder.statedb().get_balance(&contract).unwrap(), gwei(5));
+        let root = builder.withdrawal_root();
+        assert!(root.is_some());
+
+        builder.rollback("before").unwrap();
+        assert_eq!(builder.statedb().get_balance(&contract).unwrap(), gwei(0));
+        assert!(builder.withdrawal_summary().is_none());
+        assert!(builder.withdrawal_root().is_none());
+        // the savepoint is gone with the withdrawals
+        assert!(builder.rollback("after").is_err());
+
+        builder.withdrawal(vec![withdrawal]).unwrap();
+        builder.savepoint("after").unwrap();
+        builder.rollback("after").unwrap();
+        assert_eq!(builder.statedb().get_balance(&contract).unwrap(), gwei(5));
+        assert!(builder.withdrawal_summary().is_some());
+        assert_eq!(builder.withdrawal_root(), root);
+    }
+}
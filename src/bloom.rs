@@ -0,0 +1,147 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, Log, SH160, SH256};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::keccak_hash;
+
+pub const BLOOM_SIZE: usize = 256;
+
+// The 2048 bits logs bloom, accumulated log by log so the receipt and the
+// block bloom don't need to walk all the logs again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LogsBloom(pub [u8; BLOOM_SIZE]);
+
+impl Default for LogsBloom {
+    fn default() -> Self {
+        Self([0_u8; BLOOM_SIZE])
+    }
+}
+
+impl core::fmt::Debug for LogsBloom {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.to_hex())
+    }
+}
+
+impl LogsBloom {
+    pub fn from_logs(logs: &[Log]) -> Self {
+        let mut bloom = Self::default();
+        for log in logs {
+            bloom.accrue_log(log);
+        }
+        bloom
+    }
+
+    // the (byte index, bit mask) of the 3 bits of `data`
+    fn bits(data: &[u8]) -> [(usize, u8); 3] {
+        let hash = keccak_hash(data);
+        let mut bits = [(0, 0); 3];
+        for (n, i) in [0, 2, 4].into_iter().enumerate() {
+            let bit = (((hash[i] as usize) << 8) | hash[i + 1] as usize) & 2047;
+            bits[n] = (BLOOM_SIZE - 1 - bit / 8, 1 << (bit % 8));
+        }
+        bits
+    }
+
+    pub fn accrue(&mut self, data: &[u8]) {
+        for (idx, mask) in Self::bits(data) {
+            self.0[idx] |= mask;
+        }
+    }
+
+    pub fn accrue_log(&mut self, log: &Log) {
+        self.accrue(log.address.0.as_bytes());
+        for topic in &log.topics {
+            self.accrue(topic.0.as_bytes());
+        }
+    }
+
+    pub fn accrue_bloom(&mut self, other: &LogsBloom) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b;
+        }
+    }
+
+    // false positives are possible, a false means `data` is never accrued
+    pub fn contains(&self, data: &[u8]) -> bool {
+        Self::bits(data)
+            .into_iter()
+            .all(|(idx, mask)| self.0[idx] & mask == mask)
+    }
+
+    // check the bloom before walking the logs, see LogFilter::matches
+    pub fn might_match(&self, filter: &LogFilter) -> bool {
+        let address = filter.address.is_empty()
+            || filter
+                .address
+                .iter()
+                .any(|address| self.contains(address.0.as_bytes()));
+        address
+            && filter.topics.iter().all(|topics| match topics {
+                Some(topics) => {
+                    topics.is_empty()
+                        || topics.iter().any(|topic| self.contains(topic.0.as_bytes()))
+                }
+                None => true,
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|b| *b == 0)
+    }
+
+    pub fn to_hex(&self) -> HexBytes {
+        self.0.to_vec().into()
+    }
+}
+
+// The address and topics criteria of eth_getLogs. An empty address list
+// matches any address, a None topic matches any topic at that position and
+// the alternatives of a position are ORed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogFilter {
+    #[serde(default)]
+    pub address: Vec<SH160>,
+    #[serde(default)]
+    pub topics: Vec<Option<Vec<SH256>>>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &Log) -> bool {
+        if !self.address.is_empty() && !self.address.contains(&log.address) {
+            return false;
+        }
+        if self.topics.len() > log.topics.len() {
+            return false;
+        }
+        self.topics
+            .iter()
+            .zip(&log.topics)
+            .all(|(expect, topic)| match expect {
+                Some(expect) => expect.is_empty() || expect.contains(topic),
+                None => true,
+            })
+    }
+}
+
+impl Serialize for LogsBloom {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.to_hex().serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for LogsBloom {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let data = HexBytes::deserialize(d)?;
+        if data.len() != BLOOM_SIZE {
+            return Err(serde::de::Error::custom(format!(
+                "invalid bloom length: {}",
+                data.len()
+            )));
+        }
+        let mut bloom = Self::default();
+        bloom.0.copy_from_slice(&data);
+        Ok(bloom)
+    }
+}
@@ -0,0 +1,195 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{H160, H256, SH160, SH256, SU256, U256};
+use evm::{
+    executor::stack::{
+        IsPrecompileResult, PrecompileFailure, PrecompileHandle, PrecompileOutput,
+        PrecompileSet as EvmPrecompileSet,
+    },
+    ExitError, ExitSucceed,
+};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+
+use crate::{PrecompileResult, PrecompileSet};
+
+// the hevm address used by forge-std, `Vm(address(uint160(uint256(keccak256("hevm cheat code")))))`
+pub const CHEATCODE_ADDRESS: H160 = H160([
+    0x71, 0x09, 0x70, 0x9e, 0xcf, 0xa9, 0x1a, 0x80, 0x62, 0x6f, 0xf3, 0x98, 0x9d, 0x68, 0xf6, 0x7f,
+    0x5b, 0x1d, 0xd1, 0x2d,
+]);
+
+const SELECTOR_WARP: [u8; 4] = [0xe5, 0xd6, 0xbf, 0x02]; // warp(uint256)
+const SELECTOR_DEAL: [u8; 4] = [0xc8, 0x8a, 0x5e, 0x6d]; // deal(address,uint256)
+const SELECTOR_STORE: [u8; 4] = [0x70, 0xca, 0x10, 0xbb]; // store(address,bytes32,bytes32)
+const SELECTOR_LOAD: [u8; 4] = [0x66, 0x7f, 0x9d, 0x70]; // load(address,bytes32)
+const SELECTOR_START_PRANK: [u8; 4] = [0x06, 0x44, 0x7d, 0x56]; // startPrank(address)
+const SELECTOR_STOP_PRANK: [u8; 4] = [0x90, 0xc5, 0x01, 0x3b]; // stopPrank()
+
+// The cheatcode state of a BlockBuilder, only available with the `testing`
+// feature. The evm caches the accounts it already loaded, so `deal` and
+// `store` are seen by the reads of the same tx only if the account or the
+// slot is not loaded yet, they always land in the statedb when the tx is
+// committed. The prank works on the tx level: the following txs are executed
+// as the pranked address.
+#[derive(Debug, Default)]
+pub struct Cheatcodes {
+    timestamp: RefCell<Option<u64>>,
+    prank: RefCell<Option<SH160>>,
+    balances: RefCell<BTreeMap<SH160, SU256>>,
+    storages: RefCell<BTreeMap<SH160, BTreeMap<SH256, SH256>>>,
+}
+
+impl Cheatcodes {
+    pub fn warp(&self, timestamp: u64) {
+        *self.timestamp.borrow_mut() = Some(timestamp);
+    }
+
+    pub fn deal(&self, address: SH160, balance: SU256) {
+        self.balances.borrow_mut().insert(address, balance);
+    }
+
+    pub fn store(&self, address: SH160, index: SH256, value: SH256) {
+        self.storages
+            .borrow_mut()
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+    }
+
+    pub fn start_prank(&self, address: SH160) {
+        *self.prank.borrow_mut() = Some(address);
+    }
+
+    pub fn stop_prank(&self) {
+        *self.prank.borrow_mut() = None;
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        *self.timestamp.borrow()
+    }
+
+    pub fn prank(&self) -> Option<SH160> {
+        *self.prank.borrow()
+    }
+
+    pub fn balance(&self, address: &SH160) -> Option<SU256> {
+        self.balances.borrow().get(address).cloned()
+    }
+
+    pub fn storage(&self, address: &SH160, index: &SH256) -> Option<SH256> {
+        let storages = self.storages.borrow();
+        storages.get(address)?.get(index).cloned()
+    }
+
+    // writes the pending deal/store into the statedb, they are dropped if the
+    // tx is reverted.
+    pub(crate) fn flush<D: StateDB>(
+        &self,
+        statedb: &mut D,
+        success: bool,
+    ) -> Result<(), statedb::Error> {
+        let balances = core::mem::take(&mut *self.balances.borrow_mut());
+        let storages = core::mem::take(&mut *self.storages.borrow_mut());
+        if !success {
+            return Ok(());
+        }
+        for (address, balance) in balances {
+            statedb.set_balance(&address, balance.raw().clone().into())?;
+        }
+        for (address, storage) in storages {
+            for (index, value) in storage {
+                statedb.set_state(&address, &index, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&self, input: &[u8]) -> PrecompileResult {
+        fn word(input: &[u8], idx: usize) -> Result<H256, PrecompileFailure> {
+            match input.get(4 + idx * 32..4 + (idx + 1) * 32) {
+                Some(word) => Ok(H256::from_slice(word)),
+                None => Err(PrecompileFailure::Error {
+                    exit_status: ExitError::Other("cheatcode: input too short".into()),
+                }),
+            }
+        }
+        fn address(word: H256) -> SH160 {
+            H160::from_slice(&word.0[12..]).into()
+        }
+
+        let mut selector = [0_u8; 4];
+        match input.get(..4) {
+            Some(n) => selector.copy_from_slice(n),
+            None => {
+                return Err(PrecompileFailure::Error {
+                    exit_status: ExitError::Other("cheatcode: missing selector".into()),
+                })
+            }
+        }
+        let mut output = Vec::new();
+        match selector {
+            SELECTOR_WARP => self.warp(U256::from_big_endian(&word(input, 0)?.0).low_u64()),
+            SELECTOR_DEAL => self.deal(
+                address(word(input, 0)?),
+                U256::from_big_endian(&word(input, 1)?.0).into(),
+            ),
+            SELECTOR_STORE => self.store(
+                address(word(input, 0)?),
+                word(input, 1)?.into(),
+                word(input, 2)?.into(),
+            ),
+            SELECTOR_LOAD => {
+                // only the pending stores, the statedb is not reachable here
+                let value = self.storage(&address(word(input, 0)?), &word(input, 1)?.into());
+                output = value.unwrap_or_default().0.as_bytes().to_vec();
+            }
+            SELECTOR_START_PRANK => self.start_prank(address(word(input, 0)?)),
+            SELECTOR_STOP_PRANK => self.stop_prank(),
+            _ => {
+                return Err(PrecompileFailure::Error {
+                    exit_status: ExitError::Other("cheatcode: unknown selector".into()),
+                })
+            }
+        }
+        Ok(PrecompileOutput {
+            exit_status: ExitSucceed::Returned,
+            output,
+        })
+    }
+}
+
+// Routes the calls to CHEATCODE_ADDRESS to the cheatcodes, the others go to
+// the engine's precompile set.
+pub struct CheatcodePrecompileSet<'a> {
+    inner: &'a PrecompileSet,
+    cheatcodes: Option<&'a Cheatcodes>,
+}
+
+impl<'a> CheatcodePrecompileSet<'a> {
+    pub fn new(inner: &'a PrecompileSet, cheatcodes: Option<&'a Cheatcodes>) -> Self {
+        Self { inner, cheatcodes }
+    }
+}
+
+impl<'a> EvmPrecompileSet for CheatcodePrecompileSet<'a> {
+    fn execute(&self, handle: &mut impl PrecompileHandle) -> Option<PrecompileResult> {
+        match self.cheatcodes {
+            Some(cheatcodes) if handle.code_address() == CHEATCODE_ADDRESS => {
+                Some(cheatcodes.run(handle.input()))
+            }
+            _ => self.inner.execute(handle),
+        }
+    }
+
+    fn is_precompile(&self, address: H160, remaining_gas: u64) -> IsPrecompileResult {
+        if self.cheatcodes.is_some() && address == CHEATCODE_ADDRESS {
+            return IsPrecompileResult::Answer {
+                is_precompile: true,
+                extra_cost: 0,
+            };
+        }
+        self.inner.is_precompile(address, remaining_gas)
+    }
+}
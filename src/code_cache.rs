@@ -0,0 +1,89 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{HexBytes, SH160, SH256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::keccak_hash;
+
+// Contract code shared across the txs of a block. The code itself is keyed by
+// its hash so identical bytecode deployed at different addresses is stored
+// once, and an address index avoids asking the StateDB again.
+#[derive(Debug, Default)]
+pub struct CodeCache {
+    codes: RefCell<BTreeMap<SH256, Arc<HexBytes>>>,
+    hashes: RefCell<BTreeMap<SH160, SH256>>,
+}
+
+impl CodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_by_hash(&self, hash: &SH256) -> Option<Arc<HexBytes>> {
+        self.codes.borrow().get(hash).cloned()
+    }
+
+    pub fn get(&self, address: &SH160) -> Option<(SH256, Arc<HexBytes>)> {
+        let hash = *self.hashes.borrow().get(address)?;
+        let code = self.get_by_hash(&hash)?;
+        Some((hash, code))
+    }
+
+    pub fn insert(&self, address: SH160, code: HexBytes) -> (SH256, Arc<HexBytes>) {
+        let hash: SH256 = keccak_hash(&code).into();
+        let code = self
+            .codes
+            .borrow_mut()
+            .entry(hash)
+            .or_insert_with(|| Arc::new(code))
+            .clone();
+        self.hashes.borrow_mut().insert(address, hash);
+        (hash, code)
+    }
+
+    // should be called whenever the code of the address may change(create, suicide)
+    pub fn invalidate(&self, address: &SH160) {
+        self.hashes.borrow_mut().remove(address);
+    }
+
+    // the codes are immutable by hash, only the address index may be stale after a revert
+    pub fn reset_addresses(&self) {
+        self.hashes.borrow_mut().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.borrow().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::H160;
+
+    #[test]
+    fn test_code_cache() {
+        let a: SH160 = H160::repeat_byte(0x11).into();
+        let b: SH160 = H160::repeat_byte(0x22).into();
+        let code: HexBytes = vec![0x60, 0x00, 0x60, 0x00, 0xf3].into();
+        let cache = CodeCache::new();
+        assert!(cache.get(&a).is_none());
+
+        // the same bytecode at two addresses is stored once
+        let (hash, first) = cache.insert(a, code.clone());
+        let (_, second) = cache.insert(b, code.clone());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(hash, SH256::from(keccak_hash(&code)));
+        assert_eq!(cache.get(&b).unwrap().0, hash);
+
+        cache.invalidate(&a);
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_some());
+        cache.reset_addresses();
+        assert!(cache.get(&b).is_none());
+        assert!(Arc::ptr_eq(&cache.get_by_hash(&hash).unwrap(), &first));
+    }
+}
@@ -0,0 +1,68 @@
+use std::prelude::v1::*;
+
+use core::convert::TryFrom;
+use crypto::Secp256k1PrivateKey;
+use eth_types::{H160, SH160};
+use k256::ecdsa::{recoverable, signature::Signer, SigningKey};
+
+use crate::{CryptoProvider, DefaultCryptoProvider};
+
+// The secp256k1 paths backed by the constant time k256 implementation, for
+// the deployments where the timing of the enclave is observable. Install it
+// with set_crypto_provider before executing any block:
+//
+//     set_crypto_provider(&ConstantTimeCryptoProvider).unwrap();
+//
+// The hashes only see public data and stay on the default provider.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantTimeCryptoProvider;
+
+impl ConstantTimeCryptoProvider {
+    fn uncompressed(key: &k256::ecdsa::VerifyingKey) -> [u8; 64] {
+        let point = key.to_encoded_point(false);
+        let mut out = [0_u8; 64];
+        out.copy_from_slice(&point.as_bytes()[1..]);
+        out
+    }
+}
+
+impl CryptoProvider for ConstantTimeCryptoProvider {
+    fn keccak256(&self, data: &[u8]) -> [u8; 32] {
+        DefaultCryptoProvider.keccak256(data)
+    }
+
+    fn keccak256_parts(&self, f: &mut dyn FnMut(&mut dyn FnMut(&[u8]))) -> [u8; 32] {
+        DefaultCryptoProvider.keccak256_parts(f)
+    }
+
+    fn sha256(&self, data: &[u8]) -> [u8; 32] {
+        DefaultCryptoProvider.sha256(data)
+    }
+
+    fn secp256k1_ecrecover(&self, sig: &[u8; 65], hash: &[u8; 32]) -> Option<[u8; 64]> {
+        let mut raw = *sig;
+        raw[64] = raw[64].checked_sub(27)?;
+        let sig = recoverable::Signature::try_from(&raw[..]).ok()?;
+        let key = sig
+            .recover_verifying_key_from_digest_bytes(hash.into())
+            .ok()?;
+        Some(Self::uncompressed(&key))
+    }
+
+    // r ++ s ++ recovery id, same as the default provider
+    fn secp256k1_sign(&self, prvkey: &Secp256k1PrivateKey, msg: &[u8]) -> [u8; 65] {
+        let raw = prvkey.to_raw_bytes();
+        let key = SigningKey::from_bytes(&raw[..]).expect("valid secp256k1 private key");
+        let sig: recoverable::Signature = key.sign(msg);
+        let mut out = [0_u8; 65];
+        out.copy_from_slice(sig.as_ref());
+        out
+    }
+
+    fn secp256k1_recover_signer(&self, sig: &[u8; 65], msg: &[u8]) -> Option<SH160> {
+        let sig = recoverable::Signature::try_from(&sig[..]).ok()?;
+        let key = sig.recover_verifying_key(msg).ok()?;
+        let hash = self.keccak256(&Self::uncompressed(&key));
+        Some(H160::from_slice(&hash[12..]).into())
+    }
+}
@@ -0,0 +1,119 @@
+use std::prelude::v1::*;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crypto::{Secp256k1PrivateKey, Secp256k1RecoverableSignature};
+use eth_types::SH160;
+
+// The hashing and the signatures of the executor go through the installed
+// provider, the default one is backed by the `crypto` crate.
+pub trait CryptoProvider: Sync {
+    fn keccak256(&self, data: &[u8]) -> [u8; 32];
+
+    // hashes the slices fed by `f` as one message
+    fn keccak256_parts(&self, f: &mut dyn FnMut(&mut dyn FnMut(&[u8]))) -> [u8; 32] {
+        let mut data = Vec::new();
+        f(&mut |part| data.extend_from_slice(part));
+        self.keccak256(&data)
+    }
+
+    fn sha256(&self, data: &[u8]) -> [u8; 32];
+
+    // the ecrecover precompile: v is 27 or 28, returns the 64 bytes public key
+    fn secp256k1_ecrecover(&self, sig: &[u8; 65], hash: &[u8; 32]) -> Option<[u8; 64]>;
+
+    // signs keccak(msg), the result is accepted by secp256k1_recover_signer
+    fn secp256k1_sign(&self, prvkey: &Secp256k1PrivateKey, msg: &[u8]) -> [u8; 65];
+
+    fn secp256k1_recover_signer(&self, sig: &[u8; 65], msg: &[u8]) -> Option<SH160>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultCryptoProvider;
+
+impl CryptoProvider for DefaultCryptoProvider {
+    fn keccak256(&self, data: &[u8]) -> [u8; 32] {
+        crypto::keccak_hash(data)
+    }
+
+    fn keccak256_parts(&self, f: &mut dyn FnMut(&mut dyn FnMut(&[u8]))) -> [u8; 32] {
+        crypto::keccak_encode(|hash| f(hash))
+    }
+
+    fn sha256(&self, data: &[u8]) -> [u8; 32] {
+        crypto::sha256_sum(data)
+    }
+
+    fn secp256k1_ecrecover(&self, sig: &[u8; 65], hash: &[u8; 32]) -> Option<[u8; 64]> {
+        crypto::secp256k1_ecdsa_recover(sig, hash)
+    }
+
+    fn secp256k1_sign(&self, prvkey: &Secp256k1PrivateKey, msg: &[u8]) -> [u8; 65] {
+        prvkey.sign(msg).to_array()
+    }
+
+    fn secp256k1_recover_signer(&self, sig: &[u8; 65], msg: &[u8]) -> Option<SH160> {
+        let sig = Secp256k1RecoverableSignature::new(*sig);
+        Some(
+            crypto::secp256k1_recover_pubkey(&sig, msg)
+                .eth_accountid()
+                .into(),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct SetCryptoProviderError;
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+static mut PROVIDER: &dyn CryptoProvider = &DefaultCryptoProvider;
+
+// Can only be called once, before the first block is executed. The hashes
+// computed before would come from the default provider.
+pub fn set_crypto_provider(
+    provider: &'static dyn CryptoProvider,
+) -> Result<(), SetCryptoProviderError> {
+    match STATE.compare_exchange(
+        UNINITIALIZED,
+        INITIALIZING,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    ) {
+        Ok(_) => {
+            unsafe { PROVIDER = provider };
+            STATE.store(INITIALIZED, Ordering::SeqCst);
+            Ok(())
+        }
+        Err(_) => Err(SetCryptoProviderError),
+    }
+}
+
+pub fn crypto_provider() -> &'static dyn CryptoProvider {
+    if STATE.load(Ordering::SeqCst) != INITIALIZED {
+        return &DefaultCryptoProvider;
+    }
+    unsafe { PROVIDER }
+}
+
+pub(crate) fn keccak_hash(data: &[u8]) -> [u8; 32] {
+    crypto_provider().keccak256(data)
+}
+
+pub(crate) fn keccak_encode<F>(f: F) -> [u8; 32]
+where
+    F: FnOnce(&mut dyn FnMut(&[u8])),
+{
+    let mut f = Some(f);
+    crypto_provider().keccak256_parts(&mut |hash| {
+        if let Some(f) = f.take() {
+            f(hash)
+        }
+    })
+}
+
+pub(crate) fn sha256_sum(data: &[u8]) -> [u8; 32] {
+    crypto_provider().sha256(data)
+}
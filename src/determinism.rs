@@ -0,0 +1,129 @@
+use std::prelude::v1::*;
+
+use eth_types::SH256;
+use serde::{Deserialize, Serialize};
+
+// The audit digest of the block after one tx, see ExecutionAudit
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxDigest {
+    pub tx_hash: SH256,
+    pub digest: SH256,
+}
+
+// The audit digests after every tx of a run. A run on one machine records it,
+// the run of the same block on another machine or another build checks
+// against it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeterminismRecord {
+    pub txs: Vec<TxDigest>,
+}
+
+// The first tx whose digest differs from the recorded run. `field` is
+// "tx_hash" if the recorded run has another tx (or none) at its position and
+// "audit_digest" if the tx consumed other inputs or gave another result.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Divergence {
+    pub tx_hash: SH256,
+    pub field: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeterminismReport {
+    pub txs_checked: u64,
+    pub divergences: Vec<Divergence>,
+}
+
+impl DeterminismReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+// Compares the audit digest after every tx with the one of an independent run.
+// Anything the execution reads besides its inputs (the clock, the randomness,
+// the iteration order of a HashMap which is seeded per instance) changes the
+// reads or the results hashed by the audit, and the tx is rejected with
+// CommitError::Nondeterministic. Without the expected record it only records,
+// the record is the reference of the later runs.
+#[derive(Debug, Default)]
+pub struct DeterminismCheck {
+    expected: Option<DeterminismRecord>,
+    record: DeterminismRecord,
+    report: DeterminismReport,
+}
+
+impl DeterminismCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn against(expected: DeterminismRecord) -> Self {
+        Self {
+            expected: Some(expected),
+            ..Default::default()
+        }
+    }
+
+    // `index` is the position of the tx in the block, the txs after it are
+    // dropped from the record as they are reverted.
+    pub fn check(&mut self, index: usize, tx_hash: SH256, digest: SH256) -> Option<Divergence> {
+        self.record.txs.truncate(index);
+        self.record.txs.push(TxDigest { tx_hash, digest });
+        let expected = self.expected.as_ref()?;
+        self.report.txs_checked += 1;
+        let field = match expected.txs.get(index) {
+            Some(expected) if expected.tx_hash != tx_hash => "tx_hash",
+            None => "tx_hash",
+            Some(expected) if expected.digest != digest => "audit_digest",
+            Some(_) => return None,
+        };
+        let divergence = Divergence {
+            tx_hash,
+            field: field.into(),
+        };
+        self.report.divergences.push(divergence.clone());
+        Some(divergence)
+    }
+
+    pub fn record(&self) -> &DeterminismRecord {
+        &self.record
+    }
+
+    pub fn report(&self) -> DeterminismReport {
+        self.report.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::H256;
+
+    fn hash(n: u8) -> SH256 {
+        H256::repeat_byte(n).into()
+    }
+
+    #[test]
+    fn test_determinism_check() {
+        let mut first = DeterminismCheck::new();
+        assert!(first.check(0, hash(1), hash(11)).is_none());
+        assert!(first.check(1, hash(2), hash(12)).is_none());
+        // the second tx is reverted and another one takes its place
+        assert!(first.check(1, hash(3), hash(13)).is_none());
+        assert_eq!(first.record().txs.len(), 2);
+        assert_eq!(first.report().txs_checked, 0);
+
+        let mut second = DeterminismCheck::against(first.record().clone());
+        assert!(second.check(0, hash(1), hash(11)).is_none());
+        let divergence = second.check(1, hash(3), hash(14)).unwrap();
+        assert_eq!(divergence.field, "audit_digest");
+        assert_eq!(second.check(2, hash(4), hash(15)).unwrap().field, "tx_hash");
+        let report = second.report();
+        assert_eq!(report.txs_checked, 3);
+        assert!(!report.is_deterministic());
+    }
+}
@@ -1,23 +1,93 @@
 use std::prelude::v1::*;
 
 use eth_types::{
-    Block, BlockHeader, HexBytes, Receipt, Signer, TransactionInner, Withdrawal, SH160, SH256,
-    SU256, SU64, U256,
+    Block, BlockHeader, BlockHeaderTrait, HexBytes, Log, Receipt, Signer, TransactionInner,
+    Withdrawal, SH160, SH256, SU256, SU64, U256,
 };
+use rlp::RlpStream;
 use statedb::StateDB;
 use std::sync::Arc;
 
-use crate::{BlockHashGetter, Engine, ExecuteResult, PrecompileSet, TxContext};
+use crate::{
+    BlockHashGetter, Engine, ExecuteResult, ForkSchedule, PrecompileSet, RefundRules,
+    SignerSchedule, TimestampFork, TxContext, WithdrawalOutcome,
+};
+
+// rlp([[address, [topic, ...], data], ...]) of the receipts
+pub(crate) fn append_logs(stream: &mut RlpStream, logs: &[Log]) {
+    stream.begin_list(logs.len());
+    for log in logs {
+        stream.begin_list(3);
+        stream.append(&log.address.0.as_bytes());
+        stream.begin_list(log.topics.len());
+        for topic in &log.topics {
+            stream.append(&topic.0.as_bytes());
+        }
+        stream.append(&log.data.as_bytes());
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Ethereum {
     signer: Signer,
+    signer_schedule: Option<SignerSchedule>,
+    eip3529_block: Option<u64>,
+    system_address: Option<SH160>,
+    fork_schedule: Option<ForkSchedule>,
+    precompile: Arc<PrecompileSet>,
+    cancun_precompile: Arc<PrecompileSet>,
 }
 
 impl Ethereum {
     pub fn new(chain_id: SU256) -> Self {
         let signer = Signer::new(chain_id);
-        Self { signer }
+        Self {
+            signer,
+            signer_schedule: None,
+            eip3529_block: None,
+            system_address: None,
+            fork_schedule: None,
+            precompile: Arc::new(PrecompileSet::berlin()),
+            cancun_precompile: Arc::new(PrecompileSet::cancun()),
+        }
+    }
+
+    // replay the historical blocks with the signer of their fork
+    pub fn with_signer_schedule(mut self, schedule: SignerSchedule) -> Self {
+        self.signer_schedule = Some(schedule);
+        self
+    }
+
+    // the precompiles of a chain which repriced or disabled some of them,
+    // used for all the forks
+    pub fn with_precompile(mut self, precompile: PrecompileSet) -> Self {
+        self.precompile = Arc::new(precompile);
+        self.cancun_precompile = self.precompile.clone();
+        self
+    }
+
+    // pick the evm config and the precompiles by the block timestamp, the
+    // shanghai config is used for all the blocks without a schedule.
+    pub fn with_fork_schedule(mut self, schedule: ForkSchedule) -> Self {
+        self.fork_schedule = Some(schedule);
+        self
+    }
+
+    pub fn fork_at(&self, header: &BlockHeader) -> Option<TimestampFork> {
+        let schedule = self.fork_schedule.as_ref()?;
+        Some(schedule.fork_at(header.timestamp.as_u64()))
+    }
+
+    // the txs sent by `address` are not charged, see Engine::system_address
+    pub fn with_system_address(mut self, address: SH160) -> Self {
+        self.system_address = Some(address);
+        self
+    }
+
+    // the blocks before `number` are executed with the pre EIP-3529 refunds
+    pub fn with_eip3529_block(mut self, number: u64) -> Self {
+        self.eip3529_block = Some(number);
+        self
     }
 }
 
@@ -72,60 +142,128 @@ impl Engine for Ethereum {
         evm::Config::shanghai()
     }
 
-    fn precompile(&self) -> PrecompileSet {
-        PrecompileSet::berlin()
+    fn evm_config_at(&self, header: &Self::BlockHeader) -> evm::Config {
+        let mut cfg = match self.fork_at(header) {
+            Some(fork) => fork.evm_config(),
+            None => self.evm_config(),
+        };
+        if let Some(eip3529_block) = self.eip3529_block {
+            RefundRules::at(eip3529_block, header.number.as_u64()).apply(&mut cfg);
+        }
+        cfg
+    }
+
+    fn precompile(&self) -> Arc<PrecompileSet> {
+        self.precompile.clone()
+    }
+
+    fn precompile_at(&self, header: &Self::BlockHeader) -> Arc<PrecompileSet> {
+        match self.fork_at(header) {
+            Some(TimestampFork::Cancun) => self.cancun_precompile.clone(),
+            _ => self.precompile.clone(),
+        }
     }
 
     fn signer(&self) -> Signer {
         self.signer.clone()
     }
 
+    fn signer_schedule(&self) -> Option<SignerSchedule> {
+        self.signer_schedule.clone()
+    }
+
+    fn system_address(&self) -> Option<SH160> {
+        self.system_address
+    }
+
     fn tx_context<'a, H: BlockHashGetter>(
         &self,
         ctx: &mut TxContext<'a, Self::Transaction, Self::BlockHeader, H>,
     ) {
-        ctx.block_base_fee = ctx.header.base_fee_per_gas;
+        ctx.block_base_fee = ctx.header.base_fee();
         ctx.miner = Some(ctx.header.miner);
+        // DIFFICULTY is PREVRANDAO after the merge, the difficulty is zero then
+        ctx.difficulty = ctx.header.difficulty;
+        ctx.prev_randao = match ctx.header.difficulty.is_zero() {
+            true => Some(ctx.header.mix_hash),
+            false => None,
+        };
+        if let TransactionInner::Blob(tx) = ctx.tx {
+            ctx.blob_hashes = tx.blob_versioned_hashes.clone();
+        }
+        if let Some(TimestampFork::Cancun) = self.fork_at(ctx.header) {
+            let excess_blob_gas = ctx.header.excess_blob_gas.unwrap_or_default();
+            ctx.blob_base_fee = Some(Self::calc_blob_base_fee(excess_blob_gas.as_u64()));
+        }
+        if let Some(schedule) = &self.fork_schedule {
+            ctx.eof = schedule.eof_at(ctx.header.timestamp.as_u64());
+        }
     }
 
     fn build_receipt(
         &self,
         cumulative_gas_used: u64,
-        result: &ExecuteResult,
+        result: ExecuteResult,
         tx_idx: usize,
         tx: &Self::Transaction,
         _header: &Self::BlockHeader,
     ) -> Self::Receipt {
-        let mut receipt = Receipt {
+        Receipt {
             status: (result.success as u64).into(),
             transaction_hash: tx.hash(),
             transaction_index: (tx_idx as u64).into(),
             r#type: Some(tx.ty().into()),
             gas_used: result.used_gas.into(),
             cumulative_gas_used: (cumulative_gas_used + result.used_gas).into(),
-            logs: result.logs.clone(),
-            logs_bloom: HexBytes::new(),
+            logs_bloom: result.logs_bloom.to_hex(),
+            logs: result.logs,
 
             // not affect the rlp encoding
             contract_address: None,
             root: None,
             block_hash: None,
             block_number: None,
-        };
-        receipt.logs_bloom = eth_types::create_bloom([&receipt].into_iter()).to_hex();
-        receipt
+        }
     }
 
-    fn process_withdrawals<D: StateDB>(
+    fn encode_receipt(&self, receipt: &Self::Receipt) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&receipt.status.as_u64());
+        stream.append(&receipt.cumulative_gas_used.as_u64());
+        stream.append(&receipt.logs_bloom.as_bytes());
+        append_logs(&mut stream, &receipt.logs);
+        let mut out = Vec::new();
+        match receipt.r#type.map(|ty| ty.as_u64()).unwrap_or(0) {
+            0 => {}
+            ty => out.push(ty as u8),
+        }
+        out.extend_from_slice(&stream.out());
+        out
+    }
+
+    // rlp([index, validator_index, address, amount])
+    fn encode_withdrawal(&self, withdrawal: &Self::Withdrawal) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&withdrawal.index.as_u64());
+        stream.append(&withdrawal.validator_index.as_u64());
+        stream.append(&withdrawal.address.0.as_bytes());
+        stream.append(&withdrawal.amount.as_u64());
+        stream.out().to_vec()
+    }
+
+    fn withdrawal_address(&self, withdrawal: &Self::Withdrawal) -> Option<SH160> {
+        Some(withdrawal.address)
+    }
+
+    // the amount is in gwei, a zero amount still touches the account
+    fn process_withdrawal<D: StateDB>(
         &mut self,
         statedb: &mut D,
-        withdrawals: &[Self::Withdrawal],
-    ) -> Result<(), statedb::Error> {
-        for withdrawal in withdrawals {
-            let amount = withdrawal.amount.as_u256() * eth_types::gwei();
-            statedb.add_balance(&withdrawal.address, &amount.into())?;
-        }
-        Ok(())
+        withdrawal: &Self::Withdrawal,
+    ) -> Result<WithdrawalOutcome, statedb::Error> {
+        let amount: SU256 = (withdrawal.amount.as_u256() * eth_types::gwei()).into();
+        statedb.add_balance(&withdrawal.address, &amount)?;
+        Ok(WithdrawalOutcome::Credited(amount))
     }
 
     fn finalize_block<D: StateDB>(
@@ -195,4 +333,22 @@ impl Ethereum {
             return base_fee.max(0.into()).into();
         }
     }
+
+    // EIP-4844: fake_exponential(MIN_BASE_FEE_PER_BLOB_GAS, excess_blob_gas,
+    // BLOB_BASE_FEE_UPDATE_FRACTION)
+    pub fn calc_blob_base_fee(excess_blob_gas: u64) -> SU256 {
+        const MIN_BASE_FEE_PER_BLOB_GAS: u64 = 1;
+        const BLOB_BASE_FEE_UPDATE_FRACTION: u64 = 3338477;
+        let numerator = U256::from(excess_blob_gas);
+        let denominator = U256::from(BLOB_BASE_FEE_UPDATE_FRACTION);
+        let mut output = U256::zero();
+        let mut accum = U256::from(MIN_BASE_FEE_PER_BLOB_GAS) * denominator;
+        let mut i = U256::one();
+        while !accum.is_zero() {
+            output = output.saturating_add(accum);
+            accum = accum.saturating_mul(numerator) / (denominator * i);
+            i += U256::one();
+        }
+        (output / denominator).into()
+    }
 }
@@ -0,0 +1,342 @@
+use std::prelude::v1::*;
+
+// The EVM Object Format v1 containers (EIP-3540) and their code validation
+// (EIP-3670). The evm only runs legacy bytecode, the validation lets the
+// executor tell an EOF tx apart instead of running it as legacy code, see
+// TxContext::eof.
+//
+//     container := magic, version, types header, code header,
+//                  [container header], data header, terminator, body
+//     body      := types, code+, container*, data
+
+pub const EOF_MAGIC: [u8; 2] = [0xef, 0x00];
+pub const EOF_VERSION: u8 = 1;
+
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_CONTAINER: u8 = 0x03;
+const KIND_DATA: u8 = 0xff;
+const TERMINATOR: u8 = 0x00;
+
+const MAX_CODE_SECTIONS: usize = 1024;
+const MAX_CONTAINER_SECTIONS: usize = 256;
+const MAX_STACK_HEIGHT: u16 = 1023;
+// the outputs of a function which never returns
+const NON_RETURNING: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EofError {
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    // the header or a section ends before its declared size
+    Truncated,
+    UnexpectedKind {
+        expect: u8,
+        got: u8,
+    },
+    InvalidSectionCount {
+        kind: u8,
+        count: usize,
+    },
+    EmptySection {
+        kind: u8,
+    },
+    InvalidTypesSize {
+        size: usize,
+        code_sections: usize,
+    },
+    // the first code section must take no inputs and never return
+    InvalidFirstType,
+    InvalidType {
+        section: usize,
+    },
+    TrailingBytes {
+        size: usize,
+    },
+    UndefinedOpcode {
+        section: usize,
+        pc: usize,
+        opcode: u8,
+    },
+    TruncatedImmediate {
+        section: usize,
+        pc: usize,
+        opcode: u8,
+    },
+    InvalidContainer {
+        section: usize,
+        err: Box<EofError>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EofTypeSection {
+    pub inputs: u8,
+    pub outputs: u8,
+    pub max_stack_height: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EofContainer {
+    pub version: u8,
+    pub types: Vec<EofTypeSection>,
+    pub codes: Vec<Vec<u8>>,
+    pub containers: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+pub fn is_eof(code: &[u8]) -> bool {
+    code.starts_with(&EOF_MAGIC)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, EofError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, EofError> {
+        let buf = self.bytes(2)?;
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], EofError> {
+        let buf = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(EofError::Truncated)?;
+        self.pos += n;
+        Ok(buf)
+    }
+
+    fn kind(&mut self, expect: u8) -> Result<(), EofError> {
+        match self.u8()? {
+            got if got == expect => Ok(()),
+            got => Err(EofError::UnexpectedKind { expect, got }),
+        }
+    }
+
+    // num_sections(u16), size(u16)+
+    fn sizes(&mut self, kind: u8, max: usize) -> Result<Vec<usize>, EofError> {
+        let count = self.u16()? as usize;
+        if count == 0 || count > max {
+            return Err(EofError::InvalidSectionCount { kind, count });
+        }
+        let mut sizes = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.u16()? as usize {
+                0 => return Err(EofError::EmptySection { kind }),
+                size => sizes.push(size),
+            }
+        }
+        Ok(sizes)
+    }
+}
+
+impl EofContainer {
+    // the container format of EIP-3540, the code is not looked into
+    pub fn parse(code: &[u8]) -> Result<Self, EofError> {
+        let mut r = Reader { data: code, pos: 0 };
+        if r.bytes(2).map_err(|_| EofError::InvalidMagic)? != EOF_MAGIC {
+            return Err(EofError::InvalidMagic);
+        }
+        let version = r.u8()?;
+        if version != EOF_VERSION {
+            return Err(EofError::UnsupportedVersion(version));
+        }
+
+        r.kind(KIND_TYPES)?;
+        let types_size = r.u16()? as usize;
+        r.kind(KIND_CODE)?;
+        let code_sizes = r.sizes(KIND_CODE, MAX_CODE_SECTIONS)?;
+        if types_size != code_sizes.len() * 4 {
+            return Err(EofError::InvalidTypesSize {
+                size: types_size,
+                code_sections: code_sizes.len(),
+            });
+        }
+        let container_sizes = match r.u8()? {
+            KIND_CONTAINER => {
+                let sizes = r.sizes(KIND_CONTAINER, MAX_CONTAINER_SECTIONS)?;
+                r.kind(KIND_DATA)?;
+                sizes
+            }
+            KIND_DATA => Vec::new(),
+            got => {
+                return Err(EofError::UnexpectedKind {
+                    expect: KIND_DATA,
+                    got,
+                })
+            }
+        };
+        let data_size = r.u16()? as usize;
+        r.kind(TERMINATOR)?;
+
+        let mut types = Vec::with_capacity(code_sizes.len());
+        for section in 0..code_sizes.len() {
+            let ty = EofTypeSection {
+                inputs: r.u8()?,
+                outputs: r.u8()?,
+                max_stack_height: r.u16()?,
+            };
+            if ty.inputs > 127
+                || ty.outputs > NON_RETURNING
+                || ty.max_stack_height > MAX_STACK_HEIGHT
+            {
+                return Err(EofError::InvalidType { section });
+            }
+            types.push(ty);
+        }
+        if types[0].inputs != 0 || types[0].outputs != NON_RETURNING {
+            return Err(EofError::InvalidFirstType);
+        }
+        let mut codes = Vec::with_capacity(code_sizes.len());
+        for size in code_sizes {
+            codes.push(r.bytes(size)?.to_vec());
+        }
+        let mut containers = Vec::with_capacity(container_sizes.len());
+        for size in container_sizes {
+            containers.push(r.bytes(size)?.to_vec());
+        }
+        let data = r.bytes(data_size)?.to_vec();
+        if r.pos != code.len() {
+            return Err(EofError::TrailingBytes {
+                size: code.len() - r.pos,
+            });
+        }
+        Ok(Self {
+            version,
+            types,
+            codes,
+            containers,
+            data,
+        })
+    }
+
+    // parse and check the code sections (EIP-3670), the sub containers are
+    // validated the same way. the stack and the jump validation of the
+    // later EOF EIPs is not covered.
+    pub fn validate(code: &[u8]) -> Result<Self, EofError> {
+        let container = Self::parse(code)?;
+        for (section, code) in container.codes.iter().enumerate() {
+            validate_code(section, code)?;
+        }
+        for (section, sub) in container.containers.iter().enumerate() {
+            Self::validate(sub).map_err(|err| EofError::InvalidContainer {
+                section,
+                err: Box::new(err),
+            })?;
+        }
+        Ok(container)
+    }
+}
+
+// the size of the immediate of `opcode` at `pc`, None if the opcode is not
+// defined in EOF code.
+fn immediate_size(code: &[u8], pc: usize, opcode: u8) -> Option<usize> {
+    match opcode {
+        // the legacy jumps, the gas and the code introspection are removed
+        0x38 | 0x39 | 0x3b | 0x3c | 0x3f | 0x56 | 0x57 | 0x58 | 0x5a => None,
+        0xf0 | 0xf1 | 0xf2 | 0xf4 | 0xf5 | 0xfa | 0xff => None,
+        0x00..=0x0b | 0x10..=0x1d | 0x20 | 0x30..=0x3f | 0x40..=0x4a | 0x50..=0x5f => Some(0),
+        0x60..=0x7f => Some((opcode - 0x5f) as usize),
+        0x80..=0xa4 => Some(0),
+        // DATALOAD, DATASIZE, DATACOPY and DATALOADN
+        0xd0 | 0xd2 | 0xd3 => Some(0),
+        0xd1 => Some(2),
+        // RJUMP, RJUMPI, CALLF and JUMPF take a u16, RETF none
+        0xe0 | 0xe1 | 0xe3 | 0xe5 => Some(2),
+        0xe4 => Some(0),
+        // RJUMPV: max_index(u8), (max_index + 1) * offset(u16)
+        0xe2 => Some(
+            code.get(pc + 1)
+                .map(|max_index| 1 + (*max_index as usize + 1) * 2)
+                .unwrap_or(1),
+        ),
+        // DUPN, SWAPN, EXCHANGE, EOFCREATE and RETURNCONTRACT
+        0xe6 | 0xe7 | 0xe8 | 0xec | 0xee => Some(1),
+        // RETURN, REVERT, INVALID and the EXT*CALLs, RETURNDATALOAD
+        0xf3 | 0xfd | 0xfe | 0xf7 | 0xf8 | 0xf9 | 0xfb => Some(0),
+        _ => None,
+    }
+}
+
+fn validate_code(section: usize, code: &[u8]) -> Result<(), EofError> {
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        let size = immediate_size(code, pc, opcode).ok_or(EofError::UndefinedOpcode {
+            section,
+            pc,
+            opcode,
+        })?;
+        if pc + size >= code.len() {
+            return Err(EofError::TruncatedImmediate {
+                section,
+                pc,
+                opcode,
+            });
+        }
+        pc += 1 + size;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // types(00 80 0000), a single code section and no data
+    fn container(code: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xef, 0x00, 0x01, 0x01, 0x00, 0x04, 0x02, 0x00, 0x01];
+        out.extend_from_slice(&(code.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0xff, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00]);
+        out.extend_from_slice(code);
+        out
+    }
+
+    #[test]
+    fn test_eof_validation() {
+        let stop = container(&[0x00]);
+        assert!(is_eof(&stop));
+        let parsed = EofContainer::validate(&stop).unwrap();
+        assert_eq!(parsed.codes, vec![vec![0x00]]);
+
+        // PUSH2 with one byte
+        assert_eq!(
+            EofContainer::validate(&container(&[0x61, 0x01])),
+            Err(EofError::TruncatedImmediate {
+                section: 0,
+                pc: 0,
+                opcode: 0x61
+            })
+        );
+        // JUMP is a legacy opcode
+        assert_eq!(
+            EofContainer::validate(&container(&[0x56])),
+            Err(EofError::UndefinedOpcode {
+                section: 0,
+                pc: 0,
+                opcode: 0x56
+            })
+        );
+        let mut trailing = stop.clone();
+        trailing.push(0x00);
+        assert_eq!(
+            EofContainer::parse(&trailing),
+            Err(EofError::TrailingBytes { size: 1 })
+        );
+        assert_eq!(
+            EofContainer::parse(&stop[..stop.len() - 1]),
+            Err(EofError::Truncated)
+        );
+        assert_eq!(
+            EofContainer::parse(&[0x60, 0x00]),
+            Err(EofError::InvalidMagic)
+        );
+    }
+}
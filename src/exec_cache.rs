@@ -0,0 +1,266 @@
+use std::prelude::v1::*;
+
+use eth_types::{BlockHeaderTrait, TxTrait, SH256, SU256};
+use std::collections::BTreeMap;
+
+use crate::{keccak_encode, BlockHashGetter, ExecuteResult, FeePolicy, ReceiptContext, TxContext};
+
+// One tx execution, the post state root is where the statedb is moved to
+// when the result is reused.
+#[derive(Debug, Clone)]
+pub struct CachedExecution {
+    pub result: ExecuteResult,
+    pub receipt_ctx: ReceiptContext,
+    pub post_root: SH256,
+}
+
+// The key of a cached execution, an identical pre state root and block
+// context means the tx sees exactly the same inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExecutionKey {
+    pub tx_hash: SH256,
+    pub pre_root: SH256,
+    // see block_context_hash
+    pub block_context: SH256,
+}
+
+// The per tx results keyed by ExecutionKey, re-proving a block after a failure
+// later in the batch can skip the txs that already ran.
+//
+// A hit moves the statedb to the cached post root, the builder checks that
+// every account and slot the tx touched resolves under that root and runs the
+// tx otherwise, e.g. when the cache comes from a builder over another statedb.
+// The cache is not used while the builder records the witness or the audit
+// digest, they have to see every read of the execution.
+#[derive(Debug, Default)]
+pub struct ExecutionCache {
+    entries: BTreeMap<ExecutionKey, CachedExecution>,
+    hits: u64,
+    misses: u64,
+}
+
+impl ExecutionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, key: &ExecutionKey) -> Option<CachedExecution> {
+        match self.entries.get(key) {
+            Some(cached) => {
+                self.hits += 1;
+                Some(cached.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: ExecutionKey, cached: CachedExecution) {
+        self.entries.insert(key, cached);
+    }
+
+    // a hit which couldn't be reused, counted as a miss
+    pub fn unusable(&mut self, key: &ExecutionKey) {
+        if self.entries.remove(key).is_some() {
+            self.hits = self.hits.saturating_sub(1);
+            self.misses += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // (hits, misses)
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn u256_bytes(val: &SU256) -> [u8; 32] {
+    let mut buf = [0_u8; 32];
+    val.raw().to_big_endian(&mut buf);
+    buf
+}
+
+// The hash of what the tx sees of the block after Engine::tx_context: the
+// header fields of the evm, the parent hash for BLOCKHASH and the fee inputs,
+// the whole fee policy and the fee vaults included.
+pub fn block_context_hash<T, B, H>(ctx: &TxContext<'_, T, B, H>) -> SH256
+where
+    T: TxTrait,
+    B: BlockHeaderTrait,
+    H: BlockHashGetter,
+{
+    let number = ctx.header.number().as_u64();
+    let parent_hash = match number {
+        0 => SH256::default(),
+        number => ctx.block_hash_getter.get_hash(number, number - 1),
+    };
+    let optional = |val: &Option<SU256>| match val {
+        Some(val) => u256_bytes(val),
+        None => [0xff_u8; 32],
+    };
+    keccak_encode(|hash| {
+        hash(&u256_bytes(&ctx.chain_id));
+        hash(&number.to_be_bytes());
+        hash(&ctx.header.timestamp().as_u64().to_be_bytes());
+        hash(&ctx.header.gas_limit().as_u64().to_be_bytes());
+        hash(ctx.header.miner().0.as_bytes());
+        hash(parent_hash.0.as_bytes());
+        match &ctx.miner {
+            Some(miner) => hash(miner.0.as_bytes()),
+            None => hash(&[0_u8]),
+        }
+        hash(&optional(&ctx.block_base_fee));
+        hash(&u256_bytes(&ctx.difficulty));
+        match &ctx.prev_randao {
+            Some(prev_randao) => hash(prev_randao.0.as_bytes()),
+            None => hash(&[0_u8]),
+        }
+        hash(&optional(&ctx.blob_base_fee));
+        match &ctx.fee_policy {
+            FeePolicy::Normal => hash(&[0_u8]),
+            FeePolicy::NoFee => hash(&[1_u8]),
+            FeePolicy::ExtraFee { amount, recipient } => {
+                hash(&[2_u8]);
+                hash(&u256_bytes(amount));
+                match recipient {
+                    Some(recipient) => hash(recipient.0.as_bytes()),
+                    None => hash(&[0_u8]),
+                }
+            }
+            FeePolicy::Overcommit { max_excess } => {
+                hash(&[3_u8]);
+                hash(&max_excess.to_be_bytes());
+            }
+        }
+        for vault in [&ctx.fee_vaults.base_fee, &ctx.fee_vaults.priority_fee] {
+            match vault {
+                Some(vault) => hash(vault.0.as_bytes()),
+                None => hash(&[0_u8]),
+            }
+        }
+        hash(&[ctx.eof as u8]);
+    })
+    .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{NoopBlockHashGetter, PrecompileSet};
+    use eth_types::{BlockHeader, Transaction, TransactionInner, H160, H256, SH160};
+    use std::collections::BTreeSet;
+
+    fn hash(n: u8) -> SH256 {
+        H256::repeat_byte(n).into()
+    }
+
+    fn cached(post_root: SH256) -> CachedExecution {
+        CachedExecution {
+            result: ExecuteResult::default(),
+            receipt_ctx: ReceiptContext {
+                caller: SH160::default(),
+                nonce: 0,
+                extra_fee: None,
+            },
+            post_root,
+        }
+    }
+
+    #[test]
+    fn test_execution_cache() {
+        let key = ExecutionKey {
+            tx_hash: hash(1),
+            pre_root: hash(2),
+            block_context: hash(3),
+        };
+        let mut cache = ExecutionCache::new();
+        assert!(cache.get(&key).is_none());
+        cache.insert(key, cached(hash(4)));
+
+        let hit = cache.get(&key).unwrap();
+        assert_eq!(hit.post_root, hash(4));
+        assert_eq!(cache.stats(), (1, 1));
+
+        // the same tx over the same state in another block
+        let other_block = ExecutionKey {
+            block_context: hash(5),
+            ..key
+        };
+        assert!(cache.get(&other_block).is_none());
+        let other_state = ExecutionKey {
+            pre_root: hash(6),
+            ..key
+        };
+        assert!(cache.get(&other_state).is_none());
+        assert_eq!(cache.stats(), (1, 3));
+
+        // the post root is not in the statedb
+        cache.unusable(&key);
+        assert_eq!(cache.stats(), (0, 4));
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.len(), 0);
+
+        // an entry which was never a hit
+        cache.insert(key, cached(hash(4)));
+        cache.unusable(&key);
+        assert_eq!(cache.stats(), (0, 6));
+    }
+
+    #[test]
+    fn test_block_context_hash() {
+        let txs: BTreeMap<String, Transaction> =
+            serde_json::from_str(include_str!("testdata/block_builder.json")).unwrap();
+        let tx: TransactionInner = txs["call"].inner().unwrap();
+        let header = BlockHeader::default();
+        let cfg = evm::Config::shanghai();
+        let precompile = PrecompileSet::berlin();
+        let ctx = TxContext::builder()
+            .chain_id(1_u64.into())
+            .caller(SH160::default())
+            .cfg(&cfg)
+            .precompile(&precompile)
+            .tx(&tx)
+            .header(&header)
+            .block_hash_getter(&NoopBlockHashGetter)
+            .build()
+            .unwrap();
+        let base = block_context_hash(&ctx);
+        let recipient: SH160 = H160::repeat_byte(1).into();
+        let extra_fee = |recipient: Option<SH160>| FeePolicy::ExtraFee {
+            amount: 1_u64.into(),
+            recipient,
+        };
+        let policies = [
+            FeePolicy::NoFee,
+            extra_fee(None),
+            extra_fee(Some(recipient)),
+            FeePolicy::Overcommit { max_excess: 1 },
+            FeePolicy::Overcommit { max_excess: 2 },
+        ];
+        let mut hashes = vec![base];
+        for policy in policies {
+            let mut other = ctx.clone();
+            other.fee_policy = policy;
+            hashes.push(block_context_hash(&other));
+        }
+        let mut other = ctx.clone();
+        other.fee_vaults.base_fee = Some(recipient);
+        hashes.push(block_context_hash(&other));
+        let mut other = ctx.clone();
+        other.fee_vaults.priority_fee = Some(recipient);
+        hashes.push(block_context_hash(&other));
+
+        let distinct: BTreeSet<SH256> = hashes.iter().cloned().collect();
+        assert_eq!(distinct.len(), hashes.len());
+        assert_eq!(block_context_hash(&ctx.clone()), base);
+    }
+}
@@ -0,0 +1,144 @@
+use std::prelude::v1::*;
+
+use eth_types::{SH160, SU256};
+
+// How the gas of a tx is paid, set by Engine::tx_context or the caller of
+// TxContext::builder.
+//
+// Normal: the caller buys gas_limit * gas_price up front, the unused gas is
+// refunded and the tip of the used gas goes to the miner. The execution using
+// more gas than it bought is an error.
+//
+// NoFee: nothing is checked, charged, refunded or paid to the miner. The
+// used gas keeps the refunded gas since no refund is applied, e.g. eth_call.
+//
+// ExtraFee: Normal plus a fixed fee charged with the gas, it's part of the
+// balance check. The fee goes to the recipient, the miner if None, and is
+// burned if both are None.
+//
+// Overcommit: Normal, but the execution may use up to max_excess more gas
+// than it bought. The excess is neither charged nor paid to the miner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeePolicy {
+    Normal,
+    NoFee,
+    ExtraFee {
+        amount: SU256,
+        recipient: Option<SH160>,
+    },
+    Overcommit {
+        max_excess: u64,
+    },
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl FeePolicy {
+    // whether the caller pays for the gas, the balance check included
+    pub fn charges_gas(&self) -> bool {
+        !matches!(self, Self::NoFee)
+    }
+
+    pub fn extra_fee(&self) -> Option<SU256> {
+        match self {
+            Self::ExtraFee { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    pub fn extra_fee_recipient(&self, miner: Option<SH160>) -> Option<SH160> {
+        match self {
+            Self::ExtraFee { recipient, .. } => recipient.or(miner),
+            _ => None,
+        }
+    }
+
+    // the part of the used gas the caller pays for out of the bought gas,
+    // None if the excess is over the allowance.
+    pub fn charged_gas(&self, bought: u64, used: u64) -> Option<u64> {
+        let allowance = match self {
+            Self::NoFee => u64::MAX,
+            Self::Overcommit { max_excess } => *max_excess,
+            Self::Normal | Self::ExtraFee { .. } => 0,
+        };
+        if used.saturating_sub(bought) > allowance {
+            return None;
+        }
+        Some(used.min(bought))
+    }
+}
+
+// Where the fees of the used gas go instead of the default, set by
+// Engine::fee_vaults. The base fee is burned and the tip goes to the miner
+// unless a vault is set, e.g. the BaseFeeVault and the SequencerFeeVault of
+// the op stack. Only applied when the fee policy charges gas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeVaults {
+    pub base_fee: Option<SH160>,
+    pub priority_fee: Option<SH160>,
+}
+
+impl FeeVaults {
+    pub fn priority_fee_recipient(&self, miner: Option<SH160>) -> Option<SH160> {
+        self.priority_fee.or(miner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::H160;
+
+    #[test]
+    fn test_fee_policy_charged_gas() {
+        assert_eq!(FeePolicy::Normal.charged_gas(100, 60), Some(60));
+        assert_eq!(FeePolicy::Normal.charged_gas(100, 100), Some(100));
+        assert_eq!(FeePolicy::Normal.charged_gas(100, 101), None);
+
+        let extra = FeePolicy::ExtraFee {
+            amount: 1u64.into(),
+            recipient: None,
+        };
+        assert_eq!(extra.charged_gas(100, 101), None);
+
+        let overcommit = FeePolicy::Overcommit { max_excess: 10 };
+        assert_eq!(overcommit.charged_gas(100, 60), Some(60));
+        assert_eq!(overcommit.charged_gas(100, 110), Some(100));
+        assert_eq!(overcommit.charged_gas(100, 111), None);
+
+        // the refunded gas is added back, it can be over the limit
+        assert_eq!(FeePolicy::NoFee.charged_gas(100, u64::MAX), Some(100));
+    }
+
+    #[test]
+    fn test_fee_policy_extra_fee() {
+        let miner: Option<SH160> = Some(H160::repeat_byte(1).into());
+        let recipient: Option<SH160> = Some(H160::repeat_byte(2).into());
+
+        assert!(FeePolicy::Normal.charges_gas());
+        assert!(!FeePolicy::NoFee.charges_gas());
+        assert_eq!(FeePolicy::Normal.extra_fee(), None);
+        assert_eq!(FeePolicy::Normal.extra_fee_recipient(miner), None);
+        assert_eq!(FeePolicy::NoFee.extra_fee_recipient(miner), None);
+
+        let to_miner = FeePolicy::ExtraFee {
+            amount: 5u64.into(),
+            recipient: None,
+        };
+        assert!(to_miner.charges_gas());
+        assert_eq!(to_miner.extra_fee(), Some(5u64.into()));
+        assert_eq!(to_miner.extra_fee_recipient(miner), miner);
+        assert_eq!(to_miner.extra_fee_recipient(None), None);
+
+        let to_recipient = FeePolicy::ExtraFee {
+            amount: 5u64.into(),
+            recipient,
+        };
+        assert_eq!(to_recipient.extra_fee_recipient(miner), recipient);
+        assert_eq!(to_recipient.extra_fee_recipient(None), recipient);
+    }
+}
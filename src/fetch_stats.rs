@@ -0,0 +1,74 @@
+use std::prelude::v1::*;
+
+use core::time::Duration;
+use eth_types::FetchStateResult;
+use serde::{Deserialize, Serialize};
+
+// the upper bounds of the latency buckets in milliseconds, the last bucket
+// takes the rest.
+pub const FETCH_LATENCY_BUCKETS_MS: [u64; 7] = [1, 5, 10, 50, 100, 500, 1000];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchLatencyHistogram {
+    // counts[i] is the calls up to FETCH_LATENCY_BUCKETS_MS[i], the last one
+    // is the calls over 1s
+    pub counts: [u64; 8],
+    pub total_ms: u64,
+}
+
+impl FetchLatencyHistogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        let idx = FETCH_LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(FETCH_LATENCY_BUCKETS_MS.len());
+        self.counts[idx] += 1;
+        self.total_ms += ms;
+    }
+}
+
+// The prefetch statistics of a block: how much of the requested state the
+// StateDB didn't have and what fetching it cost. Read it from
+// BlockBuilder::fetch_stats to size the witness caches.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateFetchStats {
+    pub accounts_requested: u64,
+    pub slots_requested: u64,
+    pub accounts_missed: u64,
+    pub slots_missed: u64,
+    pub codes_missed: u64,
+    // the prefetcher calls
+    pub fetches: u64,
+    pub proof_bytes: u64,
+    pub code_bytes: u64,
+    // without std nothing is timed
+    pub latency: FetchLatencyHistogram,
+}
+
+impl StateFetchStats {
+    // the missed share of the requested accounts and slots
+    pub fn miss_rate(&self) -> f64 {
+        let requested = self.accounts_requested + self.slots_requested;
+        if requested == 0 {
+            return 0.0;
+        }
+        (self.accounts_missed + self.slots_missed) as f64 / requested as f64
+    }
+
+    pub(crate) fn record_fetched(&mut self, result: &[FetchStateResult]) {
+        self.fetches += 1;
+        for state in result {
+            if let Some(acc) = &state.acc {
+                let storage_nodes = acc.storage_proof.iter().flat_map(|s| s.proof.iter());
+                for node in acc.account_proof.iter().chain(storage_nodes) {
+                    self.proof_bytes += node.len() as u64;
+                }
+            }
+            if let Some(code) = &state.code {
+                self.code_bytes += code.len() as u64;
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::prelude::v1::*;
+
+use eth_types::Block;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::{verify_block, EthereumPobSource, Pob, VerifyError};
+
+// The C ABI of the stateless verification for the hosts and the enclave
+// runtimes which don't link rust directly, see include/evm_executor.h. The
+// pob is the binary encoding of Pob::to_bytes and the poe is Poe::encode.
+// Panics are caught and reported as EVM_EXECUTOR_ERR_PANIC.
+
+pub const EVM_EXECUTOR_OK: i32 = 0;
+pub const EVM_EXECUTOR_ERR_NULL_POINTER: i32 = 1;
+pub const EVM_EXECUTOR_ERR_DECODE_POB: i32 = 2;
+pub const EVM_EXECUTOR_ERR_MISSING_EXPECTED: i32 = 3;
+pub const EVM_EXECUTOR_ERR_EXECUTE: i32 = 4;
+// the required size is written to the out length, call again with a larger buffer
+pub const EVM_EXECUTOR_ERR_BUFFER_TOO_SMALL: i32 = 5;
+pub const EVM_EXECUTOR_ERR_PANIC: i32 = 6;
+
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], i32> {
+    if ptr.is_null() {
+        return Err(EVM_EXECUTOR_ERR_NULL_POINTER);
+    }
+    Ok(std::slice::from_raw_parts(ptr, len))
+}
+
+unsafe fn output(data: &[u8], out: *mut u8, out_cap: usize, out_len: *mut usize) -> i32 {
+    if out_len.is_null() {
+        return EVM_EXECUTOR_ERR_NULL_POINTER;
+    }
+    *out_len = data.len();
+    if data.len() > out_cap {
+        return EVM_EXECUTOR_ERR_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return EVM_EXECUTOR_ERR_NULL_POINTER;
+    }
+    std::ptr::copy_nonoverlapping(data.as_ptr(), out, data.len());
+    EVM_EXECUTOR_OK
+}
+
+fn guarded<F: FnOnce() -> Result<Vec<u8>, i32>>(f: F) -> Result<Vec<u8>, i32> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => Err(EVM_EXECUTOR_ERR_PANIC),
+    }
+}
+
+fn decode_pob(data: &[u8]) -> Result<Pob<Block>, i32> {
+    Pob::<Block>::from_bytes(data).map_err(|err| {
+        error!("decode pob fail: {:?}", err);
+        EVM_EXECUTOR_ERR_DECODE_POB
+    })
+}
+
+// writes the 32 bytes state hash of the pob to `out`
+#[no_mangle]
+pub unsafe extern "C" fn evm_executor_pob_state_hash(
+    pob: *const u8,
+    pob_len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> i32 {
+    let pob = match input(pob, pob_len) {
+        Ok(pob) => pob,
+        Err(code) => return code,
+    };
+    let hash = guarded(|| Ok(decode_pob(pob)?.data.state_hash().0.as_bytes().to_vec()));
+    match hash {
+        Ok(hash) => output(&hash, out, out_cap, out_len),
+        Err(code) => code,
+    }
+}
+
+// verify and execute the block of the pob, the unsigned poe is written to
+// `poe_out` on success.
+#[no_mangle]
+pub unsafe extern "C" fn evm_executor_verify_block(
+    pob: *const u8,
+    pob_len: usize,
+    poe_out: *mut u8,
+    poe_cap: usize,
+    poe_len: *mut usize,
+) -> i32 {
+    let pob = match input(pob, pob_len) {
+        Ok(pob) => pob,
+        Err(code) => return code,
+    };
+    let poe = guarded(|| {
+        let pob = decode_pob(pob)?;
+        match verify_block(&EthereumPobSource, pob) {
+            Ok(poe) => Ok(poe.encode()),
+            Err(VerifyError::MissingExpected) => Err(EVM_EXECUTOR_ERR_MISSING_EXPECTED),
+            Err(VerifyError::Execute(err)) => {
+                error!("verify block fail: {:?}", err);
+                Err(EVM_EXECUTOR_ERR_EXECUTE)
+            }
+        }
+    });
+    match poe {
+        Ok(poe) => output(&poe, poe_out, poe_cap, poe_len),
+        Err(code) => code,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture() -> Vec<u8> {
+        std::fs::read("src/testdata/regression/17100000.pob").unwrap()
+    }
+
+    #[test]
+    fn test_pob_state_hash() {
+        let pob = fixture();
+        let expect = Pob::<Block>::from_bytes(&pob).unwrap().data.state_hash();
+        let mut out = [0_u8; 32];
+        let mut len = 0;
+        let code = unsafe {
+            evm_executor_pob_state_hash(pob.as_ptr(), pob.len(), out.as_mut_ptr(), 16, &mut len)
+        };
+        assert_eq!(code, EVM_EXECUTOR_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(len, 32);
+        let code = unsafe {
+            evm_executor_pob_state_hash(pob.as_ptr(), pob.len(), out.as_mut_ptr(), 32, &mut len)
+        };
+        assert_eq!(code, EVM_EXECUTOR_OK);
+        assert_eq!(&out[..], expect.0.as_bytes());
+
+        let code = unsafe {
+            evm_executor_pob_state_hash(std::ptr::null(), 0, out.as_mut_ptr(), 32, &mut len)
+        };
+        assert_eq!(code, EVM_EXECUTOR_ERR_NULL_POINTER);
+        let code = unsafe {
+            evm_executor_pob_state_hash(pob.as_ptr(), 10, out.as_mut_ptr(), 32, &mut len)
+        };
+        assert_eq!(code, EVM_EXECUTOR_ERR_DECODE_POB);
+    }
+
+    #[test]
+    fn test_verify_block() {
+        let pob = fixture();
+        let expect = verify_block(&EthereumPobSource, Pob::from_bytes(&pob).unwrap())
+            .unwrap()
+            .encode();
+        let mut len = 0;
+        let code = unsafe {
+            evm_executor_verify_block(pob.as_ptr(), pob.len(), std::ptr::null_mut(), 0, &mut len)
+        };
+        assert_eq!(code, EVM_EXECUTOR_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(len, expect.len());
+
+        let mut poe = vec![0_u8; len];
+        let code = unsafe {
+            evm_executor_verify_block(
+                pob.as_ptr(),
+                pob.len(),
+                poe.as_mut_ptr(),
+                poe.len(),
+                &mut len,
+            )
+        };
+        assert_eq!(code, EVM_EXECUTOR_OK);
+        assert_eq!(poe, expect);
+    }
+}
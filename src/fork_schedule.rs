@@ -0,0 +1,74 @@
+use std::prelude::v1::*;
+
+// The forks activated by the block timestamp, the ones before the merge are
+// activated by number and are handled by SignerSchedule and RefundRules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimestampFork {
+    Paris,
+    Shanghai,
+    Cancun,
+}
+
+impl TimestampFork {
+    pub fn evm_config(&self) -> evm::Config {
+        match self {
+            Self::Paris => evm::Config::merge(),
+            Self::Shanghai => evm::Config::shanghai(),
+            Self::Cancun => evm::Config::cancun(),
+        }
+    }
+}
+
+// The activation time of the timestamp forks from the chain spec, the forks
+// without a time are never activated. EOF is a flag on top of the fork, it's
+// not scheduled on mainnet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForkSchedule {
+    pub shanghai_time: Option<u64>,
+    pub cancun_time: Option<u64>,
+    pub eof_time: Option<u64>,
+}
+
+impl ForkSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mainnet() -> Self {
+        Self {
+            shanghai_time: Some(1681338455),
+            cancun_time: Some(1710338135),
+            eof_time: None,
+        }
+    }
+
+    pub fn with_shanghai(mut self, time: u64) -> Self {
+        self.shanghai_time = Some(time);
+        self
+    }
+
+    pub fn with_cancun(mut self, time: u64) -> Self {
+        self.cancun_time = Some(time);
+        self
+    }
+
+    pub fn with_eof(mut self, time: u64) -> Self {
+        self.eof_time = Some(time);
+        self
+    }
+
+    pub fn eof_at(&self, timestamp: u64) -> bool {
+        matches!(self.eof_time, Some(time) if timestamp >= time)
+    }
+
+    pub fn fork_at(&self, timestamp: u64) -> TimestampFork {
+        let active = |time: Option<u64>| matches!(time, Some(time) if timestamp >= time);
+        if active(self.cancun_time) {
+            TimestampFork::Cancun
+        } else if active(self.shanghai_time) {
+            TimestampFork::Shanghai
+        } else {
+            TimestampFork::Paris
+        }
+    }
+}
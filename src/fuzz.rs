@@ -0,0 +1,173 @@
+use std::prelude::v1::*;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use eth_types::{Block, HexBytes, H160, SH160, SH256};
+use std::collections::BTreeMap;
+
+use crate::{
+    Pob, PobData, PobExpected, Poe, PoeReplayProtection, PoeRollupMeta, PrecompileSet, POB_VERSION,
+    POB_VERSION_V1, POB_VERSION_V4, POE_VERSION_V1, POE_VERSION_V2, POE_VERSION_V3, POE_VERSION_V4,
+    POE_VERSION_V5,
+};
+
+// The entrypoints never return an error, any panic inside them is the bug.
+// They take the raw bytes so both cargo-fuzz and afl targets can call them:
+//
+//     fuzz_target!(|data: &[u8]| evm_executor::fuzz_precompile(data));
+
+lazy_static::lazy_static! {
+    static ref BERLIN: PrecompileSet = PrecompileSet::berlin();
+    static ref SCROLL: PrecompileSet = PrecompileSet::scroll();
+}
+
+#[derive(Debug, Arbitrary)]
+pub struct PrecompileInput {
+    pub address: u8,
+    pub input: Vec<u8>,
+}
+
+fn sh256(u: &mut Unstructured) -> Result<SH256> {
+    Ok(<[u8; 32]>::arbitrary(u)?.into())
+}
+
+fn sh160(u: &mut Unstructured) -> Result<SH160> {
+    Ok(H160::from(<[u8; 20]>::arbitrary(u)?).into())
+}
+
+fn hex_bytes(u: &mut Unstructured) -> Result<HexBytes> {
+    Ok(Vec::<u8>::arbitrary(u)?.into())
+}
+
+fn list<'a, T>(
+    u: &mut Unstructured<'a>,
+    f: fn(&mut Unstructured<'a>) -> Result<T>,
+) -> Result<Vec<T>> {
+    let len = u.int_in_range(0..=16)?;
+    (0..len).map(|_| f(u)).collect()
+}
+
+impl<'a> Arbitrary<'a> for PobExpected {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            state_root: sh256(u)?,
+            receipts_root: sh256(u)?,
+            gas_used: u64::arbitrary(u)?,
+            logs_bloom: hex_bytes(u)?,
+            receipts: None,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for PobData {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let version = u.int_in_range(POB_VERSION_V1..=POB_VERSION)?;
+        let mut block_hashes = BTreeMap::new();
+        for _ in 0..u.int_in_range(0..=16)? {
+            block_hashes.insert(u64::arbitrary(u)?, sh256(u)?);
+        }
+        Ok(Self {
+            version,
+            chain_id: u64::arbitrary(u)?,
+            prev_state_root: sh256(u)?,
+            block_hashes,
+            mpt_nodes: list(u, hex_bytes)?,
+            codes: list(u, hex_bytes)?,
+            // the withdrawals come from the block, they are not worth a generator
+            withdrawals: None,
+            blob_versioned_hashes: list(u, sh256)?,
+            blob_commitments: list(u, hex_bytes)?,
+            expected: match version >= POB_VERSION_V4 {
+                true => Option::<PobExpected>::arbitrary(u)?,
+                false => None,
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Poe {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let version = u.int_in_range(POE_VERSION_V1..=POE_VERSION_V5)?;
+        let mut poe = Poe {
+            version,
+            batch_hash: sh256(u)?,
+            state_hash: sh256(u)?,
+            prev_state_root: sh256(u)?,
+            new_state_root: sh256(u)?,
+            withdrawal_root: sh256(u)?,
+            replay: None,
+            merkle_root: None,
+            rollup: None,
+            audit_digest: None,
+            attestation: None,
+            signature: <[u8; 65]>::arbitrary(u)?.to_vec().into(),
+        };
+        if version >= POE_VERSION_V2 {
+            poe.replay = Some(PoeReplayProtection {
+                nonce: u64::arbitrary(u)?,
+                expiry: u64::arbitrary(u)?,
+                verifying_contract: sh160(u)?,
+            });
+        }
+        if version >= POE_VERSION_V3 {
+            poe.merkle_root = Some(sh256(u)?);
+        }
+        if version >= POE_VERSION_V4 {
+            poe.rollup = Some(PoeRollupMeta {
+                batch_index: u64::arbitrary(u)?,
+                l1_origin_hash: sh256(u)?,
+                total_gas_used: u64::arbitrary(u)?,
+            });
+        }
+        if version >= POE_VERSION_V5 {
+            poe.audit_digest = Some(sh256(u)?);
+        }
+        Ok(poe)
+    }
+}
+
+pub fn fuzz_precompile(data: &[u8]) {
+    let input = match PrecompileInput::arbitrary_take_rest(Unstructured::new(data)) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    let mut addr = H160::default();
+    addr.0[addr.0.len() - 1] = input.address;
+    for set in [&*BERLIN, &*SCROLL] {
+        let _ = set.run_raw(&addr, &input.input);
+    }
+}
+
+pub fn fuzz_pob_bytes(data: &[u8]) {
+    let pob = match Pob::<Block>::from_bytes(data) {
+        Ok(pob) => pob,
+        Err(_) => return,
+    };
+    let again = Pob::<Block>::from_bytes(&pob.to_bytes()).expect("decode the encoded pob");
+    assert_eq!(pob.data.state_hash(), again.data.state_hash());
+}
+
+pub fn fuzz_pob_data(data: &[u8]) {
+    let pob_data = match PobData::arbitrary_take_rest(Unstructured::new(data)) {
+        Ok(pob_data) => pob_data,
+        Err(_) => return,
+    };
+    pob_data
+        .check_version()
+        .expect("generated version is supported");
+    assert_eq!(
+        pob_data.state_hash(),
+        SH256::from(crate::keccak_hash(&pob_data.canonical_encoding()))
+    );
+}
+
+pub fn fuzz_poe(data: &[u8]) {
+    let _ = Poe::decode(data);
+
+    let poe = match Poe::arbitrary_take_rest(Unstructured::new(data)) {
+        Ok(poe) => poe,
+        Err(_) => return,
+    };
+    let encoded = poe.encode();
+    let decoded = Poe::decode(&encoded).expect("decode the encoded poe");
+    assert_eq!(decoded.encode(), encoded);
+}
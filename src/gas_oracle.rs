@@ -0,0 +1,121 @@
+use std::prelude::v1::*;
+
+use eth_types::{BlockHeaderTrait, ReceiptTrait, TxTrait, SU256};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::Ethereum;
+
+pub const DEFAULT_ORACLE_BLOCKS: usize = 20;
+pub const DEFAULT_ORACLE_PERCENTILE: usize = 60;
+
+#[derive(Debug, Clone)]
+struct BlockFees {
+    base_fee: SU256,
+    gas_limit: u64,
+    gas_used: u64,
+    tips: Vec<SU256>, // the effective tips of the txs
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeSuggestion {
+    pub base_fee: SU256, // of the next block
+    pub max_priority_fee_per_gas: SU256,
+    pub max_fee_per_gas: SU256,
+}
+
+// Suggests the fees from the recently built blocks: the tip is a percentile
+// of the effective tips in the window and the fee cap leaves room for the
+// base fee to double.
+#[derive(Debug, Clone)]
+pub struct GasOracle {
+    blocks: VecDeque<BlockFees>,
+    max_blocks: usize,
+    percentile: usize,
+    default_tip: SU256, // used when the window has no tx
+}
+
+impl Default for GasOracle {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_ORACLE_BLOCKS,
+            DEFAULT_ORACLE_PERCENTILE,
+            SU256::from(1_000_000_000_u64),
+        )
+    }
+}
+
+impl GasOracle {
+    pub fn new(max_blocks: usize, percentile: usize, default_tip: SU256) -> Self {
+        Self {
+            blocks: VecDeque::with_capacity(max_blocks),
+            max_blocks: max_blocks.max(1),
+            percentile: percentile.min(100),
+            default_tip,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // the blocks should be ingested in order, the oldest one is dropped
+    // when the window is full.
+    pub fn ingest<B, T, R>(&mut self, header: &B, txs: &[Arc<T>], receipts: &[R])
+    where
+        B: BlockHeaderTrait,
+        T: TxTrait,
+        R: ReceiptTrait,
+    {
+        let base_fee = header.base_fee().unwrap_or_default();
+        let tips = txs
+            .iter()
+            .map(|tx| {
+                let fee_cap = *tx.max_fee_per_gas();
+                let headroom = match fee_cap > base_fee {
+                    true => fee_cap - &base_fee,
+                    false => SU256::zero(),
+                };
+                (*tx.max_priority_fee_per_gas()).min(headroom)
+            })
+            .collect();
+        if self.blocks.len() == self.max_blocks {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(BlockFees {
+            base_fee,
+            gas_limit: header.gas_limit().as_u64(),
+            gas_used: receipts
+                .iter()
+                .map(|receipt| receipt.gas_used().as_u64())
+                .sum(),
+            tips,
+        });
+    }
+
+    pub fn suggest(&self) -> FeeSuggestion {
+        let base_fee = match self.blocks.back() {
+            Some(last) => {
+                Ethereum::calc_base_fee(last.gas_limit, last.gas_used, last.base_fee.into())
+            }
+            None => SU256::zero(),
+        };
+        let mut tips: Vec<SU256> = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.tips.iter().cloned())
+            .collect();
+        let tip = match tips.len() {
+            0 => self.default_tip,
+            n => {
+                tips.sort_unstable();
+                tips[(n - 1) * self.percentile / 100]
+            }
+        };
+        FeeSuggestion {
+            base_fee,
+            max_priority_fee_per_gas: tip,
+            max_fee_per_gas: base_fee * &SU256::from(2_u64) + tip,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, SH160, SH256, SU256, SU64};
+use serde::{Deserialize, Serialize};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+
+use crate::snapshot::apply_account;
+
+pub type GenesisAlloc = BTreeMap<SH160, GenesisAccount>;
+
+// The `alloc` section of a geth genesis spec, the other fields of the spec
+// belong to the header and are ignored here.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Genesis {
+    #[serde(default)]
+    pub alloc: GenesisAlloc,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GenesisAccount {
+    pub balance: SU256,
+    #[serde(default)]
+    pub code: HexBytes,
+    #[serde(default)]
+    pub storage: BTreeMap<SH256, SH256>,
+    #[serde(default)]
+    pub nonce: SU64,
+}
+
+impl Genesis {
+    // the statedb is expected to be empty, returns the genesis state root
+    pub fn apply<D: StateDB>(&self, statedb: &mut D) -> Result<SH256, statedb::Error> {
+        apply_genesis_alloc(statedb, &self.alloc)
+    }
+}
+
+pub fn apply_genesis_alloc<D: StateDB>(
+    statedb: &mut D,
+    alloc: &GenesisAlloc,
+) -> Result<SH256, statedb::Error> {
+    for (address, account) in alloc {
+        apply_account(
+            statedb,
+            address,
+            account.balance.raw().clone(),
+            account.nonce.as_u64().into(),
+            &account.code,
+            account
+                .storage
+                .iter()
+                .filter(|(_, v)| **v != SH256::default())
+                .map(|(k, v)| (k.clone(), v.clone())),
+        )?;
+    }
+    statedb.flush()
+}
@@ -0,0 +1,85 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::SH256;
+use std::collections::BTreeMap;
+
+use crate::keccak_hash;
+
+// inputs larger than this are hashed directly, the largest contract code is 24KB
+const MAX_INPUT_LEN: usize = 32 << 10;
+
+// A small LRU of keccak results, identical byte strings(code, trie keys) are
+// hashed repeatedly when proving consecutive blocks.
+#[derive(Debug)]
+pub struct KeccakCache {
+    cap: usize,
+    inner: RefCell<KeccakLru>,
+}
+
+#[derive(Debug, Default)]
+struct KeccakLru {
+    tick: u64,
+    entries: BTreeMap<Vec<u8>, (SH256, u64)>,
+    order: BTreeMap<u64, Vec<u8>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for KeccakCache {
+    fn default() -> Self {
+        Self::new(4096)
+    }
+}
+
+impl KeccakCache {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            inner: RefCell::new(KeccakLru::default()),
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> SH256 {
+        if self.cap == 0 || data.len() > MAX_INPUT_LEN {
+            return keccak_hash(data).into();
+        }
+        let mut lru = self.inner.borrow_mut();
+        let lru = &mut *lru;
+        lru.tick += 1;
+        let tick = lru.tick;
+        if let Some((hash, last_used)) = lru.entries.get_mut(data) {
+            lru.order.remove(last_used);
+            *last_used = tick;
+            lru.order.insert(tick, data.to_vec());
+            lru.hits += 1;
+            return *hash;
+        }
+
+        lru.misses += 1;
+        let hash: SH256 = keccak_hash(data).into();
+        if lru.entries.len() >= self.cap {
+            let oldest = lru.order.keys().next().cloned();
+            if let Some(data) = oldest.and_then(|tick| lru.order.remove(&tick)) {
+                lru.entries.remove(&data);
+            }
+        }
+        lru.entries.insert(data.to_vec(), (hash, tick));
+        lru.order.insert(tick, data.to_vec());
+        hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.borrow().entries.len()
+    }
+
+    // returns (hits, misses)
+    pub fn stats(&self) -> (u64, u64) {
+        let lru = self.inner.borrow();
+        (lru.hits, lru.misses)
+    }
+
+    pub fn clear(&self) {
+        *self.inner.borrow_mut() = KeccakLru::default();
+    }
+}
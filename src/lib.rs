@@ -1,17 +1,75 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(any(feature = "std", feature = "tstd")), feature(alloc_prelude))]
 #[cfg(feature = "tstd")]
 #[macro_use]
 extern crate sgxlib as std;
+// neither std nor tstd: a plain alloc build for the non-SGX constrained targets
+#[cfg(not(any(feature = "std", feature = "tstd")))]
+#[macro_use]
+extern crate alloc as std;
+
+#[macro_use]
+mod log;
+
+mod crypto_provider;
+pub use crypto_provider::*;
+
+#[cfg(feature = "ct-crypto")]
+mod crypto_ct;
+#[cfg(feature = "ct-crypto")]
+pub use crypto_ct::*;
 
 mod engines;
 pub use engines::*;
 
+mod op_receipt;
+pub use op_receipt::*;
+
 mod tx_executor;
 pub use tx_executor::*;
 
+mod tx_validation;
+pub use tx_validation::*;
+
+mod signer_schedule;
+pub use signer_schedule::*;
+
+mod fork_schedule;
+pub use fork_schedule::*;
+
+mod refund_rules;
+pub use refund_rules::*;
+
+mod tx_policy;
+pub use tx_policy::*;
+
+mod fee_policy;
+pub use fee_policy::*;
+
+mod gas_oracle;
+pub use gas_oracle::*;
+
 mod types;
 pub use types::*;
 
+mod metrics;
+pub use metrics::*;
+
+mod bloom;
+pub use bloom::*;
+
+mod trace;
+pub use trace::*;
+
+mod selfdestruct;
+pub use selfdestruct::*;
+
+mod rpc;
+pub use rpc::*;
+
+mod rpc_receipt;
+pub use rpc_receipt::*;
+
 mod precompile;
 pub use precompile::*;
 
@@ -21,8 +79,140 @@ pub use state_proxy::*;
 mod block_builder;
 pub use block_builder::*;
 
+mod batch_executor;
+pub use batch_executor::*;
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+mod bench;
+#[cfg(any(feature = "std", feature = "tstd"))]
+pub use bench::*;
+
+#[cfg(all(feature = "ffi", not(any(feature = "std", feature = "tstd"))))]
+compile_error!("the ffi feature needs std or tstd");
+#[cfg(all(feature = "ffi", any(feature = "std", feature = "tstd")))]
+mod ffi;
+#[cfg(all(feature = "ffi", any(feature = "std", feature = "tstd")))]
+pub use ffi::*;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(feature = "std")]
+mod regression;
+#[cfg(feature = "std")]
+pub use regression::*;
+
+#[cfg(feature = "fuzz")]
+mod fuzz;
+#[cfg(feature = "fuzz")]
+pub use fuzz::*;
+
+#[cfg(feature = "std")]
+mod replay;
+#[cfg(feature = "std")]
+pub use replay::*;
+
+#[cfg(feature = "state-tests")]
+mod state_tests;
+#[cfg(feature = "state-tests")]
+pub use state_tests::*;
+
 mod pob;
 pub use pob::*;
 
+mod pob_verify;
+pub use pob_verify::*;
+
+mod pob_batch;
+pub use pob_batch::*;
+
+mod pob_stream;
+pub use pob_stream::*;
+
+mod pob_import;
+pub use pob_import::*;
+
 mod poe;
-pub use poe::*;
\ No newline at end of file
+pub use poe::*;
+
+mod poe_multi;
+pub use poe_multi::*;
+
+mod poe_signer;
+pub use poe_signer::*;
+
+mod poe_cosign;
+pub use poe_cosign::*;
+
+#[cfg(feature = "tstd")]
+mod poe_key;
+#[cfg(feature = "tstd")]
+pub use poe_key::*;
+
+mod poe_attestation;
+pub use poe_attestation::*;
+
+#[cfg(feature = "bls")]
+mod poe_bls;
+#[cfg(feature = "bls")]
+pub use poe_bls::*;
+
+mod code_cache;
+pub use code_cache::*;
+
+mod keccak_cache;
+pub use keccak_cache::*;
+
+mod recovery_cache;
+pub use recovery_cache::*;
+
+mod exec_cache;
+pub use exec_cache::*;
+
+mod access_hints;
+pub use access_hints::*;
+
+mod fetch_stats;
+pub use fetch_stats::*;
+
+mod speculative;
+pub use speculative::*;
+
+mod mpt;
+
+mod receipt_proof;
+pub use receipt_proof::*;
+
+mod witness;
+pub use witness::*;
+
+mod pob_state;
+pub use pob_state::*;
+
+mod audit;
+pub use audit::*;
+
+mod determinism;
+pub use determinism::*;
+
+mod eof;
+pub use eof::*;
+
+mod withdrawal;
+pub use withdrawal::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod genesis;
+pub use genesis::*;
+
+#[cfg(feature = "testing")]
+mod cheatcodes;
+#[cfg(feature = "testing")]
+pub use cheatcodes::*;
+
+#[cfg(feature = "opcode-stats")]
+mod opcode_stats;
+#[cfg(feature = "opcode-stats")]
+pub use opcode_stats::*;
@@ -0,0 +1,46 @@
+// glog needs std or tstd, the plain alloc build drops the logs but still
+// type checks the arguments so both configurations see the same code.
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+macro_rules! debug {
+    ($($arg:tt)+) => { glog::debug!($($arg)+) };
+}
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+macro_rules! info {
+    ($($arg:tt)+) => { glog::info!($($arg)+) };
+}
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+macro_rules! error {
+    ($($arg:tt)+) => { glog::error!($($arg)+) };
+}
+
+#[cfg(not(any(feature = "std", feature = "tstd")))]
+macro_rules! discard_log {
+    (target: $target:expr, $($arg:tt)+) => {
+        if false {
+            let _ = ($target, format_args!($($arg)+));
+        }
+    };
+    ($($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(not(any(feature = "std", feature = "tstd")))]
+macro_rules! debug {
+    ($($arg:tt)+) => { discard_log!($($arg)+) };
+}
+
+#[cfg(not(any(feature = "std", feature = "tstd")))]
+macro_rules! info {
+    ($($arg:tt)+) => { discard_log!($($arg)+) };
+}
+
+#[cfg(not(any(feature = "std", feature = "tstd")))]
+macro_rules! error {
+    ($($arg:tt)+) => { discard_log!($($arg)+) };
+}
@@ -0,0 +1,71 @@
+use std::prelude::v1::*;
+
+use core::time::Duration;
+
+// The metrics are only collected with the `metrics` feature, otherwise the
+// recorders are no-ops so the no_std/tstd builds don't pull prometheus.
+#[cfg(feature = "metrics")]
+mod inner {
+    use prometheus::{
+        register_histogram_vec_with_registry, register_histogram_with_registry,
+        register_int_counter_with_registry, Histogram, HistogramVec, IntCounter, Registry,
+    };
+
+    lazy_static::lazy_static! {
+        pub static ref REGISTRY: Registry = Registry::new();
+        pub static ref TXS_EXECUTED: IntCounter = register_int_counter_with_registry!(
+            "evm_executor_txs_executed_total",
+            "Number of executed txs",
+            REGISTRY
+        )
+        .unwrap();
+        pub static ref GAS_PROCESSED: IntCounter = register_int_counter_with_registry!(
+            "evm_executor_gas_processed_total",
+            "Gas used by the executed txs",
+            REGISTRY
+        )
+        .unwrap();
+        pub static ref STATE_FETCH_SECONDS: Histogram = register_histogram_with_registry!(
+            "evm_executor_state_fetch_seconds",
+            "Latency of the state prefetch calls",
+            REGISTRY
+        )
+        .unwrap();
+        pub static ref PRECOMPILE_SECONDS: HistogramVec = register_histogram_vec_with_registry!(
+            "evm_executor_precompile_seconds",
+            "Time spent in the precompiles",
+            &["address"],
+            REGISTRY
+        )
+        .unwrap();
+    }
+}
+
+// the registry to be scraped by the integrator
+#[cfg(feature = "metrics")]
+pub fn metrics_registry() -> &'static prometheus::Registry {
+    &inner::REGISTRY
+}
+
+#[allow(unused_variables)]
+pub(crate) fn record_tx(gas: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        inner::TXS_EXECUTED.inc();
+        inner::GAS_PROCESSED.inc_by(gas);
+    }
+}
+
+#[allow(unused_variables)]
+pub(crate) fn observe_state_fetch(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    inner::STATE_FETCH_SECONDS.observe(elapsed.as_secs_f64());
+}
+
+// only timed with the feature, the precompiles are on the hot path
+#[cfg(feature = "metrics")]
+pub(crate) fn observe_precompile(address: &str, elapsed: Duration) {
+    inner::PRECOMPILE_SECONDS
+        .with_label_values(&[address])
+        .observe(elapsed.as_secs_f64());
+}
@@ -0,0 +1,763 @@
+use std::prelude::v1::*;
+
+use eth_types::{H256, SH256};
+use rlp::{Rlp, RlpStream};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::MptChild;
+
+pub(crate) fn decode_err(err: rlp::DecoderError) -> String {
+    format!("invalid mpt node: {:?}", err)
+}
+
+fn key_to_nibbles(key: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(key.len() * 2);
+    for b in key {
+        out.push(b >> 4);
+        out.push(b & 0x0f);
+    }
+    out
+}
+
+// decode the hex prefix encoding, returns (nibbles, is_leaf)
+pub(crate) fn decode_path(data: &[u8]) -> Result<(Vec<u8>, bool), String> {
+    let flag = match data.first() {
+        Some(flag) => flag >> 4,
+        None => return Err("empty node path".into()),
+    };
+    let is_leaf = flag & 2 != 0;
+    let mut nibbles = key_to_nibbles(&data[1..]);
+    if flag & 1 != 0 {
+        nibbles.insert(0, data[0] & 0x0f);
+    }
+    Ok((nibbles, is_leaf))
+}
+
+pub(crate) fn node_ref(item: &Rlp) -> Result<Option<MptChild>, String> {
+    if item.is_list() {
+        // embedded node which is shorter than 32 bytes
+        return Ok(Some(MptChild::Inline(item.as_raw().to_vec())));
+    }
+    let data = item.data().map_err(decode_err)?;
+    match data.len() {
+        0 => Ok(None),
+        32 => Ok(Some(MptChild::Hash(H256::from_slice(data).into()))),
+        n => Err(format!("invalid node reference length: {}", n)),
+    }
+}
+
+// Walk the trie from `root` along `key`, every node on the path which exists
+// in `nodes` is added to `touched`. Returns the value of the leaf, or None if
+// the key doesn't exist or the path leaves the known part of the trie.
+pub(crate) fn get(
+    nodes: &BTreeMap<SH256, &[u8]>,
+    root: SH256,
+    key: &[u8],
+    touched: &mut BTreeSet<SH256>,
+) -> Result<Option<Vec<u8>>, String> {
+    let nibbles = key_to_nibbles(key);
+    let mut pos = 0;
+    let mut current = MptChild::Hash(root);
+    loop {
+        let data = match &current {
+            MptChild::Hash(hash) => match nodes.get(hash) {
+                Some(node) => {
+                    touched.insert(*hash);
+                    node.to_vec()
+                }
+                None => return Ok(None),
+            },
+            MptChild::Inline(data) => data.clone(),
+        };
+        let node = Rlp::new(&data);
+        match node.item_count().map_err(decode_err)? {
+            17 => {
+                if pos == nibbles.len() {
+                    let value = node.at(16).map_err(decode_err)?;
+                    return Ok(Some(value.data().map_err(decode_err)?.to_vec()));
+                }
+                let child = node.at(nibbles[pos] as usize).map_err(decode_err)?;
+                pos += 1;
+                current = match node_ref(&child)? {
+                    Some(child) => child,
+                    None => return Ok(None),
+                };
+            }
+            2 => {
+                let path = node.at(0).map_err(decode_err)?;
+                let (path, is_leaf) = decode_path(path.data().map_err(decode_err)?)?;
+                if !nibbles[pos..].starts_with(&path) {
+                    return Ok(None);
+                }
+                pos += path.len();
+                let child = node.at(1).map_err(decode_err)?;
+                if is_leaf {
+                    if pos != nibbles.len() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(child.data().map_err(decode_err)?.to_vec()));
+                }
+                current = match node_ref(&child)? {
+                    Some(child) => child,
+                    None => return Ok(None),
+                };
+            }
+            n => return Err(format!("unexpected item count: {}", n)),
+        }
+    }
+}
+
+// Deleting a key collapses a branch left with a single child into that child,
+// which resolves the child even if no touched key passes through it. For every
+// touched branch with a single untouched child, the child in `nodes` is added
+// to `touched`.
+pub(crate) fn collapse_siblings(
+    nodes: &BTreeMap<SH256, &[u8]>,
+    touched: &mut BTreeSet<SH256>,
+) -> Result<(), String> {
+    let mut siblings = Vec::new();
+    for hash in touched.iter() {
+        let node = Rlp::new(nodes[hash]);
+        if node.item_count().map_err(decode_err)? != 17 {
+            continue;
+        }
+        let mut untouched = Vec::new();
+        for idx in 0..16 {
+            if let Some(MptChild::Hash(child)) = node_ref(&node.at(idx).map_err(decode_err)?)? {
+                if !touched.contains(&child) {
+                    untouched.push(child);
+                }
+            }
+        }
+        if untouched.len() == 1 && nodes.contains_key(&untouched[0]) {
+            siblings.push(untouched[0]);
+        }
+    }
+    touched.extend(siblings);
+    Ok(())
+}
+
+// the account leaf value: rlp([nonce, balance, storage_root, code_hash])
+pub(crate) fn account_storage_root(value: &[u8]) -> Result<SH256, String> {
+    let acc = Rlp::new(value);
+    let root = acc.at(2).map_err(decode_err)?;
+    let root = root.data().map_err(decode_err)?;
+    if root.len() != 32 {
+        return Err(format!("invalid storage root length: {}", root.len()));
+    }
+    Ok(H256::from_slice(root).into())
+}
+
+fn encode_path(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        out.push(((flag | 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        out.push(flag << 4);
+        nibbles
+    };
+    for pair in rest.chunks(2) {
+        out.push((pair[0] << 4) | pair[1]);
+    }
+    out
+}
+
+fn append_child(stream: &mut RlpStream, node: &[u8]) {
+    if node.len() < 32 {
+        stream.append_raw(node, 1);
+    } else {
+        stream.append(&crate::keccak_hash(node).as_slice());
+    }
+}
+
+// Encode the node of `items` (sorted by the nibbles) below `depth`. The nodes
+// on the path of `key` which are referenced by hash are pushed to `proof`,
+// the deepest first.
+fn build_node(
+    items: &[(Vec<u8>, &[u8])],
+    depth: usize,
+    key: Option<&[u8]>,
+    proof: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    let mut stream = RlpStream::new();
+    if items.len() == 1 {
+        stream.begin_list(2);
+        stream.append(&encode_path(&items[0].0[depth..], true));
+        stream.append(&items[0].1);
+    } else {
+        let first = &items[0].0;
+        let last = &items[items.len() - 1].0;
+        let prefix = first[depth..]
+            .iter()
+            .zip(&last[depth..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix > 0 {
+            let path = &first[depth..depth + prefix];
+            let key = key
+                .filter(|key| key.len() >= depth + prefix && &key[depth..depth + prefix] == path);
+            let child = build_node(items, depth + prefix, key, proof);
+            stream.begin_list(2);
+            stream.append(&encode_path(path, false));
+            append_child(&mut stream, &child);
+        } else {
+            stream.begin_list(17);
+            // a shorter path sorts first, its value ends at this branch
+            let (value, mut start) = match items[0].0.len() == depth {
+                true => (Some(items[0].1), 1),
+                false => (None, 0),
+            };
+            for nibble in 0..16 {
+                let end = start
+                    + items[start..]
+                        .iter()
+                        .take_while(|(path, _)| path[depth] == nibble)
+                        .count();
+                if start == end {
+                    stream.append_empty_data();
+                    continue;
+                }
+                let key = key.filter(|key| key.len() > depth && key[depth] == nibble);
+                let child = build_node(&items[start..end], depth + 1, key, proof);
+                append_child(&mut stream, &child);
+                start = end;
+            }
+            match value {
+                Some(value) => stream.append(&value),
+                None => stream.append_empty_data(),
+            };
+        }
+    }
+    let node = stream.out().to_vec();
+    if key.is_some() && (node.len() >= 32 || depth == 0) {
+        proof.push(node.clone());
+    }
+    node
+}
+
+// Build the trie of `items` in memory, returns the root and the proof of
+// `key`, which starts from the root node. The proof of a missing key proves
+// its absence.
+pub(crate) fn build_proof(items: &BTreeMap<Vec<u8>, Vec<u8>>, key: &[u8]) -> (SH256, Vec<Vec<u8>>) {
+    let mut proof = Vec::new();
+    if items.is_empty() {
+        let node = rlp::NULL_RLP.to_vec();
+        let root = crate::keccak_hash(&node).into();
+        proof.push(node);
+        return (root, proof);
+    }
+    let items: Vec<_> = items
+        .iter()
+        .map(|(key, value)| (key_to_nibbles(key), value.as_slice()))
+        .collect();
+    let key = key_to_nibbles(key);
+    let root = build_node(&items, 0, Some(&key), &mut proof);
+    proof.reverse();
+    (crate::keccak_hash(&root).into(), proof)
+}
+
+// the trie of the receipts, transactions and withdrawals, keyed by rlp(index)
+pub(crate) fn ordered_trie_root(values: &[Vec<u8>]) -> SH256 {
+    let items: BTreeMap<Vec<u8>, Vec<u8>> = values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| (rlp::encode(&(idx as u64)).to_vec(), value.clone()))
+        .collect();
+    trie_root(&items)
+}
+
+// the children of the root branch are encoded on their own threads once the
+// trie is large enough to pay for the spawns
+#[cfg(feature = "std")]
+const PARALLEL_TRIE_ITEMS: usize = 128;
+
+// Same root as build_proof, without collecting any proof.
+pub(crate) fn trie_root(items: &BTreeMap<Vec<u8>, Vec<u8>>) -> SH256 {
+    if items.is_empty() {
+        return crate::keccak_hash(&rlp::NULL_RLP).into();
+    }
+    let items: Vec<_> = items
+        .iter()
+        .map(|(key, value)| (key_to_nibbles(key), value.as_slice()))
+        .collect();
+    #[cfg(feature = "std")]
+    if items.len() >= PARALLEL_TRIE_ITEMS {
+        if let Some(root) = build_root_branch(&items) {
+            return crate::keccak_hash(&root).into();
+        }
+    }
+    let root = build_node(&items, 0, None, &mut Vec::new());
+    crate::keccak_hash(&root).into()
+}
+
+// None if the root is not a branch, all the keys share the first nibble then.
+#[cfg(feature = "std")]
+fn build_root_branch(items: &[(Vec<u8>, &[u8])]) -> Option<Vec<u8>> {
+    let first = &items[0].0;
+    let last = &items[items.len() - 1].0;
+    if !first.is_empty() && first.first() == last.first() {
+        return None;
+    }
+    let (value, mut start) = match first.is_empty() {
+        true => (Some(items[0].1), 1),
+        false => (None, 0),
+    };
+    let mut handles = Vec::with_capacity(16);
+    for nibble in 0..16 {
+        let end = start
+            + items[start..]
+                .iter()
+                .take_while(|(path, _)| path[0] == nibble)
+                .count();
+        if start == end {
+            handles.push(None);
+            continue;
+        }
+        let group: Vec<(Vec<u8>, Vec<u8>)> = items[start..end]
+            .iter()
+            .map(|(path, value)| (path.clone(), value.to_vec()))
+            .collect();
+        start = end;
+        handles.push(Some(std::thread::spawn(move || {
+            let group: Vec<_> = group
+                .iter()
+                .map(|(path, value)| (path.clone(), value.as_slice()))
+                .collect();
+            build_node(&group, 1, None, &mut Vec::new())
+        })));
+    }
+
+    let mut stream = RlpStream::new_list(17);
+    for handle in handles {
+        match handle {
+            Some(handle) => {
+                let child = handle.join().expect("trie thread panicked");
+                append_child(&mut stream, &child);
+            }
+            None => {
+                stream.append_empty_data();
+            }
+        }
+    }
+    match value {
+        Some(value) => stream.append(&value),
+        None => stream.append_empty_data(),
+    };
+    Some(stream.out().to_vec())
+}
+
+pub(crate) fn verify_proof(
+    root: SH256,
+    key: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Option<Vec<u8>>, String> {
+    let pool: NodePool = proof
+        .iter()
+        .map(|node| (crate::keccak_hash(node).into(), node.clone()))
+        .collect();
+    // unlike `get`, a node missing on the path fails the proof
+    TrieNode::from_root(root).get(&pool, key)
+}
+
+// keccak(rlp("")), the root of an empty trie
+pub(crate) const EMPTY_ROOT: [u8; 32] = [
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+];
+
+// A decoded trie node, the paths are in nibbles. The children which are not
+// touched yet are kept as Hash and resolved from the node pool on demand.
+#[derive(Debug, Clone)]
+pub(crate) enum TrieNode {
+    Empty,
+    Leaf(Vec<u8>, Vec<u8>),
+    Extension(Vec<u8>, Box<TrieNode>),
+    Branch(Vec<TrieNode>, Option<Vec<u8>>),
+    Hash(SH256),
+}
+
+type NodePool = BTreeMap<SH256, Vec<u8>>;
+
+fn missing_node(hash: &SH256) -> String {
+    format!("missing trie node: {:?}", hash)
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+impl TrieNode {
+    pub(crate) fn from_root(root: SH256) -> Self {
+        match root.0 .0 == EMPTY_ROOT {
+            true => TrieNode::Empty,
+            false => TrieNode::Hash(root),
+        }
+    }
+
+    fn empty_branch() -> Vec<TrieNode> {
+        vec![TrieNode::Empty; 16]
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        let node = Rlp::new(data);
+        if !node.is_list() {
+            return match node.data().map_err(decode_err)?.is_empty() {
+                true => Ok(TrieNode::Empty),
+                false => Err("unexpected trie node".into()),
+            };
+        }
+        let child = |item: Rlp| -> Result<TrieNode, String> {
+            match node_ref(&item)? {
+                None => Ok(TrieNode::Empty),
+                Some(MptChild::Hash(hash)) => Ok(TrieNode::Hash(hash)),
+                Some(MptChild::Inline(data)) => Self::decode(&data),
+            }
+        };
+        match node.item_count().map_err(decode_err)? {
+            17 => {
+                let mut children = Vec::with_capacity(16);
+                for idx in 0..16 {
+                    children.push(child(node.at(idx).map_err(decode_err)?)?);
+                }
+                let value = node.at(16).map_err(decode_err)?;
+                let value = value.data().map_err(decode_err)?;
+                let value = match value.is_empty() {
+                    true => None,
+                    false => Some(value.to_vec()),
+                };
+                Ok(TrieNode::Branch(children, value))
+            }
+            2 => {
+                let path = node.at(0).map_err(decode_err)?;
+                let (path, is_leaf) = decode_path(path.data().map_err(decode_err)?)?;
+                let item = node.at(1).map_err(decode_err)?;
+                if is_leaf {
+                    let value = item.data().map_err(decode_err)?.to_vec();
+                    return Ok(TrieNode::Leaf(path, value));
+                }
+                Ok(TrieNode::Extension(path, Box::new(child(item)?)))
+            }
+            n => Err(format!("unexpected item count: {}", n)),
+        }
+    }
+
+    fn resolve(self, pool: &NodePool) -> Result<Self, String> {
+        match self {
+            TrieNode::Hash(hash) => match pool.get(&hash) {
+                Some(data) => Self::decode(data),
+                None => Err(missing_node(&hash)),
+            },
+            node => Ok(node),
+        }
+    }
+
+    pub(crate) fn get(&self, pool: &NodePool, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.get_path(pool, &key_to_nibbles(key))
+    }
+
+    fn get_path(&self, pool: &NodePool, path: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            TrieNode::Empty => Ok(None),
+            TrieNode::Leaf(leaf, value) => match leaf[..] == path[..] {
+                true => Ok(Some(value.clone())),
+                false => Ok(None),
+            },
+            TrieNode::Extension(ext, child) => match path.starts_with(ext) {
+                true => child.get_path(pool, &path[ext.len()..]),
+                false => Ok(None),
+            },
+            TrieNode::Branch(children, value) => match path.split_first() {
+                Some((idx, rest)) => children[*idx as usize].get_path(pool, rest),
+                None => Ok(value.clone()),
+            },
+            TrieNode::Hash(_) => self.clone().resolve(pool)?.get_path(pool, path),
+        }
+    }
+
+    pub(crate) fn insert(
+        self,
+        pool: &NodePool,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Self, String> {
+        self.insert_path(pool, &key_to_nibbles(key), value)
+    }
+
+    // the branch where `a` and `b` diverge after their common prefix
+    fn split(common: usize, a: (&[u8], TrieNode), b: (&[u8], Vec<u8>)) -> Self {
+        let mut children = Self::empty_branch();
+        let mut value = None;
+        match a.0.len() == common {
+            true => {
+                if let TrieNode::Leaf(_, leaf_value) = a.1 {
+                    value = Some(leaf_value);
+                }
+            }
+            false => children[a.0[common] as usize] = a.1,
+        }
+        match b.0.len() == common {
+            true => value = Some(b.1),
+            false => {
+                children[b.0[common] as usize] = TrieNode::Leaf(b.0[common + 1..].to_vec(), b.1)
+            }
+        }
+        let branch = TrieNode::Branch(children, value);
+        match common {
+            0 => branch,
+            _ => TrieNode::Extension(b.0[..common].to_vec(), Box::new(branch)),
+        }
+    }
+
+    fn insert_path(self, pool: &NodePool, path: &[u8], value: Vec<u8>) -> Result<Self, String> {
+        Ok(match self.resolve(pool)? {
+            TrieNode::Empty => TrieNode::Leaf(path.to_vec(), value),
+            TrieNode::Leaf(leaf, leaf_value) => {
+                if leaf[..] == path[..] {
+                    return Ok(TrieNode::Leaf(leaf, value));
+                }
+                let common = common_prefix(&leaf, path);
+                let rest = match leaf.len() == common {
+                    true => TrieNode::Leaf(Vec::new(), leaf_value),
+                    false => TrieNode::Leaf(leaf[common + 1..].to_vec(), leaf_value),
+                };
+                Self::split(common, (&leaf, rest), (path, value))
+            }
+            TrieNode::Extension(ext, child) => {
+                let common = common_prefix(&ext, path);
+                if common == ext.len() {
+                    let child = child.insert_path(pool, &path[common..], value)?;
+                    return Ok(TrieNode::Extension(ext, Box::new(child)));
+                }
+                let rest = match ext.len() - common - 1 {
+                    0 => *child,
+                    _ => TrieNode::Extension(ext[common + 1..].to_vec(), child),
+                };
+                Self::split(common, (&ext, rest), (path, value))
+            }
+            TrieNode::Branch(mut children, branch_value) => match path.split_first() {
+                Some((idx, rest)) => {
+                    let idx = *idx as usize;
+                    let child = core::mem::replace(&mut children[idx], TrieNode::Empty);
+                    children[idx] = child.insert_path(pool, rest, value)?;
+                    TrieNode::Branch(children, branch_value)
+                }
+                None => TrieNode::Branch(children, Some(value)),
+            },
+            TrieNode::Hash(_) => unreachable!("resolved"),
+        })
+    }
+
+    pub(crate) fn delete(self, pool: &NodePool, key: &[u8]) -> Result<Self, String> {
+        self.delete_path(pool, &key_to_nibbles(key))
+    }
+
+    fn delete_path(self, pool: &NodePool, path: &[u8]) -> Result<Self, String> {
+        Ok(match self.resolve(pool)? {
+            TrieNode::Empty => TrieNode::Empty,
+            TrieNode::Leaf(leaf, value) => match leaf[..] == path[..] {
+                true => TrieNode::Empty,
+                false => TrieNode::Leaf(leaf, value),
+            },
+            TrieNode::Extension(ext, child) => {
+                if !path.starts_with(&ext) {
+                    return Ok(TrieNode::Extension(ext, child));
+                }
+                let child = child.delete_path(pool, &path[ext.len()..])?;
+                Self::join(ext, child)
+            }
+            TrieNode::Branch(mut children, mut value) => {
+                match path.split_first() {
+                    Some((idx, rest)) => {
+                        let idx = *idx as usize;
+                        let child = core::mem::replace(&mut children[idx], TrieNode::Empty);
+                        children[idx] = child.delete_path(pool, rest)?;
+                    }
+                    None => value = None,
+                }
+                Self::collapse(pool, children, value)?
+            }
+            TrieNode::Hash(_) => unreachable!("resolved"),
+        })
+    }
+
+    // prepend `prefix` to the path of a node whose parent is removed
+    fn join(mut prefix: Vec<u8>, node: TrieNode) -> Self {
+        match node {
+            TrieNode::Empty => TrieNode::Empty,
+            TrieNode::Leaf(path, value) => {
+                prefix.extend_from_slice(&path);
+                TrieNode::Leaf(prefix, value)
+            }
+            TrieNode::Extension(path, child) => {
+                prefix.extend_from_slice(&path);
+                TrieNode::Extension(prefix, child)
+            }
+            node if prefix.is_empty() => node,
+            node => TrieNode::Extension(prefix, Box::new(node)),
+        }
+    }
+
+    // a branch with a single child is merged into it, which resolves the
+    // sibling of the deleted key.
+    fn collapse(
+        pool: &NodePool,
+        mut children: Vec<TrieNode>,
+        value: Option<Vec<u8>>,
+    ) -> Result<Self, String> {
+        let used: Vec<usize> = (0..16)
+            .filter(|idx| !matches!(children[*idx], TrieNode::Empty))
+            .collect();
+        Ok(match (used.len(), value) {
+            (0, None) => TrieNode::Empty,
+            (0, Some(value)) => TrieNode::Leaf(Vec::new(), value),
+            (1, None) => {
+                let idx = used[0];
+                let child = core::mem::replace(&mut children[idx], TrieNode::Empty);
+                Self::join(vec![idx as u8], child.resolve(pool)?)
+            }
+            (_, value) => TrieNode::Branch(children, value),
+        })
+    }
+
+    // The rlp encoding of the node, the encoded children which are
+    // referenced by hash are added to `out`.
+    fn encode(&self, out: &mut NodePool) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        match self {
+            TrieNode::Empty => stream.append_empty_data(),
+            TrieNode::Leaf(path, value) => {
+                stream.begin_list(2);
+                stream.append(&encode_path(path, true));
+                stream.append(value)
+            }
+            TrieNode::Extension(path, child) => {
+                stream.begin_list(2);
+                stream.append(&encode_path(path, false));
+                child.append_ref(&mut stream, out);
+                &mut stream
+            }
+            TrieNode::Branch(children, value) => {
+                stream.begin_list(17);
+                for child in children {
+                    child.append_ref(&mut stream, out);
+                }
+                match value {
+                    Some(value) => stream.append(value),
+                    None => stream.append_empty_data(),
+                }
+            }
+            TrieNode::Hash(_) => unreachable!("a hash node is only encoded as a reference"),
+        };
+        stream.out().to_vec()
+    }
+
+    fn append_ref(&self, stream: &mut RlpStream, out: &mut NodePool) {
+        match self {
+            TrieNode::Empty => {
+                stream.append_empty_data();
+            }
+            TrieNode::Hash(hash) => {
+                stream.append(&hash.0.as_bytes());
+            }
+            node => {
+                let data = node.encode(out);
+                if data.len() < 32 {
+                    stream.append_raw(&data, 1);
+                } else {
+                    let hash: SH256 = crate::keccak_hash(&data).into();
+                    stream.append(&hash.0.as_bytes());
+                    out.insert(hash, data);
+                }
+            }
+        }
+    }
+
+    // hash the trie, the new nodes are added to `out`
+    pub(crate) fn commit(&self, out: &mut NodePool) -> SH256 {
+        match self {
+            TrieNode::Empty => H256::from(EMPTY_ROOT).into(),
+            TrieNode::Hash(hash) => *hash,
+            node => {
+                let data = node.encode(out);
+                let hash: SH256 = crate::keccak_hash(&data).into();
+                out.insert(hash, data);
+                hash
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(val: &str) -> SH256 {
+        serde_json::from_str(&format!("\"{}\"", val)).unwrap()
+    }
+
+    // the root on a single thread
+    fn sequential_root(items: &BTreeMap<Vec<u8>, Vec<u8>>) -> SH256 {
+        let items: Vec<_> = items
+            .iter()
+            .map(|(key, value)| (key_to_nibbles(key), value.as_slice()))
+            .collect();
+        crate::keccak_hash(&build_node(&items, 0, None, &mut Vec::new())).into()
+    }
+
+    #[test]
+    fn test_trie_root() {
+        let mut items = BTreeMap::new();
+        for (key, value) in [
+            ("doe", "reindeer"),
+            ("dog", "puppy"),
+            ("dogglesworth", "cat"),
+        ] {
+            items.insert(key.as_bytes().to_vec(), value.as_bytes().to_vec());
+        }
+        let root = hash("0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3");
+        assert_eq!(trie_root(&items), root);
+        assert_eq!(build_proof(&items, b"dog").0, root);
+        assert_eq!(trie_root(&BTreeMap::new()).0 .0, EMPTY_ROOT);
+    }
+
+    #[test]
+    fn test_parallel_trie_root() {
+        let values: Vec<Vec<u8>> = (0..300_usize)
+            .map(|idx| vec![(idx % 251) as u8; 40])
+            .collect();
+        let root = hash("0xee35c5f95ede4571bcf78d587aeef7eb2e125791531240ba91cb10509d6c14a3");
+        assert_eq!(ordered_trie_root(&values), root);
+
+        let mut items: BTreeMap<Vec<u8>, Vec<u8>> = values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                (
+                    (idx as u32)
+                        .wrapping_mul(0x9e37_79b9)
+                        .to_be_bytes()
+                        .to_vec(),
+                    value.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(trie_root(&items), sequential_root(&items));
+        // a value at the root branch
+        items.insert(Vec::new(), vec![1; 40]);
+        assert_eq!(trie_root(&items), sequential_root(&items));
+        // every key shares the first nibble, the root is an extension
+        let shared: BTreeMap<Vec<u8>, Vec<u8>> = values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                (
+                    [&[0x05_u8][..], &(idx as u32).to_be_bytes()].concat(),
+                    value.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(trie_root(&shared), sequential_root(&shared));
+    }
+}
@@ -0,0 +1,106 @@
+use std::prelude::v1::*;
+
+use eth_types::Receipt;
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+
+use crate::{append_logs, ReceiptContext};
+
+// the EIP-2718 type of the op stack deposit tx
+pub const DEPOSIT_TX_TYPE: u8 = 0x7e;
+
+// The receipt fields of an op stack deposit tx which are in the receipts
+// trie, an op engine fills them in Engine::extend_receipt and appends them in
+// Engine::encode_receipt. Without them the receipts root of a block with
+// deposits doesn't match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepositReceiptFields {
+    // the sender nonce before the deposit, since regolith
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_nonce: Option<u64>,
+    // 1 since canyon
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_receipt_version: Option<u64>,
+}
+
+impl DepositReceiptFields {
+    pub fn new(ctx: &ReceiptContext, regolith: bool, canyon: bool) -> Self {
+        Self {
+            deposit_nonce: match regolith {
+                true => Some(ctx.nonce),
+                false => None,
+            },
+            deposit_receipt_version: match canyon {
+                true => Some(1),
+                false => None,
+            },
+        }
+    }
+
+    // 0x7e || rlp([status, cumulative_gas_used, logs_bloom, logs,
+    //              deposit_nonce?, deposit_receipt_version?])
+    pub fn encode_receipt(&self, receipt: &Receipt) -> Vec<u8> {
+        let items = 4
+            + self.deposit_nonce.is_some() as usize
+            + self.deposit_receipt_version.is_some() as usize;
+        let mut stream = RlpStream::new_list(items);
+        stream.append(&receipt.status.as_u64());
+        stream.append(&receipt.cumulative_gas_used.as_u64());
+        stream.append(&receipt.logs_bloom.as_bytes());
+        append_logs(&mut stream, &receipt.logs);
+        if let Some(nonce) = &self.deposit_nonce {
+            stream.append(nonce);
+        }
+        if let Some(version) = &self.deposit_receipt_version {
+            stream.append(version);
+        }
+        let mut out = vec![DEPOSIT_TX_TYPE];
+        out.extend_from_slice(&stream.out());
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::{SH160, SH256};
+
+    #[test]
+    fn test_deposit_receipt() {
+        let receipt = Receipt {
+            status: 1_u64.into(),
+            transaction_hash: SH256::default(),
+            transaction_index: 0_u64.into(),
+            r#type: Some((DEPOSIT_TX_TYPE as u64).into()),
+            gas_used: 21000_u64.into(),
+            cumulative_gas_used: 21000_u64.into(),
+            logs_bloom: vec![0_u8; 256].into(),
+            logs: Vec::new(),
+            contract_address: None,
+            root: None,
+            block_hash: None,
+            block_number: None,
+        };
+        let ctx = ReceiptContext {
+            caller: SH160::default(),
+            nonce: 5,
+            extra_fee: None,
+        };
+
+        // 0x7e, list(0x010a bytes): 01, 82 5208, b9 0100 (256 zero bytes), c0, 05, 01
+        let mut expect = vec![
+            0x7e, 0xf9, 0x01, 0x0a, 0x01, 0x82, 0x52, 0x08, 0xb9, 0x01, 0x00,
+        ];
+        expect.extend_from_slice(&[0_u8; 256]);
+        expect.extend_from_slice(&[0xc0, 0x05, 0x01]);
+        let fields = DepositReceiptFields::new(&ctx, true, true);
+        assert_eq!(fields.encode_receipt(&receipt), expect);
+
+        // bedrock: the eth receipt with the deposit type
+        let fields = DepositReceiptFields::new(&ctx, false, false);
+        let encoded = fields.encode_receipt(&receipt);
+        assert_eq!(&encoded[1..4], &[0xf9, 0x01, 0x08]);
+        assert_eq!(encoded.len(), expect.len() - 2);
+    }
+}
@@ -0,0 +1,128 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use evm_gasometer::tracing as gas_tracing;
+use evm_runtime::tracing as step_tracing;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OpcodeGas {
+    pub count: u64,
+    pub gas: u64,
+}
+
+// The invocation count and the gas of every opcode over the recorded txs,
+// collected from the evm tracing events without building a struct trace.
+// The gas of an opcode is its own static and dynamic cost including the
+// memory expansion, the gas forwarded to a sub call is counted by the
+// opcodes of the callee.
+#[derive(Debug, Default)]
+pub struct OpcodeGasStats {
+    opcodes: RefCell<BTreeMap<u8, OpcodeGas>>,
+    intrinsic_gas: RefCell<u64>,
+    txs: RefCell<u64>,
+}
+
+#[derive(Default)]
+struct Collector {
+    opcodes: BTreeMap<u8, OpcodeGas>,
+    intrinsic_gas: u64,
+    // the opcode whose cost is not recorded yet
+    pending: Option<u8>,
+}
+
+impl Collector {
+    fn record(&mut self, gas: u64) {
+        if let Some(opcode) = self.pending.take() {
+            self.opcodes.entry(opcode).or_default().gas += gas;
+        }
+    }
+}
+
+struct StepListener(Rc<RefCell<Collector>>);
+
+impl step_tracing::EventListener for StepListener {
+    fn event(&mut self, event: step_tracing::Event) {
+        if let step_tracing::Event::Step { opcode, .. } = event {
+            let mut collector = self.0.borrow_mut();
+            collector.opcodes.entry(opcode.0).or_default().count += 1;
+            collector.pending = Some(opcode.0);
+        }
+    }
+}
+
+struct GasListener(Rc<RefCell<Collector>>);
+
+impl gas_tracing::EventListener for GasListener {
+    // only the first cost after a step belongs to the opcode, the calls
+    // record the forwarded gas right after their own cost.
+    fn event(&mut self, event: gas_tracing::Event) {
+        let mut collector = self.0.borrow_mut();
+        match event {
+            gas_tracing::Event::RecordCost { cost, .. } => collector.record(cost),
+            gas_tracing::Event::RecordDynamicCost {
+                gas_cost,
+                memory_gas,
+                snapshot,
+                ..
+            } => {
+                // memory_gas is the total after the expansion
+                let expansion = snapshot
+                    .map(|snapshot| memory_gas.saturating_sub(snapshot.memory_gas))
+                    .unwrap_or(0);
+                collector.record(gas_cost + expansion);
+            }
+            gas_tracing::Event::RecordTransaction { cost, .. } => {
+                collector.intrinsic_gas += cost;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl OpcodeGasStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // run the execution of one tx with the listeners installed
+    pub(crate) fn collect<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let collector = Rc::new(RefCell::new(Collector::default()));
+        let mut steps = StepListener(collector.clone());
+        let mut gas = GasListener(collector.clone());
+        let out = step_tracing::using(&mut steps, || gas_tracing::using(&mut gas, f));
+
+        let collector = collector.borrow();
+        let mut opcodes = self.opcodes.borrow_mut();
+        for (opcode, stat) in &collector.opcodes {
+            let entry = opcodes.entry(*opcode).or_default();
+            entry.count += stat.count;
+            entry.gas += stat.gas;
+        }
+        *self.intrinsic_gas.borrow_mut() += collector.intrinsic_gas;
+        *self.txs.borrow_mut() += 1;
+        out
+    }
+
+    pub fn opcodes(&self) -> BTreeMap<u8, OpcodeGas> {
+        self.opcodes.borrow().clone()
+    }
+
+    pub fn get(&self, opcode: u8) -> OpcodeGas {
+        self.opcodes
+            .borrow()
+            .get(&opcode)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn intrinsic_gas(&self) -> u64 {
+        *self.intrinsic_gas.borrow()
+    }
+
+    pub fn txs(&self) -> u64 {
+        *self.txs.borrow()
+    }
+}
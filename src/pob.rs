@@ -1,20 +1,127 @@
 use std::prelude::v1::*;
 
-use crypto::keccak_hash;
-use eth_types::{Block, FetchStateResult, HexBytes, SH256};
-use serde::{Deserialize, Serialize};
+use eth_types::{
+    Block, BlockHeader, FetchStateResult, HexBytes, Log, Receipt, Transaction, TransactionInner,
+    Withdrawal, H160, H256, SH160, SH256, SU256, U256,
+};
+use rlp::{DecoderError, Rlp, RlpStream};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 
+use crate::pob_state::trimmed;
+use crate::{append_logs, keccak_hash, mpt, ExecutionWitness, PobUnused};
+
+// the first released layout, archives written before the version field was
+// introduced are decoded as this version.
+pub const POB_VERSION_V1: u32 = 1;
+// carries the withdrawals and the blob commitments
+pub const POB_VERSION_V2: u32 = 2;
+// the state_hash also commits the codes, block_hashes, chain_id and prev_state_root
+pub const POB_VERSION_V3: u32 = 3;
+// optionally carries the expected execution results
+pub const POB_VERSION_V4: u32 = 4;
+pub const POB_VERSION: u32 = POB_VERSION_V4;
+
+pub const BLOB_COMMITMENT_VERSION_KZG: u8 = 0x01;
+
+// the first byte of the binary encoding
+const POB_BYTES_RAW: u8 = 0;
+const POB_BYTES_ZSTD: u8 = 1;
+
+#[derive(Debug)]
+pub enum PobError {
+    UnsupportedVersion {
+        version: u32,
+        latest: u32,
+    },
+    Decode(String),
+    InconsistentChainId {
+        idx: usize,
+        expect: u64,
+        got: u64,
+    },
+    NonConsecutiveBlock {
+        idx: usize,
+        expect: u64,
+        got: u64,
+    },
+    UnlinkedBlock {
+        idx: usize,
+        expect: SH256,
+        got: SH256,
+    },
+    EmptyBatch,
+}
+
+// the block carried by a Pob, engines with extra block fields can provide
+// their own block type.
+pub trait PobBlock {
+    fn number(&self) -> u64;
+    fn hash(&self) -> SH256;
+    fn parent_hash(&self) -> SH256;
+    // the block item of the binary encoding
+    fn rlp_append(&self, s: &mut RlpStream);
+    fn rlp_decode(rlp: &Rlp) -> Result<Self, DecoderError>
+    where
+        Self: Sized;
+}
+
+impl PobBlock for Block {
+    fn number(&self) -> u64 {
+        self.header.number.as_u64()
+    }
+
+    fn hash(&self) -> SH256 {
+        self.header.hash()
+    }
+
+    fn parent_hash(&self) -> SH256 {
+        self.header.parent_hash
+    }
+
+    // rlp([header, txs, withdrawals]), a tx is its signed envelope. the rpc
+    // only fields of the block and the txs are not kept.
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        append_header(s, &self.header);
+        s.begin_list(self.transactions.len());
+        for tx in &self.transactions {
+            // an unsupported tx fails the decoding
+            let raw = tx.inner().map(|tx| tx.to_bytes()).unwrap_or_default();
+            s.append(&raw);
+        }
+        append_opt(s, &self.withdrawals, |s, withdrawals| {
+            append_withdrawals(s, withdrawals)
+        });
+    }
+
+    fn rlp_decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let mut transactions = Vec::new();
+        for raw in rlp.list_at::<Vec<u8>>(1)? {
+            let tx = TransactionInner::from_bytes(&raw)
+                .map_err(|_| DecoderError::Custom("invalid tx"))?;
+            transactions.push(Transaction::from(tx));
+        }
+        Ok(Block {
+            header: decode_header(&rlp.at(0)?)?,
+            transactions,
+            withdrawals: opt_at(rlp, 2, |item, idx| decode_withdrawals(&item.at(idx)?))?,
+            ..Default::default()
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
-pub struct Pob {
-    pub block: Block,
+pub struct Pob<B = Block> {
+    pub block: B,
     pub data: PobData,
 
     state_hash: Option<SH256>,
 }
 
-impl Pob {
-    pub fn new(block: Block, data: PobData) -> Pob {
+impl<B: PobBlock> Pob<B> {
+    pub fn new(block: B, data: PobData) -> Pob<B> {
         Pob {
             block,
             data,
@@ -24,12 +131,12 @@ impl Pob {
 
     pub fn from_proof(
         chain_id: u64,
-        blk: Block,
+        blk: B,
         prev_state_root: SH256,
         block_hashes: BTreeMap<u64, SH256>,
         codes: BTreeMap<SH256, HexBytes>,
         states: Vec<FetchStateResult>,
-    ) -> Pob {
+    ) -> Pob<B> {
         let codes = codes.into_values().collect();
         let mut mpt_nodes = BTreeMap::new();
         for state in states {
@@ -53,6 +160,7 @@ impl Pob {
             block_hashes,
             mpt_nodes,
             codes,
+            ..Default::default()
         };
         Pob::new(blk, data)
     }
@@ -62,28 +170,1023 @@ impl Pob {
             return hash.clone();
         }
 
-        self.data.mpt_nodes.sort_unstable();
-        // the mpt_nodes should be in order
-        let hash: SH256 = crypto::keccak_encode(|hash| {
-            for item in &self.data.mpt_nodes {
-                hash(&item);
-            }
-        })
-        .into();
+        let hash = self.data.state_hash();
         self.state_hash = Some(hash.clone());
         hash
     }
 
     pub fn block_hash(&self) -> SH256 {
-        self.block.header.hash()
+        self.block.hash()
+    }
+
+    pub fn unused(&self, witness: &ExecutionWitness) -> Result<PobUnused, PobError> {
+        self.data.unused(witness)
+    }
+
+    pub fn prune(&mut self, unused: &PobUnused) -> usize {
+        let removed = self.data.prune(unused);
+        if removed > 0 {
+            self.state_hash = None;
+        }
+        removed
+    }
+}
+
+impl<B> Pob<B>
+where
+    B: PobBlock + Serialize + DeserializeOwned,
+{
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("pob should be serializable")
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Pob<B>, PobError> {
+        let pob: Pob<B> =
+            serde_json::from_slice(data).map_err(|err| PobError::Decode(format!("{:?}", err)))?;
+        pob.data.check_version()?;
+        Ok(pob)
+    }
+
+    // binary encoding: [flag] + rlp([version, block, chain_id, prev_state_root, block_hashes, mpt_nodes, codes])
+    // since v2: + [withdrawals, blob_versioned_hashes, blob_commitments]
+    // since v4: + [expected]
+    // the block item is provided by the PobBlock, the withdrawals and the
+    // expected results are optional fields.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![POB_BYTES_RAW];
+        out.extend_from_slice(&self.rlp_bytes());
+        out
+    }
+
+    #[cfg(feature = "zstd")]
+    pub fn to_compressed_bytes(&self, level: i32) -> Vec<u8> {
+        let data = self.rlp_bytes();
+        let mut out = vec![POB_BYTES_ZSTD];
+        out.extend(zstd::stream::encode_all(&data[..], level).expect("compress in memory"));
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Pob<B>, PobError> {
+        let (flag, data) = match data.split_first() {
+            Some(n) => n,
+            None => return Err(PobError::Decode("empty pob bytes".into())),
+        };
+        match *flag {
+            POB_BYTES_RAW => Self::from_rlp_bytes(data),
+            #[cfg(feature = "zstd")]
+            POB_BYTES_ZSTD => {
+                let data = zstd::stream::decode_all(data)
+                    .map_err(|err| PobError::Decode(format!("decompress fail: {:?}", err)))?;
+                Self::from_rlp_bytes(&data)
+            }
+            #[cfg(not(feature = "zstd"))]
+            POB_BYTES_ZSTD => Err(PobError::Decode(
+                "compressed pob requires the zstd feature".into(),
+            )),
+            flag => Err(PobError::Decode(format!(
+                "unknown pob bytes flag: {}",
+                flag
+            ))),
+        }
+    }
+
+    fn rlp_bytes(&self) -> Vec<u8> {
+        let data = &self.data;
+        let mut s = RlpStream::new_list(data.rlp_list_len());
+        s.append(&data.version);
+        self.block.rlp_append(&mut s);
+        s.append(&data.chain_id);
+        s.append(&data.prev_state_root.0.as_bytes());
+        s.begin_list(data.block_hashes.len());
+        for (number, hash) in &data.block_hashes {
+            s.begin_list(2);
+            s.append(number);
+            s.append(&hash.0.as_bytes());
+        }
+        s.begin_list(data.mpt_nodes.len());
+        for node in &data.mpt_nodes {
+            s.append(&node.as_bytes());
+        }
+        s.begin_list(data.codes.len());
+        for code in &data.codes {
+            s.append(&code.as_bytes());
+        }
+        if data.version >= POB_VERSION_V2 {
+            append_opt(&mut s, &data.withdrawals, |s, withdrawals| {
+                append_withdrawals(s, withdrawals)
+            });
+            s.begin_list(data.blob_versioned_hashes.len());
+            for hash in &data.blob_versioned_hashes {
+                s.append(&hash.0.as_bytes());
+            }
+            s.begin_list(data.blob_commitments.len());
+            for commitment in &data.blob_commitments {
+                s.append(&commitment.as_bytes());
+            }
+        }
+        if data.version >= POB_VERSION_V4 {
+            append_opt(&mut s, &data.expected, |s, expected| expected.rlp_append(s));
+        }
+        s.out().to_vec()
+    }
+
+    fn from_rlp_bytes(data: &[u8]) -> Result<Pob<B>, PobError> {
+        let rlp_err = |err: DecoderError| PobError::Decode(format!("{:?}", err));
+        let rlp = Rlp::new(data);
+        let mut data = PobData {
+            version: rlp.val_at(0).map_err(rlp_err)?,
+            ..Default::default()
+        };
+        data.check_version()?;
+        if rlp.item_count().map_err(rlp_err)? != data.rlp_list_len() {
+            return Err(rlp_err(DecoderError::RlpIncorrectListLen));
+        }
+        let block = Self::decode_rlp_fields(&rlp, &mut data).map_err(rlp_err)?;
+        Ok(Pob::new(block, data))
+    }
+
+    fn decode_rlp_fields(rlp: &Rlp, data: &mut PobData) -> Result<B, DecoderError> {
+        let block = B::rlp_decode(&rlp.at(1)?)?;
+        data.chain_id = rlp.val_at(2)?;
+        data.prev_state_root = hash_at(rlp, 3)?;
+        for item in rlp.at(4)?.iter() {
+            data.block_hashes
+                .insert(item.val_at(0)?, hash_at(&item, 1)?);
+        }
+        let to_bytes = |list: Vec<Vec<u8>>| list.into_iter().map(HexBytes::from).collect();
+        data.mpt_nodes = to_bytes(rlp.list_at(5)?);
+        data.codes = to_bytes(rlp.list_at(6)?);
+        if data.version >= POB_VERSION_V2 {
+            data.withdrawals = opt_at(rlp, 7, |item, idx| decode_withdrawals(&item.at(idx)?))?;
+            let hashes = rlp.at(8)?;
+            for idx in 0..hashes.item_count()? {
+                data.blob_versioned_hashes.push(hash_at(&hashes, idx)?);
+            }
+            data.blob_commitments = to_bytes(rlp.list_at(9)?);
+        }
+        if data.version >= POB_VERSION_V4 {
+            data.expected = opt_at(rlp, 10, |item, idx| PobExpected::rlp_decode(&item.at(idx)?))?;
+        }
+        Ok(block)
     }
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PobData {
+    #[serde(default = "PobData::legacy_version")]
+    pub version: u32,
     pub chain_id: u64,
     pub prev_state_root: SH256,
     pub block_hashes: BTreeMap<u64, SH256>,
     pub mpt_nodes: Vec<HexBytes>,
     pub codes: Vec<HexBytes>,
+
+    // since v2
+    #[serde(default)]
+    pub withdrawals: Option<Vec<Withdrawal>>,
+    #[serde(default)]
+    pub blob_versioned_hashes: Vec<SH256>,
+    #[serde(default)]
+    pub blob_commitments: Vec<HexBytes>, // 48 bytes kzg commitments
+
+    // since v4
+    #[serde(default)]
+    pub expected: Option<PobExpected>,
+}
+
+// The execution results of the block, so a verifier can cross-check its own
+// results without any external data source.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PobExpected {
+    pub state_root: SH256,
+    pub receipts_root: SH256,
+    pub gas_used: u64,
+    pub logs_bloom: HexBytes,
+    #[serde(default)]
+    pub receipts: Option<Vec<Receipt>>,
+}
+
+impl PobExpected {
+    pub fn from_header(header: &BlockHeader, receipts: Option<Vec<Receipt>>) -> Self {
+        Self {
+            state_root: header.state_root,
+            receipts_root: header.receipts_root,
+            gas_used: header.gas_used.as_u64(),
+            logs_bloom: header.logs_bloom.clone(),
+            receipts,
+        }
+    }
+
+    // rlp([state_root, receipts_root, gas_used, logs_bloom, receipts]), a receipt is
+    // rlp([type, status, cumulative_gas_used, logs_bloom, logs, transaction_hash, transaction_index, gas_used])
+    // the rpc only fields of the receipts and the logs are not kept.
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(5);
+        s.append(&self.state_root.0.as_bytes());
+        s.append(&self.receipts_root.0.as_bytes());
+        s.append(&self.gas_used);
+        s.append(&self.logs_bloom.as_bytes());
+        append_opt(s, &self.receipts, |s, receipts| {
+            s.begin_list(receipts.len());
+            for receipt in receipts {
+                s.begin_list(8);
+                append_opt(s, &receipt.r#type, |s, ty| {
+                    s.append(&ty.as_u64());
+                });
+                s.append(&receipt.status.as_u64());
+                s.append(&receipt.cumulative_gas_used.as_u64());
+                s.append(&receipt.logs_bloom.as_bytes());
+                append_logs(s, &receipt.logs);
+                s.append(&receipt.transaction_hash.0.as_bytes());
+                s.append(&receipt.transaction_index.as_u64());
+                s.append(&receipt.gas_used.as_u64());
+            }
+        });
+    }
+
+    fn rlp_decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 5 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Self {
+            state_root: hash_at(rlp, 0)?,
+            receipts_root: hash_at(rlp, 1)?,
+            gas_used: rlp.val_at(2)?,
+            logs_bloom: bytes_at(rlp, 3)?,
+            receipts: opt_at(rlp, 4, |item, idx| {
+                item.at(idx)?
+                    .iter()
+                    .map(|receipt| decode_receipt(&receipt))
+                    .collect()
+            })?,
+        })
+    }
+}
+
+fn decode_receipt(rlp: &Rlp) -> Result<Receipt, DecoderError> {
+    if rlp.item_count()? != 8 {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+    let mut logs = Vec::new();
+    for log in rlp.at(4)?.iter() {
+        let topics = log.at(1)?;
+        let mut log = Log {
+            address: address_at(&log, 0)?,
+            data: bytes_at(&log, 2)?,
+            ..Default::default()
+        };
+        for idx in 0..topics.item_count()? {
+            log.topics.push(hash_at(&topics, idx)?);
+        }
+        logs.push(log);
+    }
+    Ok(Receipt {
+        r#type: opt_at(rlp, 0, |item, idx| Ok(item.val_at::<u64>(idx)?.into()))?,
+        status: rlp.val_at::<u64>(1)?.into(),
+        cumulative_gas_used: rlp.val_at::<u64>(2)?.into(),
+        logs_bloom: bytes_at(rlp, 3)?,
+        logs,
+        transaction_hash: hash_at(rlp, 5)?,
+        transaction_index: rlp.val_at::<u64>(6)?.into(),
+        gas_used: rlp.val_at::<u64>(7)?.into(),
+
+        // not kept in the binary encoding
+        contract_address: None,
+        root: None,
+        block_hash: None,
+        block_number: None,
+    })
+}
+
+impl Default for PobData {
+    fn default() -> Self {
+        Self {
+            version: POB_VERSION,
+            chain_id: 0,
+            prev_state_root: SH256::default(),
+            block_hashes: BTreeMap::new(),
+            mpt_nodes: Vec::new(),
+            codes: Vec::new(),
+            withdrawals: None,
+            blob_versioned_hashes: Vec::new(),
+            blob_commitments: Vec::new(),
+            expected: None,
+        }
+    }
+}
+
+impl PobData {
+    fn legacy_version() -> u32 {
+        POB_VERSION_V1
+    }
+
+    // the number of the items in the binary encoding
+    fn rlp_list_len(&self) -> usize {
+        if self.version >= POB_VERSION_V4 {
+            11
+        } else if self.version >= POB_VERSION_V2 {
+            10
+        } else {
+            7
+        }
+    }
+
+    // the canonical order of mpt_nodes and codes: ascending by the raw bytes
+    fn canonical_sorted(list: &[HexBytes]) -> Vec<&HexBytes> {
+        let mut list: Vec<&HexBytes> = list.iter().collect();
+        list.sort_unstable();
+        list
+    }
+
+    // the pieces of the canonical encoding:
+    //  * v1: the sorted mpt_nodes
+    //  * v2: the sorted mpt_nodes || withdrawals_hash || blobs_hash
+    //  * since v3: chain_id(u64 be) || prev_state_root || nodes_hash || codes_hash || block_hashes_hash
+    //    || withdrawals_hash || blobs_hash
+    //    nodes_hash/codes_hash = keccak(keccak(item_0) || keccak(item_1) ...) in the canonical order
+    //    block_hashes_hash = keccak(number_0(u64 be) || hash_0 || ...) ascending by the number
+    //    withdrawals_hash = keccak(the withdrawals item of the binary encoding)
+    //    blobs_hash = keccak(rlp([blob_versioned_hashes, blob_commitments]))
+    fn canonical_parts(&self) -> Vec<Cow<[u8]>> {
+        let nodes = Self::canonical_sorted(&self.mpt_nodes);
+        if self.version < POB_VERSION_V3 {
+            let mut parts: Vec<Cow<[u8]>> = nodes
+                .into_iter()
+                .map(|node| Cow::Borrowed(node.as_bytes()))
+                .collect();
+            if self.version >= POB_VERSION_V2 {
+                parts.push(Cow::Owned(self.withdrawals_hash().to_vec()));
+                parts.push(Cow::Owned(self.blobs_hash().to_vec()));
+            }
+            return parts;
+        }
+
+        let list_hash = |list: Vec<&HexBytes>| {
+            crate::keccak_encode(|hash| {
+                for item in list {
+                    hash(&keccak_hash(item));
+                }
+            })
+        };
+        let nodes_hash = list_hash(nodes);
+        let codes_hash = list_hash(Self::canonical_sorted(&self.codes));
+        let block_hashes_hash = crate::keccak_encode(|hash| {
+            for (number, block_hash) in &self.block_hashes {
+                hash(&number.to_be_bytes());
+                hash(block_hash.0.as_bytes());
+            }
+        });
+        vec![
+            Cow::Owned(self.chain_id.to_be_bytes().to_vec()),
+            Cow::Borrowed(self.prev_state_root.0.as_bytes()),
+            Cow::Owned(nodes_hash.to_vec()),
+            Cow::Owned(codes_hash.to_vec()),
+            Cow::Owned(block_hashes_hash.to_vec()),
+            Cow::Owned(self.withdrawals_hash().to_vec()),
+            Cow::Owned(self.blobs_hash().to_vec()),
+        ]
+    }
+
+    fn withdrawals_hash(&self) -> [u8; 32] {
+        let mut s = RlpStream::new();
+        append_opt(&mut s, &self.withdrawals, |s, withdrawals| {
+            append_withdrawals(s, withdrawals)
+        });
+        keccak_hash(&s.out())
+    }
+
+    fn blobs_hash(&self) -> [u8; 32] {
+        let mut s = RlpStream::new_list(2);
+        s.begin_list(self.blob_versioned_hashes.len());
+        for hash in &self.blob_versioned_hashes {
+            s.append(&hash.0.as_bytes());
+        }
+        s.begin_list(self.blob_commitments.len());
+        for commitment in &self.blob_commitments {
+            s.append(&commitment.as_bytes());
+        }
+        keccak_hash(&s.out())
+    }
+
+    // the deterministic encoding committed by state_hash, verifiers in other
+    // languages can reproduce it from the fields without any local state.
+    pub fn canonical_encoding(&self) -> Vec<u8> {
+        self.canonical_parts().concat()
+    }
+
+    pub fn state_hash(&self) -> SH256 {
+        let parts = self.canonical_parts();
+        crate::keccak_encode(|hash| {
+            for part in &parts {
+                hash(&part[..]);
+            }
+        })
+        .into()
+    }
+
+    pub fn check_version(&self) -> Result<(), PobError> {
+        if self.version == 0 || self.version > POB_VERSION {
+            return Err(PobError::UnsupportedVersion {
+                version: self.version,
+                latest: POB_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    // versioned_hash = BLOB_COMMITMENT_VERSION_KZG ++ sha256(commitment)[1..]
+    pub fn kzg_to_versioned_hash(commitment: &[u8]) -> SH256 {
+        let mut hash = crate::sha256_sum(commitment);
+        hash[0] = BLOB_COMMITMENT_VERSION_KZG;
+        H256::from_slice(&hash[..]).into()
+    }
+}
+
+#[derive(Debug)]
+pub enum ProofError {
+    InvalidNode(String),
+    InvalidProof(String),
+    InvalidAccount(String),
+    InvalidStorage(String),
+}
+
+fn invalid_node(err: rlp::DecoderError) -> ProofError {
+    ProofError::InvalidNode(format!("{:?}", err))
+}
+
+// the reference to a child node, the nodes shorter than 32 bytes are
+// embedded in their parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MptChild {
+    Hash(SH256),
+    Inline(Vec<u8>),
+}
+
+// a decoded mpt node, the paths are in nibbles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MptNode {
+    Branch {
+        children: Vec<Option<MptChild>>,
+        value: Option<Vec<u8>>,
+    },
+    Extension {
+        path: Vec<u8>,
+        child: MptChild,
+    },
+    Leaf {
+        path: Vec<u8>,
+        value: Vec<u8>,
+    },
+}
+
+impl MptNode {
+    pub fn decode(data: &[u8]) -> Result<Self, ProofError> {
+        let node = Rlp::new(data);
+        match node.item_count().map_err(invalid_node)? {
+            17 => {
+                let mut children = Vec::with_capacity(16);
+                for idx in 0..16 {
+                    let child = node.at(idx).map_err(invalid_node)?;
+                    children.push(mpt::node_ref(&child).map_err(ProofError::InvalidNode)?);
+                }
+                let value = node.at(16).map_err(invalid_node)?;
+                let value = value.data().map_err(invalid_node)?;
+                Ok(MptNode::Branch {
+                    children,
+                    value: Some(value.to_vec()).filter(|value| !value.is_empty()),
+                })
+            }
+            2 => {
+                let path = node.at(0).map_err(invalid_node)?;
+                let path = path.data().map_err(invalid_node)?;
+                let (path, is_leaf) = mpt::decode_path(path).map_err(ProofError::InvalidNode)?;
+                let item = node.at(1).map_err(invalid_node)?;
+                if is_leaf {
+                    let value = item.data().map_err(invalid_node)?.to_vec();
+                    return Ok(MptNode::Leaf { path, value });
+                }
+                match mpt::node_ref(&item).map_err(ProofError::InvalidNode)? {
+                    Some(child) => Ok(MptNode::Extension { path, child }),
+                    None => Err(ProofError::InvalidNode("empty extension child".into())),
+                }
+            }
+            n => Err(ProofError::InvalidNode(format!(
+                "unexpected item count: {}",
+                n
+            ))),
+        }
+    }
+}
+
+// an optional field is a list of zero or one item
+fn append_opt<T>(s: &mut RlpStream, val: &Option<T>, f: impl Fn(&mut RlpStream, &T)) {
+    match val {
+        Some(val) => {
+            s.begin_list(1);
+            f(s, val);
+        }
+        None => {
+            s.begin_list(0);
+        }
+    }
+}
+
+fn opt_at<T>(
+    rlp: &Rlp,
+    idx: usize,
+    f: impl Fn(&Rlp, usize) -> Result<T, DecoderError>,
+) -> Result<Option<T>, DecoderError> {
+    let item = rlp.at(idx)?;
+    match item.item_count()? {
+        0 => Ok(None),
+        1 => Ok(Some(f(&item, 0)?)),
+        _ => Err(DecoderError::RlpIncorrectListLen),
+    }
+}
+
+fn hash_at(rlp: &Rlp, idx: usize) -> Result<SH256, DecoderError> {
+    let item = rlp.at(idx)?;
+    let data = item.data()?;
+    if data.len() != 32 {
+        return Err(DecoderError::RlpInvalidLength);
+    }
+    Ok(H256::from_slice(data).into())
+}
+
+fn address_at(rlp: &Rlp, idx: usize) -> Result<SH160, DecoderError> {
+    let item = rlp.at(idx)?;
+    let data = item.data()?;
+    if data.len() != 20 {
+        return Err(DecoderError::RlpInvalidLength);
+    }
+    Ok(H160::from_slice(data).into())
+}
+
+fn u256_at(rlp: &Rlp, idx: usize) -> Result<SU256, DecoderError> {
+    let item = rlp.at(idx)?;
+    let data = item.data()?;
+    if data.len() > 32 {
+        return Err(DecoderError::RlpIsTooBig);
+    }
+    Ok(U256::from_big_endian(data).into())
+}
+
+fn bytes_at(rlp: &Rlp, idx: usize) -> Result<HexBytes, DecoderError> {
+    Ok(rlp.val_at::<Vec<u8>>(idx)?.into())
+}
+
+// the header fields in the consensus order, the fields of the later forks are
+// optional.
+fn append_header(s: &mut RlpStream, header: &BlockHeader) {
+    s.begin_list(20);
+    s.append(&header.parent_hash.0.as_bytes());
+    s.append(&header.sha3_uncles.0.as_bytes());
+    s.append(&header.miner.0.as_bytes());
+    s.append(&header.state_root.0.as_bytes());
+    s.append(&header.transactions_root.0.as_bytes());
+    s.append(&header.receipts_root.0.as_bytes());
+    s.append(&header.logs_bloom.as_bytes());
+    s.append(&trimmed(header.difficulty.raw()));
+    s.append(&header.number.as_u64());
+    s.append(&header.gas_limit.as_u64());
+    s.append(&header.gas_used.as_u64());
+    s.append(&header.timestamp.as_u64());
+    s.append(&header.extra_data.as_bytes());
+    s.append(&header.mix_hash.0.as_bytes());
+    s.append(&header.nonce.as_bytes());
+    s.append(&trimmed(header.base_fee_per_gas.raw()));
+    append_opt(s, &header.withdrawals_root, |s, root| {
+        s.append(&root.0.as_bytes());
+    });
+    append_opt(s, &header.blob_gas_used, |s, gas| {
+        s.append(&gas.as_u64());
+    });
+    append_opt(s, &header.excess_blob_gas, |s, gas| {
+        s.append(&gas.as_u64());
+    });
+    append_opt(s, &header.parent_beacon_block_root, |s, root| {
+        s.append(&root.0.as_bytes());
+    });
+}
+
+fn decode_header(rlp: &Rlp) -> Result<BlockHeader, DecoderError> {
+    if rlp.item_count()? != 20 {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+    let u64_at = |idx: usize| -> Result<u64, DecoderError> { rlp.val_at(idx) };
+    Ok(BlockHeader {
+        parent_hash: hash_at(rlp, 0)?,
+        sha3_uncles: hash_at(rlp, 1)?,
+        miner: address_at(rlp, 2)?,
+        state_root: hash_at(rlp, 3)?,
+        transactions_root: hash_at(rlp, 4)?,
+        receipts_root: hash_at(rlp, 5)?,
+        logs_bloom: bytes_at(rlp, 6)?,
+        difficulty: u256_at(rlp, 7)?,
+        number: u64_at(8)?.into(),
+        gas_limit: u64_at(9)?.into(),
+        gas_used: u64_at(10)?.into(),
+        timestamp: u64_at(11)?.into(),
+        extra_data: bytes_at(rlp, 12)?,
+        mix_hash: hash_at(rlp, 13)?,
+        nonce: bytes_at(rlp, 14)?,
+        base_fee_per_gas: u256_at(rlp, 15)?,
+        withdrawals_root: opt_at(rlp, 16, hash_at)?,
+        blob_gas_used: opt_at(rlp, 17, |item, idx| Ok(item.val_at::<u64>(idx)?.into()))?,
+        excess_blob_gas: opt_at(rlp, 18, |item, idx| Ok(item.val_at::<u64>(idx)?.into()))?,
+        parent_beacon_block_root: opt_at(rlp, 19, hash_at)?,
+        ..Default::default()
+    })
+}
+
+// rlp([index, validator_index, address, amount]) of each withdrawal, the same
+// as the withdrawals trie.
+fn append_withdrawals(s: &mut RlpStream, withdrawals: &[Withdrawal]) {
+    s.begin_list(withdrawals.len());
+    for withdrawal in withdrawals {
+        s.begin_list(4);
+        s.append(&withdrawal.index.as_u64());
+        s.append(&withdrawal.validator_index.as_u64());
+        s.append(&withdrawal.address.0.as_bytes());
+        s.append(&withdrawal.amount.as_u64());
+    }
+}
+
+fn decode_withdrawals(rlp: &Rlp) -> Result<Vec<Withdrawal>, DecoderError> {
+    if !rlp.is_list() {
+        return Err(DecoderError::RlpExpectedToBeList);
+    }
+    let mut withdrawals = Vec::new();
+    for item in rlp.iter() {
+        if item.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        withdrawals.push(Withdrawal {
+            index: item.val_at::<u64>(0)?.into(),
+            validator_index: item.val_at::<u64>(1)?.into(),
+            address: address_at(&item, 2)?,
+            amount: item.val_at::<u64>(3)?.into(),
+        });
+    }
+    Ok(withdrawals)
+}
+
+// the account leaf of the state trie: rlp([nonce, balance, storage_root, code_hash])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofAccount {
+    pub nonce: u64,
+    pub balance: SU256,
+    pub storage_root: SH256,
+    pub code_hash: SH256,
+}
+
+impl ProofAccount {
+    pub fn decode(data: &[u8]) -> Result<Self, ProofError> {
+        let invalid = |err: rlp::DecoderError| ProofError::InvalidAccount(format!("{:?}", err));
+        let hash_at = |acc: &Rlp, idx: usize| -> Result<SH256, ProofError> {
+            let item = acc.at(idx).map_err(invalid)?;
+            let data = item.data().map_err(invalid)?;
+            if data.len() != 32 {
+                return Err(ProofError::InvalidAccount(format!(
+                    "invalid hash length: {}",
+                    data.len()
+                )));
+            }
+            Ok(H256::from_slice(data).into())
+        };
+        let acc = Rlp::new(data);
+        let balance = acc.at(1).map_err(invalid)?;
+        let balance = balance.data().map_err(invalid)?;
+        if balance.len() > 32 {
+            return Err(ProofError::InvalidAccount(format!(
+                "invalid balance length: {}",
+                balance.len()
+            )));
+        }
+        Ok(Self {
+            nonce: acc.val_at(0).map_err(invalid)?,
+            balance: U256::from_big_endian(balance).into(),
+            storage_root: hash_at(&acc, 2)?,
+            code_hash: hash_at(&acc, 3)?,
+        })
+    }
+}
+
+fn verify_proof(
+    root: &SH256,
+    key: &[u8],
+    proof: &[HexBytes],
+) -> Result<Option<Vec<u8>>, ProofError> {
+    let proof: Vec<Vec<u8>> = proof.iter().map(|node| node.as_bytes().to_vec()).collect();
+    mpt::verify_proof(*root, key, &proof).map_err(ProofError::InvalidProof)
+}
+
+// Verify the account proof of eth_getProof against the state root, returns
+// None if the proof shows the account doesn't exist.
+pub fn verify_account_proof(
+    state_root: &SH256,
+    address: &SH160,
+    proof: &[HexBytes],
+) -> Result<Option<ProofAccount>, ProofError> {
+    let key = keccak_hash(address.0.as_bytes());
+    match verify_proof(state_root, &key, proof)? {
+        Some(value) => Ok(Some(ProofAccount::decode(&value)?)),
+        None => Ok(None),
+    }
+}
+
+// Verify the storage proof of eth_getProof against the storage root of the
+// account, a missing slot is zero.
+pub fn verify_storage_proof(
+    storage_root: &SH256,
+    slot: &SH256,
+    proof: &[HexBytes],
+) -> Result<SU256, ProofError> {
+    let key = keccak_hash(slot.0.as_bytes());
+    let value = match verify_proof(storage_root, &key, proof)? {
+        Some(value) => value,
+        None => return Ok(SU256::zero()),
+    };
+    let value = Rlp::new(&value);
+    let value = value
+        .data()
+        .map_err(|err| ProofError::InvalidStorage(format!("{:?}", err)))?;
+    if value.len() > 32 {
+        return Err(ProofError::InvalidStorage(format!(
+            "invalid slot length: {}",
+            value.len()
+        )));
+    }
+    Ok(U256::from_big_endian(value).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pob_data_version() {
+        let legacy = br#"{"chain_id":1,"prev_state_root":"0x0000000000000000000000000000000000000000000000000000000000000000","block_hashes":{},"mpt_nodes":[],"codes":[]}"#;
+        let data: PobData = serde_json::from_slice(legacy).unwrap();
+        assert_eq!(data.version, POB_VERSION_V1);
+        assert!(data.check_version().is_ok());
+
+        let mut data = PobData::default();
+        data.version = POB_VERSION + 1;
+        let data: PobData = serde_json::from_slice(&serde_json::to_vec(&data).unwrap()).unwrap();
+        assert!(matches!(
+            data.check_version(),
+            Err(PobError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_canonical_encoding() {
+        let nodes: Vec<HexBytes> = vec![vec![3_u8, 4].into(), vec![1_u8, 2].into()];
+        let mut a = PobData::default();
+        a.mpt_nodes = nodes.clone();
+        let mut b = PobData::default();
+        b.mpt_nodes = nodes.into_iter().rev().collect();
+        assert_eq!(a.canonical_encoding(), b.canonical_encoding());
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        a.version = POB_VERSION_V1;
+        assert_eq!(a.canonical_encoding(), vec![1, 2, 3, 4]);
+        a.version = POB_VERSION_V2;
+        assert_eq!(a.canonical_encoding()[..4], [1, 2, 3, 4]);
+        assert_eq!(a.canonical_encoding().len(), 4 + 64);
+    }
+
+    #[test]
+    fn test_pob_data_fields() {
+        for version in [POB_VERSION_V2, POB_VERSION_V3, POB_VERSION_V4] {
+            let data = pob(version).data;
+            let fields = |data: &PobData| {
+                format!(
+                    "{:?}",
+                    (
+                        &data.withdrawals,
+                        &data.blob_versioned_hashes,
+                        &data.blob_commitments
+                    )
+                )
+            };
+            assert!(data.withdrawals.is_some());
+            assert_eq!(data.blob_versioned_hashes.len(), 1);
+            assert_eq!(data.blob_commitments.len(), 1);
+
+            let json: PobData =
+                serde_json::from_slice(&serde_json::to_vec(&data).unwrap()).unwrap();
+            assert_eq!(fields(&json), fields(&data));
+            assert_eq!(json.state_hash(), data.state_hash());
+            let bytes = Pob::<Block>::from_bytes(&pob(version).to_bytes()).unwrap();
+            assert_eq!(fields(&bytes.data), fields(&data));
+            assert_eq!(bytes.data.state_hash(), data.state_hash());
+
+            let hash = data.state_hash();
+            let mut changed = pob(version).data;
+            changed.withdrawals.as_mut().unwrap()[0].amount = 1_u64.into();
+            assert_ne!(changed.state_hash(), hash);
+            // no withdrawals before shanghai differs from an empty list
+            let mut changed = pob(version).data;
+            changed.withdrawals = Some(Vec::new());
+            let empty = changed.state_hash();
+            changed.withdrawals = None;
+            assert_ne!(changed.state_hash(), empty);
+            assert_ne!(empty, hash);
+            let mut changed = pob(version).data;
+            changed.blob_versioned_hashes[0] = H256::repeat_byte(0x01).into();
+            assert_ne!(changed.state_hash(), hash);
+            let mut changed = pob(version).data;
+            changed.blob_commitments[0] = vec![0xc1_u8; 48].into();
+            assert_ne!(changed.state_hash(), hash);
+        }
+    }
+
+    // a block with a tx and a withdrawal, the data carries the fields of the version
+    fn pob(version: u32) -> Pob {
+        let txs: BTreeMap<String, Transaction> =
+            serde_json::from_str(include_str!("testdata/block_builder.json")).unwrap();
+        let withdrawal = Withdrawal {
+            index: 1000_u64.into(),
+            validator_index: 42_u64.into(),
+            address: H160::repeat_byte(0x11).into(),
+            amount: 32_000_000_u64.into(),
+        };
+        let mut block = Block::default();
+        block.header.number = 17_100_000_u64.into();
+        block.header.parent_hash = H256::repeat_byte(0x99).into();
+        block.header.base_fee_per_gas = 25_000_000_000_u64.into();
+        block.header.withdrawals_root = Some(H256::repeat_byte(0x77).into());
+        block.header.excess_blob_gas = Some(131_072_u64.into());
+        block.transactions.push(txs["call"].clone());
+        block.withdrawals = Some(vec![withdrawal.clone()]);
+
+        let mut data = PobData {
+            version,
+            chain_id: 1,
+            prev_state_root: H256::repeat_byte(0x01).into(),
+            mpt_nodes: vec![vec![0xc0_u8].into()],
+            codes: vec![vec![0x60_u8, 0x00].into()],
+            ..Default::default()
+        };
+        data.block_hashes
+            .insert(17_099_999, block.header.parent_hash);
+        if version >= POB_VERSION_V2 {
+            let commitment = vec![0xc0_u8; 48];
+            data.withdrawals = Some(vec![withdrawal]);
+            data.blob_versioned_hashes
+                .push(PobData::kzg_to_versioned_hash(&commitment));
+            data.blob_commitments.push(commitment.into());
+        }
+        if version >= POB_VERSION_V4 {
+            let receipt = Receipt {
+                status: 1_u64.into(),
+                transaction_hash: H256::repeat_byte(0x55).into(),
+                transaction_index: 0_u64.into(),
+                r#type: Some(2_u64.into()),
+                gas_used: 21_000_u64.into(),
+                cumulative_gas_used: 21_000_u64.into(),
+                logs_bloom: vec![0_u8; 256].into(),
+                logs: vec![Log {
+                    address: H160::repeat_byte(0x11).into(),
+                    topics: vec![H256::repeat_byte(0x66).into()],
+                    data: vec![1_u8, 2, 3].into(),
+                    ..Default::default()
+                }],
+                contract_address: None,
+                root: None,
+                block_hash: None,
+                block_number: None,
+            };
+            data.expected = Some(PobExpected::from_header(&block.header, Some(vec![receipt])));
+        }
+        Pob::new(block, data)
+    }
+
+    fn assert_same(a: &Pob, b: &Pob) {
+        assert_eq!(format!("{:?}", a.data), format!("{:?}", b.data));
+        assert_eq!(
+            format!("{:?}", a.block.header),
+            format!("{:?}", b.block.header)
+        );
+        assert_eq!(
+            format!("{:?}", a.block.withdrawals),
+            format!("{:?}", b.block.withdrawals)
+        );
+        assert_eq!(a.block.transactions.len(), b.block.transactions.len());
+        assert_eq!(a.block.transactions[0].hash, b.block.transactions[0].hash);
+        assert_eq!(a.block_hash(), b.block_hash());
+    }
+
+    #[test]
+    fn test_pob_bytes() {
+        for version in [
+            POB_VERSION_V1,
+            POB_VERSION_V2,
+            POB_VERSION_V3,
+            POB_VERSION_V4,
+        ] {
+            let pob = pob(version);
+            let bytes = pob.to_bytes();
+            assert_eq!(bytes[0], POB_BYTES_RAW);
+            let decoded = Pob::<Block>::from_bytes(&bytes).unwrap();
+            assert_same(&pob, &decoded);
+            assert_eq!(decoded.to_bytes(), bytes);
+
+            #[cfg(feature = "zstd")]
+            {
+                let compressed = pob.to_compressed_bytes(3);
+                assert_eq!(compressed[0], POB_BYTES_ZSTD);
+                assert_same(&pob, &Pob::<Block>::from_bytes(&compressed).unwrap());
+            }
+
+            for len in 0..bytes.len() {
+                assert!(matches!(
+                    Pob::<Block>::from_bytes(&bytes[..len]),
+                    Err(PobError::Decode(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pob_bytes_garbage() {
+        let decode = |data: &[u8]| Pob::<Block>::from_bytes(data);
+        assert!(matches!(decode(&[0xff; 64]), Err(PobError::Decode(_))));
+        assert!(matches!(
+            decode(&[POB_BYTES_RAW, 0xde, 0xad, 0xbe, 0xef]),
+            Err(PobError::Decode(_))
+        ));
+        assert!(matches!(
+            decode(&[POB_BYTES_ZSTD, 0xde, 0xad, 0xbe, 0xef]),
+            Err(PobError::Decode(_))
+        ));
+
+        // the items of v4 behind the version of v2
+        let mut bytes = pob(POB_VERSION_V4).to_bytes();
+        let rlp = Rlp::new(&bytes[1..]);
+        let mut s = RlpStream::new_list(rlp.item_count().unwrap());
+        s.append(&POB_VERSION_V2);
+        for item in rlp.iter().skip(1) {
+            s.append_raw(item.as_raw(), 1);
+        }
+        bytes.truncate(1);
+        bytes.extend_from_slice(&s.out());
+        assert!(matches!(decode(&bytes), Err(PobError::Decode(_))));
+
+        let mut s = RlpStream::new_list(1);
+        s.append(&(POB_VERSION + 1));
+        let mut bytes = vec![POB_BYTES_RAW];
+        bytes.extend_from_slice(&s.out());
+        assert!(matches!(
+            decode(&bytes),
+            Err(PobError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_account_proof() {
+        let mut acc = RlpStream::new_list(4);
+        acc.append(&7_u64);
+        acc.append(&vec![1_u8, 0]);
+        acc.append(&mpt::EMPTY_ROOT.as_slice());
+        acc.append(&[0x11_u8; 32].as_slice());
+
+        let mut items = BTreeMap::new();
+        for idx in 0..8_u8 {
+            let address: SH160 = H160::repeat_byte(idx).into();
+            items.insert(
+                keccak_hash(address.0.as_bytes()).to_vec(),
+                acc.out().to_vec(),
+            );
+        }
+        let address: SH160 = H160::repeat_byte(3).into();
+        let (root, proof) = mpt::build_proof(&items, &keccak_hash(address.0.as_bytes()));
+        let proof: Vec<HexBytes> = proof.into_iter().map(HexBytes::from).collect();
+
+        let got = verify_account_proof(&root, &address, &proof)
+            .unwrap()
+            .unwrap();
+        assert_eq!(got.nonce, 7);
+        assert_eq!(got.balance, SU256::from(256_u64));
+        assert_eq!(got.storage_root.0 .0, mpt::EMPTY_ROOT);
+
+        // the proof of a missing key proves its absence
+        let missing: SH160 = H160::repeat_byte(0xff).into();
+        let (_, absence) = mpt::build_proof(&items, &keccak_hash(missing.0.as_bytes()));
+        let absence: Vec<HexBytes> = absence.into_iter().map(HexBytes::from).collect();
+        assert!(matches!(
+            verify_account_proof(&root, &missing, &absence),
+            Ok(None)
+        ));
+        assert!(matches!(
+            verify_account_proof(&root, &address, &proof[1..]),
+            Err(ProofError::InvalidProof(_))
+        ));
+        assert!(matches!(
+            verify_storage_proof(&mpt::EMPTY_ROOT.into(), &SH256::default(), &[]),
+            Ok(value) if value == SU256::zero()
+        ));
+    }
 }
@@ -0,0 +1,287 @@
+use std::prelude::v1::*;
+
+use eth_types::{Block, HexBytes, Withdrawal, SH256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{keccak_hash, Pob, PobBlock, PobData, PobError, PobExpected};
+
+// Consecutive blocks share most of their witness, the batch keeps one pool of
+// mpt nodes and codes and every block refers to it by index. A multi chain
+// batch, e.g. an L2 and its L1 anchor blocks, sets the chain id of the blocks
+// which are not on the batch chain.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PobBatch<B = Block> {
+    pub chain_id: u64,
+    pub mpt_nodes: Vec<HexBytes>,
+    pub codes: Vec<HexBytes>,
+    pub blocks: Vec<PobBatchItem<B>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PobBatchItem<B> {
+    pub version: u32,
+    // None on the batch chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    pub block: B,
+    pub prev_state_root: SH256,
+    pub block_hashes: BTreeMap<u64, SH256>,
+    pub mpt_nodes: Vec<u32>,
+    pub codes: Vec<u32>,
+    #[serde(default)]
+    pub withdrawals: Option<Vec<Withdrawal>>,
+    #[serde(default)]
+    pub blob_versioned_hashes: Vec<SH256>,
+    #[serde(default)]
+    pub blob_commitments: Vec<HexBytes>,
+    #[serde(default)]
+    pub expected: Option<PobExpected>,
+}
+
+#[derive(Default)]
+struct Pool {
+    items: Vec<HexBytes>,
+    index: BTreeMap<SH256, u32>,
+}
+
+impl Pool {
+    fn add(&mut self, item: HexBytes) -> u32 {
+        let hash: SH256 = keccak_hash(&item).into();
+        let items = &mut self.items;
+        *self.index.entry(hash).or_insert_with(|| {
+            items.push(item);
+            (items.len() - 1) as u32
+        })
+    }
+}
+
+impl<B: PobBlock> PobBatch<B> {
+    pub fn new(pobs: Vec<Pob<B>>) -> Result<Self, PobError> {
+        Self::build(pobs, false)
+    }
+
+    // the blocks may come from different chains, they are consecutive per
+    // chain and the batch chain is the one of the first block.
+    pub fn new_multi_chain(pobs: Vec<Pob<B>>) -> Result<Self, PobError> {
+        Self::build(pobs, true)
+    }
+
+    fn build(pobs: Vec<Pob<B>>, multi_chain: bool) -> Result<Self, PobError> {
+        let chain_id = match pobs.first() {
+            Some(pob) => pob.data.chain_id,
+            None => return Err(PobError::EmptyBatch),
+        };
+
+        let mut nodes = Pool::default();
+        let mut codes = Pool::default();
+        let mut blocks = Vec::with_capacity(pobs.len());
+        // the number and the hash of the last block per chain
+        let mut prevs: BTreeMap<u64, (u64, SH256)> = BTreeMap::new();
+        for (idx, pob) in pobs.into_iter().enumerate() {
+            pob.data.check_version()?;
+            if pob.data.chain_id != chain_id && !multi_chain {
+                return Err(PobError::InconsistentChainId {
+                    idx,
+                    expect: chain_id,
+                    got: pob.data.chain_id,
+                });
+            }
+            let number = pob.block.number();
+            let prev = prevs.insert(pob.data.chain_id, (number, pob.block_hash()));
+            if let Some((prev_number, prev_hash)) = prev {
+                if number != prev_number + 1 {
+                    return Err(PobError::NonConsecutiveBlock {
+                        idx,
+                        expect: prev_number + 1,
+                        got: number,
+                    });
+                }
+                // a block of another fork with the same number
+                let parent_hash = pob.block.parent_hash();
+                if parent_hash != prev_hash {
+                    return Err(PobError::UnlinkedBlock {
+                        idx,
+                        expect: prev_hash,
+                        got: parent_hash,
+                    });
+                }
+            }
+
+            let data = pob.data;
+            blocks.push(PobBatchItem {
+                version: data.version,
+                chain_id: match data.chain_id == chain_id {
+                    true => None,
+                    false => Some(data.chain_id),
+                },
+                block: pob.block,
+                prev_state_root: data.prev_state_root,
+                block_hashes: data.block_hashes,
+                mpt_nodes: data.mpt_nodes.into_iter().map(|n| nodes.add(n)).collect(),
+                codes: data.codes.into_iter().map(|n| codes.add(n)).collect(),
+                withdrawals: data.withdrawals,
+                blob_versioned_hashes: data.blob_versioned_hashes,
+                blob_commitments: data.blob_commitments,
+                expected: data.expected,
+            });
+        }
+
+        Ok(Self {
+            chain_id,
+            mpt_nodes: nodes.items,
+            codes: codes.items,
+            blocks,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_multi_chain(&self) -> bool {
+        self.blocks.iter().any(|item| item.chain_id.is_some())
+    }
+
+    pub fn into_pobs(self) -> Result<Vec<Pob<B>>, PobError> {
+        let mut out = Vec::with_capacity(self.blocks.len());
+        for item in self.blocks {
+            let data = PobData {
+                version: item.version,
+                chain_id: item.chain_id.unwrap_or(self.chain_id),
+                prev_state_root: item.prev_state_root,
+                block_hashes: item.block_hashes,
+                mpt_nodes: Self::resolve(&self.mpt_nodes, &item.mpt_nodes)?,
+                codes: Self::resolve(&self.codes, &item.codes)?,
+                withdrawals: item.withdrawals,
+                blob_versioned_hashes: item.blob_versioned_hashes,
+                blob_commitments: item.blob_commitments,
+                expected: item.expected,
+            };
+            out.push(Pob::new(item.block, data));
+        }
+        Ok(out)
+    }
+
+    fn resolve(pool: &[HexBytes], refs: &[u32]) -> Result<Vec<HexBytes>, PobError> {
+        let mut out = Vec::with_capacity(refs.len());
+        for idx in refs {
+            match pool.get(*idx as usize) {
+                Some(item) => out.push(item.clone()),
+                None => {
+                    return Err(PobError::Decode(format!(
+                        "batch reference out of range: {}/{}",
+                        idx,
+                        pool.len()
+                    )))
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::H256;
+
+    // every block shares the first node and has one of its own
+    fn pob(chain_id: u64, number: u64) -> Pob<Block> {
+        let mut block = Block::default();
+        block.header.number = number.into();
+        let data = PobData {
+            chain_id,
+            mpt_nodes: vec![vec![0xc0].into(), number.to_be_bytes().to_vec().into()],
+            codes: vec![vec![0x60, 0x00].into()],
+            ..Default::default()
+        };
+        Pob::new(block, data)
+    }
+
+    // the parent hash of every block is the hash of the previous one on its chain
+    fn link(mut pobs: Vec<Pob<Block>>) -> Vec<Pob<Block>> {
+        let mut hashes = BTreeMap::new();
+        for pob in &mut pobs {
+            if let Some(hash) = hashes.get(&pob.data.chain_id) {
+                pob.block.header.parent_hash = *hash;
+            }
+            hashes.insert(pob.data.chain_id, pob.block_hash());
+        }
+        pobs
+    }
+
+    #[test]
+    fn test_pob_batch() {
+        let mut pobs = link(vec![pob(1, 10), pob(1, 11), pob(1, 12)]);
+        let hashes: Vec<SH256> = pobs.iter_mut().map(|pob| pob.state_hash()).collect();
+        let batch = PobBatch::new(pobs).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.mpt_nodes.len(), 4);
+        assert_eq!(batch.codes.len(), 1);
+        assert!(!batch.is_multi_chain());
+        let mut pobs = batch.into_pobs().unwrap();
+        let restored: Vec<SH256> = pobs.iter_mut().map(|pob| pob.state_hash()).collect();
+        assert_eq!(restored, hashes);
+
+        assert!(matches!(
+            PobBatch::<Block>::new(Vec::new()),
+            Err(PobError::EmptyBatch)
+        ));
+        assert!(matches!(
+            PobBatch::new(link(vec![pob(1, 10), pob(1, 12)])),
+            Err(PobError::NonConsecutiveBlock {
+                idx: 1,
+                expect: 11,
+                got: 12
+            })
+        ));
+        assert!(matches!(
+            PobBatch::new(vec![pob(1, 10), pob(2, 11)]),
+            Err(PobError::InconsistentChainId { idx: 1, .. })
+        ));
+
+        // the block 11 of another fork
+        let mut pobs = link(vec![pob(1, 10), pob(1, 11)]);
+        let expect = pobs[0].block_hash();
+        pobs[1].block.header.parent_hash = H256::repeat_byte(0x99).into();
+        assert!(matches!(
+            PobBatch::new(pobs),
+            Err(PobError::UnlinkedBlock { idx: 1, expect: hash, .. }) if hash == expect
+        ));
+
+        let mut tampered = PobBatch::new(vec![pob(1, 10)]).unwrap();
+        tampered.blocks[0].mpt_nodes.push(7);
+        assert!(tampered.into_pobs().is_err());
+    }
+
+    #[test]
+    fn test_pob_batch_multi_chain() {
+        // an L2 with its L1 anchor blocks in between
+        let pobs = link(vec![pob(10, 100), pob(1, 5), pob(10, 101), pob(1, 6)]);
+        let batch = PobBatch::new_multi_chain(pobs).unwrap();
+        assert!(batch.is_multi_chain());
+        assert_eq!(batch.chain_id, 10);
+        assert_eq!(batch.blocks[1].chain_id, Some(1));
+        let chain_ids: Vec<u64> = batch
+            .into_pobs()
+            .unwrap()
+            .iter()
+            .map(|pob| pob.data.chain_id)
+            .collect();
+        assert_eq!(chain_ids, vec![10, 1, 10, 1]);
+
+        // the blocks are linked per chain
+        assert!(matches!(
+            PobBatch::new_multi_chain(link(vec![pob(10, 100), pob(1, 5), pob(1, 7)])),
+            Err(PobError::NonConsecutiveBlock { idx: 2, .. })
+        ));
+        // the L1 block doesn't follow the L2 block before it
+        let mut pobs = link(vec![pob(10, 100), pob(1, 5), pob(10, 101), pob(1, 6)]);
+        pobs[3].block.header.parent_hash = pobs[2].block_hash();
+        assert!(matches!(
+            PobBatch::new_multi_chain(pobs),
+            Err(PobError::UnlinkedBlock { idx: 3, .. })
+        ));
+    }
+}
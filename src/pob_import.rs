@@ -0,0 +1,110 @@
+use std::prelude::v1::*;
+
+use eth_types::{BlockHeader, HexBytes, H256, SH256};
+use rlp::Rlp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{keccak_hash, PobData, PobError};
+
+// The header in the witness, geth returns the json header while reth and the
+// stateless witness format carry the rlp encoded header.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum WitnessHeader {
+    Json(BlockHeader),
+    Rlp(HexBytes),
+}
+
+impl WitnessHeader {
+    // returns (number, hash, state_root)
+    fn summary(&self) -> Result<(u64, SH256, SH256), PobError> {
+        match self {
+            WitnessHeader::Json(header) => {
+                Ok((header.number.as_u64(), header.hash(), header.state_root))
+            }
+            WitnessHeader::Rlp(data) => {
+                let rlp_err = |err: rlp::DecoderError| {
+                    PobError::Decode(format!("invalid witness header: {:?}", err))
+                };
+                let header = Rlp::new(data);
+                let state_root: Vec<u8> = header.val_at(3).map_err(rlp_err)?;
+                if state_root.len() != 32 {
+                    return Err(PobError::Decode(format!(
+                        "invalid state root length: {}",
+                        state_root.len()
+                    )));
+                }
+                let number: u64 = header.val_at(8).map_err(rlp_err)?;
+                Ok((
+                    number,
+                    keccak_hash(data).into(),
+                    H256::from_slice(&state_root).into(),
+                ))
+            }
+        }
+    }
+}
+
+// The output of debug_executionWitness
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DebugExecutionWitness {
+    #[serde(default)]
+    pub headers: Vec<WitnessHeader>,
+    #[serde(default)]
+    pub codes: Vec<HexBytes>,
+    #[serde(default)]
+    pub state: Vec<HexBytes>,
+    #[serde(default)]
+    pub keys: Vec<HexBytes>,
+}
+
+impl DebugExecutionWitness {
+    // the stateless witness: rlp([headers, codes, state])
+    pub fn from_rlp(data: &[u8]) -> Result<Self, PobError> {
+        let rlp_err = |err: rlp::DecoderError| PobError::Decode(format!("{:?}", err));
+        let rlp = Rlp::new(data);
+        let mut headers = Vec::new();
+        for header in rlp.at(0).map_err(rlp_err)?.iter() {
+            headers.push(WitnessHeader::Rlp(header.as_raw().to_vec().into()));
+        }
+        let to_bytes = |list: Vec<Vec<u8>>| list.into_iter().map(HexBytes::from).collect();
+        Ok(Self {
+            headers,
+            codes: to_bytes(rlp.list_at(1).map_err(rlp_err)?),
+            state: to_bytes(rlp.list_at(2).map_err(rlp_err)?),
+            keys: Vec::new(),
+        })
+    }
+}
+
+impl PobData {
+    // the parent header(the one with the highest number) provides the prev_state_root
+    pub fn from_execution_witness(
+        chain_id: u64,
+        witness: DebugExecutionWitness,
+    ) -> Result<PobData, PobError> {
+        let mut block_hashes = BTreeMap::new();
+        let mut parent: Option<(u64, SH256)> = None;
+        for header in &witness.headers {
+            let (number, hash, state_root) = header.summary()?;
+            block_hashes.insert(number, hash);
+            if parent.map(|(n, _)| number > n).unwrap_or(true) {
+                parent = Some((number, state_root));
+            }
+        }
+        let prev_state_root = match parent {
+            Some((_, state_root)) => state_root,
+            None => return Err(PobError::Decode("missing parent header in witness".into())),
+        };
+
+        Ok(PobData {
+            chain_id,
+            prev_state_root,
+            block_hashes,
+            mpt_nodes: witness.state,
+            codes: witness.codes,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,457 @@
+use std::prelude::v1::*;
+
+use eth_types::{FetchStateResult, HexBytes, H256, SH160, SH256, SU256, U256};
+use rlp::{Rlp, RlpStream};
+use statedb::{MissingState, StateDB};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::mpt::TrieNode;
+use crate::{keccak_hash, PobData, ProofAccount};
+
+// keccak("")
+const EMPTY_CODE_HASH: [u8; 32] = [
+    0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7, 0x03, 0xc0,
+    0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04, 0x5d, 0x85, 0xa4, 0x70,
+];
+
+fn state_err(msg: String) -> statedb::Error {
+    statedb::Error::WithKey(msg)
+}
+
+pub(crate) fn trimmed(val: &U256) -> Vec<u8> {
+    let mut buf = [0_u8; 32];
+    val.to_big_endian(&mut buf);
+    let start = buf.iter().position(|b| *b != 0).unwrap_or(32);
+    buf[start..].to_vec()
+}
+
+#[derive(Debug, Clone)]
+struct PobAccount {
+    exists: bool,
+    nonce: u64,
+    balance: SU256,
+    code_hash: SH256,
+    storage: TrieNode,
+    dirty: bool,
+}
+
+impl PobAccount {
+    fn empty() -> Self {
+        Self {
+            exists: false,
+            nonce: 0,
+            balance: SU256::zero(),
+            code_hash: H256::from(EMPTY_CODE_HASH).into(),
+            storage: TrieNode::Empty,
+            dirty: false,
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, String> {
+        let acc = ProofAccount::decode(data).map_err(|err| format!("{:?}", err))?;
+        Ok(Self {
+            exists: true,
+            nonce: acc.nonce,
+            balance: acc.balance,
+            code_hash: acc.code_hash,
+            storage: TrieNode::from_root(acc.storage_root),
+            dirty: false,
+        })
+    }
+
+    fn encode(&self, storage_root: &SH256) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&self.nonce);
+        stream.append(&trimmed(self.balance.raw()));
+        stream.append(&storage_root.0.as_bytes());
+        stream.append(&self.code_hash.0.as_bytes());
+        stream.out().to_vec()
+    }
+}
+
+// A StateDB over the witness of a Pob, the state is read from the mpt nodes
+// and the codes of the PobData and nothing is fetched remotely. Reading a
+// path outside of the witness is an error, the Pob is incomplete then.
+#[derive(Debug, Clone)]
+pub struct PobStateDB {
+    nodes: BTreeMap<SH256, Vec<u8>>,
+    codes: BTreeMap<SH256, Arc<HexBytes>>,
+    trie: TrieNode,
+    accounts: BTreeMap<SH160, PobAccount>,
+}
+
+impl PobStateDB {
+    pub fn new(data: &PobData) -> Self {
+        let nodes = data
+            .mpt_nodes
+            .iter()
+            .map(|node| (keccak_hash(node).into(), node.to_vec()))
+            .collect();
+        let codes = data
+            .codes
+            .iter()
+            .map(|code| (keccak_hash(code).into(), Arc::new(code.clone())))
+            .collect();
+        Self {
+            nodes,
+            codes,
+            trie: TrieNode::from_root(data.prev_state_root),
+            accounts: BTreeMap::new(),
+        }
+    }
+
+    fn load(&self, address: &SH160) -> Result<PobAccount, String> {
+        let key = keccak_hash(address.0.as_bytes());
+        match self.trie.get(&self.nodes, &key)? {
+            Some(data) => PobAccount::decode(&data),
+            None => Ok(PobAccount::empty()),
+        }
+    }
+
+    fn account(&mut self, address: &SH160) -> Result<&mut PobAccount, statedb::Error> {
+        if !self.accounts.contains_key(address) {
+            let account = self.load(address).map_err(state_err)?;
+            self.accounts.insert(*address, account);
+        }
+        Ok(self.accounts.get_mut(address).unwrap())
+    }
+
+    fn modify<F>(&mut self, address: &SH160, f: F) -> Result<(), statedb::Error>
+    where
+        F: FnOnce(&mut PobAccount) -> Result<(), statedb::Error>,
+    {
+        let account = self.account(address)?;
+        f(account)?;
+        account.exists = true;
+        account.dirty = true;
+        Ok(())
+    }
+}
+
+impl StateDB for PobStateDB {
+    fn try_get_nonce(&self, address: &SH160) -> Option<u64> {
+        match self.accounts.get(address) {
+            Some(account) => Some(account.nonce),
+            None => self.load(address).ok().map(|account| account.nonce),
+        }
+    }
+
+    fn get_nonce(&mut self, address: &SH160) -> Result<u64, statedb::Error> {
+        Ok(self.account(address)?.nonce)
+    }
+
+    fn get_balance(&mut self, address: &SH160) -> Result<SU256, statedb::Error> {
+        Ok(self.account(address)?.balance)
+    }
+
+    fn get_account_basic(&mut self, address: &SH160) -> Result<(SU256, u64), statedb::Error> {
+        let account = self.account(address)?;
+        Ok((account.balance, account.nonce))
+    }
+
+    fn get_code(&mut self, address: &SH160) -> Result<Arc<HexBytes>, statedb::Error> {
+        let code_hash = self.account(address)?.code_hash;
+        if code_hash.0 .0 == EMPTY_CODE_HASH {
+            return Ok(Arc::new(HexBytes::from(Vec::new())));
+        }
+        match self.codes.get(&code_hash) {
+            Some(code) => Ok(code.clone()),
+            None => Err(state_err(format!("missing code: {:?}", code_hash))),
+        }
+    }
+
+    fn get_state(&mut self, address: &SH160, index: &SH256) -> Result<SH256, statedb::Error> {
+        self.account(address)?;
+        let key = keccak_hash(index.0.as_bytes());
+        let value = self.accounts[address]
+            .storage
+            .get(&self.nodes, &key)
+            .map_err(state_err)?;
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(SH256::default()),
+        };
+        let data = Rlp::new(&value)
+            .data()
+            .map_err(|err| state_err(format!("{:?}", err)))?
+            .to_vec();
+        if data.len() > 32 {
+            return Err(state_err(format!("invalid slot length: {}", data.len())));
+        }
+        let mut buf = [0_u8; 32];
+        buf[32 - data.len()..].copy_from_slice(&data);
+        Ok(H256::from(buf).into())
+    }
+
+    fn exist(&mut self, address: &SH160) -> Result<bool, statedb::Error> {
+        Ok(self.account(address)?.exists)
+    }
+
+    fn set_balance(&mut self, address: &SH160, val: SU256) -> Result<(), statedb::Error> {
+        self.modify(address, |account| {
+            account.balance = val;
+            Ok(())
+        })
+    }
+
+    fn add_balance(&mut self, address: &SH160, val: &SU256) -> Result<(), statedb::Error> {
+        self.modify(address, |account| {
+            account.balance = account.balance + val.clone();
+            Ok(())
+        })
+    }
+
+    fn sub_balance(&mut self, address: &SH160, val: &SU256) -> Result<(), statedb::Error> {
+        self.modify(address, |account| {
+            if &account.balance < val {
+                return Err(state_err(format!(
+                    "insufficient balance: {:?}, have {}, want {}",
+                    address, account.balance, val
+                )));
+            }
+            account.balance = account.balance - val;
+            Ok(())
+        })
+    }
+
+    fn set_nonce(&mut self, address: &SH160, val: SU256) -> Result<(), statedb::Error> {
+        self.modify(address, |account| {
+            account.nonce = val.as_u64();
+            Ok(())
+        })
+    }
+
+    fn set_code(&mut self, address: &SH160, code: Vec<u8>) -> Result<(), statedb::Error> {
+        let code_hash: SH256 = keccak_hash(&code).into();
+        self.codes
+            .entry(code_hash)
+            .or_insert_with(|| Arc::new(code.into()));
+        self.modify(address, |account| {
+            account.code_hash = code_hash;
+            Ok(())
+        })
+    }
+
+    fn set_state(
+        &mut self,
+        address: &SH160,
+        index: &SH256,
+        value: SH256,
+    ) -> Result<(), statedb::Error> {
+        self.account(address)?;
+        let key = keccak_hash(index.0.as_bytes());
+        let nodes = &self.nodes;
+        let account = self.accounts.get_mut(address).unwrap();
+        let storage = core::mem::replace(&mut account.storage, TrieNode::Empty);
+        let value = trimmed(&U256::from_big_endian(value.0.as_bytes()));
+        account.storage = match value.is_empty() {
+            true => storage.delete(nodes, &key),
+            false => storage.insert(nodes, &key, rlp::encode(&value).to_vec()),
+        }
+        .map_err(state_err)?;
+        account.exists = true;
+        account.dirty = true;
+        Ok(())
+    }
+
+    fn suicide(&mut self, address: &SH160) -> Result<(), statedb::Error> {
+        let mut account = PobAccount::empty();
+        account.dirty = true;
+        self.accounts.insert(*address, account);
+        Ok(())
+    }
+
+    // the proofs are merged into the node pool, the witness already carries
+    // the codes.
+    fn apply_states(&mut self, result: Vec<FetchStateResult>) -> Result<(), statedb::Error> {
+        for state in result {
+            if let Some(acc) = state.acc {
+                let storage_nodes = acc.storage_proof.into_iter().flat_map(|s| s.proof);
+                for node in acc.account_proof.into_iter().chain(storage_nodes) {
+                    self.nodes.insert(keccak_hash(&node).into(), node.to_vec());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_missing_state(
+        &mut self,
+        address: &SH160,
+        storages: &[SH256],
+    ) -> Result<MissingState, statedb::Error> {
+        let account = match self.load(address) {
+            Ok(account) => account,
+            Err(_) => {
+                return Ok(MissingState {
+                    account: true,
+                    code: true,
+                    storages: storages.to_vec(),
+                })
+            }
+        };
+        let code = account.code_hash.0 .0 != EMPTY_CODE_HASH
+            && !self.codes.contains_key(&account.code_hash);
+        let storages = storages
+            .iter()
+            .filter(|index| {
+                let key = keccak_hash(index.0.as_bytes());
+                account.storage.get(&self.nodes, &key).is_err()
+            })
+            .cloned()
+            .collect();
+        Ok(MissingState {
+            account: false,
+            code,
+            storages,
+        })
+    }
+
+    fn flush(&mut self) -> Result<SH256, statedb::Error> {
+        let Self {
+            nodes,
+            trie,
+            accounts,
+            ..
+        } = self;
+        // the trie and the dirty flags are kept until every account is in, a
+        // failed flush leaves the state as it was
+        let mut root = trie.clone();
+        for (address, account) in accounts.iter_mut() {
+            if !account.dirty {
+                continue;
+            }
+            let key = keccak_hash(address.0.as_bytes());
+            root = match account.exists {
+                true => {
+                    let storage_root = account.storage.commit(nodes);
+                    account.storage = TrieNode::from_root(storage_root);
+                    root.insert(nodes, &key, account.encode(&storage_root))
+                }
+                false => root.delete(nodes, &key),
+            }
+            .map_err(state_err)?;
+        }
+        let state_root = root.commit(nodes);
+        *trie = TrieNode::from_root(state_root);
+        for account in accounts.values_mut() {
+            account.dirty = false;
+        }
+        Ok(state_root)
+    }
+
+    fn revert(&mut self, root: SH256) {
+        self.trie = TrieNode::from_root(root);
+        self.accounts.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpt;
+    use eth_types::H160;
+    use std::collections::BTreeSet;
+
+    fn hash(val: &str) -> SH256 {
+        serde_json::from_str(&format!("\"{}\"", val)).unwrap()
+    }
+
+    fn ether(n: u64) -> SU256 {
+        SU256::from(n) * SU256::from(1_000_000_000_000_000_000_u64)
+    }
+
+    fn account(nonce: u64, balance: SU256) -> Vec<u8> {
+        let account = PobAccount {
+            exists: true,
+            nonce,
+            balance,
+            ..PobAccount::empty()
+        };
+        account.encode(&keccak_hash(&rlp::NULL_RLP).into())
+    }
+
+    // the witness of `proved` in the account trie of `accounts`
+    fn pob_data(accounts: &[(SH160, Vec<u8>)], proved: &[SH160]) -> PobData {
+        let items = accounts
+            .iter()
+            .map(|(address, account)| (keccak_hash(address.0.as_bytes()).to_vec(), account.clone()))
+            .collect();
+        let mut data = PobData::default();
+        let mut nodes = BTreeSet::new();
+        for address in proved {
+            let (root, proof) = mpt::build_proof(&items, &keccak_hash(address.0.as_bytes()));
+            data.prev_state_root = root;
+            nodes.extend(proof);
+        }
+        data.mpt_nodes = nodes.into_iter().map(HexBytes::from).collect();
+        data
+    }
+
+    #[test]
+    fn test_pob_state() {
+        let a: SH160 = H160::repeat_byte(0x11).into();
+        let b: SH160 = H160::repeat_byte(0x22).into();
+        let slot: SH256 = H256::from_low_u64_be(1).into();
+        let prev_root = hash("0xb3e58fdbabd36dfe1519fdc910e208791c4d3fd0153ee4b650be5112ddcf41c6");
+        let data = pob_data(&[(a, account(1, ether(1)))], &[a, b]);
+        assert_eq!(data.prev_state_root, prev_root);
+
+        let mut db = PobStateDB::new(&data);
+        assert_eq!(db.get_account_basic(&a).unwrap(), (ether(1), 1));
+        assert!(db.exist(&a).unwrap());
+        assert!(!db.exist(&b).unwrap());
+        assert_eq!(db.get_state(&a, &slot).unwrap(), SH256::default());
+        assert_eq!(db.flush().unwrap(), prev_root);
+
+        // the withdrawals of src/testdata/regression/17100000.pob
+        db.add_balance(
+            &a,
+            &(SU256::from(32_000_000_u64) * SU256::from(1_000_000_000_u64)),
+        )
+        .unwrap();
+        db.add_balance(&b, &ether(1)).unwrap();
+        let root = hash("0x2305f34249837c55b490c322c6ed8404a87d40c43daeadb02cf512f63307baf9");
+        assert_eq!(db.flush().unwrap(), root);
+
+        db.set_state(&a, &slot, H256::from_low_u64_be(0x2a).into())
+            .unwrap();
+        db.suicide(&b).unwrap();
+        let with_slot = hash("0x6161756a8db04aff0f550ace331bad32094e867a56651b69ad35c4f381dc5215");
+        assert_eq!(db.flush().unwrap(), with_slot);
+        assert_eq!(
+            db.get_state(&a, &slot).unwrap(),
+            H256::from_low_u64_be(0x2a).into()
+        );
+        db.set_state(&a, &slot, SH256::default()).unwrap();
+        let without_slot =
+            hash("0xb3f7f85edb061e6e93859366cb6537e438490ec4f0fdef2924f725b34945d7bd");
+        assert_eq!(db.flush().unwrap(), without_slot);
+
+        db.revert(prev_root);
+        assert_eq!(db.get_balance(&a).unwrap(), ether(1));
+        assert!(!db.exist(&b).unwrap());
+        assert_eq!(db.flush().unwrap(), prev_root);
+        db.revert(with_slot);
+        assert_eq!(
+            db.get_state(&a, &slot).unwrap(),
+            H256::from_low_u64_be(0x2a).into()
+        );
+    }
+
+    #[test]
+    fn test_pob_state_flush_fail() {
+        let a: SH160 = H160::repeat_byte(0x11).into();
+        let b: SH160 = H160::repeat_byte(0x22).into();
+        let accounts = [(a, account(1, ether(1))), (b, account(0, ether(1)))];
+        // deleting `a` collapses the root branch into `b`, which is not in
+        // the witness
+        let mut db = PobStateDB::new(&pob_data(&accounts, &[a]));
+        db.suicide(&a).unwrap();
+        assert!(db.flush().is_err());
+        // the trie and the deletion are kept
+        assert!(db.flush().is_err());
+        assert!(db.exist(&b).is_err());
+    }
+}
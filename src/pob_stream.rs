@@ -0,0 +1,196 @@
+use std::prelude::v1::*;
+
+use core::marker::PhantomData;
+use eth_types::HexBytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Pob, PobBlock, PobError};
+
+// Stream framing: a list of records `[tag: u8][len: u32 be][payload]`.
+// The header is the json of the Pob without mpt_nodes and codes, which then
+// follow one record per item so they can be inserted before the full
+// payload arrives.
+const TAG_HEADER: u8 = 1;
+const TAG_MPT_NODE: u8 = 2;
+const TAG_CODE: u8 = 3;
+const TAG_END: u8 = 4;
+
+const RECORD_HEADER_LEN: usize = 5;
+const MAX_RECORD_LEN: usize = 256 << 20;
+
+#[derive(Debug)]
+pub enum PobStreamItem<B> {
+    Header(Pob<B>),
+    MptNode(HexBytes),
+    Code(HexBytes),
+    End,
+}
+
+fn write_record(out: &mut Vec<u8>, tag: u8, payload: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+impl<B> Pob<B>
+where
+    B: PobBlock + Serialize + DeserializeOwned + Clone,
+{
+    pub fn to_stream_bytes(&self) -> Vec<u8> {
+        let mut header = Pob::new(self.block.clone(), Default::default());
+        header.data.version = self.data.version;
+        header.data.chain_id = self.data.chain_id;
+        header.data.prev_state_root = self.data.prev_state_root;
+        header.data.block_hashes = self.data.block_hashes.clone();
+        header.data.withdrawals = self.data.withdrawals.clone();
+        header.data.blob_versioned_hashes = self.data.blob_versioned_hashes.clone();
+        header.data.blob_commitments = self.data.blob_commitments.clone();
+        header.data.expected = self.data.expected.clone();
+
+        let mut out = Vec::new();
+        write_record(&mut out, TAG_HEADER, &header.encode());
+        for node in &self.data.mpt_nodes {
+            write_record(&mut out, TAG_MPT_NODE, node.as_bytes());
+        }
+        for code in &self.data.codes {
+            write_record(&mut out, TAG_CODE, code.as_bytes());
+        }
+        write_record(&mut out, TAG_END, &[]);
+        out
+    }
+}
+
+#[derive(Debug)]
+pub struct PobStreamDecoder<B> {
+    buf: Vec<u8>,
+    header_seen: bool,
+    finished: bool,
+    _marker: PhantomData<B>,
+}
+
+impl<B> PobStreamDecoder<B>
+where
+    B: PobBlock + Serialize + DeserializeOwned,
+{
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            header_seen: false,
+            finished: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // returns the items which are complete after this chunk
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<PobStreamItem<B>>, PobError> {
+        if self.finished {
+            return Err(PobError::Decode("pob stream already finished".into()));
+        }
+        self.buf.extend_from_slice(chunk);
+
+        let mut items = Vec::new();
+        let mut offset = 0;
+        while !self.finished && self.buf.len() - offset >= RECORD_HEADER_LEN {
+            let tag = self.buf[offset];
+            let mut len = [0_u8; 4];
+            len.copy_from_slice(&self.buf[offset + 1..offset + RECORD_HEADER_LEN]);
+            let len = u32::from_be_bytes(len) as usize;
+            if len > MAX_RECORD_LEN {
+                return Err(PobError::Decode(format!(
+                    "pob stream record too large: {}",
+                    len
+                )));
+            }
+            let start = offset + RECORD_HEADER_LEN;
+            if self.buf.len() - start < len {
+                break;
+            }
+            let payload = &self.buf[start..start + len];
+            offset = start + len;
+
+            if !self.header_seen && tag != TAG_HEADER {
+                return Err(PobError::Decode(
+                    "pob stream should start with header".into(),
+                ));
+            }
+            let item = match tag {
+                TAG_HEADER if !self.header_seen => {
+                    self.header_seen = true;
+                    PobStreamItem::Header(Pob::decode(payload)?)
+                }
+                TAG_MPT_NODE => PobStreamItem::MptNode(payload.to_vec().into()),
+                TAG_CODE => PobStreamItem::Code(payload.to_vec().into()),
+                TAG_END => {
+                    self.finished = true;
+                    PobStreamItem::End
+                }
+                tag => {
+                    return Err(PobError::Decode(format!(
+                        "unexpected pob stream record: {}",
+                        tag
+                    )))
+                }
+            };
+            items.push(item);
+        }
+        self.buf.drain(..offset);
+        Ok(items)
+    }
+
+    // decode the whole stream into a Pob
+    pub fn decode_all(data: &[u8]) -> Result<Pob<B>, PobError> {
+        let mut decoder = Self::new();
+        let mut pob = None;
+        for item in decoder.feed(data)? {
+            match item {
+                PobStreamItem::Header(header) => pob = Some(header),
+                PobStreamItem::MptNode(node) => {
+                    if let Some(pob) = &mut pob {
+                        pob.data.mpt_nodes.push(node);
+                    }
+                }
+                PobStreamItem::Code(code) => {
+                    if let Some(pob) = &mut pob {
+                        pob.data.codes.push(code);
+                    }
+                }
+                PobStreamItem::End => {}
+            }
+        }
+        if !decoder.is_finished() {
+            return Err(PobError::Decode("incomplete pob stream".into()));
+        }
+        pob.ok_or_else(|| PobError::Decode("missing pob stream header".into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::Block;
+
+    #[test]
+    fn test_stream_round_trip() {
+        let mut pob = Pob::new(Block::default(), Default::default());
+        pob.data.codes = vec![vec![0x60_u8, 0x00].into()];
+        let stream = pob.to_stream_bytes();
+
+        let decoded = PobStreamDecoder::<Block>::decode_all(&stream).unwrap();
+        assert_eq!(decoded.data.codes, pob.data.codes);
+        assert_eq!(decoded.data.state_hash(), pob.data.state_hash());
+
+        // the same items when it arrives in small chunks
+        let mut decoder = PobStreamDecoder::<Block>::new();
+        let mut items = 0;
+        for chunk in stream.chunks(7) {
+            items += decoder.feed(chunk).unwrap().len();
+        }
+        assert!(decoder.is_finished());
+        assert_eq!(items, 3);
+        assert!(PobStreamDecoder::<Block>::decode_all(&stream[..stream.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, SH256};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    keccak_hash, MptChild, MptNode, Pob, PobBlock, PobData, PobExpected, ProofAccount, ProofError,
+};
+
+#[derive(Debug)]
+pub enum PobVerifyError {
+    MissingRoot(SH256),
+    UnlinkedNode(SH256),
+    InvalidNode {
+        hash: SH256,
+        reason: String,
+    },
+    UnknownCode(SH256),
+    BlockHashOutOfRange {
+        number: u64,
+        current: u64,
+    },
+    UnexpectedParentHash {
+        number: u64,
+        expect: SH256,
+        got: SH256,
+    },
+    BlobCommitmentMismatch {
+        index: usize,
+    },
+    ResultMismatch {
+        field: &'static str,
+        expect: String,
+        got: String,
+    },
+}
+
+impl<B: PobBlock> Pob<B> {
+    // check the witness before the execution:
+    //  * every mpt node is reachable from the prev_state_root
+    //  * every code is referenced by an account in the witness
+    //  * the block hashes are inside the BLOCKHASH window and match the parent
+    //  * the blob commitments match the versioned hashes
+    pub fn verify(&self) -> Result<(), PobVerifyError> {
+        let data = &self.data;
+        let mut nodes = BTreeMap::new();
+        for node in &data.mpt_nodes {
+            let hash: SH256 = keccak_hash(node).into();
+            nodes.insert(hash, node.as_bytes());
+        }
+
+        if !nodes.is_empty() && !nodes.contains_key(&data.prev_state_root) {
+            return Err(PobVerifyError::MissingRoot(data.prev_state_root));
+        }
+
+        let mut walker = TrieWalker {
+            nodes: &nodes,
+            visited: BTreeSet::new(),
+            code_hashes: BTreeSet::new(),
+        };
+        walker.walk_hash(data.prev_state_root, TrieKind::Account)?;
+
+        for hash in nodes.keys() {
+            if !walker.visited.contains(hash) {
+                return Err(PobVerifyError::UnlinkedNode(*hash));
+            }
+        }
+
+        for code in &data.codes {
+            if code.is_empty() {
+                continue;
+            }
+            let hash: SH256 = keccak_hash(code).into();
+            if !walker.code_hashes.contains(&hash) {
+                return Err(PobVerifyError::UnknownCode(hash));
+            }
+        }
+
+        if !data.blob_commitments.is_empty() {
+            if data.blob_commitments.len() != data.blob_versioned_hashes.len() {
+                return Err(PobVerifyError::BlobCommitmentMismatch {
+                    index: data
+                        .blob_commitments
+                        .len()
+                        .min(data.blob_versioned_hashes.len()),
+                });
+            }
+            for (index, commitment) in data.blob_commitments.iter().enumerate() {
+                let hash = PobData::kzg_to_versioned_hash(commitment);
+                if hash != data.blob_versioned_hashes[index] {
+                    return Err(PobVerifyError::BlobCommitmentMismatch { index });
+                }
+            }
+        }
+
+        let current = self.block.number();
+        for number in data.block_hashes.keys() {
+            if *number >= current || current - *number > 256 {
+                return Err(PobVerifyError::BlockHashOutOfRange {
+                    number: *number,
+                    current,
+                });
+            }
+        }
+        if current > 0 {
+            if let Some(hash) = data.block_hashes.get(&(current - 1)) {
+                let parent_hash = self.block.parent_hash();
+                if hash != &parent_hash {
+                    return Err(PobVerifyError::UnexpectedParentHash {
+                        number: current - 1,
+                        expect: parent_hash,
+                        got: *hash,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TrieKind {
+    Account,
+    Storage,
+}
+
+struct TrieWalker<'a> {
+    nodes: &'a BTreeMap<SH256, &'a [u8]>,
+    visited: BTreeSet<SH256>,
+    code_hashes: BTreeSet<SH256>,
+}
+
+impl<'a> TrieWalker<'a> {
+    fn walk_hash(&mut self, hash: SH256, kind: TrieKind) -> Result<(), PobVerifyError> {
+        if !self.visited.insert(hash) {
+            return Ok(());
+        }
+        // the witness only contains the touched part of the trie
+        let node = match self.nodes.get(&hash) {
+            Some(node) => *node,
+            None => return Ok(()),
+        };
+        self.walk_node(hash, node, kind)
+    }
+
+    fn walk_node(
+        &mut self,
+        hash: SH256,
+        node: &[u8],
+        kind: TrieKind,
+    ) -> Result<(), PobVerifyError> {
+        let invalid = |err: ProofError| PobVerifyError::InvalidNode {
+            hash,
+            reason: format!("{:?}", err),
+        };
+        match MptNode::decode(node).map_err(invalid)? {
+            MptNode::Branch { children, .. } => {
+                for child in children.into_iter().flatten() {
+                    self.walk_child(hash, child, kind)?;
+                }
+            }
+            MptNode::Extension { child, .. } => self.walk_child(hash, child, kind)?,
+            MptNode::Leaf { value, .. } => {
+                if kind == TrieKind::Account {
+                    let acc = ProofAccount::decode(&value).map_err(invalid)?;
+                    self.code_hashes.insert(acc.code_hash);
+                    self.walk_hash(acc.storage_root, TrieKind::Storage)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn walk_child(
+        &mut self,
+        parent: SH256,
+        child: MptChild,
+        kind: TrieKind,
+    ) -> Result<(), PobVerifyError> {
+        match child {
+            MptChild::Hash(hash) => self.walk_hash(hash, kind),
+            // embedded node which is shorter than 32 bytes
+            MptChild::Inline(node) => self.walk_node(parent, &node, kind),
+        }
+    }
+}
+
+impl PobExpected {
+    // compare the results of the local execution with the embedded ones
+    pub fn check(
+        &self,
+        state_root: &SH256,
+        receipts_root: &SH256,
+        gas_used: u64,
+        logs_bloom: &HexBytes,
+    ) -> Result<(), PobVerifyError> {
+        fn mismatch<T: core::fmt::Debug>(
+            field: &'static str,
+            expect: &T,
+            got: &T,
+        ) -> PobVerifyError {
+            PobVerifyError::ResultMismatch {
+                field,
+                expect: format!("{:?}", expect),
+                got: format!("{:?}", got),
+            }
+        }
+        if &self.state_root != state_root {
+            return Err(mismatch("state_root", &self.state_root, state_root));
+        }
+        if &self.receipts_root != receipts_root {
+            return Err(mismatch(
+                "receipts_root",
+                &self.receipts_root,
+                receipts_root,
+            ));
+        }
+        if self.gas_used != gas_used {
+            return Err(mismatch("gas_used", &self.gas_used, &gas_used));
+        }
+        if &self.logs_bloom != logs_bloom {
+            return Err(mismatch("logs_bloom", &self.logs_bloom, logs_bloom));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::{Block, HexBytes, H160, H256, SH160};
+    use rlp::RlpStream;
+
+    // a trie of a single account, the root is the leaf
+    fn leaf(address: &SH160, code: &[u8]) -> Vec<u8> {
+        let mut account = RlpStream::new_list(4);
+        account.append(&1_u64);
+        account.append(&1_000_u64);
+        account.append(&keccak_hash(&rlp::NULL_RLP).to_vec());
+        account.append(&keccak_hash(code).to_vec());
+        let mut path = vec![0x20];
+        path.extend_from_slice(&keccak_hash(address.0.as_bytes()));
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&path);
+        stream.append(&account.out().to_vec());
+        stream.out().to_vec()
+    }
+
+    // an account with a code at block 300, the parent hash is in the block
+    // hashes
+    fn pob() -> Pob {
+        let code = vec![0x60, 0x00];
+        let node = leaf(&H160::repeat_byte(0x11).into(), &code);
+        let mut data = PobData {
+            prev_state_root: keccak_hash(&node).into(),
+            mpt_nodes: vec![node.into()],
+            codes: vec![code.into()],
+            ..Default::default()
+        };
+
+        let mut block = Block::default();
+        block.header.number = 300_u64.into();
+        block.header.parent_hash = H256::repeat_byte(0x99).into();
+        data.block_hashes.insert(44, H256::repeat_byte(0x44).into());
+        data.block_hashes.insert(299, block.header.parent_hash);
+        Pob::new(block, data)
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(pob().verify().is_ok());
+
+        let other = leaf(&H160::repeat_byte(0x22).into(), &[]);
+        let other_hash: SH256 = keccak_hash(&other).into();
+        let mut unlinked = pob();
+        unlinked.data.mpt_nodes.push(HexBytes::from(other));
+        assert!(matches!(
+            unlinked.verify(),
+            Err(PobVerifyError::UnlinkedNode(hash)) if hash == other_hash
+        ));
+
+        let mut missing_root = pob();
+        missing_root.data.prev_state_root = other_hash;
+        assert!(matches!(
+            missing_root.verify(),
+            Err(PobVerifyError::MissingRoot(_))
+        ));
+
+        let mut unknown_code = pob();
+        unknown_code.data.codes.push(vec![0x60, 0x01].into());
+        let code_hash: SH256 = keccak_hash(&[0x60, 0x01]).into();
+        assert!(matches!(
+            unknown_code.verify(),
+            Err(PobVerifyError::UnknownCode(hash)) if hash == code_hash
+        ));
+
+        // BLOCKHASH only reaches the last 256 blocks
+        let mut too_old = pob();
+        too_old
+            .data
+            .block_hashes
+            .insert(43, H256::repeat_byte(0x43).into());
+        assert!(matches!(
+            too_old.verify(),
+            Err(PobVerifyError::BlockHashOutOfRange {
+                number: 43,
+                current: 300
+            })
+        ));
+        let mut current = pob();
+        current
+            .data
+            .block_hashes
+            .insert(300, H256::repeat_byte(0x30).into());
+        assert!(matches!(
+            current.verify(),
+            Err(PobVerifyError::BlockHashOutOfRange { number: 300, .. })
+        ));
+
+        let mut other_parent = pob();
+        other_parent.block.header.parent_hash = H256::repeat_byte(0x98).into();
+        assert!(matches!(
+            other_parent.verify(),
+            Err(PobVerifyError::UnexpectedParentHash { number: 299, .. })
+        ));
+    }
+}
@@ -1,17 +1,124 @@
 use std::prelude::v1::*;
 
-use crypto::{Secp256k1PrivateKey, Secp256k1RecoverableSignature};
-use eth_types::{HexBytes, SH160, SH256, SU256};
+use core::ops::Range;
+use crypto::Secp256k1PrivateKey;
+use eth_types::{HexBytes, H160, H256, SH160, SH256, SU256};
 use serde::{Deserialize, Serialize};
 use solidity::EncodeArg;
+use std::collections::BTreeSet;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::{crypto_provider, PoeAttestation, PoeSigner, RecoveryCache};
+
+pub const POE_VERSION_V1: u32 = 1;
+// the signed payload binds the replay protection fields
+pub const POE_VERSION_V2: u32 = 2;
+// the signed payload binds the merkle root of the block state hashes
+pub const POE_VERSION_V3: u32 = 3;
+// the signed payload binds the rollup metadata
+pub const POE_VERSION_V4: u32 = 4;
+// the signed payload binds the execution audit digest
+pub const POE_VERSION_V5: u32 = 5;
+
+// the optional fields set in an encoded poe, see Poe::encode
+const POE_FIELD_REPLAY: u64 = 1;
+const POE_FIELD_MERKLE_ROOT: u64 = 2;
+const POE_FIELD_ROLLUP: u64 = 4;
+const POE_FIELD_AUDIT_DIGEST: u64 = 8;
+const POE_FIELD_ALL: u64 = 15;
+
+// the domains of the PoeMerkleTree hashes
+const MERKLE_LEAF_PREFIX: u8 = 0x00;
+const MERKLE_NODE_PREFIX: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum PoeError {
+    Unsigned,
+    InvalidSignatureLength(usize),
+    InvalidSignature,
+    SignerMismatch {
+        expect: SH160,
+        got: SH160,
+    },
+    MissingReplayProtection,
+    Expired {
+        expiry: u64,
+        now: u64,
+    },
+    ContractMismatch {
+        expect: SH160,
+        got: SH160,
+    },
+    Decode(String),
+    DuplicateSigner(SH160),
+    UnknownSigner(SH160),
+    QuorumNotReached {
+        threshold: usize,
+        got: usize,
+    },
+    PayloadMismatch {
+        signer: SH160,
+        expect: SH256,
+        got: SH256,
+    },
+    EmptyChain,
+    UnlinkedStateRoot {
+        idx: usize,
+        expect: SH256,
+        got: SH256,
+    },
+    BatchHashMismatch {
+        idx: usize,
+        expect: SH256,
+        got: SH256,
+    },
+    WithdrawalRootMismatch {
+        idx: usize,
+        expect: SH256,
+        got: SH256,
+    },
+    MissingAttestation,
+    InvalidQuote(String),
+    QuoteBindingMismatch {
+        expect: SH256,
+        got: SH256,
+    },
+    #[cfg(feature = "bls")]
+    Bls(blst::BLST_ERROR),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoeReplayProtection {
+    pub nonce: u64,
+    pub expiry: u64, // timestamp, 0 means never expire
+    pub verifying_contract: SH160,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoeRollupMeta {
+    pub batch_index: u64,
+    pub l1_origin_hash: SH256,
+    pub total_gas_used: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Poe {
+    #[serde(default = "Poe::legacy_version")]
+    pub version: u32,
     pub batch_hash: SH256,
     pub state_hash: SH256,
     pub prev_state_root: SH256,
     pub new_state_root: SH256,
     pub withdrawal_root: SH256,
+    #[serde(default)]
+    pub replay: Option<PoeReplayProtection>, // since v2
+    #[serde(default)]
+    pub merkle_root: Option<SH256>, // since v3
+    #[serde(default)]
+    pub rollup: Option<PoeRollupMeta>, // since v4
+    #[serde(default)]
+    pub audit_digest: Option<SH256>, // since v5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<PoeAttestation>, // not part of the signed payload
     pub signature: HexBytes, // 65bytes
 }
 
@@ -23,10 +130,16 @@ impl Poe {
         withdrawal_root: SH256,
     ) -> Self {
         Self {
+            version: POE_VERSION_V1,
             state_hash,
             prev_state_root,
             new_state_root,
             withdrawal_root,
+            replay: None,
+            merkle_root: None,
+            rollup: None,
+            audit_digest: None,
+            attestation: None,
             signature: vec![0_u8; 65].into(),
             batch_hash: SH256::default(),
         }
@@ -37,59 +150,173 @@ impl Poe {
             return Err("length of block poe is zero".into());
         }
 
-        let mut prev_state_root = None;
-        let mut new_state_root = None;
-        let mut withdrawal_root = None;
-        for (idx, poe) in block_poes.iter().enumerate() {
-            if prev_state_root.is_none() {
-                prev_state_root = Some(poe.prev_state_root);
+        for (idx, pair) in block_poes.windows(2).enumerate() {
+            if let Err(PoeError::UnlinkedStateRoot { idx, expect, got }) =
+                Self::check_link(idx + 1, &pair[0], &pair[1])
+            {
+                return Err(format!(
+                    "unexpected state_root in poe[{}]: want: {:?}, got: {:?}",
+                    idx, expect, got
+                ));
             }
-
-            if let Some(state_root) = &new_state_root {
-                if state_root != &poe.prev_state_root {
-                    return Err(format!(
-                        "unexpected state_root in poe[{}]: want: {:?}, got: {:?}",
-                        idx, state_root, poe.prev_state_root
-                    ));
-                }
-            }
-            new_state_root = Some(poe.new_state_root);
-            withdrawal_root = Some(poe.withdrawal_root);
         }
+        let first = &block_poes[0];
+        let last = &block_poes[block_poes.len() - 1];
 
-        let state_hash = crypto::keccak_encode(|hash| {
+        let state_hash = crate::keccak_encode(|hash| {
             for poe in block_poes {
                 hash(&poe.state_hash.0);
             }
         })
         .into();
         let batch_poe = Self {
+            version: POE_VERSION_V1,
             batch_hash,
             state_hash,
-            prev_state_root: prev_state_root.expect("prev_state_root should not be none"),
-            new_state_root: new_state_root.expect("new_state_root should not be none"),
-            withdrawal_root: withdrawal_root.expect("withdrawal_root should not be none"),
+            prev_state_root: first.prev_state_root,
+            new_state_root: last.new_state_root,
+            withdrawal_root: last.withdrawal_root,
+            replay: None,
+            merkle_root: None,
+            rollup: None,
+            audit_digest: None,
+            attestation: None,
             signature: vec![0_u8; 65].into(),
         };
 
         Ok(batch_poe)
     }
 
+    // the batch poe which also binds the merkle root over the block state
+    // hashes, a single block can be opened with the proof from the tree.
+    pub fn merkle_batch(
+        batch_hash: SH256,
+        block_poes: &[Self],
+    ) -> Result<(Self, PoeMerkleTree), String> {
+        let mut batch_poe = Self::batch(batch_hash, block_poes)?;
+        let tree = PoeMerkleTree::new(block_poes.iter().map(|poe| poe.state_hash).collect());
+        batch_poe.version = batch_poe.version.max(POE_VERSION_V3);
+        batch_poe.merkle_root = Some(tree.root());
+        Ok((batch_poe, tree))
+    }
+
+    // `next` (at `idx` of the sequence) should continue from the state of `prev`
+    pub fn check_link(idx: usize, prev: &Poe, next: &Poe) -> Result<(), PoeError> {
+        if prev.new_state_root != next.prev_state_root {
+            return Err(PoeError::UnlinkedStateRoot {
+                idx,
+                expect: prev.new_state_root,
+                got: next.prev_state_root,
+            });
+        }
+        Ok(())
+    }
+
+    // check the poes form a continuous sequence within the same batch. the
+    // withdrawal root only moves forward: it changes with the state and a
+    // root which was replaced never comes back.
+    pub fn verify_chain(poes: &[Poe]) -> Result<(), PoeError> {
+        let first = match poes.first() {
+            Some(poe) => poe,
+            None => return Err(PoeError::EmptyChain),
+        };
+        let mut replaced = BTreeSet::new();
+        for (idx, pair) in poes.windows(2).enumerate() {
+            let idx = idx + 1;
+            if pair[1].batch_hash != first.batch_hash {
+                return Err(PoeError::BatchHashMismatch {
+                    idx,
+                    expect: first.batch_hash,
+                    got: pair[1].batch_hash,
+                });
+            }
+            Self::check_link(idx, &pair[0], &pair[1])?;
+            // the withdrawal root is derived from the state, it can't change
+            // without a state transition.
+            let (prev, next) = (&pair[0].withdrawal_root, &pair[1].withdrawal_root);
+            if prev == next {
+                continue;
+            }
+            if pair[1].prev_state_root == pair[1].new_state_root || replaced.contains(next) {
+                return Err(PoeError::WithdrawalRootMismatch {
+                    idx,
+                    expect: *prev,
+                    got: *next,
+                });
+            }
+            replaced.insert(*prev);
+        }
+        Ok(())
+    }
+
+    fn legacy_version() -> u32 {
+        POE_VERSION_V1
+    }
+
+    // bind the poe to a contract, should be called before signing
+    pub fn with_replay_protection(mut self, replay: PoeReplayProtection) -> Self {
+        self.version = self.version.max(POE_VERSION_V2);
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn with_rollup_meta(mut self, rollup: PoeRollupMeta) -> Self {
+        self.version = self.version.max(POE_VERSION_V4);
+        self.rollup = Some(rollup);
+        self
+    }
+
+    // see ExecutionAudit
+    pub fn with_audit_digest(mut self, audit_digest: SH256) -> Self {
+        self.version = self.version.max(POE_VERSION_V5);
+        self.audit_digest = Some(audit_digest);
+        self
+    }
+
+    pub fn check_replay(&self, verifying_contract: &SH160, now: u64) -> Result<(), PoeError> {
+        let replay = match &self.replay {
+            Some(replay) if self.version >= POE_VERSION_V2 => replay,
+            _ => return Err(PoeError::MissingReplayProtection),
+        };
+        if &replay.verifying_contract != verifying_contract {
+            return Err(PoeError::ContractMismatch {
+                expect: *verifying_contract,
+                got: replay.verifying_contract,
+            });
+        }
+        if replay.expiry != 0 && replay.expiry < now {
+            return Err(PoeError::Expired {
+                expiry: replay.expiry,
+                now,
+            });
+        }
+        Ok(())
+    }
+
     pub fn sign(&mut self, chain_id: &SU256, prvkey: &Secp256k1PrivateKey) {
+        self.sign_with(chain_id, prvkey)
+    }
+
+    pub fn sign_with<S: PoeSigner + ?Sized>(&mut self, chain_id: &SU256, signer: &S) {
         let data = self.sign_msg(chain_id);
-        let sig = prvkey.sign(&data);
-        self.signature = sig.to_array().to_vec().into();
+        self.signature = signer.sign_msg(&data).to_vec().into();
     }
 }
 
 impl Default for Poe {
     fn default() -> Self {
         Self {
+            version: POE_VERSION_V1,
             batch_hash: SH256::default(),
             state_hash: SH256::default(),
             prev_state_root: SH256::default(),
             new_state_root: SH256::default(),
             withdrawal_root: SH256::default(),
+            replay: None,
+            merkle_root: None,
+            rollup: None,
+            audit_digest: None,
+            attestation: None,
             signature: vec![0_u8; 65].into(),
         }
     }
@@ -104,30 +331,503 @@ impl Poe {
         encoder.add(&self.prev_state_root);
         encoder.add(&self.new_state_root);
         encoder.add(&self.withdrawal_root);
+        self.add_versioned_fields(&mut encoder);
         encoder.add(self.signature.as_bytes());
         encoder.encode()
     }
 
+    fn add_versioned_fields(&self, encoder: &mut solidity::Encoder) {
+        if self.version >= POE_VERSION_V2 {
+            let replay = self.replay.clone().unwrap_or_default();
+            encoder.add(&SU256::from(replay.nonce));
+            encoder.add(&SU256::from(replay.expiry));
+            encoder.add(&replay.verifying_contract);
+        }
+        if self.version >= POE_VERSION_V3 {
+            encoder.add(&self.merkle_root.unwrap_or_default());
+        }
+        if self.version >= POE_VERSION_V4 {
+            let rollup = self.rollup.clone().unwrap_or_default();
+            encoder.add(&SU256::from(rollup.batch_index));
+            encoder.add(&rollup.l1_origin_hash);
+            encoder.add(&SU256::from(rollup.total_gas_used));
+        }
+        if self.version >= POE_VERSION_V5 {
+            encoder.add(&self.audit_digest.unwrap_or_default());
+        }
+    }
+
+    // the optional fields which are set, within the ones of the version
+    fn field_flags(&self) -> u64 {
+        let mut flags = 0;
+        if self.version >= POE_VERSION_V2 && self.replay.is_some() {
+            flags |= POE_FIELD_REPLAY;
+        }
+        if self.version >= POE_VERSION_V3 && self.merkle_root.is_some() {
+            flags |= POE_FIELD_MERKLE_ROOT;
+        }
+        if self.version >= POE_VERSION_V4 && self.rollup.is_some() {
+            flags |= POE_FIELD_ROLLUP;
+        }
+        if self.version >= POE_VERSION_V5 && self.audit_digest.is_some() {
+            flags |= POE_FIELD_AUDIT_DIGEST;
+        }
+        flags
+    }
+
+    // version || field flags || the signed fields || signature, the absent
+    // optional fields are zero words.
     pub fn encode(&self) -> Vec<u8> {
         let mut encoder = solidity::Encoder::new("");
+        encoder.add(&SU256::from(self.version as u64));
+        encoder.add(&SU256::from(self.field_flags()));
         encoder.add(&self.batch_hash);
         encoder.add(&self.state_hash);
         encoder.add(&self.prev_state_root);
         encoder.add(&self.new_state_root);
         encoder.add(&self.withdrawal_root);
+        self.add_versioned_fields(&mut encoder);
         encoder.add(self.signature.as_bytes());
         encoder.encode()
     }
 
+    // The inverse of encode(). Only the canonical encoding is accepted: a
+    // known version, the flags within it, zero words for the absent fields
+    // and a 65 bytes signature with a zero padding.
+    pub fn decode(data: &[u8]) -> Result<Self, PoeError> {
+        let word = |idx: usize| -> Result<&[u8], PoeError> {
+            data.get(idx * 32..(idx + 1) * 32)
+                .ok_or_else(|| PoeError::Decode(format!("poe too short: {}", data.len())))
+        };
+        let hash =
+            |idx: usize| -> Result<SH256, PoeError> { Ok(H256::from_slice(word(idx)?).into()) };
+        let uint = |idx: usize| -> Result<u64, PoeError> {
+            let w = word(idx)?;
+            if w[..24].iter().any(|b| *b != 0) {
+                return Err(PoeError::Decode(format!("uint overflow at word {}", idx)));
+            }
+            let mut buf = [0_u8; 8];
+            buf.copy_from_slice(&w[24..]);
+            Ok(u64::from_be_bytes(buf))
+        };
+
+        // the head ends with the signature offset, followed by the signature
+        // length and the padded signature
+        let (version, head_words) = match uint(0)? {
+            1 => (POE_VERSION_V1, 8),
+            2 => (POE_VERSION_V2, 11),
+            3 => (POE_VERSION_V3, 12),
+            4 => (POE_VERSION_V4, 15),
+            5 => (POE_VERSION_V5, 16),
+            version => {
+                return Err(PoeError::Decode(format!(
+                    "unsupported poe version: {}",
+                    version
+                )))
+            }
+        };
+        let flags = uint(1)?;
+        if flags & !POE_FIELD_ALL != 0 {
+            return Err(PoeError::Decode(format!(
+                "unknown field flags: {:#x}",
+                flags
+            )));
+        }
+        if uint(head_words - 1)? != head_words as u64 * 32 {
+            return Err(PoeError::Decode("unexpected signature offset".into()));
+        }
+        let sig_len = uint(head_words)?;
+        if sig_len != 65 {
+            return Err(PoeError::InvalidSignatureLength(
+                usize::try_from(sig_len).unwrap_or(usize::MAX),
+            ));
+        }
+        let start = (head_words + 1) * 32;
+        if data.len() != start + 96 {
+            return Err(PoeError::Decode(format!(
+                "unexpected poe length: {}",
+                data.len()
+            )));
+        }
+        if data[start + 65..].iter().any(|b| *b != 0) {
+            return Err(PoeError::Decode("non-zero signature padding".into()));
+        }
+
+        // the words of an optional field, None if its flag is not set
+        let field = |flag: u64, words: Range<usize>| -> Result<Option<usize>, PoeError> {
+            if flags & flag != 0 {
+                return Ok(Some(words.start));
+            }
+            for idx in words {
+                if word(idx)?.iter().any(|b| *b != 0) {
+                    return Err(PoeError::Decode(format!("non-zero absent word {}", idx)));
+                }
+            }
+            Ok(None)
+        };
+
+        let mut poe = Poe::default();
+        poe.version = version;
+        poe.batch_hash = hash(2)?;
+        poe.state_hash = hash(3)?;
+        poe.prev_state_root = hash(4)?;
+        poe.new_state_root = hash(5)?;
+        poe.withdrawal_root = hash(6)?;
+        if version >= POE_VERSION_V2 {
+            if let Some(idx) = field(POE_FIELD_REPLAY, 7..10)? {
+                let contract = word(idx + 2)?;
+                if contract[..12].iter().any(|b| *b != 0) {
+                    return Err(PoeError::Decode("invalid verifying contract".into()));
+                }
+                poe.replay = Some(PoeReplayProtection {
+                    nonce: uint(idx)?,
+                    expiry: uint(idx + 1)?,
+                    verifying_contract: H160::from_slice(&contract[12..]).into(),
+                });
+            }
+        }
+        if version >= POE_VERSION_V3 {
+            if let Some(idx) = field(POE_FIELD_MERKLE_ROOT, 10..11)? {
+                poe.merkle_root = Some(hash(idx)?);
+            }
+        }
+        if version >= POE_VERSION_V4 {
+            if let Some(idx) = field(POE_FIELD_ROLLUP, 11..14)? {
+                poe.rollup = Some(PoeRollupMeta {
+                    batch_index: uint(idx)?,
+                    l1_origin_hash: hash(idx + 1)?,
+                    total_gas_used: uint(idx + 2)?,
+                });
+            }
+        }
+        if version >= POE_VERSION_V5 {
+            if let Some(idx) = field(POE_FIELD_AUDIT_DIGEST, 14..15)? {
+                poe.audit_digest = Some(hash(idx)?);
+            }
+        }
+        // the flags of the fields the version doesn't have
+        if flags & !poe.field_flags() != 0 {
+            return Err(PoeError::Decode(format!("field flags beyond v{}", version)));
+        }
+        poe.signature = data[start..start + 65].to_vec().into();
+        Ok(poe)
+    }
+
+    // panics if the signature is not 65 bytes, see try_recover
     pub fn recover(&self, chain_id: &SU256) -> SH160 {
+        self.try_recover(chain_id).expect("invalid poe signature")
+    }
+
+    pub fn try_recover(&self, chain_id: &SU256) -> Result<SH160, PoeError> {
+        self.recover_signature(chain_id, &self.signature)
+    }
+
+    // same as try_recover, the signatures recovered before are not recovered again
+    pub fn try_recover_cached(
+        &self,
+        chain_id: &SU256,
+        cache: &RecoveryCache,
+    ) -> Result<SH160, PoeError> {
+        self.recover_signature_with(chain_id, &self.signature, Some(cache))
+    }
+
+    // recover the signer of `signature` over the payload of this poe, the
+    // signature field of self is ignored.
+    pub fn recover_signature(&self, chain_id: &SU256, signature: &[u8]) -> Result<SH160, PoeError> {
+        self.recover_signature_with(chain_id, signature, None)
+    }
+
+    fn recover_signature_with(
+        &self,
+        chain_id: &SU256,
+        signature: &[u8],
+        cache: Option<&RecoveryCache>,
+    ) -> Result<SH160, PoeError> {
+        if signature.len() != 65 {
+            return Err(PoeError::InvalidSignatureLength(signature.len()));
+        }
+        if signature.iter().all(|b| *b == 0) {
+            return Err(PoeError::Unsigned);
+        }
         let mut tmp = self.clone();
         tmp.signature = vec![0_u8; 65].into();
         let data = tmp.sign_msg(chain_id);
         let mut sig = [0_u8; 65];
-        sig.copy_from_slice(&self.signature);
-        let sig = Secp256k1RecoverableSignature::new(sig);
-        crypto::secp256k1_recover_pubkey(&sig, &data)
-            .eth_accountid()
-            .into()
+        sig.copy_from_slice(signature);
+        match cache {
+            Some(cache) => cache.recover_signer(&sig, &data),
+            None => crypto_provider().secp256k1_recover_signer(&sig, &data),
+        }
+        .ok_or(PoeError::InvalidSignature)
+    }
+
+    pub fn verify(&self, chain_id: &SU256, expected_signer: &SH160) -> Result<(), PoeError> {
+        let signer = self.try_recover(chain_id)?;
+        if &signer != expected_signer {
+            return Err(PoeError::SignerMismatch {
+                expect: *expected_signer,
+                got: signer,
+            });
+        }
+        Ok(())
+    }
+}
+
+// A merkle tree over the block state hashes, the leaves are padded with zero
+// hashes to the power of two. The leaves and the nodes are hashed under their
+// own prefix so a node can't be passed off as a leaf:
+//
+//     leaf = keccak(0x00 || state_hash)
+//     node = keccak(0x01 || left || right)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PoeMerkleTree {
+    pub leaves: Vec<SH256>,
+}
+
+impl PoeMerkleTree {
+    pub fn new(leaves: Vec<SH256>) -> Self {
+        Self { leaves }
+    }
+
+    fn hash_leaf(leaf: &SH256) -> SH256 {
+        crate::keccak_encode(|hash| {
+            hash(&[MERKLE_LEAF_PREFIX]);
+            hash(leaf.0.as_bytes());
+        })
+        .into()
+    }
+
+    fn hash_pair(left: &SH256, right: &SH256) -> SH256 {
+        crate::keccak_encode(|hash| {
+            hash(&[MERKLE_NODE_PREFIX]);
+            hash(left.0.as_bytes());
+            hash(right.0.as_bytes());
+        })
+        .into()
+    }
+
+    fn levels(&self) -> Vec<Vec<SH256>> {
+        let mut level = self.leaves.clone();
+        level.resize(self.leaves.len().next_power_of_two(), SH256::default());
+        let level = level.iter().map(Self::hash_leaf).collect();
+        let mut levels = vec![level];
+        while levels.last().map(|l| l.len() > 1).unwrap_or(false) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| Self::hash_pair(&pair[0], &pair[1]))
+                .collect();
+            levels.push(next);
+        }
+        levels
+    }
+
+    pub fn root(&self) -> SH256 {
+        if self.leaves.is_empty() {
+            return SH256::default();
+        }
+        self.levels().last().unwrap()[0]
+    }
+
+    // the sibling hashes from the leaf to the root
+    pub fn proof(&self, index: usize) -> Option<Vec<SH256>> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+        let levels = self.levels();
+        let mut proof = Vec::with_capacity(levels.len() - 1);
+        let mut idx = index;
+        for level in &levels[..levels.len() - 1] {
+            proof.push(level[idx ^ 1]);
+            idx /= 2;
+        }
+        Some(proof)
+    }
+
+    // the depth of the tree of `leaves` is fixed, a shorter or a longer
+    // proof is refused
+    pub fn verify_proof(
+        root: &SH256,
+        leaf: &SH256,
+        index: usize,
+        leaves: usize,
+        proof: &[SH256],
+    ) -> bool {
+        if index >= leaves || proof.len() != leaves.next_power_of_two().trailing_zeros() as usize {
+            return false;
+        }
+        let mut hash = Self::hash_leaf(leaf);
+        let mut idx = index;
+        for sibling in proof {
+            hash = if idx % 2 == 0 {
+                Self::hash_pair(&hash, sibling)
+            } else {
+                Self::hash_pair(sibling, &hash)
+            };
+            idx /= 2;
+        }
+        &hash == root
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merkle_proof() {
+        let leaves: Vec<SH256> = (1..=5_u8)
+            .map(|n| {
+                let mut hash = SH256::default();
+                hash.0 .0[31] = n;
+                hash
+            })
+            .collect();
+        let tree = PoeMerkleTree::new(leaves.clone());
+        let root = tree.root();
+        let verify = |leaf: &SH256, idx: usize, leaves: usize, proof: &[SH256]| {
+            PoeMerkleTree::verify_proof(&root, leaf, idx, leaves, proof)
+        };
+        for (idx, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(idx).unwrap();
+            assert_eq!(proof.len(), 3);
+            assert!(verify(leaf, idx, 5, &proof));
+            assert!(!verify(leaf, idx ^ 1, 5, &proof));
+            assert!(!verify(leaf, idx, 5, &proof[..2]));
+            assert!(!verify(leaf, idx + 8, 5, &proof));
+        }
+        assert!(tree.proof(5).is_none());
+
+        // an inner node is not a leaf of the shorter proof
+        let node = tree.levels()[1][0];
+        let proof = tree.proof(0).unwrap();
+        assert!(!verify(&node, 0, 5, &proof[1..]));
+        assert!(!verify(&node, 0, 3, &proof[1..]));
+
+        let single = PoeMerkleTree::new(vec![leaves[0]]);
+        assert_eq!(single.proof(0).unwrap().len(), 0);
+        assert!(PoeMerkleTree::verify_proof(
+            &single.root(),
+            &leaves[0],
+            0,
+            1,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let hash = |n: u8| -> SH256 { H256::repeat_byte(n).into() };
+        let poe = |prev: u8, new: u8, withdrawal: u8| {
+            let mut poe = Poe::single_block(hash(0), hash(prev), hash(new), hash(withdrawal));
+            poe.batch_hash = hash(9);
+            poe
+        };
+        assert!(Poe::verify_chain(&[poe(1, 2, 1), poe(2, 3, 2), poe(3, 4, 2)]).is_ok());
+        assert!(matches!(Poe::verify_chain(&[]), Err(PoeError::EmptyChain)));
+
+        let unlinked = [poe(1, 2, 1), poe(3, 4, 1)];
+        assert!(matches!(
+            Poe::verify_chain(&unlinked),
+            Err(PoeError::UnlinkedStateRoot { idx: 1, .. })
+        ));
+        let mut other_batch = poe(2, 3, 1);
+        other_batch.batch_hash = hash(8);
+        assert!(matches!(
+            Poe::verify_chain(&[poe(1, 2, 1), other_batch]),
+            Err(PoeError::BatchHashMismatch { idx: 1, .. })
+        ));
+
+        // a block without a state transition moves the withdrawal root
+        assert!(matches!(
+            Poe::verify_chain(&[poe(1, 2, 1), poe(2, 2, 2)]),
+            Err(PoeError::WithdrawalRootMismatch { idx: 1, .. })
+        ));
+        // the root goes back to the one it replaced
+        assert!(matches!(
+            Poe::verify_chain(&[poe(1, 2, 1), poe(2, 3, 2), poe(3, 4, 1)]),
+            Err(PoeError::WithdrawalRootMismatch { idx: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_poe_decode() {
+        let mut hash = SH256::default();
+        hash.0 .0[0] = 1;
+        let mut poe = Poe::single_block(hash, hash, SH256::default(), hash);
+        poe.signature = vec![7_u8; 65].into();
+
+        // a nonce which looks like a v1 signature offset
+        let replay = PoeReplayProtection {
+            nonce: 192,
+            expiry: 1700000000,
+            verifying_contract: SH160::default(),
+        };
+        let mut merkle = poe.clone().with_replay_protection(replay.clone());
+        merkle.version = POE_VERSION_V3;
+        merkle.merkle_root = Some(hash);
+        let rollup = PoeRollupMeta {
+            batch_index: 3,
+            l1_origin_hash: hash,
+            total_gas_used: 21000,
+        };
+        for poe in [
+            poe.clone(),
+            poe.clone().with_replay_protection(replay),
+            merkle.clone(),
+            merkle.clone().with_audit_digest(hash),
+            // the fields before v4 are absent
+            poe.clone().with_rollup_meta(rollup),
+            poe.clone().with_audit_digest(SH256::default()),
+        ] {
+            assert_eq!(Poe::decode(&poe.encode()).unwrap(), poe);
+        }
+    }
+
+    #[test]
+    fn test_poe_decode_malformed() {
+        let mut poe = Poe::default();
+        poe.signature = vec![7_u8; 65].into();
+        let encoded = poe.encode();
+        let set_word = |data: &[u8], idx: usize, val: &[u8]| {
+            let mut data = data.to_vec();
+            let end = (idx + 1) * 32;
+            data[idx * 32..end].iter_mut().for_each(|b| *b = 0);
+            data[end - val.len()..end].copy_from_slice(val);
+            data
+        };
+
+        assert!(Poe::decode(&encoded[..100]).is_err());
+        assert!(Poe::decode(&encoded[..encoded.len() - 1]).is_err());
+        // the v1 head is 8 words, the signature length follows
+        assert!(matches!(
+            Poe::decode(&set_word(&encoded, 8, &[0xff; 32])),
+            Err(PoeError::Decode(_))
+        ));
+        assert!(matches!(
+            Poe::decode(&set_word(&encoded, 8, &[0xff; 8])),
+            Err(PoeError::InvalidSignatureLength(_))
+        ));
+        assert!(matches!(
+            Poe::decode(&set_word(&encoded, 8, &[64])),
+            Err(PoeError::InvalidSignatureLength(64))
+        ));
+        // unknown version, a flag beyond the version, a non-zero padding
+        assert!(Poe::decode(&set_word(&encoded, 0, &[6])).is_err());
+        assert!(Poe::decode(&set_word(&encoded, 1, &[1])).is_err());
+        let mut padded = encoded.clone();
+        *padded.last_mut().unwrap() = 1;
+        assert!(Poe::decode(&padded).is_err());
+
+        // an absent field with a non-zero word
+        let v5 = poe.clone().with_audit_digest(SH256::default()).encode();
+        assert!(Poe::decode(&set_word(&v5, 7, &[1])).is_err());
+
+        // the short input with a huge signature length
+        let mut short = vec![0_u8; 224];
+        short[5 * 32 + 31] = 192;
+        short[6 * 32..].iter_mut().for_each(|b| *b = 0xff);
+        assert!(Poe::decode(&short).is_err());
     }
 }
@@ -0,0 +1,77 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, SH256, SU256};
+use serde::{Deserialize, Serialize};
+
+use crate::{Poe, PoeError};
+
+// sgx quote v3: header(48) || report body(384) || signature data
+const QUOTE_HEADER_LEN: usize = 48;
+const REPORT_BODY_LEN: usize = 384;
+const MR_ENCLAVE_OFFSET: usize = QUOTE_HEADER_LEN + 64;
+const MR_SIGNER_OFFSET: usize = QUOTE_HEADER_LEN + 128;
+const REPORT_DATA_OFFSET: usize = QUOTE_HEADER_LEN + 320;
+
+// The DCAP quote of the enclave which produced the Poe, the first 32 bytes
+// of the report data commit to the Poe payload hash.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PoeAttestation {
+    pub quote: HexBytes,
+}
+
+impl PoeAttestation {
+    fn field(&self, offset: usize) -> Result<SH256, PoeError> {
+        if self.quote.len() < QUOTE_HEADER_LEN + REPORT_BODY_LEN {
+            return Err(PoeError::InvalidQuote(format!(
+                "quote too short: {}",
+                self.quote.len()
+            )));
+        }
+        let mut out = SH256::default();
+        out.0 .0.copy_from_slice(&self.quote[offset..offset + 32]);
+        Ok(out)
+    }
+
+    pub fn mr_enclave(&self) -> Result<SH256, PoeError> {
+        self.field(MR_ENCLAVE_OFFSET)
+    }
+
+    pub fn mr_signer(&self) -> Result<SH256, PoeError> {
+        self.field(MR_SIGNER_OFFSET)
+    }
+
+    pub fn report_data(&self) -> Result<SH256, PoeError> {
+        self.field(REPORT_DATA_OFFSET)
+    }
+}
+
+impl Poe {
+    // the hash of the signed payload with an empty signature, the enclave
+    // should put it into the report data when generating the quote.
+    pub fn payload_hash(&self, chain_id: &SU256) -> SH256 {
+        let mut tmp = self.clone();
+        tmp.signature = vec![0_u8; 65].into();
+        tmp.attestation = None;
+        crate::keccak_hash(&tmp.sign_msg(chain_id)).into()
+    }
+
+    pub fn with_attestation(mut self, quote: HexBytes) -> Self {
+        self.attestation = Some(PoeAttestation { quote });
+        self
+    }
+
+    // Only checks the quote commits to this poe, the quote itself should be
+    // verified against the DCAP collateral by the caller.
+    pub fn check_attestation(&self, chain_id: &SU256) -> Result<&PoeAttestation, PoeError> {
+        let attestation = match &self.attestation {
+            Some(attestation) => attestation,
+            None => return Err(PoeError::MissingAttestation),
+        };
+        let expect = self.payload_hash(chain_id);
+        let got = attestation.report_data()?;
+        if expect != got {
+            return Err(PoeError::QuoteBindingMismatch { expect, got });
+        }
+        Ok(attestation)
+    }
+}
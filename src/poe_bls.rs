@@ -0,0 +1,141 @@
+use std::prelude::v1::*;
+
+use blst::min_pk::{AggregateSignature, PublicKey, SecretKey, Signature};
+use blst::BLST_ERROR;
+use eth_types::{HexBytes, SU256};
+
+use crate::{Poe, PoeError};
+
+// BLS12-381 signatures over the Poe payload, public keys in G1 and
+// signatures in G2 so that signatures from many provers can be aggregated.
+pub const POE_BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+fn check(err: BLST_ERROR) -> Result<(), PoeError> {
+    match err {
+        BLST_ERROR::BLST_SUCCESS => Ok(()),
+        err => Err(PoeError::Bls(err)),
+    }
+}
+
+fn public_keys(pubkeys: &[HexBytes]) -> Result<Vec<PublicKey>, PoeError> {
+    pubkeys
+        .iter()
+        .map(|pk| PublicKey::from_bytes(pk).map_err(PoeError::Bls))
+        .collect()
+}
+
+impl Poe {
+    // the signed message is the payload with an empty signature
+    fn bls_msg(&self, chain_id: &SU256) -> Vec<u8> {
+        let mut tmp = self.clone();
+        tmp.signature = vec![0_u8; 65].into();
+        tmp.sign_msg(chain_id)
+    }
+
+    pub fn bls_sign(&self, chain_id: &SU256, sk: &SecretKey) -> HexBytes {
+        let sig = sk.sign(&self.bls_msg(chain_id), POE_BLS_DST, &[]);
+        sig.to_bytes().to_vec().into()
+    }
+
+    pub fn bls_verify(
+        &self,
+        chain_id: &SU256,
+        pubkey: &[u8],
+        signature: &[u8],
+    ) -> Result<(), PoeError> {
+        let pk = PublicKey::from_bytes(pubkey).map_err(PoeError::Bls)?;
+        let sig = Signature::from_bytes(signature).map_err(PoeError::Bls)?;
+        check(sig.verify(true, &self.bls_msg(chain_id), POE_BLS_DST, &[], &pk, true))
+    }
+
+    pub fn bls_aggregate(signatures: &[HexBytes]) -> Result<HexBytes, PoeError> {
+        let sigs = signatures
+            .iter()
+            .map(|sig| Signature::from_bytes(sig).map_err(PoeError::Bls))
+            .collect::<Result<Vec<_>, _>>()?;
+        let sigs: Vec<&Signature> = sigs.iter().collect();
+        let agg = AggregateSignature::aggregate(&sigs, true).map_err(PoeError::Bls)?;
+        Ok(agg.to_signature().to_bytes().to_vec().into())
+    }
+
+    // every prover signed this poe
+    pub fn bls_verify_aggregate(
+        &self,
+        chain_id: &SU256,
+        pubkeys: &[HexBytes],
+        signature: &[u8],
+    ) -> Result<(), PoeError> {
+        let pks = public_keys(pubkeys)?;
+        let pks: Vec<&PublicKey> = pks.iter().collect();
+        let sig = Signature::from_bytes(signature).map_err(PoeError::Bls)?;
+        check(sig.fast_aggregate_verify(true, &self.bls_msg(chain_id), POE_BLS_DST, &pks))
+    }
+
+    // the aggregated signature of different poes, poes[i] is signed by pubkeys[i]
+    pub fn bls_verify_aggregate_batch(
+        chain_id: &SU256,
+        poes: &[Poe],
+        pubkeys: &[HexBytes],
+        signature: &[u8],
+    ) -> Result<(), PoeError> {
+        if poes.len() != pubkeys.len() {
+            return Err(PoeError::Bls(BLST_ERROR::BLST_VERIFY_FAIL));
+        }
+        let msgs: Vec<Vec<u8>> = poes.iter().map(|poe| poe.bls_msg(chain_id)).collect();
+        let msgs: Vec<&[u8]> = msgs.iter().map(|msg| msg.as_slice()).collect();
+        let pks = public_keys(pubkeys)?;
+        let pks: Vec<&PublicKey> = pks.iter().collect();
+        let sig = Signature::from_bytes(signature).map_err(PoeError::Bls)?;
+        check(sig.aggregate_verify(true, &msgs, POE_BLS_DST, &pks, true))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::{H256, SH256};
+
+    fn poe(n: u8) -> Poe {
+        let hash = |n: u8| -> SH256 { H256::repeat_byte(n).into() };
+        Poe::single_block(hash(n), hash(2), hash(3), hash(4))
+    }
+
+    fn key(n: u8) -> (SecretKey, HexBytes) {
+        let sk = SecretKey::key_gen(&[n; 32], &[]).unwrap();
+        let pk = sk.sk_to_pk().to_bytes().to_vec().into();
+        (sk, pk)
+    }
+
+    #[test]
+    fn test_bls_poe() {
+        let chain_id = SU256::from(1_u64);
+        let (sk1, pk1) = key(1);
+        let (sk2, pk2) = key(2);
+        let (first, other) = (poe(1), poe(5));
+
+        let sig1 = first.bls_sign(&chain_id, &sk1);
+        assert!(first.bls_verify(&chain_id, &pk1, &sig1).is_ok());
+        assert!(first.bls_verify(&chain_id, &pk2, &sig1).is_err());
+        assert!(first.bls_verify(&SU256::from(2_u64), &pk1, &sig1).is_err());
+        assert!(other.bls_verify(&chain_id, &pk1, &sig1).is_err());
+
+        // both provers signed the same poe
+        let sig2 = first.bls_sign(&chain_id, &sk2);
+        let agg = Poe::bls_aggregate(&[sig1.clone(), sig2]).unwrap();
+        let pubkeys = [pk1, pk2];
+        assert!(first
+            .bls_verify_aggregate(&chain_id, &pubkeys, &agg)
+            .is_ok());
+        assert!(first
+            .bls_verify_aggregate(&chain_id, &pubkeys[..1], &agg)
+            .is_err());
+
+        // each prover signed its own poe
+        let agg = Poe::bls_aggregate(&[sig1, other.bls_sign(&chain_id, &sk2)]).unwrap();
+        let poes = [first.clone(), other.clone()];
+        assert!(Poe::bls_verify_aggregate_batch(&chain_id, &poes, &pubkeys, &agg).is_ok());
+        let swapped = [other, first];
+        assert!(Poe::bls_verify_aggregate_batch(&chain_id, &swapped, &pubkeys, &agg).is_err());
+        assert!(Poe::bls_verify_aggregate_batch(&chain_id, &poes, &pubkeys[..1], &agg).is_err());
+    }
+}
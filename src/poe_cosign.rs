@@ -0,0 +1,221 @@
+use std::prelude::v1::*;
+
+use eth_types::{SH160, SH256, SU256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{MultiPoe, Poe, PoeAttestation, PoeError};
+
+// The combined proof of the enclaves which executed the same Pob. The
+// attestations are optional and keyed by the signer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CoSignedPoe {
+    pub multi: MultiPoe,
+    #[serde(default)]
+    pub attestations: BTreeMap<SH160, PoeAttestation>,
+}
+
+// Collects the Poes signed by the enclaves of a committee. Every enclave
+// executes the Pob on its own, the first accepted Poe fixes the payload and
+// the others must sign the identical payload, so a single compromised
+// enclave can't get a different state transition into the proof.
+#[derive(Debug)]
+pub struct PoeCoSigning {
+    chain_id: SU256,
+    committee: Vec<SH160>,
+    threshold: usize,
+    payload: Option<SH256>,
+    multi: Option<MultiPoe>,
+    attestations: BTreeMap<SH160, PoeAttestation>,
+}
+
+impl PoeCoSigning {
+    pub fn new(chain_id: SU256, committee: Vec<SH160>, threshold: usize) -> Self {
+        Self {
+            chain_id,
+            committee,
+            threshold,
+            payload: None,
+            multi: None,
+            attestations: BTreeMap::new(),
+        }
+    }
+
+    // the hash of the payload all the signers agreed on so far
+    pub fn payload(&self) -> Option<SH256> {
+        self.payload
+    }
+
+    pub fn len(&self) -> usize {
+        self.multi.as_ref().map(|multi| multi.len()).unwrap_or(0)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.len() >= self.threshold
+    }
+
+    // returns the signer of the accepted partial Poe
+    pub fn add(&mut self, partial: Poe) -> Result<SH160, PoeError> {
+        let signer = partial.try_recover(&self.chain_id)?;
+        if !self.committee.contains(&signer) {
+            return Err(PoeError::UnknownSigner(signer));
+        }
+        let payload = partial.payload_hash(&self.chain_id);
+        if let Some(expect) = self.payload {
+            if expect != payload {
+                return Err(PoeError::PayloadMismatch {
+                    signer,
+                    expect,
+                    got: payload,
+                });
+            }
+        }
+        if partial.attestation.is_some() {
+            partial.check_attestation(&self.chain_id)?;
+        }
+
+        let multi = self.multi.get_or_insert_with(|| {
+            let mut poe = partial.clone();
+            poe.attestation = None;
+            MultiPoe::new(poe)
+        });
+        multi.add_signature(&self.chain_id, partial.signature.clone())?;
+        self.payload = Some(payload);
+        if let Some(attestation) = partial.attestation {
+            self.attestations.insert(signer, attestation);
+        }
+        Ok(signer)
+    }
+
+    pub fn finish(self) -> Result<CoSignedPoe, PoeError> {
+        let multi = match self.multi {
+            Some(multi) => multi,
+            None => {
+                return Err(PoeError::QuorumNotReached {
+                    threshold: self.threshold,
+                    got: 0,
+                })
+            }
+        };
+        multi.check_quorum(&self.chain_id, &self.committee, self.threshold)?;
+        Ok(CoSignedPoe {
+            multi,
+            attestations: self.attestations,
+        })
+    }
+}
+
+impl CoSignedPoe {
+    pub fn verify(
+        &self,
+        chain_id: &SU256,
+        committee: &[SH160],
+        threshold: usize,
+    ) -> Result<(), PoeError> {
+        self.multi.check_quorum(chain_id, committee, threshold)?;
+        let expect = self.multi.poe.payload_hash(chain_id);
+        for (signer, attestation) in &self.attestations {
+            if !self.multi.signers.contains(signer) {
+                return Err(PoeError::UnknownSigner(*signer));
+            }
+            let got = attestation.report_data()?;
+            if got != expect {
+                return Err(PoeError::QuoteBindingMismatch { expect, got });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::Secp256k1PrivateKey;
+    use eth_types::H256;
+
+    use crate::PoeSigner;
+
+    fn poe(new_state_root: u8) -> Poe {
+        let hash = |n: u8| -> SH256 { H256::repeat_byte(n).into() };
+        Poe::single_block(hash(1), hash(2), hash(new_state_root), hash(4))
+    }
+
+    fn partial(chain_id: &SU256, mut poe: Poe, key: &Secp256k1PrivateKey) -> Poe {
+        poe.sign(chain_id, key);
+        poe
+    }
+
+    // a quote with the report data at the offset of the sgx quote v3
+    fn quote(report_data: &SH256) -> Vec<u8> {
+        let mut quote = vec![0_u8; 48 + 384];
+        quote[48 + 320..].copy_from_slice(report_data.0.as_bytes());
+        quote
+    }
+
+    #[test]
+    fn test_co_signing() {
+        let chain_id = SU256::from(1_u64);
+        let keys: Vec<_> = (0..3).map(|_| Secp256k1PrivateKey::random()).collect();
+        let committee: Vec<SH160> = keys[..2].iter().map(|key| key.address()).collect();
+        let payload = poe(3).payload_hash(&chain_id);
+
+        let mut co_signing = PoeCoSigning::new(chain_id.clone(), committee.clone(), 2);
+        let attested = poe(3).with_attestation(quote(&payload).into());
+        let signer = co_signing
+            .add(partial(&chain_id, attested, &keys[0]))
+            .unwrap();
+        assert_eq!(signer, committee[0]);
+        assert_eq!(co_signing.payload(), Some(payload));
+        assert!(!co_signing.is_complete());
+
+        assert!(matches!(
+            co_signing.add(partial(&chain_id, poe(3), &keys[2])),
+            Err(PoeError::UnknownSigner(_))
+        ));
+        // another state transition from the second enclave
+        assert!(matches!(
+            co_signing.add(partial(&chain_id, poe(5), &keys[1])),
+            Err(PoeError::PayloadMismatch { .. })
+        ));
+        let unbound = poe(3).with_attestation(quote(&SH256::default()).into());
+        assert!(matches!(
+            co_signing.add(partial(&chain_id, unbound, &keys[1])),
+            Err(PoeError::QuoteBindingMismatch { .. })
+        ));
+        assert!(matches!(
+            co_signing.add(partial(&chain_id, poe(3), &keys[0])),
+            Err(PoeError::DuplicateSigner(_))
+        ));
+        co_signing
+            .add(partial(&chain_id, poe(3), &keys[1]))
+            .unwrap();
+        assert!(co_signing.is_complete());
+
+        let co_signed = co_signing.finish().unwrap();
+        assert_eq!(co_signed.attestations.len(), 1);
+        assert!(co_signed.verify(&chain_id, &committee, 2).is_ok());
+        assert!(co_signed.verify(&chain_id, &committee, 3).is_err());
+
+        let mut tampered = co_signed.clone();
+        let attestation = tampered.attestations.remove(&committee[0]).unwrap();
+        tampered.attestations.insert(keys[2].address(), attestation);
+        assert!(matches!(
+            tampered.verify(&chain_id, &committee, 2),
+            Err(PoeError::UnknownSigner(_))
+        ));
+
+        let empty = PoeCoSigning::new(chain_id.clone(), committee.clone(), 2);
+        assert!(matches!(
+            empty.finish(),
+            Err(PoeError::QuorumNotReached { got: 0, .. })
+        ));
+        let mut incomplete = PoeCoSigning::new(chain_id.clone(), committee, 2);
+        incomplete
+            .add(partial(&chain_id, poe(3), &keys[1]))
+            .unwrap();
+        assert!(matches!(
+            incomplete.finish(),
+            Err(PoeError::QuorumNotReached { got: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+use std::prelude::v1::*;
+
+use crypto::Secp256k1PrivateKey;
+use eth_types::SH160;
+use sgxlib::sgx_tseal::SgxSealedData;
+use sgxlib::sgx_types::sgx_sealed_data_t;
+use std::untrusted::fs;
+
+use crate::PoeSigner;
+
+// The Poe signing key of the enclave. It's generated inside the enclave and
+// only leaves it sealed to the enclave identity, the operator keeps the
+// sealed file and the key is only reachable through PoeSigner.
+pub struct SealedPoeKey {
+    key: Secp256k1PrivateKey,
+    address: SH160,
+}
+
+#[derive(Debug)]
+pub enum PoeKeyError {
+    Io(String),
+    Seal(String),
+    Unseal(String),
+    InvalidKey,
+}
+
+impl SealedPoeKey {
+    // unseals the key at `path`, or generates and seals a new one if the
+    // file doesn't exist.
+    pub fn load_or_generate(path: &str) -> Result<Self, PoeKeyError> {
+        match fs::read(path) {
+            Ok(sealed) => Self::unseal(&sealed),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let key = Self::new(Secp256k1PrivateKey::random());
+                fs::write(path, key.seal()?).map_err(|err| PoeKeyError::Io(format!("{}", err)))?;
+                Ok(key)
+            }
+            Err(err) => Err(PoeKeyError::Io(format!("{}", err))),
+        }
+    }
+
+    fn new(key: Secp256k1PrivateKey) -> Self {
+        let address = key.public().eth_accountid().into();
+        Self { key, address }
+    }
+
+    pub fn seal(&self) -> Result<Vec<u8>, PoeKeyError> {
+        let raw = self.key.to_raw_bytes();
+        let sealed = SgxSealedData::<[u8]>::seal_data(&[], &raw[..])
+            .map_err(|err| PoeKeyError::Seal(format!("{:?}", err)))?;
+        let size = SgxSealedData::<[u8]>::calc_raw_sealed_data_size(0, raw.len() as u32);
+        let mut out = vec![0_u8; size as usize];
+        let ptr = out.as_mut_ptr() as *mut sgx_sealed_data_t;
+        match unsafe { sealed.to_raw_sealed_data_t(ptr, size) } {
+            Some(_) => Ok(out),
+            None => Err(PoeKeyError::Seal("sealed data buffer too small".into())),
+        }
+    }
+
+    pub fn unseal(sealed: &[u8]) -> Result<Self, PoeKeyError> {
+        let mut buf = sealed.to_vec();
+        let ptr = buf.as_mut_ptr() as *mut sgx_sealed_data_t;
+        let sealed =
+            unsafe { SgxSealedData::<[u8]>::from_raw_sealed_data_t(ptr, buf.len() as u32) }
+                .ok_or_else(|| PoeKeyError::Unseal("malformed sealed data".into()))?;
+        let data = sealed
+            .unseal_data()
+            .map_err(|err| PoeKeyError::Unseal(format!("{:?}", err)))?;
+        let key = Secp256k1PrivateKey::from_bytes(data.get_decrypt_txt())
+            .map_err(|_| PoeKeyError::InvalidKey)?;
+        Ok(Self::new(key))
+    }
+}
+
+impl PoeSigner for SealedPoeKey {
+    fn address(&self) -> SH160 {
+        self.address
+    }
+
+    fn sign_msg(&self, msg: &[u8]) -> [u8; 65] {
+        PoeSigner::sign_msg(&self.key, msg)
+    }
+}
@@ -0,0 +1,165 @@
+use std::prelude::v1::*;
+
+use crypto::Secp256k1PrivateKey;
+use eth_types::{HexBytes, SH160, SU256};
+use serde::{Deserialize, Serialize};
+
+use crate::{Poe, PoeError, PoeSigner};
+
+// The signatures from a committee of attestors over the same Poe payload.
+// The signatures are kept sorted by the signer so the on-chain verifier can
+// reject duplicates by checking the signers are strictly increasing.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiPoe {
+    pub poe: Poe, // the signature field is unused
+    pub signers: Vec<SH160>,
+    pub signatures: Vec<HexBytes>,
+}
+
+impl MultiPoe {
+    pub fn new(mut poe: Poe) -> Self {
+        poe.signature = vec![0_u8; 65].into();
+        Self {
+            poe,
+            signers: Vec::new(),
+            signatures: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn sign(
+        &mut self,
+        chain_id: &SU256,
+        prvkey: &Secp256k1PrivateKey,
+    ) -> Result<SH160, PoeError> {
+        self.sign_with(chain_id, prvkey)
+    }
+
+    pub fn sign_with<S: PoeSigner + ?Sized>(
+        &mut self,
+        chain_id: &SU256,
+        signer: &S,
+    ) -> Result<SH160, PoeError> {
+        let sig = signer.sign_msg(&self.poe.sign_msg(chain_id));
+        self.add_signature(chain_id, sig.to_vec().into())
+    }
+
+    // returns the recovered signer
+    pub fn add_signature(
+        &mut self,
+        chain_id: &SU256,
+        signature: HexBytes,
+    ) -> Result<SH160, PoeError> {
+        let signer = self.poe.recover_signature(chain_id, &signature)?;
+        match self.signers.binary_search(&signer) {
+            Ok(_) => Err(PoeError::DuplicateSigner(signer)),
+            Err(idx) => {
+                self.signers.insert(idx, signer);
+                self.signatures.insert(idx, signature);
+                Ok(signer)
+            }
+        }
+    }
+
+    // every signature should come from the committee and at least
+    // `threshold` of them are required.
+    pub fn check_quorum(
+        &self,
+        chain_id: &SU256,
+        committee: &[SH160],
+        threshold: usize,
+    ) -> Result<(), PoeError> {
+        for (signer, signature) in self.signers.iter().zip(&self.signatures) {
+            let got = self.poe.recover_signature(chain_id, signature)?;
+            if &got != signer {
+                return Err(PoeError::SignerMismatch {
+                    expect: *signer,
+                    got,
+                });
+            }
+            if !committee.contains(signer) {
+                return Err(PoeError::UnknownSigner(*signer));
+            }
+        }
+        if self.signers.len() < threshold {
+            return Err(PoeError::QuorumNotReached {
+                threshold,
+                got: self.signers.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // the payload of the poe followed by the concatenated 65bytes signatures
+    pub fn encode(&self) -> Vec<u8> {
+        let mut poe = self.poe.clone();
+        poe.signature = self
+            .signatures
+            .iter()
+            .flat_map(|sig| sig.iter().cloned())
+            .collect::<Vec<u8>>()
+            .into();
+        poe.encode()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::{H256, SH256};
+
+    fn poe() -> Poe {
+        let hash = |n: u8| -> SH256 { H256::repeat_byte(n).into() };
+        Poe::single_block(hash(1), hash(2), hash(3), hash(4))
+    }
+
+    fn address(key: &Secp256k1PrivateKey) -> SH160 {
+        key.public().eth_accountid().into()
+    }
+
+    #[test]
+    fn test_multi_poe() {
+        let chain_id = SU256::from(1_u64);
+        let keys: Vec<_> = (0..3).map(|_| Secp256k1PrivateKey::random()).collect();
+        let committee: Vec<SH160> = keys.iter().map(address).collect();
+
+        let mut multi = MultiPoe::new(poe());
+        for key in &keys[..2] {
+            assert_eq!(multi.sign(&chain_id, key).unwrap(), address(key));
+        }
+        assert!(matches!(
+            multi.sign(&chain_id, &keys[0]),
+            Err(PoeError::DuplicateSigner(_))
+        ));
+        assert_eq!(multi.len(), 2);
+        assert!(multi.signers[0] < multi.signers[1]);
+
+        assert!(multi.check_quorum(&chain_id, &committee, 2).is_ok());
+        assert!(matches!(
+            multi.check_quorum(&chain_id, &committee, 3),
+            Err(PoeError::QuorumNotReached {
+                threshold: 3,
+                got: 2
+            })
+        ));
+        assert!(matches!(
+            multi.check_quorum(&chain_id, &committee[2..], 1),
+            Err(PoeError::UnknownSigner(_))
+        ));
+        // the signatures don't come from the other chain
+        assert!(matches!(
+            multi.check_quorum(&SU256::from(2_u64), &committee, 2),
+            Err(PoeError::SignerMismatch { .. })
+        ));
+
+        let mut swapped = multi.clone();
+        swapped.signatures.swap(0, 1);
+        assert!(matches!(
+            swapped.check_quorum(&chain_id, &committee, 2),
+            Err(PoeError::SignerMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,24 @@
+use std::prelude::v1::*;
+
+use crypto::Secp256k1PrivateKey;
+use eth_types::SH160;
+
+use crate::crypto_provider;
+
+// Signs the Poe payloads without exposing the key, see Poe::sign_with. The
+// raw private key implements it for the existing callers.
+pub trait PoeSigner {
+    fn address(&self) -> SH160;
+    // the msg is Poe::sign_msg, the result is accepted by Poe::try_recover
+    fn sign_msg(&self, msg: &[u8]) -> [u8; 65];
+}
+
+impl PoeSigner for Secp256k1PrivateKey {
+    fn address(&self) -> SH160 {
+        self.public().eth_accountid().into()
+    }
+
+    fn sign_msg(&self, msg: &[u8]) -> [u8; 65] {
+        crypto_provider().secp256k1_sign(self, msg)
+    }
+}
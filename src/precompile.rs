@@ -2,10 +2,11 @@ use std::prelude::v1::*;
 
 use std::collections::BTreeMap;
 
-use crypto::{keccak_hash, secp256k1_ecdsa_recover, sha256_sum};
 use eth_types::{HexBytes, H160, SU256, U256};
 use std::borrow::Cow;
 
+use crate::{crypto_provider, keccak_hash, sha256_sum};
+
 use evm::{
     executor::stack::{
         IsPrecompileResult, PrecompileFailure, PrecompileHandle, PrecompileOutput,
@@ -15,7 +16,7 @@ use evm::{
 };
 use num_bigint::BigUint;
 use num_traits::identities::{One, Zero};
-use std::ops::Deref;
+use core::ops::Deref;
 
 lazy_static::lazy_static! {
     static ref SECP256K1N: SU256 = "115792089237316195423570985008687907852837564279074904382605163141518161494337".into();
@@ -26,6 +27,28 @@ pub type PrecompileResult = Result<PrecompileOutput, PrecompileFailure>;
 #[derive(Debug, Default)]
 pub struct PrecompileSet {
     fns: BTreeMap<H160, Box<dyn PrecompiledContract + Send + Sync>>,
+    repricing: BTreeMap<H160, PrecompileRepricing>,
+}
+
+// The gas of a precompile on a chain which repriced it, applied on top of
+// the contract's own required_gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrecompileRepricing {
+    Fixed(u64),
+    // required_gas * numerator / denominator, rounded down
+    Scale { numerator: u64, denominator: u64 },
+}
+
+impl PrecompileRepricing {
+    pub fn apply(&self, gas: u64) -> u64 {
+        match self {
+            Self::Fixed(gas) => *gas,
+            Self::Scale { numerator, denominator } => {
+                let gas = gas as u128 * *numerator as u128 / (*denominator).max(1) as u128;
+                gas.min(u64::MAX as u128) as u64
+            }
+        }
+    }
 }
 
 impl PrecompileSet {
@@ -59,6 +82,14 @@ impl PrecompileSet {
         def
     }
 
+    // the point evaluation(0x0a) is not supported, a call to it fails the
+    // execution instead of being treated as a call to an empty account.
+    pub fn cancun() -> Self {
+        let mut def = Self::berlin();
+        def.add(10, PrecompileUnimplemented { addr: 10 });
+        def
+    }
+
     pub fn scroll() -> Self {
         let mut def = Self::default();
         for i in 1..=9 {
@@ -93,6 +124,36 @@ impl PrecompileSet {
         self.fns.keys().map(|k| k.clone()).collect()
     }
 
+    // override the gas of the precompile at `addr`, the contract is kept
+    pub fn reprice(mut self, addr: H160, repricing: PrecompileRepricing) -> Self {
+        self.repricing.insert(addr, repricing);
+        self
+    }
+
+    // the gas charged for calling `addr` with `input`, including the repricing
+    pub fn required_gas(&self, addr: &H160, input: &[u8]) -> Option<u64> {
+        let p = self.fns.get(addr)?;
+        Some(self.gas_of(addr, p.as_ref(), input))
+    }
+
+    fn gas_of<P>(&self, addr: &H160, p: &P, input: &[u8]) -> u64
+    where
+        P: PrecompiledContract + ?Sized,
+    {
+        let gas = p.required_gas(input);
+        match self.repricing.get(addr) {
+            Some(repricing) => repricing.apply(gas),
+            None => gas,
+        }
+    }
+
+    // runs the contract outside of the evm, the gas is not charged
+    #[cfg(feature = "fuzz")]
+    pub(crate) fn run_raw(&self, addr: &H160, input: &[u8]) -> Option<(u64, PrecompileResult)> {
+        let p = self.fns.get(addr)?;
+        Some((self.gas_of(addr, p.as_ref(), input), p.run(input)))
+    }
+
     fn add<P>(&mut self, idx: u8, p: P)
     where
         P: PrecompiledContract + Send + Sync + 'static,
@@ -106,8 +167,18 @@ impl PrecompileSet {
 
 impl EvmPrecompileSet for PrecompileSet {
     fn execute(&self, handle: &mut impl PrecompileHandle) -> Option<PrecompileResult> {
-        let p = self.fns.get(&handle.code_address())?;
-        Some(run_precompiled_contract(p.as_ref(), handle))
+        let addr = handle.code_address();
+        let p = self.fns.get(&addr)?;
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
+        let gas_cost = self.gas_of(&addr, p.as_ref(), handle.input());
+        let result = run_precompiled_contract(p.as_ref(), gas_cost, handle);
+        #[cfg(feature = "metrics")]
+        crate::metrics::observe_precompile(
+            &format!("{:?}", handle.code_address()),
+            start.elapsed(),
+        );
+        Some(result)
     }
 
     fn is_precompile(&self, address: H160, _remaining_gas: u64) -> IsPrecompileResult {
@@ -118,11 +189,14 @@ impl EvmPrecompileSet for PrecompileSet {
     }
 }
 
-fn run_precompiled_contract<P>(p: &P, handle: &mut impl PrecompileHandle) -> PrecompileResult
+fn run_precompiled_contract<P>(
+    p: &P,
+    gas_cost: u64,
+    handle: &mut impl PrecompileHandle,
+) -> PrecompileResult
 where
     P: PrecompiledContract + ?Sized,
 {
-    let gas_cost = p.required_gas(handle.input());
     handle.record_cost(gas_cost)?;
     p.run(handle.input())
 }
@@ -145,7 +219,7 @@ impl PrecompiledContract for PrecompileUnimplemented {
         0
     }
     fn run(&self, _: &[u8]) -> PrecompileResult {
-        glog::error!("unimplemented addr: {}", self.addr);
+        error!("unimplemented addr: {}", self.addr);
         PrecompileResult::Err(PrecompileFailure::Fatal {
             exit_status: ExitFatal::NotSupported,
         })
@@ -175,16 +249,24 @@ const MUL_INPUT_LEN: usize = 128;
 /// Pair element length.
 const PAIR_ELEMENT_LEN: usize = 192;
 
+/// Reads `N` bytes at `pos`, the part beyond the input is zero-padded.
+fn read_padded<const N: usize>(input: &[u8], pos: usize) -> [u8; N] {
+    let mut buf = [0u8; N];
+    if pos < input.len() {
+        let end = input.len().min(pos + N);
+        buf[..end - pos].copy_from_slice(&input[pos..end]);
+    }
+    buf
+}
+
 /// Reads the `x` and `y` points from an input at a given position.
 fn read_point(input: &[u8], pos: usize) -> bn::G1 {
     use bn::{AffineG1, Fq, Group, G1};
 
-    let mut px_buf = [0u8; 32];
-    px_buf.copy_from_slice(&input[pos..(pos + 32)]);
+    let px_buf: [u8; 32] = read_padded(input, pos);
     let px = Fq::from_slice(&px_buf).unwrap(); // .unwrap(); //.map_err(|_| Error::Bn128FieldPointNotAMember)?;
 
-    let mut py_buf = [0u8; 32];
-    py_buf.copy_from_slice(&input[(pos + 32)..(pos + 64)]);
+    let py_buf: [u8; 32] = read_padded(input, pos + 32);
     let py = Fq::from_slice(&py_buf).unwrap(); //.unwrap(); //.map_err(|_| Error::Bn128FieldPointNotAMember)?;
 
     if px == Fq::zero() && py == bn::Fq::zero() {
@@ -204,11 +286,9 @@ impl PrecompiledContract for PrecompileAddIstanbul {
     fn run(&self, input: &[u8]) -> PrecompileResult {
         use bn::AffineG1;
 
-        let mut input = input.to_vec();
-        input.resize(ADD_INPUT_LEN, 0);
-
-        let p1 = read_point(&input, 0);
-        let p2 = read_point(&input, 64);
+        let input = &input[..input.len().min(ADD_INPUT_LEN)];
+        let p1 = read_point(input, 0);
+        let p2 = read_point(input, 64);
 
         let mut output = [0u8; 64];
         if let Some(sum) = AffineG1::from_jacobian(p1 + p2) {
@@ -239,13 +319,10 @@ impl PrecompiledContract for PrecompileMulIstanbul {
     fn run(&self, input: &[u8]) -> PrecompileResult {
         use bn::AffineG1;
 
-        let mut input = input.to_vec();
-        input.resize(MUL_INPUT_LEN, 0);
-
-        let p = read_point(&input, 0);
+        let input = &input[..input.len().min(MUL_INPUT_LEN)];
+        let p = read_point(input, 0);
 
-        let mut fr_buf = [0u8; 32];
-        fr_buf.copy_from_slice(&input[64..96]);
+        let fr_buf: [u8; 32] = read_padded(input, 64);
         // Fr::from_slice can only fail on incorect length, and this is not a case.
         let fr = bn::Fr::from_slice(&fr_buf[..]).unwrap();
 
@@ -403,7 +480,7 @@ impl PrecompiledContract for PrecompileEcrecover {
                 return Vec::new();
             }
 
-            let pubkey = match secp256k1_ecdsa_recover(&sig, &msg) {
+            let pubkey = match crypto_provider().secp256k1_ecrecover(&sig, &msg) {
                 Some(pubkey) => pubkey,
                 None => return Vec::new(),
             };
@@ -462,7 +539,7 @@ impl PrecompiledContract for PrecompileRipemd160Hash {
     }
 
     fn run(&self, input: &[u8]) -> PrecompileResult {
-        glog::debug!("input: {:?}", HexBytes::from(input.to_vec()));
+        debug!("input: {:?}", HexBytes::from(input.to_vec()));
         use ripemd160::{Digest, Ripemd160};
         let output = Ripemd160::digest(input).to_vec();
         let mut val = [0_u8; 32];
@@ -619,15 +696,9 @@ pub struct PrecompileBigModExp {
 
 impl PrecompiledContract for PrecompileBigModExp {
     fn required_gas(&self, input: &[u8]) -> u64 {
-        // Padding data to be at least 32 * 3 bytes.
-        let mut data: Vec<u8> = input.into();
-        while data.len() < 32 * 3 {
-            data.push(0);
-        }
-
-        let base_len = U256::from(&data[0..32]).as_usize();
-        let exp_len = U256::from(&data[32..64]).as_usize();
-        let mod_len = U256::from(&data[64..96]).as_usize();
+        let base_len = U256::from(&read_padded::<32>(input, 0)[..]).as_usize();
+        let exp_len = U256::from(&read_padded::<32>(input, 32)[..]).as_usize();
+        let mod_len = U256::from(&read_padded::<32>(input, 64)[..]).as_usize();
 
         let input = input.get(96..).unwrap_or(&[]);
 
@@ -686,15 +757,9 @@ impl PrecompiledContract for PrecompileBigModExp {
     }
 
     fn run(&self, input: &[u8]) -> PrecompileResult {
-        // Padding data to be at least 32 * 3 bytes.
-        let mut data: Vec<u8> = input.into();
-        while data.len() < 32 * 3 {
-            data.push(0);
-        }
-
-        let base_length = U256::from(&data[0..32]);
-        let exponent_length = U256::from(&data[32..64]);
-        let modulus_length = U256::from(&data[64..96]);
+        let base_length = U256::from(&read_padded::<32>(input, 0)[..]);
+        let exponent_length = U256::from(&read_padded::<32>(input, 32)[..]);
+        let modulus_length = U256::from(&read_padded::<32>(input, 64)[..]);
 
         // if base_length > U256::from(usize::max_value())
         //     || exponent_length > U256::from(usize::max_value())
@@ -726,54 +791,37 @@ impl PrecompiledContract for PrecompileBigModExp {
             });
         }
 
-        let mut base_arr = Vec::new();
-        let mut exponent_arr = Vec::new();
-        let mut modulus_arr = Vec::new();
-
-        for i in 0..base_length {
-            if 96 + i >= data.len() {
-                base_arr.push(0u8);
-            } else {
-                base_arr.push(data[96 + i]);
-            }
-        }
-        for i in 0..exponent_length {
-            if 96 + base_length + i >= data.len() {
-                exponent_arr.push(0u8);
-            } else {
-                exponent_arr.push(data[96 + base_length + i]);
-            }
-        }
-        for i in 0..modulus_length {
-            if 96 + base_length + exponent_length + i >= data.len() {
-                modulus_arr.push(0u8);
-            } else {
-                modulus_arr.push(data[96 + base_length + exponent_length + i]);
-            }
+        // base, exponent and modulus share one zero-extended buffer
+        let total = base_length + exponent_length + modulus_length;
+        let mut scratch = vec![0u8; total];
+        let body = input.get(96..).unwrap_or(&[]);
+        let copied = body.len().min(total);
+        scratch[..copied].copy_from_slice(&body[..copied]);
+        let (base_arr, rest) = scratch.split_at(base_length);
+        let (exponent_arr, modulus_arr) = rest.split_at(exponent_length);
+
+        let base = BigUint::from_bytes_be(base_arr);
+        let exponent = BigUint::from_bytes_be(exponent_arr);
+        let modulus = BigUint::from_bytes_be(modulus_arr);
+
+        // reuse the scratch buffer for the left-padded output
+        scratch.truncate(modulus_length);
+        for b in scratch.iter_mut() {
+            *b = 0;
         }
-
-        let base = BigUint::from_bytes_be(&base_arr);
-        let exponent = BigUint::from_bytes_be(&exponent_arr);
-        let modulus = BigUint::from_bytes_be(&modulus_arr);
-
-        let mut result = if modulus.is_zero() || modulus.is_one() {
-            BigUint::zero().to_bytes_be()
-        } else {
-            base.modpow(&exponent, &modulus).to_bytes_be()
-        };
-        assert!(result.len() <= modulus_length);
-        while result.len() < modulus_length {
-            result.insert(0, 0u8);
+        if !(modulus.is_zero() || modulus.is_one()) {
+            let value = base.modpow(&exponent, &modulus).to_bytes_be();
+            assert!(value.len() <= modulus_length);
+            scratch[modulus_length - value.len()..].copy_from_slice(&value);
         }
-
         Ok(PrecompileOutput {
             exit_status: ExitSucceed::Returned,
-            output: result,
+            output: scratch,
         })
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::{io::Read};
 
@@ -956,4 +1004,26 @@ mod test {
         assert_eq!(expect, output);
         assert_eq!(contract.required_gas(&input), 200); // 16
     }
+
+    #[test]
+    fn test_repricing() {
+        let mut ecrecover = H160::default();
+        ecrecover.0[19] = 1;
+        let mut identity = H160::default();
+        identity.0[19] = 4;
+        let set = PrecompileSet::berlin()
+            .reprice(ecrecover, PrecompileRepricing::Fixed(6000))
+            .reprice(
+                identity,
+                PrecompileRepricing::Scale {
+                    numerator: 3,
+                    denominator: 2,
+                },
+            );
+        assert_eq!(set.required_gas(&ecrecover, &[]), Some(6000));
+        // (15 + 3 * 2) * 3 / 2
+        assert_eq!(set.required_gas(&identity, &[0_u8; 64]), Some(31));
+        assert_eq!(PrecompileSet::berlin().required_gas(&identity, &[0_u8; 64]), Some(21));
+        assert_eq!(set.required_gas(&H160::default(), &[]), None);
+    }
 }
@@ -0,0 +1,87 @@
+use std::prelude::v1::*;
+
+use eth_types::Block;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::{verify_block, BatchSource, BlockBuilder, EthereumPobSource, Pob, PobBlock};
+
+// The python module for scripting the replays and the witness statistics,
+// the pob is the binary encoding of Pob::to_bytes. Build it as an extension
+// module with the `python` feature.
+
+fn value_err<E: core::fmt::Debug>(err: E) -> PyErr {
+    PyValueError::new_err(format!("{:?}", err))
+}
+
+fn decode_pob(pob: &[u8]) -> PyResult<Pob<Block>> {
+    Pob::<Block>::from_bytes(pob).map_err(value_err)
+}
+
+// returns the unsigned poe(Poe::encode) of the block
+#[pyfunction]
+#[pyo3(name = "verify_block")]
+fn py_verify_block(py: Python, pob: &[u8]) -> PyResult<PyObject> {
+    let pob = decode_pob(pob)?;
+    let poe = verify_block(&EthereumPobSource, pob).map_err(value_err)?;
+    Ok(PyBytes::new(py, &poe.encode()).into())
+}
+
+// the block and the witness sizes of the pob
+#[pyfunction]
+fn pob_info(py: Python, pob: &[u8]) -> PyResult<PyObject> {
+    let mut pob = decode_pob(pob)?;
+    let info = PyDict::new(py);
+    info.set_item("version", pob.data.version)?;
+    info.set_item("chain_id", pob.data.chain_id)?;
+    info.set_item("number", pob.block.number())?;
+    info.set_item("block_hash", format!("{:?}", pob.block_hash()))?;
+    info.set_item("state_hash", format!("{:?}", pob.state_hash()))?;
+    info.set_item("prev_state_root", format!("{:?}", pob.data.prev_state_root))?;
+    info.set_item("txs", pob.block.transactions.len())?;
+    let nodes = &pob.data.mpt_nodes;
+    info.set_item("mpt_nodes", nodes.len())?;
+    info.set_item("mpt_bytes", nodes.iter().map(|n| n.len()).sum::<usize>())?;
+    let codes = &pob.data.codes;
+    info.set_item("codes", codes.len())?;
+    info.set_item("code_bytes", codes.iter().map(|c| c.len()).sum::<usize>())?;
+    info.set_item("block_hashes", pob.data.block_hashes.len())?;
+    let withdrawals = pob.data.withdrawals.as_ref().map(|w| w.len());
+    info.set_item("withdrawals", withdrawals)?;
+    info.set_item("has_expected", pob.data.expected.is_some())?;
+    Ok(info.into())
+}
+
+// execute the block and return the TxTrace of each tx as json, a failed tx
+// raises a ValueError with its index.
+#[pyfunction]
+fn trace_block(pob: &[u8]) -> PyResult<Vec<String>> {
+    let pob = decode_pob(pob)?;
+    let source = EthereumPobSource;
+    let engine = source.engine(&pob).map_err(value_err)?;
+    let header = source.header(&pob).map_err(value_err)?;
+    let txs = source.transactions(&pob).map_err(value_err)?;
+    let statedb = source.statedb(&pob).map_err(value_err)?;
+    let mut builder = BlockBuilder::new(engine, statedb, pob.data.block_hashes.clone(), header)
+        .map_err(value_err)?;
+    builder.record_traces();
+    for (idx, tx) in txs.into_iter().enumerate() {
+        builder
+            .commit(tx)
+            .map_err(|err| PyValueError::new_err(format!("tx[{}]: {}", idx, err)))?;
+    }
+    let traces = builder.traces().unwrap_or_default();
+    traces
+        .iter()
+        .map(|trace| serde_json::to_string(trace).map_err(value_err))
+        .collect()
+}
+
+#[pymodule]
+fn evm_executor(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(py_verify_block, m)?)?;
+    m.add_function(wrap_pyfunction!(pob_info, m)?)?;
+    m.add_function(wrap_pyfunction!(trace_block, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, SH256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::mpt;
+
+// The merkle patricia proof of a receipt against the receipts root of the
+// block. The key is rlp(tx_index) and the value is the encoded receipt, see
+// Engine::encode_receipt.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptProof {
+    pub tx_index: u64,
+    pub receipts_root: SH256,
+    pub receipt: HexBytes,
+    pub proof: Vec<HexBytes>, // from the root node
+}
+
+impl ReceiptProof {
+    // panics if `tx_index` is out of range
+    pub fn new(receipts: &[Vec<u8>], tx_index: usize) -> Self {
+        let items: BTreeMap<Vec<u8>, Vec<u8>> = receipts
+            .iter()
+            .enumerate()
+            .map(|(idx, receipt)| (Self::key(idx as u64), receipt.clone()))
+            .collect();
+        let (receipts_root, proof) = mpt::build_proof(&items, &Self::key(tx_index as u64));
+        Self {
+            tx_index: tx_index as u64,
+            receipts_root,
+            receipt: receipts[tx_index].clone().into(),
+            proof: proof.into_iter().map(|node| node.into()).collect(),
+        }
+    }
+
+    // the receipts root of the block, without building any proof
+    pub fn root(receipts: &[Vec<u8>]) -> SH256 {
+        mpt::ordered_trie_root(receipts)
+    }
+
+    fn key(tx_index: u64) -> Vec<u8> {
+        rlp::encode(&tx_index).to_vec()
+    }
+
+    // check the receipt against the receipts root of the block header
+    pub fn verify(&self, receipts_root: &SH256) -> Result<(), String> {
+        if &self.receipts_root != receipts_root {
+            return Err(format!(
+                "receipts root mismatch: want: {:?}, got: {:?}",
+                receipts_root, self.receipts_root
+            ));
+        }
+        let proof: Vec<Vec<u8>> = self
+            .proof
+            .iter()
+            .map(|node| node.as_bytes().to_vec())
+            .collect();
+        match mpt::verify_proof(*receipts_root, &Self::key(self.tx_index), &proof)? {
+            Some(value) if value == self.receipt.as_bytes() => Ok(()),
+            Some(_) => Err(format!("receipt mismatch at index {}", self.tx_index)),
+            None => Err(format!("receipt not found at index {}", self.tx_index)),
+        }
+    }
+}
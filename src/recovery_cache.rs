@@ -0,0 +1,173 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{Signer, TxTrait, SH160, SH256, SU256};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{crypto_provider, keccak_hash};
+
+// The secp256k1 recovery results by (msg hash, signature). The batch
+// verification recovers the same Poe and tx signatures at several stages
+// (pob check, block execution, batch poe), each one costs an ecrecover.
+// The oldest entry is dropped once `cap` is reached.
+#[derive(Debug)]
+pub struct RecoveryCache {
+    cap: usize,
+    inner: RefCell<RecoveryEntries>,
+}
+
+#[derive(Debug, Default)]
+struct RecoveryEntries {
+    signers: BTreeMap<(SH256, [u8; 65]), SH160>,
+    // the tx hash covers the signature, the chain id picks the signer
+    senders: BTreeMap<(SH256, SU256), SH160>,
+    order: VecDeque<RecoveryKey>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug)]
+enum RecoveryKey {
+    Signer(SH256, [u8; 65]),
+    Sender(SH256, SU256),
+}
+
+impl Default for RecoveryCache {
+    fn default() -> Self {
+        Self::new(4096)
+    }
+}
+
+impl RecoveryCache {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            inner: RefCell::new(RecoveryEntries::default()),
+        }
+    }
+
+    // same as CryptoProvider::secp256k1_recover_signer, the failures are not
+    // cached.
+    pub fn recover_signer(&self, sig: &[u8; 65], msg: &[u8]) -> Option<SH160> {
+        let hash: SH256 = keccak_hash(msg).into();
+        if let Some(signer) = self.lookup(|e| e.signers.get(&(hash, *sig)).cloned()) {
+            return Some(signer);
+        }
+        let signer = crypto_provider().secp256k1_recover_signer(sig, msg)?;
+        self.insert(RecoveryKey::Signer(hash, *sig), signer);
+        Some(signer)
+    }
+
+    // same as TxTrait::sender, the zero address(invalid signature) is not cached
+    pub fn sender<T: TxTrait>(&self, tx: &T, signer: &Signer) -> SH160 {
+        let key = (tx.hash(), signer.chain_id.clone());
+        if let Some(sender) = self.lookup(|e| e.senders.get(&key).cloned()) {
+            return sender;
+        }
+        let sender = tx.sender(signer);
+        if sender != SH160::default() {
+            self.insert(RecoveryKey::Sender(key.0, key.1), sender);
+        }
+        sender
+    }
+
+    fn lookup<F>(&self, f: F) -> Option<SH160>
+    where
+        F: FnOnce(&RecoveryEntries) -> Option<SH160>,
+    {
+        let mut entries = self.inner.borrow_mut();
+        let found = f(&entries);
+        match found {
+            Some(_) => entries.hits += 1,
+            None => entries.misses += 1,
+        }
+        found
+    }
+
+    fn insert(&self, key: RecoveryKey, addr: SH160) {
+        let mut entries = self.inner.borrow_mut();
+        if self.cap == 0 {
+            return;
+        }
+        if entries.order.len() >= self.cap {
+            match entries.order.pop_front() {
+                Some(RecoveryKey::Signer(hash, sig)) => {
+                    entries.signers.remove(&(hash, sig));
+                }
+                Some(RecoveryKey::Sender(hash, chain_id)) => {
+                    entries.senders.remove(&(hash, chain_id));
+                }
+                None => {}
+            }
+        }
+        match &key {
+            RecoveryKey::Signer(hash, sig) => entries.signers.insert((*hash, *sig), addr),
+            RecoveryKey::Sender(hash, chain_id) => {
+                entries.senders.insert((*hash, chain_id.clone()), addr)
+            }
+        };
+        entries.order.push_back(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.borrow().order.len()
+    }
+
+    // returns (hits, misses)
+    pub fn stats(&self) -> (u64, u64) {
+        let entries = self.inner.borrow();
+        (entries.hits, entries.misses)
+    }
+
+    pub fn clear(&self) {
+        *self.inner.borrow_mut() = RecoveryEntries::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PoeSigner;
+    use crypto::Secp256k1PrivateKey;
+
+    #[test]
+    fn test_recovery_cache() {
+        let key = Secp256k1PrivateKey::random();
+        let msgs: Vec<Vec<u8>> = (0..3_u8).map(|n| vec![n; 32]).collect();
+        let sigs: Vec<[u8; 65]> = msgs.iter().map(|msg| key.sign_msg(msg)).collect();
+
+        let cache = RecoveryCache::new(2);
+        assert_eq!(
+            cache.recover_signer(&sigs[0], &msgs[0]),
+            Some(key.address())
+        );
+        assert_eq!(
+            cache.recover_signer(&sigs[0], &msgs[0]),
+            Some(key.address())
+        );
+        assert_eq!(cache.stats(), (1, 1));
+        assert_eq!(cache.len(), 1);
+
+        // the first signature is dropped once the cap is reached
+        for (sig, msg) in sigs.iter().zip(&msgs).skip(1) {
+            assert_eq!(cache.recover_signer(sig, msg), Some(key.address()));
+        }
+        assert_eq!(cache.len(), 2);
+        cache.recover_signer(&sigs[2], &msgs[2]);
+        cache.recover_signer(&sigs[0], &msgs[0]);
+        assert_eq!(cache.stats(), (2, 4));
+
+        // the same signature over another msg is not a hit
+        assert_ne!(
+            cache.recover_signer(&sigs[0], &msgs[1]),
+            Some(key.address())
+        );
+        assert_eq!(cache.stats(), (2, 5));
+
+        cache.clear();
+        assert_eq!((cache.len(), cache.stats()), (0, (0, 0)));
+        let disabled = RecoveryCache::new(0);
+        disabled.recover_signer(&sigs[0], &msgs[0]);
+        assert_eq!(disabled.len(), 0);
+    }
+}
@@ -0,0 +1,39 @@
+use std::prelude::v1::*;
+
+// The gas refund rules. EIP-3529 (london) lowered the SSTORE clear refund,
+// removed the SELFDESTRUCT refund and capped the refund at 1/5 of the used
+// gas instead of 1/2. Replaying the older blocks with the new rules yields a
+// different used_gas and the receipts diverge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefundRules {
+    PreEip3529,
+    Eip3529,
+}
+
+impl RefundRules {
+    // the rules of the block `number`, `eip3529_block` is the london block
+    pub fn at(eip3529_block: u64, number: u64) -> Self {
+        match number < eip3529_block {
+            true => Self::PreEip3529,
+            false => Self::Eip3529,
+        }
+    }
+
+    // only the refund fields are touched, the rest of the fork stays
+    pub fn apply(&self, cfg: &mut evm::Config) {
+        match self {
+            Self::PreEip3529 => {
+                cfg.refund_sstore_clears = 15000;
+                cfg.max_refund_quotient = 2;
+                // also gates the SELFDESTRUCT refund in the gasometer
+                cfg.decrease_clears_refund = false;
+            }
+            Self::Eip3529 => {
+                // SSTORE_RESET_GAS + ACCESS_LIST_STORAGE_KEY_COST
+                cfg.refund_sstore_clears = 4800;
+                cfg.max_refund_quotient = 5;
+                cfg.decrease_clears_refund = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::prelude::v1::*;
+
+use eth_types::{Block, BlockHeader, HexBytes, SH256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::{BatchSource, BlockBuilder, EthereumPobSource, Pob, PobBlock};
+
+// set it to record the current results over the fixture file
+pub const UPDATE_FIXTURES_ENV: &str = "UPDATE_FIXTURES";
+
+// The results of a replayed block, recorded once and compared on the next
+// runs so a change in the execution shows up as a test failure.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockFixture {
+    pub number: u64,
+    pub block_hash: SH256,
+    pub state_root: SH256,
+    pub receipts_root: SH256,
+    pub gas_used: u64,
+    pub logs_bloom: HexBytes,
+}
+
+impl BlockFixture {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        Self {
+            number: header.number.as_u64(),
+            block_hash: header.hash(),
+            state_root: header.state_root,
+            receipts_root: header.receipts_root,
+            gas_used: header.gas_used.as_u64(),
+            logs_bloom: header.logs_bloom.clone(),
+        }
+    }
+
+    // execute the block of the pob and take the results from the built
+    // header, the block hash is the one of the pob.
+    pub fn replay(pob: &Pob<Block>) -> Result<Self, String> {
+        let source = EthereumPobSource;
+        let engine = source.engine(pob)?;
+        let header = source.header(pob)?;
+        let txs = source.transactions(pob)?;
+        let withdrawals = source.withdrawals(pob)?;
+        let statedb = source.statedb(pob)?;
+        let mut builder =
+            BlockBuilder::new(engine, statedb, pob.data.block_hashes.clone(), header)?;
+        for (idx, tx) in txs.into_iter().enumerate() {
+            builder
+                .commit(tx)
+                .map_err(|err| format!("tx[{}] commit fail: {}", idx, err))?;
+        }
+        if let Some(withdrawals) = withdrawals {
+            builder
+                .withdrawal(withdrawals)
+                .map_err(|err| format!("{:?}", err))?;
+        }
+        let block = builder.finalize()?;
+        let mut fixture = Self::from_header(&block.header);
+        fixture.block_hash = pob.block.hash();
+        Ok(fixture)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureMismatch {
+    pub number: u64,
+    pub field: &'static str,
+    pub expect: String,
+    pub got: String,
+}
+
+// The fixtures of a set of blocks in one json file, keyed by the number.
+#[derive(Debug)]
+pub struct RegressionFixtures {
+    path: PathBuf,
+    blocks: BTreeMap<u64, BlockFixture>,
+    update: bool,
+    dirty: bool,
+}
+
+impl RegressionFixtures {
+    // a missing file is an empty set
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let blocks = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| format!("{}: {}", path.display(), err))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        };
+        Ok(Self {
+            path,
+            blocks,
+            update: std::env::var_os(UPDATE_FIXTURES_ENV).is_some(),
+            dirty: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // records the block if it's new or UPDATE_FIXTURES is set, otherwise
+    // returns the fields which differ from the recorded ones.
+    pub fn check(&mut self, got: BlockFixture) -> Vec<FixtureMismatch> {
+        let expect = match self.blocks.get(&got.number) {
+            Some(expect) if !self.update => expect,
+            _ => {
+                self.dirty = true;
+                self.blocks.insert(got.number, got);
+                return Vec::new();
+            }
+        };
+        let number = got.number;
+        let mut mismatches = Vec::new();
+        let mut diff = |field: &'static str, expect: String, got: String| {
+            if expect != got {
+                mismatches.push(FixtureMismatch {
+                    number,
+                    field,
+                    expect,
+                    got,
+                });
+            }
+        };
+        diff(
+            "block_hash",
+            format!("{:?}", expect.block_hash),
+            format!("{:?}", got.block_hash),
+        );
+        diff(
+            "state_root",
+            format!("{:?}", expect.state_root),
+            format!("{:?}", got.state_root),
+        );
+        diff(
+            "receipts_root",
+            format!("{:?}", expect.receipts_root),
+            format!("{:?}", got.receipts_root),
+        );
+        diff(
+            "gas_used",
+            expect.gas_used.to_string(),
+            got.gas_used.to_string(),
+        );
+        diff(
+            "logs_bloom",
+            format!("{:?}", expect.logs_bloom),
+            format!("{:?}", got.logs_bloom),
+        );
+        mismatches
+    }
+
+    // writes the recorded blocks back if anything changed
+    pub fn save(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(&self.blocks).map_err(|err| format!("{}", err))?;
+        std::fs::write(&self.path, data)
+            .map_err(|err| format!("{}: {}", self.path.display(), err))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // replay every *.pob(Pob::to_bytes) of `dir` in the name order and check
+    // it against the fixtures, the new blocks are recorded by save.
+    pub fn check_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<Vec<FixtureMismatch>, String> {
+        let dir = dir.as_ref();
+        let entries =
+            std::fs::read_dir(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|err| format!("{}: {}", dir.display(), err))?
+                .path();
+            if path.extension().map(|ext| ext == "pob").unwrap_or(false) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut mismatches = Vec::new();
+        for path in paths {
+            let data =
+                std::fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            let pob = Pob::<Block>::from_bytes(&data)
+                .map_err(|err| format!("{}: {:?}", path.display(), err))?;
+            let got =
+                BlockFixture::replay(&pob).map_err(|err| format!("{}: {}", path.display(), err))?;
+            mismatches.extend(self.check(got));
+        }
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // the pobs of the blocks which broke before are in
+    // src/testdata/regression. the test doesn't write the fixtures, run it
+    // with UPDATE_FIXTURES to record a new pob or a change of the results.
+    #[test]
+    fn test_regression_fixtures() {
+        let dir = Path::new("src/testdata/regression");
+        let mut fixtures = RegressionFixtures::open(dir.join("regression.json")).unwrap();
+        let recorded = fixtures.len();
+        assert!(recorded > 0);
+        let mismatches = fixtures.check_dir(dir).unwrap();
+        if std::env::var_os(UPDATE_FIXTURES_ENV).is_some() {
+            fixtures.save().unwrap();
+            return;
+        }
+        assert!(mismatches.is_empty(), "{:#?}", mismatches);
+        assert_eq!(fixtures.len(), recorded, "a pob without fixture");
+    }
+}
@@ -0,0 +1,124 @@
+use std::prelude::v1::*;
+
+use eth_types::{Block, HexBytes, Receipt, TransactionInner, SH256, SU256};
+use serde::{Deserialize, Serialize};
+use statedb::StateDB;
+use std::sync::Arc;
+
+use crate::{BlockBuilder, Ethereum, Pob};
+
+// The access to a live node, usually backed by eth_getBlockByNumber,
+// eth_getBlockReceipts and debug_executionWitness(or eth_getProof).
+pub trait ReplaySource {
+    type StateDB: StateDB;
+    fn chain_id(&self) -> Result<SU256, String>;
+    fn block(&self, number: u64) -> Result<Block, String>;
+    fn transactions(&self, block: &Block) -> Result<Vec<Arc<TransactionInner>>, String>;
+    fn receipts(&self, block: &Block) -> Result<Vec<Receipt>, String>;
+    fn prestate(&self, block: &Block) -> Result<Pob, String>;
+    fn statedb(&self, pob: &Pob) -> Result<Self::StateDB, String>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayDivergence {
+    pub field: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_index: Option<usize>,
+    pub expect: String,
+    pub got: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub block_number: u64,
+    pub block_hash: SH256,
+    pub txs: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // the execution stopped early
+    pub divergences: Vec<ReplayDivergence>,
+}
+
+impl ReplayReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.divergences.is_empty()
+    }
+
+    fn diff<T: PartialEq + core::fmt::Debug>(
+        &mut self,
+        field: &str,
+        tx_index: Option<usize>,
+        expect: &T,
+        got: &T,
+    ) {
+        if expect != got {
+            self.divergences.push(ReplayDivergence {
+                field: field.into(),
+                tx_index,
+                expect: format!("{:?}", expect),
+                got: format!("{:?}", got),
+            });
+        }
+    }
+}
+
+// fetch the block and its prestate, execute it locally and diff the results
+// against the node.
+pub fn replay_block<S: ReplaySource>(source: &S, number: u64) -> Result<ReplayReport, String> {
+    let block = source.block(number)?;
+    let txs = source.transactions(&block)?;
+    let expect_receipts = source.receipts(&block)?;
+    let pob = source.prestate(&block)?;
+    let statedb = source.statedb(&pob)?;
+
+    let mut report = ReplayReport {
+        block_number: number,
+        block_hash: block.header.hash(),
+        txs: txs.len(),
+        ..Default::default()
+    };
+
+    let engine = Ethereum::new(source.chain_id()?);
+    let mut builder = BlockBuilder::new(
+        engine,
+        statedb,
+        pob.data.block_hashes.clone(),
+        block.header.clone(),
+    )?;
+    for (idx, tx) in txs.into_iter().enumerate() {
+        let receipt = match builder.commit(tx) {
+            Ok(receipt) => receipt.clone(),
+            Err(err) => {
+                report.error = Some(format!("tx[{}] commit fail: {}", idx, err));
+                return Ok(report);
+            }
+        };
+        if let Some(expect) = expect_receipts.get(idx) {
+            report.diff("status", Some(idx), &expect.status, &receipt.status);
+            report.diff("gas_used", Some(idx), &expect.gas_used, &receipt.gas_used);
+            report.diff(
+                "logs_bloom",
+                Some(idx),
+                &expect.logs_bloom,
+                &receipt.logs_bloom,
+            );
+        }
+    }
+    if let Some(withdrawals) = pob.data.withdrawals.clone() {
+        builder
+            .withdrawal(withdrawals)
+            .map_err(|err| format!("{:?}", err))?;
+    }
+
+    let got = builder.finalize()?;
+    let (expect, got) = (&block.header, &got.header);
+    report.diff("state_root", None, &expect.state_root, &got.state_root);
+    report.diff(
+        "receipts_root",
+        None,
+        &expect.receipts_root,
+        &got.receipts_root,
+    );
+    report.diff("gas_used", None, &expect.gas_used, &got.gas_used);
+    report.diff::<HexBytes>("logs_bloom", None, &expect.logs_bloom, &got.logs_bloom);
+    Ok(report)
+}
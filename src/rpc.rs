@@ -0,0 +1,319 @@
+use std::prelude::v1::*;
+
+use eth_types::{
+    BlockHeaderTrait, HexBytes, TransactionAccessTuple, TxTrait, SH160, SH256, SU256, SU64,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use statedb::StateDB;
+use std::sync::Arc;
+
+use crate::{
+    AccessListResult, BlockHashGetter, CallFrame, CallLog, Engine, ExecuteError, ExecuteResult,
+    FeePolicy, PrecompileSet, TxContext, TxExecutor,
+};
+
+// The `from` is the caller, the rest follows the tx fields. Missing fields
+// are filled by RpcExecutor before the tx is built.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    #[serde(default)]
+    pub from: Option<SH160>,
+    #[serde(default)]
+    pub to: Option<SH160>,
+    #[serde(default)]
+    pub gas: Option<SU64>,
+    #[serde(default)]
+    pub gas_price: Option<SU256>,
+    #[serde(default)]
+    pub max_fee_per_gas: Option<SU256>,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: Option<SU256>,
+    #[serde(default)]
+    pub value: Option<SU256>,
+    #[serde(default, alias = "input")]
+    pub data: Option<HexBytes>,
+    #[serde(default)]
+    pub nonce: Option<SU64>,
+    #[serde(default)]
+    pub access_list: Option<Vec<TransactionAccessTuple>>,
+}
+
+// The unsigned tx of a call request, the engines know their tx layout. The
+// signature is never checked since the caller is taken from `from`.
+pub trait CallRequestTx: TxTrait + Sized {
+    fn from_call_request(req: &CallRequest, chain_id: &SU256) -> Self;
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<HexBytes>,
+}
+
+impl RpcError {
+    pub fn invalid_params(message: String) -> Self {
+        Self {
+            code: -32602,
+            message,
+            data: None,
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("the method {} does not exist/is not available", method),
+            data: None,
+        }
+    }
+
+    pub fn execute(err: ExecuteError) -> Self {
+        Self {
+            code: -32000,
+            message: format!("{}", err),
+            data: None,
+        }
+    }
+
+    // geth returns the revert data with the code 3
+    pub fn reverted(result: &ExecuteResult) -> Self {
+        Self {
+            code: 3,
+            message: "execution reverted".into(),
+            data: Some(result.return_data.clone()),
+        }
+    }
+}
+
+const MIN_GAS: u64 = 21000;
+
+// Serves the read only calls against a StateDB snapshot. Every call starts
+// from the snapshot root, the changes are reverted after the execution.
+pub struct RpcExecutor<'a, E: Engine, D: StateDB, H: BlockHashGetter> {
+    engine: &'a E,
+    header: &'a E::BlockHeader,
+    block_hash_getter: &'a H,
+    statedb: D,
+    root: SH256,
+    cfg: evm::Config,
+    precompile: Arc<PrecompileSet>,
+}
+
+impl<'a, E, D, H> RpcExecutor<'a, E, D, H>
+where
+    E: Engine,
+    E::Transaction: CallRequestTx,
+    D: StateDB,
+    H: BlockHashGetter,
+{
+    pub fn new(
+        engine: &'a E,
+        header: &'a E::BlockHeader,
+        block_hash_getter: &'a H,
+        mut statedb: D,
+    ) -> Result<Self, statedb::Error> {
+        let root = statedb.flush()?;
+        Ok(Self {
+            cfg: engine.evm_config_at(header),
+            precompile: engine.precompile_at(header),
+            engine,
+            header,
+            block_hash_getter,
+            statedb,
+            root,
+        })
+    }
+
+    pub fn handle(&mut self, req: RpcRequest) -> RpcResponse {
+        let (result, error) = match self.dispatch(&req.method, req.params) {
+            Ok(result) => (Some(result), None),
+            Err(err) => (None, Some(err)),
+        };
+        RpcResponse {
+            jsonrpc: req.jsonrpc,
+            id: req.id,
+            result,
+            error,
+        }
+    }
+
+    fn dispatch(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let req = match method {
+            "eth_call" | "eth_estimateGas" | "debug_traceCall" | "eth_createAccessList" => {
+                Self::call_request(params)?
+            }
+            _ => return Err(RpcError::method_not_found(method)),
+        };
+        let result = match method {
+            "eth_call" => serde_json::to_value(self.call(req)?),
+            "eth_estimateGas" => serde_json::to_value(SU64::from(self.estimate_gas(req)?)),
+            "debug_traceCall" => serde_json::to_value(self.trace_call(req)?),
+            _ => serde_json::to_value(self.create_access_list(req)?),
+        };
+        Ok(result.expect("rpc result should be serializable"))
+    }
+
+    // the block tag is ignored, the calls always run on the snapshot
+    fn call_request(params: Value) -> Result<CallRequest, RpcError> {
+        let param = match params {
+            Value::Array(mut list) if !list.is_empty() => list.swap_remove(0),
+            _ => return Err(RpcError::invalid_params("missing call request".into())),
+        };
+        serde_json::from_value(param).map_err(|err| RpcError::invalid_params(format!("{}", err)))
+    }
+
+    pub fn call(&mut self, req: CallRequest) -> Result<HexBytes, RpcError> {
+        let result = self.execute(req).map_err(RpcError::execute)?;
+        if !result.success {
+            return Err(RpcError::reverted(&result));
+        }
+        Ok(result.return_data)
+    }
+
+    // binary search the lowest gas limit the call succeeds with
+    pub fn estimate_gas(&mut self, mut req: CallRequest) -> Result<u64, RpcError> {
+        let mut hi = match req.gas {
+            Some(gas) => gas.as_u64(),
+            None => self.header.gas_limit().as_u64(),
+        };
+        req.gas = Some(hi.into());
+        let result = self.execute(req.clone()).map_err(RpcError::execute)?;
+        if !result.success {
+            return Err(RpcError::reverted(&result));
+        }
+
+        let mut lo = MIN_GAS - 1;
+        while lo + 1 < hi {
+            let mid = lo + (hi - lo) / 2;
+            req.gas = Some(mid.into());
+            match self.execute(req.clone()) {
+                Ok(result) if result.success => hi = mid,
+                _ => lo = mid,
+            }
+        }
+        Ok(hi)
+    }
+
+    // only the top frame, the inner calls are not exposed by the executor
+    pub fn trace_call(&mut self, req: CallRequest) -> Result<CallFrame, RpcError> {
+        let req = self.fill(req);
+        let result = self.execute(req.clone()).map_err(RpcError::execute)?;
+        let (output, error) = match result.success {
+            true => (Some(result.return_data.clone()), None),
+            false => (
+                Some(result.return_data.clone()),
+                Some("execution reverted".into()),
+            ),
+        };
+        Ok(CallFrame {
+            ty: match req.to {
+                Some(_) => "CALL".into(),
+                None => "CREATE".into(),
+            },
+            from: req.from.unwrap_or_default(),
+            to: req.to,
+            value: req.value,
+            gas: req.gas.unwrap_or_default(),
+            gas_used: result.used_gas.into(),
+            input: req.data.unwrap_or_default(),
+            output,
+            error,
+            revert_reason: None,
+            calls: Vec::new(),
+            logs: result
+                .logs
+                .iter()
+                .map(|log| CallLog {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    // the accounts and slots warm at the end of the call, without the sender,
+    // the recipient and the precompiles, the gas is measured again with the
+    // list applied.
+    pub fn create_access_list(&mut self, req: CallRequest) -> Result<AccessListResult, RpcError> {
+        let mut req = self.fill(req);
+        let result = self.execute(req.clone()).map_err(RpcError::execute)?;
+        let excluded: Vec<SH160> = req.from.into_iter().chain(req.to).collect();
+        let access_list = result.access_list(&excluded);
+
+        req.access_list = Some(access_list.clone());
+        let result = self.execute(req).map_err(RpcError::execute)?;
+        Ok(AccessListResult {
+            access_list,
+            gas_used: result.used_gas,
+            error: match result.success {
+                true => None,
+                false => Some("execution reverted".into()),
+            },
+        })
+    }
+
+    fn fill(&mut self, mut req: CallRequest) -> CallRequest {
+        if req.from.is_none() {
+            req.from = Some(SH160::default());
+        }
+        if req.gas.is_none() {
+            req.gas = Some(self.header.gas_limit());
+        }
+        // no fee is charged, the price only has to pass the base fee check
+        if req.gas_price.is_none() && req.max_fee_per_gas.is_none() {
+            req.gas_price = Some(self.header.base_fee().unwrap_or_default());
+        }
+        if req.nonce.is_none() {
+            let from = req.from.unwrap_or_default();
+            req.nonce = Some(self.statedb.get_nonce(&from).unwrap_or_default().into());
+        }
+        req
+    }
+
+    fn execute(&mut self, req: CallRequest) -> Result<ExecuteResult, ExecuteError> {
+        let req = self.fill(req);
+        let chain_id = self.engine.signer().chain_id;
+        let tx = E::Transaction::from_call_request(&req, &chain_id);
+        let mut ctx = TxContext::builder()
+            .chain_id(chain_id)
+            .caller(req.from.unwrap_or_default())
+            .cfg(&self.cfg)
+            .precompile(&self.precompile)
+            .tx(&tx)
+            .header(self.header)
+            .block_hash_getter(self.block_hash_getter)
+            .fee_policy(FeePolicy::NoFee)
+            .build()
+            .expect("all the required fields are set");
+        self.engine.tx_context(&mut ctx);
+        ctx.miner = None;
+
+        let result = TxExecutor::new(ctx, &mut self.statedb).execute();
+        self.statedb.revert(self.root);
+        result
+    }
+}
@@ -0,0 +1,127 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, TxTrait, SH160, SH256, SU256, SU64};
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+
+use crate::{keccak_hash, ExecuteResult};
+
+// The eth_getTransactionReceipt result, the field names and the omitted
+// fields follow geth.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcReceipt {
+    pub transaction_hash: SH256,
+    pub transaction_index: SU64,
+    pub block_hash: SH256,
+    pub block_number: SU64,
+    pub from: SH160,
+    pub to: Option<SH160>,
+    pub cumulative_gas_used: SU64,
+    pub gas_used: SU64,
+    pub effective_gas_price: SU256,
+    pub contract_address: Option<SH160>,
+    pub logs: Vec<RpcLog>,
+    pub logs_bloom: HexBytes,
+    #[serde(rename = "type")]
+    pub ty: SU64,
+    pub status: SU64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    pub address: SH160,
+    pub topics: Vec<SH256>,
+    pub data: HexBytes,
+    pub block_number: SU64,
+    pub transaction_hash: SH256,
+    pub transaction_index: SU64,
+    pub block_hash: SH256,
+    // the index in the block, not in the tx
+    pub log_index: SU64,
+    pub removed: bool,
+}
+
+// keccak(rlp([sender, nonce]))[12..], the address of a CREATE
+pub fn create_address(sender: &SH160, nonce: u64) -> SH160 {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(&sender.0.as_bytes());
+    stream.append(&nonce);
+    let hash = keccak_hash(&stream.out());
+    let mut address = SH160::default();
+    address.0 .0.copy_from_slice(&hash[12..]);
+    address
+}
+
+// Builds the receipts of a block in the tx order, the cumulative gas and the
+// log indexes carry over from the previous txs.
+#[derive(Debug, Clone)]
+pub struct RpcReceiptBuilder {
+    block_hash: SH256,
+    block_number: u64,
+    base_fee: Option<SU256>,
+    cumulative_gas_used: u64,
+    log_index: u64,
+    tx_index: u64,
+}
+
+impl RpcReceiptBuilder {
+    pub fn new(block_hash: SH256, block_number: u64, base_fee: Option<SU256>) -> Self {
+        Self {
+            block_hash,
+            block_number,
+            base_fee,
+            cumulative_gas_used: 0,
+            log_index: 0,
+            tx_index: 0,
+        }
+    }
+
+    // `from` is the recovered sender
+    pub fn push<T: TxTrait>(&mut self, tx: &T, from: SH160, result: &ExecuteResult) -> RpcReceipt {
+        let tx_hash = tx.hash();
+        let tx_index: SU64 = self.tx_index.into();
+        let logs = result
+            .logs
+            .iter()
+            .map(|log| {
+                let log_index = self.log_index;
+                self.log_index += 1;
+                RpcLog {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone(),
+                    block_number: self.block_number.into(),
+                    transaction_hash: tx_hash,
+                    transaction_index: tx_index,
+                    block_hash: self.block_hash,
+                    log_index: log_index.into(),
+                    removed: false,
+                }
+            })
+            .collect();
+        self.cumulative_gas_used += result.used_gas;
+        self.tx_index += 1;
+        let to = tx.to();
+        RpcReceipt {
+            transaction_hash: tx_hash,
+            transaction_index: tx_index,
+            block_hash: self.block_hash,
+            block_number: self.block_number.into(),
+            from,
+            to,
+            cumulative_gas_used: self.cumulative_gas_used.into(),
+            gas_used: result.used_gas.into(),
+            effective_gas_price: tx.gas_price(self.base_fee),
+            contract_address: match to {
+                Some(_) => None,
+                None => Some(create_address(&from, tx.nonce())),
+            },
+            logs,
+            logs_bloom: result.logs_bloom.to_hex(),
+            ty: u64::from(tx.ty()).into(),
+            status: (result.success as u64).into(),
+        }
+    }
+}
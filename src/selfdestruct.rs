@@ -0,0 +1,55 @@
+use std::prelude::v1::*;
+
+use eth_types::{SH160, SU256};
+#[cfg(feature = "selfdestructs")]
+use evm::tracing;
+use serde::{Deserialize, Serialize};
+
+// A SELFDESTRUCT which survived the tx, the balance is what the beneficiary
+// received. The indexers and the fee vaults need the beneficiary, which is
+// not in the Apply::Delete of the state changes. The evm only reports it
+// through the tracing events, which cost every opcode a check, so it takes
+// the selfdestructs feature.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Selfdestruct {
+    pub address: SH160,
+    pub beneficiary: SH160,
+    pub balance: SU256,
+}
+
+#[cfg(feature = "selfdestructs")]
+#[derive(Default)]
+struct SelfdestructListener(Vec<Selfdestruct>);
+
+#[cfg(feature = "selfdestructs")]
+impl tracing::EventListener for SelfdestructListener {
+    fn event(&mut self, event: tracing::Event) {
+        if let tracing::Event::Suicide {
+            address,
+            target,
+            balance,
+        } = event
+        {
+            self.0.push(Selfdestruct {
+                address: address.into(),
+                beneficiary: target.into(),
+                balance: balance.into(),
+            });
+        }
+    }
+}
+
+// run the execution of one tx and record its selfdestructs, including the
+// ones of the reverted frames, the caller keeps the deleted accounts only.
+#[cfg(feature = "selfdestructs")]
+pub(crate) fn collect_selfdestructs<R, F: FnOnce() -> R>(f: F) -> (Vec<Selfdestruct>, R) {
+    let mut listener = SelfdestructListener::default();
+    let out = tracing::using(&mut listener, f);
+    (listener.0, out)
+}
+
+#[cfg(not(feature = "selfdestructs"))]
+pub(crate) fn collect_selfdestructs<R, F: FnOnce() -> R>(f: F) -> (Vec<Selfdestruct>, R) {
+    (Vec::new(), f())
+}
@@ -0,0 +1,142 @@
+use std::prelude::v1::*;
+
+use eth_types::{Signer, TxTrait, SH160};
+use std::collections::BTreeMap;
+
+use crate::RecoveryCache;
+
+// The tx signature rules of a fork, each one accepts the txs of the older
+// ones. Homestead only has the unprotected (pre EIP-155) legacy txs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SignatureFork {
+    Homestead,
+    Eip155,
+    Berlin,
+    London,
+    Cancun,
+}
+
+impl SignatureFork {
+    // the highest EIP-2718 tx type accepted
+    pub fn max_tx_type(&self) -> u64 {
+        match self {
+            Self::Homestead | Self::Eip155 => 0,
+            Self::Berlin => 1,
+            Self::London => 2,
+            Self::Cancun => 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SignerError {
+    TxTypeNotActive { ty: u64, fork: SignatureFork },
+    InvalidSignature,
+}
+
+// The signer by block number, for the full history replay and the legacy
+// chains which changed their signature rules or chain id along the way.
+#[derive(Debug, Clone)]
+pub struct SignerSchedule {
+    forks: BTreeMap<u64, (SignatureFork, Signer)>,
+}
+
+impl SignerSchedule {
+    // the rules from the genesis
+    pub fn new(fork: SignatureFork, signer: Signer) -> Self {
+        let mut forks = BTreeMap::new();
+        forks.insert(0, (fork, signer));
+        Self { forks }
+    }
+
+    pub fn activate(mut self, number: u64, fork: SignatureFork, signer: Signer) -> Self {
+        self.forks.insert(number, (fork, signer));
+        self
+    }
+
+    // the ethereum mainnet forks, cancun is activated by timestamp so it's
+    // left to the caller.
+    pub fn mainnet() -> Self {
+        let signer = Signer::new(1u64.into());
+        Self::new(SignatureFork::Homestead, signer.clone())
+            .activate(2_675_000, SignatureFork::Eip155, signer.clone())
+            .activate(12_244_000, SignatureFork::Berlin, signer.clone())
+            .activate(12_965_000, SignatureFork::London, signer)
+    }
+
+    fn at(&self, number: u64) -> &(SignatureFork, Signer) {
+        // the genesis entry always exists
+        self.forks.range(..=number).next_back().unwrap().1
+    }
+
+    pub fn fork_at(&self, number: u64) -> SignatureFork {
+        self.at(number).0
+    }
+
+    pub fn signer_at(&self, number: u64) -> Signer {
+        self.at(number).1.clone()
+    }
+
+    // recover the sender with the rules of the block `number`
+    pub fn sender<T: TxTrait>(&self, tx: &T, number: u64) -> Result<SH160, SignerError> {
+        self.sender_with(tx, number, |signer| tx.sender(signer))
+    }
+
+    pub fn sender_cached<T: TxTrait>(
+        &self,
+        tx: &T,
+        number: u64,
+        cache: &RecoveryCache,
+    ) -> Result<SH160, SignerError> {
+        self.sender_with(tx, number, |signer| cache.sender(tx, signer))
+    }
+
+    fn sender_with<T, F>(&self, tx: &T, number: u64, recover: F) -> Result<SH160, SignerError>
+    where
+        T: TxTrait,
+        F: FnOnce(&Signer) -> SH160,
+    {
+        let (fork, signer) = self.at(number);
+        let ty = u64::from(tx.ty());
+        if ty > fork.max_tx_type() {
+            return Err(SignerError::TxTypeNotActive { ty, fork: *fork });
+        }
+        // the signer yields the zero address if the signature can't be recovered
+        let sender = recover(signer);
+        if sender == SH160::default() {
+            return Err(SignerError::InvalidSignature);
+        }
+        Ok(sender)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eth_types::SU256;
+
+    #[test]
+    fn test_signer_schedule() {
+        let mainnet = SignerSchedule::mainnet();
+        assert_eq!(mainnet.fork_at(0), SignatureFork::Homestead);
+        assert_eq!(mainnet.fork_at(2_674_999), SignatureFork::Homestead);
+        assert_eq!(mainnet.fork_at(2_675_000), SignatureFork::Eip155);
+        assert_eq!(mainnet.fork_at(12_244_000), SignatureFork::Berlin);
+        assert_eq!(mainnet.fork_at(12_964_999), SignatureFork::Berlin);
+        assert_eq!(mainnet.fork_at(u64::MAX), SignatureFork::London);
+        assert_eq!(mainnet.fork_at(12_965_000).max_tx_type(), 2);
+
+        // a legacy chain which changed its chain id, the activations may
+        // come in any order
+        let schedule = SignerSchedule::new(SignatureFork::Eip155, Signer::new(61_u64.into()))
+            .activate(200, SignatureFork::Cancun, Signer::new(63_u64.into()))
+            .activate(100, SignatureFork::London, Signer::new(62_u64.into()));
+        let chain_id = |number: u64| schedule.signer_at(number).chain_id;
+        assert_eq!(chain_id(99), SU256::from(61_u64));
+        assert_eq!(chain_id(100), SU256::from(62_u64));
+        assert_eq!(chain_id(199), SU256::from(62_u64));
+        assert_eq!(chain_id(200), SU256::from(63_u64));
+        assert_eq!(schedule.fork_at(99).max_tx_type(), 0);
+        assert_eq!(schedule.fork_at(200).max_tx_type(), 3);
+    }
+}
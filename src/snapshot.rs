@@ -0,0 +1,113 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, H256, SH160, SH256, SU256, U256};
+use serde::{Deserialize, Serialize};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+
+use crate::ExecutionWitness;
+
+// The `anvil_dumpState`/`anvil_loadState` json, only the accounts are kept,
+// the block and the transactions of the dump are ignored when loading.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StateSnapshot {
+    pub accounts: BTreeMap<SH160, SnapshotAccount>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SnapshotAccount {
+    pub nonce: u64,
+    pub balance: SU256,
+    pub code: HexBytes,
+    // the slots are quantities in the dump, not 32 bytes hashes
+    pub storage: BTreeMap<SU256, SU256>,
+}
+
+fn slot_to_u256(slot: &SH256) -> SU256 {
+    U256::from_big_endian(slot.0.as_bytes()).into()
+}
+
+fn u256_to_slot(val: &SU256) -> SH256 {
+    let mut buf = [0_u8; 32];
+    val.raw().to_big_endian(&mut buf);
+    H256::from(buf).into()
+}
+
+impl StateSnapshot {
+    // The StateDB can't be iterated, the snapshot only covers the accounts
+    // and the slots recorded by the witness.
+    pub fn capture<D: StateDB>(
+        statedb: &mut D,
+        witness: &ExecutionWitness,
+    ) -> Result<Self, statedb::Error> {
+        let mut accounts = BTreeMap::new();
+        for address in witness.accounts() {
+            if !statedb.exist(&address)? {
+                continue;
+            }
+            let (balance, nonce) = statedb.get_account_basic(&address)?;
+            let balance: U256 = balance.into();
+            let nonce: U256 = nonce.into();
+            let code = statedb.get_code(&address)?;
+            let mut storage = BTreeMap::new();
+            for index in witness.storages(&address) {
+                let val = statedb.get_state(&address, &index)?;
+                if val != SH256::default() {
+                    storage.insert(slot_to_u256(&index), slot_to_u256(&val));
+                }
+            }
+            accounts.insert(
+                address,
+                SnapshotAccount {
+                    nonce: nonce.as_u64(),
+                    balance: balance.into(),
+                    code: code.as_ref().clone().into(),
+                    storage,
+                },
+            );
+        }
+        Ok(Self { accounts })
+    }
+
+    // writes the accounts into the statedb and returns the new state root,
+    // an empty statedb gives the fixture described by the snapshot.
+    pub fn apply<D: StateDB>(&self, statedb: &mut D) -> Result<SH256, statedb::Error> {
+        for (address, account) in &self.accounts {
+            apply_account(
+                statedb,
+                address,
+                account.balance.raw().clone(),
+                account.nonce.into(),
+                &account.code,
+                account
+                    .storage
+                    .iter()
+                    .map(|(k, v)| (u256_to_slot(k), u256_to_slot(v))),
+            )?;
+        }
+        statedb.flush()
+    }
+}
+
+pub(crate) fn apply_account<D, I>(
+    statedb: &mut D,
+    address: &SH160,
+    balance: U256,
+    nonce: U256,
+    code: &[u8],
+    storage: I,
+) -> Result<(), statedb::Error>
+where
+    D: StateDB,
+    I: Iterator<Item = (SH256, SH256)>,
+{
+    statedb.set_balance(address, balance.into())?;
+    statedb.set_nonce(address, nonce.into())?;
+    if !code.is_empty() {
+        statedb.set_code(address, code.to_vec())?;
+    }
+    for (index, value) in storage {
+        statedb.set_state(address, &index, value)?;
+    }
+    Ok(())
+}
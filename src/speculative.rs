@@ -0,0 +1,63 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{TransactionAccessTuple, SH160, SH256};
+use statedb::StateDB;
+use std::collections::{BTreeMap, BTreeSet};
+
+// The state a speculative execution asked for but the StateDB doesn't have.
+// The missing state is read as empty so the execution goes on and discovers
+// the rest, the result is thrown away. See BlockBuilder::prefetch_speculative.
+#[derive(Debug, Default)]
+pub struct StateMisses {
+    misses: RefCell<BTreeMap<SH160, BTreeSet<SH256>>>,
+}
+
+impl StateMisses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, address: &SH160, index: Option<&SH256>) {
+        let mut misses = self.misses.borrow_mut();
+        let slots = misses.entry(*address).or_default();
+        if let Some(index) = index {
+            slots.insert(*index);
+        }
+    }
+
+    // whether the account, the slot or the code is not in the statedb, the
+    // missing state is recorded.
+    pub fn check<D: StateDB>(
+        &self,
+        statedb: &mut D,
+        address: &SH160,
+        index: Option<&SH256>,
+        code: bool,
+    ) -> bool {
+        let keys: Vec<SH256> = index.into_iter().cloned().collect();
+        let missing = match statedb.check_missing_state(address, &keys) {
+            Ok(state) => state.account || (code && state.code) || !state.storages.is_empty(),
+            Err(_) => true,
+        };
+        if missing {
+            self.add(address, index);
+        }
+        missing
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.misses.borrow().is_empty()
+    }
+
+    pub fn access_list(&self) -> Vec<TransactionAccessTuple> {
+        self.misses
+            .borrow()
+            .iter()
+            .map(|(address, slots)| TransactionAccessTuple {
+                address: *address,
+                storage_keys: slots.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
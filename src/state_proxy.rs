@@ -1,16 +1,20 @@
 use std::prelude::v1::*;
 
-use crate::BlockHashGetter;
+use crate::{keccak_hash, BlockHashGetter};
 
 use super::TxContext;
 use core::cell::RefCell;
-use crypto::keccak_hash;
-use eth_types::{BlockHeaderTrait, TxTrait, H160, H256, SH256, U256};
+use eth_types::{
+    BlockHeaderTrait, HexBytes, TransactionAccessTuple, TxTrait, H160, H256, SH160, SH256, U256,
+};
 use statedb::StateDB;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub struct StateProxy<'a, D: StateDB, T: TxTrait, B: BlockHeaderTrait, H: BlockHashGetter> {
     state_db: RefCell<&'a mut D>,
     ctx: TxContext<'a, T, B, H>,
+    // the accounts and slots read by this tx
+    touched: RefCell<BTreeMap<SH160, BTreeSet<SH256>>>,
 }
 
 impl<'a, D, T, B, H> StateProxy<'a, D, T, B, H>
@@ -24,6 +28,51 @@ where
         Self {
             state_db: RefCell::new(state),
             ctx,
+            touched: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn touch(&self, address: H160, index: Option<H256>) {
+        let mut touched = self.touched.borrow_mut();
+        let slots = touched.entry(address.into()).or_default();
+        if let Some(index) = index {
+            slots.insert(index.into());
+        }
+    }
+
+    pub fn touched(&self) -> Vec<TransactionAccessTuple> {
+        self.touched
+            .borrow()
+            .iter()
+            .map(|(address, slots)| TransactionAccessTuple {
+                address: *address,
+                storage_keys: slots.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    // only in the speculative mode, a missing state is recorded instead of
+    // being fetched or failing the read.
+    fn missing(&self, address: H160, index: Option<H256>, code: bool) -> bool {
+        let misses = match self.ctx.state_misses {
+            Some(misses) => misses,
+            None => return false,
+        };
+        let address: SH160 = address.into();
+        let index: Option<SH256> = index.map(|index| index.into());
+        let mut state_db = self.state_db.borrow_mut();
+        misses.check(&mut **state_db, &address, index.as_ref(), code)
+    }
+
+    fn record_code(&self, address: H160, hash: SH256, code: &HexBytes) {
+        if let Some(audit) = self.ctx.audit {
+            audit.record_code(&address.into(), &hash);
+        }
+        if let Some(witness) = self.ctx.witness {
+            witness.add_account(&address.into());
+            if !witness.has_code(&hash) {
+                witness.add_code(hash, code.clone());
+            }
         }
     }
 }
@@ -35,23 +84,40 @@ where
     B: BlockHeaderTrait,
     H: BlockHashGetter,
 {
+    // BASEFEE is rejected before london, the zero is never observed
     fn block_base_fee_per_gas(&self) -> U256 {
-        glog::debug!(target: "executor", "get base fee");
-        self.ctx.block_base_fee.into()
+        debug!(target: "executor", "get base fee");
+        self.ctx.block_base_fee.unwrap_or_default().into()
     }
 
     fn basic(&self, address: H160) -> evm::backend::Basic {
+        if self.missing(address, None, false) {
+            return evm::backend::Basic::default();
+        }
         let (balance, nonce) = self
             .state_db
             .borrow_mut()
             .get_account_basic(&address.into())
             .unwrap();
+        self.touch(address, None);
+        if let Some(witness) = self.ctx.witness {
+            witness.add_account(&address.into());
+        }
 
-        glog::debug!(target: "executor", "get basic: {:?} => {},{}", address, balance, nonce);
-        evm::backend::Basic {
+        debug!(target: "executor", "get basic: {:?} => {},{}", address, balance, nonce);
+        #[allow(unused_mut)]
+        let mut basic = evm::backend::Basic {
             balance: balance.into(),
             nonce: nonce.into(),
+        };
+        #[cfg(feature = "testing")]
+        if let Some(balance) = self.ctx.cheatcodes.and_then(|c| c.balance(&address.into())) {
+            basic.balance = balance.raw().clone();
+        }
+        if let Some(audit) = self.ctx.audit {
+            audit.record_account(&address, &basic.balance, &basic.nonce);
         }
+        basic
     }
 
     fn block_coinbase(&self) -> H160 {
@@ -59,17 +125,38 @@ where
             Some(miner) => miner,
             None => self.ctx.header.miner().clone(),
         };
-        glog::debug!(target: "executor", "get coinbase: {:?}", miner);
+        debug!(target: "executor", "get coinbase: {:?}", miner);
         miner.into()
     }
 
     fn block_difficulty(&self) -> U256 {
-        glog::debug!(target: "executor", "get difficulty: {:?}", self.ctx.difficulty);
+        if let Some(prev_randao) = &self.ctx.prev_randao {
+            debug!(target: "executor", "get prev_randao: {:?}", prev_randao);
+            return U256::from_big_endian(prev_randao.0.as_bytes());
+        }
+        debug!(target: "executor", "get difficulty: {:?}", self.ctx.difficulty);
         self.ctx.difficulty.into()
     }
 
+    fn get_blob_hash(&self, index: usize) -> Option<U256> {
+        let hash = self.ctx.blob_hashes.get(index)?;
+        debug!(target: "executor", "get blob hash: {} => {:?}", index, hash);
+        Some(U256::from_big_endian(hash.0.as_bytes()))
+    }
+
+    // None before cancun, the opcode is rejected. a fee over u128 is clamped,
+    // the excess blob gas can't reach it on a live chain
+    fn blob_gas_price(&self) -> Option<u128> {
+        debug!(target: "executor", "get blob base fee: {:?}", self.ctx.blob_base_fee);
+        let max = U256::from(u128::MAX);
+        self.ctx
+            .blob_base_fee
+            .as_ref()
+            .map(|fee| (*fee.raw()).min(max).as_u128())
+    }
+
     fn block_gas_limit(&self) -> U256 {
-        glog::debug!(target: "executor", "get gas_limit: {:?}", self.ctx.header.gas_limit());
+        debug!(target: "executor", "get gas_limit: {:?}", self.ctx.header.gas_limit());
         self.ctx.header.gas_limit().as_u64().into()
     }
 
@@ -77,75 +164,150 @@ where
         let number = number.as_u64();
         let current = self.ctx.header.number().as_u64();
         let val = self.ctx.block_hash_getter.get_hash(current, number);
-        glog::debug!(target: "executor", "get block hash: {:?} => {:?}", number, val);
+        if let Some(witness) = self.ctx.witness {
+            witness.add_block_hash(number, val);
+        }
+        if let Some(audit) = self.ctx.audit {
+            audit.record_block_hash(number, &val);
+        }
+        debug!(target: "executor", "get block hash: {:?} => {:?}", number, val);
         val.into()
     }
 
     fn block_number(&self) -> U256 {
-        glog::debug!(target: "executor", "get block number: {:?}", self.ctx.header.number());
+        debug!(target: "executor", "get block number: {:?}", self.ctx.header.number());
         self.ctx.header.number().as_u64().into()
     }
 
     fn block_timestamp(&self) -> U256 {
-        glog::debug!(target: "executor", "get timestamp: {}", self.ctx.header.timestamp());
+        #[cfg(feature = "testing")]
+        if let Some(timestamp) = self.ctx.cheatcodes.and_then(|c| c.timestamp()) {
+            return timestamp.into();
+        }
+        debug!(target: "executor", "get timestamp: {}", self.ctx.header.timestamp());
         self.ctx.header.timestamp().as_u64().into()
     }
 
     fn chain_id(&self) -> U256 {
-        glog::debug!(target: "executor", "get chain_id: {}", self.ctx.chain_id);
+        debug!(target: "executor", "get chain_id: {}", self.ctx.chain_id);
         self.ctx.chain_id.clone().into()
     }
 
     fn code(&self, address: H160) -> Vec<u8> {
+        if self.missing(address, None, true) {
+            return Vec::new();
+        }
+        self.touch(address, None);
+        if let Some(cache) = self.ctx.code_cache {
+            if let Some((hash, code)) = cache.get(&address.into()) {
+                self.record_code(address, hash, &code);
+                debug!(target: "executor", "get code(cached): {:?}, hash:{:?}, size: {}", address, hash, code.len());
+                return code.to_vec();
+            }
+        }
+
         let code = self
             .state_db
             .borrow_mut()
             .get_code(&address.into())
             .unwrap();
 
-        glog::debug!(target: "executor", "get code: {:?}, hash:{:?}, size: {}", address, SH256::from(keccak_hash(&code)), code.len());
+        if let Some(cache) = self.ctx.code_cache {
+            let (hash, code) = cache.insert(address.into(), code.as_ref().clone().into());
+            self.record_code(address, hash, &code);
+            debug!(target: "executor", "get code: {:?}, hash:{:?}, size: {}", address, hash, code.len());
+            return code.to_vec();
+        }
+
+        let hash = match self.ctx.keccak_cache {
+            Some(cache) => cache.hash(&code),
+            None => SH256::from(keccak_hash(&code)),
+        };
+        if self.ctx.witness.is_some() || self.ctx.audit.is_some() {
+            self.record_code(address, hash, &code.as_ref().clone().into());
+        }
+        debug!(target: "executor", "get code: {:?}, hash:{:?}, size: {}", address, hash, code.len());
         code.as_ref().clone().into()
     }
 
     fn exists(&self, address: H160) -> bool {
+        if self.missing(address, None, false) {
+            return false;
+        }
         let exists = self.state_db.borrow_mut().exist(&address.into()).unwrap();
-        glog::debug!(target: "executor", "get exists: {:?} => {:?}", address, exists);
+        self.touch(address, None);
+        if let Some(witness) = self.ctx.witness {
+            witness.add_account(&address.into());
+        }
+        if let Some(audit) = self.ctx.audit {
+            audit.record_exists(&address, exists);
+        }
+        debug!(target: "executor", "get exists: {:?} => {:?}", address, exists);
         exists
     }
 
     fn gas_price(&self) -> U256 {
-        glog::debug!(target: "executor", "get gas price");
-        self.ctx.tx.gas_price(self.ctx.header.base_fee()).into()
+        debug!(target: "executor", "get gas price");
+        self.ctx.tx.gas_price(self.ctx.block_base_fee).into()
     }
 
     fn origin(&self) -> H160 {
-        glog::debug!(target: "executor", "get origin");
+        debug!(target: "executor", "get origin");
         self.ctx.caller.clone().into()
     }
 
     fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
-        let val = self
+        if self.missing(address, Some(index), false) {
+            return None;
+        }
+        let val: H256 = self
             .state_db
             .borrow_mut()
             .get_state(&address.into(), &index.into())
             .unwrap()
             .into();
+        self.touch(address, Some(index));
+        if let Some(witness) = self.ctx.witness {
+            witness.add_storage(&address.into(), &index.into());
+        }
+        if let Some(audit) = self.ctx.audit {
+            audit.record_storage(&address, &index.0, &val.0);
+        }
         if val == H256::default() {
             return None;
         }
 
-        glog::debug!(target: "executor", "get storage: {:?}.{:?} = {:?}", address, index, val);
+        debug!(target: "executor", "get storage: {:?}.{:?} = {:?}", address, index, val);
         return Some(val);
     }
 
     fn storage(&self, address: H160, index: H256) -> H256 {
-        let val = self
+        if self.missing(address, Some(index), false) {
+            return H256::default();
+        }
+        let val: H256 = self
             .state_db
             .borrow_mut()
             .get_state(&address.into(), &index.into())
             .unwrap()
             .into();
-        glog::debug!(target: "executor", "get storage: {:?}.{:?} = {:?}", address, index, val);
+        self.touch(address, Some(index));
+        if let Some(witness) = self.ctx.witness {
+            witness.add_storage(&address.into(), &index.into());
+        }
+        #[cfg(feature = "testing")]
+        let val: H256 = match self
+            .ctx
+            .cheatcodes
+            .and_then(|c| c.storage(&address.into(), &index.into()))
+        {
+            Some(val) => val.into(),
+            None => val,
+        };
+        if let Some(audit) = self.ctx.audit {
+            audit.record_storage(&address, &index.0, &val.0);
+        }
+        debug!(target: "executor", "get storage: {:?}.{:?} = {:?}", address, index, val);
         val
     }
 }
@@ -0,0 +1,225 @@
+use std::prelude::v1::*;
+
+use eth_types::{
+    BlockHeader, BlockHeaderTrait, HexBytes, Log, Signer, TransactionInner, TxTrait, SH160, SH256,
+    SU256, SU64,
+};
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+use statedb::StateDB;
+use std::collections::BTreeMap;
+
+use crate::{keccak_hash, ExecuteError, PrecompileSet, TxContext, TxExecutor};
+
+// The GeneralStateTests fixtures of ethereum/tests, one file may contain
+// multiple tests keyed by the name.
+pub type StateTestFile = BTreeMap<String, StateTest>;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StateTest {
+    pub env: StateTestEnv,
+    pub pre: BTreeMap<SH160, StateTestAccount>,
+    pub post: BTreeMap<String, Vec<StateTestPost>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateTestEnv {
+    pub current_coinbase: SH160,
+    pub current_difficulty: SU256,
+    pub current_gas_limit: SU64,
+    pub current_number: SU64,
+    pub current_timestamp: SU64,
+    #[serde(default)]
+    pub current_base_fee: Option<SU256>,
+    #[serde(default)]
+    pub current_random: Option<SH256>,
+    #[serde(default)]
+    pub previous_hash: SH256,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StateTestAccount {
+    pub balance: SU256,
+    pub code: HexBytes,
+    pub nonce: SU64,
+    pub storage: BTreeMap<SH256, SH256>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateTestPost {
+    pub hash: SH256, // the post state root
+    pub logs: SH256, // keccak(rlp(logs))
+    pub txbytes: HexBytes,
+    #[serde(default)]
+    pub expect_exception: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateTestOutcome {
+    Pass,
+    Fail(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StateTestResult {
+    pub name: String,
+    pub fork: String,
+    pub index: usize,
+    pub outcome: StateTestOutcome,
+}
+
+pub fn state_test_config(fork: &str) -> Option<evm::Config> {
+    Some(match fork {
+        "Berlin" => evm::Config::berlin(),
+        "London" => evm::Config::london(),
+        "Merge" | "Paris" => evm::Config::merge(),
+        "Shanghai" => evm::Config::shanghai(),
+        _ => return None,
+    })
+}
+
+pub fn logs_hash(logs: &[Log]) -> SH256 {
+    let mut stream = RlpStream::new_list(logs.len());
+    for log in logs {
+        stream.begin_list(3);
+        stream.append(&log.address.0.as_bytes());
+        stream.begin_list(log.topics.len());
+        for topic in &log.topics {
+            stream.append(&topic.0.as_bytes());
+        }
+        stream.append(&log.data.as_bytes());
+    }
+    keccak_hash(&stream.out()).into()
+}
+
+// the block hash used by the reference implementation: keccak(number.to_string())
+fn test_block_hash(_: u64, number: u64) -> SH256 {
+    keccak_hash(number.to_string().as_bytes()).into()
+}
+
+impl StateTest {
+    pub fn header(&self) -> BlockHeader {
+        let env = &self.env;
+        BlockHeader {
+            parent_hash: env.previous_hash,
+            number: env.current_number,
+            gas_limit: env.current_gas_limit,
+            timestamp: env.current_timestamp,
+            miner: env.current_coinbase,
+            mix_hash: env.current_random.unwrap_or_default(),
+            base_fee_per_gas: env.current_base_fee.unwrap_or_default(),
+            difficulty: env.current_difficulty,
+            ..Default::default()
+        }
+    }
+
+    // `new_statedb` builds the StateDB from the pre state, and `decode_tx`
+    // decodes the signed tx of the post entry.
+    pub fn run<D, F, T>(&self, name: &str, mut new_statedb: F, decode_tx: T) -> Vec<StateTestResult>
+    where
+        D: StateDB,
+        F: FnMut(&BTreeMap<SH160, StateTestAccount>) -> Result<D, String>,
+        T: Fn(&[u8]) -> Result<TransactionInner, String>,
+    {
+        let header = self.header();
+        let precompile = PrecompileSet::berlin();
+        let signer = Signer::new(SU256::from(1_u64));
+        let mut results = Vec::new();
+        for (fork, posts) in &self.post {
+            for (index, post) in posts.iter().enumerate() {
+                let outcome = match state_test_config(fork) {
+                    Some(cfg) => {
+                        let outcome = new_statedb(&self.pre).and_then(|mut statedb| {
+                            let tx = decode_tx(&post.txbytes)?;
+                            Ok(Self::run_post(
+                                &header,
+                                &cfg,
+                                &precompile,
+                                &signer,
+                                &mut statedb,
+                                &tx,
+                                post,
+                            ))
+                        });
+                        outcome.unwrap_or_else(StateTestOutcome::Fail)
+                    }
+                    None => StateTestOutcome::Skipped(format!("unsupported fork: {}", fork)),
+                };
+                results.push(StateTestResult {
+                    name: name.into(),
+                    fork: fork.clone(),
+                    index,
+                    outcome,
+                });
+            }
+        }
+        results
+    }
+
+    fn run_post<D: StateDB>(
+        header: &BlockHeader,
+        cfg: &evm::Config,
+        precompile: &PrecompileSet,
+        signer: &Signer,
+        statedb: &mut D,
+        tx: &TransactionInner,
+        post: &StateTestPost,
+    ) -> StateTestOutcome {
+        let block_hash_getter = test_block_hash;
+        let ctx = TxContext::builder()
+            .chain_id(signer.chain_id)
+            .caller(tx.sender(signer))
+            .cfg(cfg)
+            .precompile(precompile)
+            .tx(tx)
+            .header(header)
+            .block_hash_getter(&block_hash_getter)
+            .miner(Some(header.miner))
+            .block_base_fee(header.base_fee())
+            .difficulty(header.difficulty)
+            .prev_randao(match header.difficulty.is_zero() {
+                true => Some(header.mix_hash),
+                false => None,
+            })
+            .build();
+        let ctx = match ctx {
+            Ok(ctx) => ctx,
+            Err(err) => return StateTestOutcome::Fail(format!("{:?}", err)),
+        };
+
+        let logs = match TxExecutor::new(ctx, statedb).execute() {
+            Ok(result) => result.logs,
+            Err(err) => {
+                if post.expect_exception.is_none() {
+                    return StateTestOutcome::Fail(format!("unexpected error: {}", err));
+                }
+                if let ExecuteError::StateError(err) = err {
+                    return StateTestOutcome::Fail(format!("state error: {:?}", err));
+                }
+                Vec::new()
+            }
+        };
+
+        let state_root = match statedb.flush() {
+            Ok(root) => root,
+            Err(err) => return StateTestOutcome::Fail(format!("flush state: {:?}", err)),
+        };
+        if state_root != post.hash {
+            return StateTestOutcome::Fail(format!(
+                "state root mismatch: want {:?}, got {:?}",
+                post.hash, state_root
+            ));
+        }
+        let logs_hash = logs_hash(&logs);
+        if logs_hash != post.logs {
+            return StateTestOutcome::Fail(format!(
+                "logs hash mismatch: want {:?}, got {:?}",
+                post.logs, logs_hash
+            ));
+        }
+        StateTestOutcome::Pass
+    }
+}
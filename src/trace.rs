@@ -0,0 +1,218 @@
+use std::prelude::v1::*;
+
+use eth_types::{HexBytes, TxTrait, SH160, SH256, SU256, SU64};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{ExecuteResult, StateChange};
+
+// The output types of geth's debug_traceTransaction, the field names and
+// the omitted fields follow geth so the existing tooling can consume them.
+
+// callTracer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub ty: String, // CALL, STATICCALL, DELEGATECALL, CALLCODE, CREATE, CREATE2, SELFDESTRUCT
+    pub from: SH160,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<SH160>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<SU256>,
+    pub gas: SU64,
+    pub gas_used: SU64,
+    pub input: HexBytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<HexBytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<CallFrame>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<CallLog>,
+}
+
+impl CallFrame {
+    // only the top frame, the inner calls are not exposed by the executor
+    pub fn top<T: TxTrait>(tx: &T, from: SH160, result: &ExecuteResult) -> Self {
+        let to = tx.to();
+        let input: Vec<u8> = tx.input().into();
+        Self {
+            ty: match to {
+                Some(_) => "CALL".into(),
+                None => "CREATE".into(),
+            },
+            from,
+            to,
+            value: Some(tx.value()),
+            gas: tx.gas().as_u64().into(),
+            gas_used: result.used_gas.into(),
+            input: input.into(),
+            output: Some(result.return_data.clone()),
+            error: match result.success {
+                true => None,
+                false => Some("execution reverted".into()),
+            },
+            revert_reason: None,
+            calls: Vec::new(),
+            logs: result
+                .logs
+                .iter()
+                .map(|log| CallLog {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CallLog {
+    pub address: SH160,
+    pub topics: Vec<SH256>,
+    pub data: HexBytes,
+}
+
+// prestateTracer, the account of the default mode and the diff mode
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrestateAccount {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<SU256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<HexBytes>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<SH256, SH256>,
+}
+
+pub type PrestateTrace = BTreeMap<SH160, PrestateAccount>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrestateDiff {
+    pub pre: PrestateTrace,
+    pub post: PrestateTrace,
+}
+
+impl PrestateDiff {
+    // the post side of the diff mode, derived from the applied state changes
+    pub fn post_from_result(result: &ExecuteResult) -> PrestateTrace {
+        let mut post = PrestateTrace::new();
+        if !result.success {
+            return post;
+        }
+        for apply in &result.states {
+            if let StateChange::Modify {
+                address,
+                balance,
+                nonce,
+                code,
+                storage,
+                ..
+            } = StateChange::from(apply)
+            {
+                post.insert(
+                    address,
+                    PrestateAccount {
+                        balance: Some(balance),
+                        nonce: Some(nonce.as_u64()),
+                        code,
+                        storage,
+                    },
+                );
+            }
+        }
+        post
+    }
+}
+
+// The traces of a committed tx, see BlockBuilder::record_traces
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxTrace {
+    pub tx_hash: SH256,
+    pub call: CallFrame,
+    // the post side of the prestateTracer diff mode
+    pub post: PrestateTrace,
+}
+
+impl TxTrace {
+    pub fn new<T: TxTrait>(tx: &T, from: SH160, result: &ExecuteResult) -> Self {
+        Self {
+            tx_hash: tx.hash(),
+            call: CallFrame::top(tx, from, result),
+            post: PrestateDiff::post_from_result(result),
+        }
+    }
+}
+
+// the default struct logger
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLogResult {
+    pub gas: u64,
+    pub failed: bool,
+    pub return_value: String, // hex without the 0x prefix
+    pub struct_logs: Vec<StructLog>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StructLog {
+    pub pc: u64,
+    pub op: String,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<SU256>>,
+    // 32 bytes words in hex without the 0x prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund: Option<u64>,
+}
+
+impl StructLogResult {
+    pub fn new(result: &ExecuteResult, struct_logs: Vec<StructLog>) -> Self {
+        Self {
+            gas: result.used_gas,
+            failed: !result.success,
+            return_value: hex_no_prefix(&result.return_data),
+            struct_logs,
+        }
+    }
+}
+
+pub fn hex_no_prefix(data: &[u8]) -> String {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut out = String::with_capacity(data.len() * 2);
+    for b in data {
+        out.push(HEX[(b >> 4) as usize] as char);
+        out.push(HEX[(b & 0xf) as usize] as char);
+    }
+    out
+}
+
+impl StructLog {
+    // the memory is rendered as 32 bytes words like geth
+    pub fn memory_words(memory: &[u8]) -> Vec<String> {
+        memory.chunks(32).map(hex_no_prefix).collect()
+    }
+
+    pub fn storage_words(storage: &BTreeMap<SH256, SH256>) -> BTreeMap<String, String> {
+        storage
+            .iter()
+            .map(|(k, v)| (hex_no_prefix(k.0.as_bytes()), hex_no_prefix(v.0.as_bytes())))
+            .collect()
+    }
+}
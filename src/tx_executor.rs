@@ -1,16 +1,60 @@
 use std::prelude::v1::*;
 
 use base::format::parse_ether;
-use eth_types::{BlockHeaderTrait, Log, TxTrait, H160, H256, SU256};
+use eth_types::{
+    BlockHeaderTrait, Log, TransactionAccessTuple, TxTrait, H160, H256, SH160, SH256, SU256,
+};
 use evm::{
     backend::Apply,
-    executor::stack::{MemoryStackState, StackExecutor, StackSubstateMetadata},
+    executor::stack::{Accessed, MemoryStackState, StackExecutor, StackSubstateMetadata},
 };
 use statedb::StateDB;
-use std::cmp::Ordering;
-use std::time::Instant;
+use core::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::{
+    collect_selfdestructs, is_eof, metrics, BlockHashGetter, EofContainer, ExecuteError,
+    ExecuteResult, LogsBloom, StateChangeLog, StateProxy, TxContext,
+};
+
+// the warm addresses of the EIP-2929 access sets with their warm slots,
+// ascending by the address and the slot. the `excluded` addresses are warm
+// from the start (the precompiles, the sender, the recipient and the
+// coinbase since shanghai), same as geth they are left out unless they have
+// warm slots.
+fn warm_access_list(accessed: &Accessed, excluded: &[H160]) -> Vec<TransactionAccessTuple> {
+    let mut warm: BTreeMap<H160, Vec<SH256>> = accessed
+        .accessed_addresses
+        .iter()
+        .filter(|address| !excluded.contains(address))
+        .map(|address| (*address, Vec::new()))
+        .collect();
+    for (address, index) in &accessed.accessed_storage {
+        warm.entry(*address).or_default().push((*index).into());
+    }
+    warm.into_iter()
+        .map(|(address, storage_keys)| TransactionAccessTuple {
+            address: address.into(),
+            storage_keys,
+        })
+        .collect()
+}
 
-use crate::{TxContext, ExecuteError, ExecuteResult, StateProxy, BlockHashGetter};
+// the price per gas paid to the miner, zero if the fee cap is below the base
+// fee. without the base fee nothing is burned, the miner gets the whole price
+pub fn effective_tip<T: TxTrait>(tx: &T, base_fee: Option<&SU256>) -> SU256 {
+    match base_fee {
+        Some(base_fee) => {
+            let gas_tip_cap = tx.max_priority_fee_per_gas();
+            let gas_fee_cap = tx.max_fee_per_gas();
+            if gas_fee_cap < base_fee {
+                return SU256::zero();
+            }
+            (*gas_tip_cap).min(*gas_fee_cap - base_fee)
+        }
+        None => tx.gas_price(None),
+    }
+}
 
 #[derive(Debug)]
 pub struct TxExecutor<'a, D: StateDB, T: TxTrait, B: BlockHeaderTrait, H: BlockHashGetter> {
@@ -29,7 +73,7 @@ where
     H: BlockHashGetter,
 {
     pub fn new(ctx: TxContext<'a, T, B, H>, state_db: &'a mut D) -> Self {
-        let gas_price = ctx.tx.gas_price(ctx.header.base_fee());
+        let gas_price = ctx.tx.gas_price(ctx.block_base_fee);
         Self {
             ctx,
             state_db,
@@ -40,39 +84,100 @@ where
     }
 
     pub fn execute(&mut self) -> Result<ExecuteResult, ExecuteError> {
-        let mut base_fee = self.ctx.header.base_fee().unwrap_or_default();
+        let base_fee = self.ctx.block_base_fee;
+        if let Some(witness) = self.ctx.witness {
+            witness.add_account(&self.ctx.caller);
+            if let Some(miner) = &self.ctx.miner {
+                witness.add_account(miner);
+            }
+            let vaults = &self.ctx.fee_vaults;
+            for vault in vaults.base_fee.iter().chain(vaults.priority_fee.iter()) {
+                witness.add_account(vault);
+            }
+        }
 
-        self.check_nonce(false)?;
-        self.check_base_fee(&mut base_fee)?;
+        let caller = self.ctx.caller;
+        if !self.missing(&caller, false) {
+            self.check_nonce(false)?;
+        }
+        self.check_base_fee(base_fee.as_ref())?;
+        if self.ctx.eof {
+            self.check_eof()?;
+        }
         self.buy_gas()?;
 
-        let result = self.exec_tx();
-        if self.gas < result.used_gas && self.ctx.gas_overcommit {
-            self.gas = 0;
-        } else {
-            self.gas -= result.used_gas;
+        let mut result = self.exec_tx();
+        let charged_gas = match self.ctx.fee_policy.charged_gas(self.gas, result.used_gas) {
+            Some(gas) => gas,
+            None => {
+                // give the bought gas back, the states are not applied
+                self.refund_gas()?;
+                self.refund_extra_fee()?;
+                return Err(ExecuteError::GasOvercommit {
+                    tx_hash: self.ctx.tx.hash(),
+                    gas_limit: self.gas,
+                    used_gas: result.used_gas,
+                });
+            }
+        };
+        self.gas -= charged_gas;
+        if result.success {
+            self.delete_empty_accounts(&mut result.states)?;
         }
-        let txfee = self.calculate_txfee(result.used_gas, &base_fee);
         self.apply_states(&result)?;
 
-        if let Some(miner) = &self.ctx.miner {
+        if self.ctx.fee_policy.charges_gas() {
+            let vaults = self.ctx.fee_vaults;
+            if let Some(recipient) = vaults.priority_fee_recipient(self.ctx.miner) {
+                let txfee = self.calculate_txfee(charged_gas, base_fee.as_ref());
+                self.state_db
+                    .add_balance(&recipient, &txfee)
+                    .map_err(ExecuteError::StateError)?;
+            }
+            if let (Some(vault), Some(base_fee)) = (vaults.base_fee, &base_fee) {
+                let burned = SU256::from(charged_gas) * base_fee;
+                self.state_db
+                    .add_balance(&vault, &burned)
+                    .map_err(ExecuteError::StateError)?;
+            }
+        }
+        if let Some(recipient) = self.ctx.fee_policy.extra_fee_recipient(self.ctx.miner) {
+            let extra_fee = self.ctx.fee_policy.extra_fee().unwrap_or_default();
             self.state_db
-                .add_balance(miner, &txfee)
+                .add_balance(&recipient, &extra_fee)
                 .map_err(ExecuteError::StateError)?;
         }
         self.refund_gas()?;
+        // a zero fee still touches the miner
+        if let Some(miner) = self.ctx.miner {
+            if !self.missing(&miner, true) && self.is_empty_account(&miner)? {
+                self.state_db
+                    .suicide(&miner)
+                    .map_err(ExecuteError::StateError)?;
+                result.states.push(Apply::Delete {
+                    address: miner.into(),
+                });
+            }
+        }
+        metrics::record_tx(result.used_gas);
+        if let Some(audit) = self.ctx.audit {
+            audit.record_result(&self.ctx.tx.hash(), &result);
+        }
 
         Ok(result)
     }
 
-    fn calculate_txfee(&self, gas: u64, base_fee: &SU256) -> SU256 {
-        let tx = self.ctx.tx;
-        let gas_tip_cap = tx.max_priority_fee_per_gas();
-        let gas_fee_cap = tx.max_fee_per_gas();
-        let effective_tip = (*gas_tip_cap).min(*gas_fee_cap - base_fee);
-        let extra_fee = self.ctx.extra_fee.unwrap_or(SU256::zero());
+    // only in the speculative mode, the missing account is recorded and the
+    // checks reading it are skipped like the reads of StateProxy::missing
+    fn missing(&mut self, address: &SH160, code: bool) -> bool {
+        match self.ctx.state_misses {
+            Some(misses) => misses.check(&mut *self.state_db, address, None, code),
+            None => false,
+        }
+    }
 
-        SU256::from(gas) * &effective_tip + extra_fee
+    fn calculate_txfee(&self, gas: u64, base_fee: Option<&SU256>) -> SU256 {
+        SU256::from(gas) * &effective_tip(self.ctx.tx, base_fee)
     }
 
     fn exec_tx(&mut self) -> ExecuteResult {
@@ -86,15 +191,18 @@ where
         let metadata = StackSubstateMetadata::new(gas_limit, config);
         let state = StateProxy::new(self.state_db, self.ctx.clone());
 
-        let _execute_instant = Instant::now();
         // glog::info!("gas remain: {}", metadata.gasometer().gas());
         let mem_state = MemoryStackState::new(metadata, &state);
+        #[cfg(feature = "testing")]
+        let precompile_set =
+            &crate::CheatcodePrecompileSet::new(precompile_set, self.ctx.cheatcodes);
         let mut executor = StackExecutor::new_with_precompiles(mem_state, config, precompile_set);
 
         // check balance > gas_limit * gasPrice first
-        let (reason, data) = match tx.to() {
+        let caller = self.ctx.caller.clone();
+        let transact = || match tx.to() {
             Some(to) => executor.transact_call(
-                self.ctx.caller.clone().into(),
+                caller.clone().into(),
                 to.into(),
                 tx.value().into(),
                 tx.input().into(),
@@ -102,29 +210,61 @@ where
                 access_list,
             ),
             None => executor.transact_create(
-                self.ctx.caller.clone().into(),
+                caller.clone().into(),
                 tx.value().into(),
                 tx.input().into(),
                 gas_limit,
                 access_list,
             ),
         };
+        #[cfg(feature = "opcode-stats")]
+        let transact = || match self.ctx.opcode_stats {
+            Some(stats) => stats.collect(transact),
+            None => transact(),
+        };
+        let (selfdestructs, (reason, data)) = collect_selfdestructs(transact);
 
         let mut result = ExecuteResult {
             success: reason.is_succeed(),
+            return_data: data.clone().into(),
             err: data.into(),
             used_gas: executor.used_gas(),
             logs: Vec::new(),
+            logs_bloom: LogsBloom::default(),
             states: Vec::new(),
+            touched: Vec::new(),
+            warm: Vec::new(),
+            selfdestructs: Vec::new(),
         };
 
-        if self.ctx.no_gas_fee {
+        if !self.ctx.fee_policy.charges_gas() {
             // executor.used_gas() will minus the refunded_gas but we don't need this feature when cost_gas_fee is disabled.
             use evm::executor::stack::StackState;
             let refund_gas = executor.state().metadata().gasometer().refunded_gas();
             result.used_gas += refund_gas as u64;
         }
 
+        {
+            use evm::executor::stack::StackState;
+            // no access sets before berlin
+            if let Some(accessed) = executor.state().metadata().accessed() {
+                let mut excluded = self.ctx.precompile.get_addresses();
+                excluded.push(caller.clone().into());
+                // EIP-3651, the coinbase of the evm is warm since shanghai
+                if self.ctx.cfg.warm_coinbase_address {
+                    let coinbase = match self.ctx.miner {
+                        Some(miner) => miner,
+                        None => self.ctx.header.miner().clone(),
+                    };
+                    excluded.push(coinbase.into());
+                }
+                if let Some(to) = tx.to() {
+                    excluded.push(to.into());
+                }
+                result.warm = warm_access_list(accessed, &excluded);
+            }
+        }
+
         let (storages, logs) = executor.into_state().deconstruct();
         {
             let mut log_index = 0;
@@ -143,12 +283,51 @@ where
                 log_index += 1;
             }
         }
+        result.logs_bloom = LogsBloom::from_logs(&result.logs);
         result.states = storages;
+        result.touched = state.touched();
+        // drop the reverted frames and the EIP-6780 ones which keep the account
+        for item in selfdestructs {
+            let deleted = result.states.iter().any(|change| {
+                matches!(change, Apply::Delete { address } if item.address == (*address).into())
+            });
+            if !deleted {
+                continue;
+            }
+            match result
+                .selfdestructs
+                .iter_mut()
+                .find(|prev| prev.address == item.address)
+            {
+                Some(prev) => *prev = item,
+                None => result.selfdestructs.push(item),
+            }
+        }
+        // the sender and the miner are read outside of the evm
+        for address in Some(self.ctx.caller).into_iter().chain(self.ctx.miner) {
+            if let Err(idx) = result
+                .touched
+                .binary_search_by(|item| item.address.cmp(&address))
+            {
+                result.touched.insert(
+                    idx,
+                    TransactionAccessTuple {
+                        address,
+                        storage_keys: Vec::new(),
+                    },
+                );
+            }
+        }
 
         result
     }
 
-    fn check_base_fee(&self, base_fee: &mut SU256) -> Result<(), ExecuteError> {
+    fn check_base_fee(&self, base_fee: Option<&SU256>) -> Result<(), ExecuteError> {
+        let base_fee = match base_fee {
+            Some(base_fee) if self.ctx.fee_policy.charges_gas() => base_fee,
+            // the gas is not bought, a zero gas price is fine
+            _ => return Ok(()),
+        };
         let gas_fee_cap = self.ctx.tx.max_fee_per_gas();
         if gas_fee_cap < base_fee {
             let effective_gas_tip = self.ctx.tx.effective_gas_tip(None).unwrap();
@@ -187,7 +366,7 @@ where
                 None => return Ok(()),
             }
         } else {
-            glog::debug!(target:"invalid_nonce", "check tx[{:?} {:?}] nonce", self.ctx.tx.hash(), caller);
+            debug!(target:"invalid_nonce", "check tx[{:?} {:?}] nonce", self.ctx.tx.hash(), caller);
             self.state_db
                 .get_nonce(caller)
                 .map_err(ExecuteError::StateError)?
@@ -196,12 +375,14 @@ where
             Ordering::Equal => Ok(()),
             Ordering::Greater => {
                 return Err(ExecuteError::NonceTooLow {
+                    tx_hash: self.ctx.tx.hash(),
                     got: tx_nonce,
                     expect: nonce,
                 })
             }
             Ordering::Less => {
                 return Err(ExecuteError::NonceTooHigh {
+                    tx_hash: self.ctx.tx.hash(),
                     got: tx_nonce,
                     expect: nonce,
                 });
@@ -211,31 +392,35 @@ where
 
     fn buy_gas(&mut self) -> Result<(), ExecuteError> {
         let tx = self.ctx.tx;
-        let caller = &self.ctx.caller;
+        let caller = self.ctx.caller;
         let gas: SU256 = tx.gas().as_u64().into();
         let mgval = gas * self.gas_price;
         let mut balance_check = gas * tx.max_fee_per_gas();
         balance_check = balance_check + tx.value();
-        let extra_fee = self.ctx.extra_fee.unwrap_or(SU256::default());
+        let extra_fee = self.ctx.fee_policy.extra_fee().unwrap_or_default();
         balance_check += extra_fee;
 
-        let skip_check = self.ctx.no_gas_fee;
+        let skip_check = !self.ctx.fee_policy.charges_gas() || self.missing(&caller, false);
         if !skip_check {
             let balance = self
                 .state_db
-                .get_balance(caller)
+                .get_balance(&caller)
                 .map_err(ExecuteError::StateError)?;
 
             if balance < balance_check {
                 // if !dry_run {
-                glog::info!(
+                info!(
                     "[{:?}] acc: {:?}, got balance: {}, need balance: {}",
                     tx.hash().raw(),
                     self.ctx.caller,
                     balance,
                     balance_check
                 );
-                return Err(ExecuteError::InsufficientFunds);
+                return Err(ExecuteError::InsufficientFunds {
+                    tx_hash: tx.hash(),
+                    balance,
+                    need: balance_check,
+                });
                 // }
 
                 // so the dry run can continue
@@ -246,16 +431,16 @@ where
         self.gas += tx.gas().as_u64();
 
         self.initial_gas += tx.gas().as_u64();
-        if !self.ctx.no_gas_fee {
+        if self.ctx.fee_policy.charges_gas() {
             self.state_db
-                .sub_balance(caller, &(extra_fee + mgval))
+                .sub_balance(&caller, &(extra_fee + mgval))
                 .map_err(ExecuteError::StateError)?;
         }
         Ok(())
     }
 
     fn refund_gas(&mut self) -> Result<(), ExecuteError> {
-        if !self.ctx.no_gas_fee {
+        if self.ctx.fee_policy.charges_gas() {
             let remaining = SU256::from(self.gas) * self.gas_price;
             self.state_db
                 .add_balance(&self.ctx.caller, &remaining)
@@ -265,7 +450,113 @@ where
         Ok(())
     }
 
+    fn refund_extra_fee(&mut self) -> Result<(), ExecuteError> {
+        if let Some(extra_fee) = self.ctx.fee_policy.extra_fee() {
+            self.state_db
+                .add_balance(&self.ctx.caller, &extra_fee)
+                .map_err(ExecuteError::StateError)?;
+        }
+        Ok(())
+    }
+
+    // EIP-158: the touched accounts left without code, nonce and balance are
+    // deleted. They are reported as Apply::Delete so the state change log
+    // matches what the StateDB commits.
+    fn delete_empty_accounts(&mut self, states: &mut StateChangeLog) -> Result<(), ExecuteError> {
+        if self.ctx.cfg.empty_considered_exists {
+            return Ok(());
+        }
+        for change in states.iter_mut() {
+            let address = match change {
+                Apply::Modify {
+                    address,
+                    basic,
+                    code,
+                    ..
+                } if basic.balance.is_zero() && basic.nonce.is_zero() => {
+                    let address: SH160 = (*address).into();
+                    let code_len = match code {
+                        Some(code) => code.len(),
+                        None if self.missing(&address, true) => continue,
+                        None => self.code_len(&address)?,
+                    };
+                    if code_len > 0 {
+                        continue;
+                    }
+                    address
+                }
+                _ => continue,
+            };
+            *change = Apply::Delete {
+                address: address.into(),
+            };
+        }
+        Ok(())
+    }
+
+    fn is_empty_account(&mut self, address: &SH160) -> Result<bool, ExecuteError> {
+        if self.ctx.cfg.empty_considered_exists {
+            return Ok(false);
+        }
+        let (balance, nonce) = self
+            .state_db
+            .get_account_basic(address)
+            .map_err(ExecuteError::StateError)?;
+        if !balance.is_zero() || nonce != 0 {
+            return Ok(false);
+        }
+        Ok(self.code_len(address)? == 0)
+    }
+
+    // the evm would run an EOF container as legacy code and diverge from the
+    // EOF clients, the invalid initcode and the EOF code are refused before
+    // any state is touched.
+    fn check_eof(&mut self) -> Result<(), ExecuteError> {
+        let tx_hash = self.ctx.tx.hash();
+        let address = match self.ctx.tx.to() {
+            Some(to) => to,
+            None => {
+                let initcode: Vec<u8> = self.ctx.tx.input().into();
+                if !is_eof(&initcode) {
+                    return Ok(());
+                }
+                EofContainer::validate(&initcode)
+                    .map_err(|err| ExecuteError::InvalidEof { tx_hash, err })?;
+                return Err(ExecuteError::EofNotSupported {
+                    tx_hash,
+                    address: None,
+                });
+            }
+        };
+        let code = self
+            .state_db
+            .get_code(&address)
+            .map_err(ExecuteError::StateError)?;
+        if is_eof(code.as_bytes()) {
+            return Err(ExecuteError::EofNotSupported {
+                tx_hash,
+                address: Some(address),
+            });
+        }
+        Ok(())
+    }
+
+    fn code_len(&mut self, address: &SH160) -> Result<usize, ExecuteError> {
+        let code = self
+            .state_db
+            .get_code(address)
+            .map_err(ExecuteError::StateError)?;
+        Ok(code.len())
+    }
+
     fn apply_states(&mut self, result: &ExecuteResult) -> Result<(), ExecuteError> {
+        // the evm writes below win for the accounts it loaded after the cheatcodes
+        #[cfg(feature = "testing")]
+        if let Some(cheatcodes) = self.ctx.cheatcodes {
+            cheatcodes
+                .flush(&mut *self.state_db, result.success)
+                .map_err(ExecuteError::StateError)?;
+        }
         for change in &result.states {
             match change {
                 Apply::Modify {
@@ -277,6 +568,11 @@ where
                 } => {
                     let address = (*address).into();
                     if result.success {
+                        if let Some(cache) = self.ctx.code_cache {
+                            if *reset_storage || code.is_some() {
+                                cache.invalidate(&address);
+                            }
+                        }
                         if *reset_storage {
                             self.state_db
                                 .suicide(&address)
@@ -309,6 +605,9 @@ where
                 }
                 Apply::Delete { address } => {
                     if result.success {
+                        if let Some(cache) = self.ctx.code_cache {
+                            cache.invalidate(&(*address).into());
+                        }
                         self.state_db
                             .suicide(&(*address).into())
                             .map_err(ExecuteError::StateError)?;
@@ -321,3 +620,91 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{keccak_hash, mpt, NoopBlockHashGetter, PobData, PobStateDB, PrecompileSet};
+    use eth_types::{BlockHeader, HexBytes, Signer, Transaction};
+    use rlp::RlpStream;
+
+    #[test]
+    fn test_warm_access_list() {
+        let (sender, to, coinbase, other) = (
+            H160::repeat_byte(1),
+            H160::repeat_byte(2),
+            H160::repeat_byte(3),
+            H160::repeat_byte(4),
+        );
+        let precompile = H160::from_low_u64_be(1);
+        let slot = H256::repeat_byte(5);
+        let mut accessed = Accessed::default();
+        for address in [sender, to, coinbase, other, precompile] {
+            accessed.accessed_addresses.insert(address);
+        }
+        accessed.accessed_storage.insert((to, slot));
+
+        let list = warm_access_list(&accessed, &[precompile, sender, to, coinbase]);
+        let addresses: Vec<SH160> = list.iter().map(|item| item.address).collect();
+        assert_eq!(addresses, vec![to.into(), other.into()]);
+        // the recipient is kept for its slot
+        assert_eq!(list[0].storage_keys, vec![SH256::from(slot)]);
+        assert!(list[1].storage_keys.is_empty());
+    }
+
+    #[test]
+    fn test_warm_coinbase() {
+        let contract: SH160 = H160::repeat_byte(0x11).into();
+        let miner: SH160 = H160::repeat_byte(0x33).into();
+        // BALANCE(COINBASE)
+        let code = vec![0x41, 0x31, 0x50, 0x00];
+        let storage_root: &[u8] = &mpt::EMPTY_ROOT;
+        let mut account = RlpStream::new_list(4);
+        account.append(&0_u64);
+        account.append(&0_u64);
+        account.append(&storage_root);
+        account.append(&keccak_hash(&code).as_slice());
+        let key = keccak_hash(contract.0.as_bytes()).to_vec();
+        let items: BTreeMap<Vec<u8>, Vec<u8>> = [(key.clone(), account.out().to_vec())]
+            .into_iter()
+            .collect();
+        let (root, proof) = mpt::build_proof(&items, &key);
+        let data = PobData {
+            prev_state_root: root,
+            mpt_nodes: proof.into_iter().map(HexBytes::from).collect(),
+            codes: vec![code.into()],
+            ..Default::default()
+        };
+
+        // the signed txs of the BlockBuilder tests
+        let txs: BTreeMap<String, Transaction> =
+            serde_json::from_str(include_str!("testdata/block_builder.json")).unwrap();
+        let tx = txs["call"].inner().unwrap();
+        let signer = Signer::new(1_u64.into());
+        // the coinbase of the evm is the miner of the context
+        let header = BlockHeader {
+            miner: H160::repeat_byte(0x44).into(),
+            gas_limit: 30_000_000_u64.into(),
+            ..Default::default()
+        };
+        let precompile = PrecompileSet::berlin();
+        let warm = |cfg: evm::Config| -> Vec<SH160> {
+            let ctx = TxContext::builder()
+                .chain_id(signer.chain_id)
+                .caller(tx.sender(&signer))
+                .cfg(&cfg)
+                .precompile(&precompile)
+                .tx(&tx)
+                .header(&header)
+                .block_hash_getter(&NoopBlockHashGetter)
+                .miner(Some(miner))
+                .build()
+                .unwrap();
+            let mut statedb = PobStateDB::new(&data);
+            let result = TxExecutor::new(ctx, &mut statedb).execute().unwrap();
+            result.warm.iter().map(|item| item.address).collect()
+        };
+        assert_eq!(warm(evm::Config::merge()), vec![miner]);
+        assert!(warm(evm::Config::shanghai()).is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use std::prelude::v1::*;
+
+use eth_types::{TxTrait, SH160};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxRejection {
+    GasLimitTooHigh { gas_limit: u64, max: u64 },
+    CalldataTooLarge { size: usize, max: usize },
+    SenderNotAllowed(SH160),
+    AddressDenied(SH160),
+    Other(String),
+}
+
+impl core::fmt::Display for TxRejection {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::GasLimitTooHigh { gas_limit, max } => {
+                write!(f, "tx gas limit {} above the policy cap {}", gas_limit, max)
+            }
+            Self::CalldataTooLarge { size, max } => {
+                write!(f, "calldata size {} above the policy cap {}", size, max)
+            }
+            Self::SenderNotAllowed(sender) => write!(f, "sender {:?} is not allowed", sender),
+            Self::AddressDenied(address) => write!(f, "address {:?} is denied", address),
+            Self::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+// The inclusion rules of the sequencer, consulted by BlockBuilder::commit
+// before the execution. A rejected tx doesn't touch the block.
+pub trait TxPolicy<T: TxTrait> {
+    fn check(&self, tx: &T, sender: &SH160) -> Result<(), TxRejection>;
+}
+
+// The common rules, the unset limits are not checked. The deny list applies
+// to both the sender and the recipient.
+#[derive(Debug, Clone, Default)]
+pub struct BasicTxPolicy {
+    pub max_gas_per_tx: Option<u64>,
+    pub max_calldata_size: Option<usize>,
+    pub allowed_senders: Option<BTreeSet<SH160>>,
+    pub denied_addresses: BTreeSet<SH160>,
+}
+
+impl<T: TxTrait> TxPolicy<T> for BasicTxPolicy {
+    fn check(&self, tx: &T, sender: &SH160) -> Result<(), TxRejection> {
+        if let Some(max) = self.max_gas_per_tx {
+            let gas_limit = tx.gas_limit();
+            if gas_limit > max {
+                return Err(TxRejection::GasLimitTooHigh { gas_limit, max });
+            }
+        }
+        if let Some(max) = self.max_calldata_size {
+            let size = tx.input().len();
+            if size > max {
+                return Err(TxRejection::CalldataTooLarge { size, max });
+            }
+        }
+        if let Some(allowed) = &self.allowed_senders {
+            if !allowed.contains(sender) {
+                return Err(TxRejection::SenderNotAllowed(*sender));
+            }
+        }
+        if self.denied_addresses.contains(sender) {
+            return Err(TxRejection::AddressDenied(*sender));
+        }
+        if let Some(to) = tx.to() {
+            let to: SH160 = to.into();
+            if self.denied_addresses.contains(&to) {
+                return Err(TxRejection::AddressDenied(to));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::prelude::v1::*;
+
+use eth_types::{BlockHeaderTrait, Signer, TxTrait, SH160, SU256};
+use statedb::StateDB;
+
+// geth's txMaxSize, applied to the input since the encoded size is not
+// exposed by TxTrait
+pub const DEFAULT_MAX_TX_INPUT_SIZE: usize = 4 * 32 * 1024;
+// EIP-3860
+const INITCODE_WORD_GAS: u64 = 2;
+
+#[derive(Debug)]
+pub enum TxValidationError {
+    InputTooLarge { size: usize, max: usize },
+    InitcodeTooLarge { size: usize, max: usize },
+    IntrinsicGasTooLow { gas: u64, need: u64 },
+    GasLimitExceeded { gas: u64, block_gas_limit: u64 },
+    InvalidSignature,
+    TipAboveFeeCap { tip: SU256, fee_cap: SU256 },
+    FeeCapTooLow { fee_cap: SU256, base_fee: SU256 },
+    NonceTooLow { expect: u64, got: u64 },
+    NonceTooHigh { expect: u64, got: u64 },
+    InsufficientFunds { balance: SU256, need: SU256 },
+    StateError(statedb::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct TxValidationConfig {
+    pub signer: Signer,
+    pub evm: evm::Config,
+    pub max_input_size: usize,
+    // accept the nonces ahead of the account, the pool queues them
+    pub allow_future_nonce: bool,
+}
+
+impl TxValidationConfig {
+    pub fn new(signer: Signer, evm: evm::Config) -> Self {
+        Self {
+            signer,
+            evm,
+            max_input_size: DEFAULT_MAX_TX_INPUT_SIZE,
+            allow_future_nonce: false,
+        }
+    }
+}
+
+// the gas charged before the execution: the base cost, the calldata, the
+// access list and the initcode words.
+pub fn intrinsic_gas<T: TxTrait>(tx: &T, cfg: &evm::Config) -> u64 {
+    let input: Vec<u8> = tx.input().into();
+    let mut gas = match tx.to() {
+        Some(_) => cfg.gas_transaction_call,
+        None => cfg.gas_transaction_create,
+    };
+    for b in &input {
+        gas += match b {
+            0 => cfg.gas_transaction_zero_data,
+            _ => cfg.gas_transaction_non_zero_data,
+        };
+    }
+    if let Some(al) = tx.access_list() {
+        for tat in al {
+            gas += cfg.gas_access_list_address;
+            gas += cfg.gas_access_list_storage_key * tat.storage_keys.len() as u64;
+        }
+    }
+    if tx.to().is_none() && cfg.max_initcode_size.is_some() {
+        gas += INITCODE_WORD_GAS * ((input.len() as u64 + 31) / 32);
+    }
+    gas
+}
+
+// The admission checks of a tx pool. The stateless ones run first so a
+// malformed tx never touches the StateDB. Returns the sender.
+pub fn validate_tx<T, B, D>(
+    tx: &T,
+    statedb: &mut D,
+    header: &B,
+    cfg: &TxValidationConfig,
+) -> Result<SH160, TxValidationError>
+where
+    T: TxTrait,
+    B: BlockHeaderTrait,
+    D: StateDB,
+{
+    let size = tx.input().len();
+    if size > cfg.max_input_size {
+        return Err(TxValidationError::InputTooLarge {
+            size,
+            max: cfg.max_input_size,
+        });
+    }
+    if let (None, Some(max)) = (tx.to(), cfg.evm.max_initcode_size) {
+        if size > max {
+            return Err(TxValidationError::InitcodeTooLarge { size, max });
+        }
+    }
+
+    let gas = tx.gas().as_u64();
+    let need = intrinsic_gas(tx, &cfg.evm);
+    if gas < need {
+        return Err(TxValidationError::IntrinsicGasTooLow { gas, need });
+    }
+    let block_gas_limit = header.gas_limit().as_u64();
+    if gas > block_gas_limit {
+        return Err(TxValidationError::GasLimitExceeded {
+            gas,
+            block_gas_limit,
+        });
+    }
+
+    let tip = *tx.max_priority_fee_per_gas();
+    let fee_cap = *tx.max_fee_per_gas();
+    if tip > fee_cap {
+        return Err(TxValidationError::TipAboveFeeCap { tip, fee_cap });
+    }
+    if let Some(base_fee) = header.base_fee() {
+        if fee_cap < base_fee {
+            return Err(TxValidationError::FeeCapTooLow { fee_cap, base_fee });
+        }
+    }
+
+    // the signer yields the zero address if the signature can't be recovered
+    let sender = tx.sender(&cfg.signer);
+    if sender == SH160::default() {
+        return Err(TxValidationError::InvalidSignature);
+    }
+
+    let nonce = statedb
+        .get_nonce(&sender)
+        .map_err(TxValidationError::StateError)?;
+    let got = tx.nonce();
+    if got < nonce {
+        return Err(TxValidationError::NonceTooLow { expect: nonce, got });
+    }
+    if got > nonce && !cfg.allow_future_nonce {
+        return Err(TxValidationError::NonceTooHigh { expect: nonce, got });
+    }
+
+    let balance = statedb
+        .get_balance(&sender)
+        .map_err(TxValidationError::StateError)?;
+    let need = SU256::from(gas) * &fee_cap + tx.value();
+    if balance < need {
+        return Err(TxValidationError::InsufficientFunds { balance, need });
+    }
+    Ok(sender)
+}
@@ -1,15 +1,33 @@
 use std::prelude::v1::*;
 
-use eth_types::{BlockHeaderTrait, HexBytes, Log, TxTrait, H256, SH160, SH256, SU256};
-use evm::backend::Apply;
+use eth_types::{
+    BlockHeaderTrait, HexBytes, Log, TransactionAccessTuple, TxTrait, H256, SH160, SH256, SU256,
+};
+use evm::backend::{Apply, Basic};
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+#[cfg(any(feature = "std", feature = "tstd"))]
+use std::collections::HashMap;
+use std::ops::Range;
 
-use crate::PrecompileSet;
+#[cfg(feature = "testing")]
+use crate::Cheatcodes;
+#[cfg(feature = "opcode-stats")]
+use crate::OpcodeGasStats;
+use crate::{
+    CodeCache, EofError, ExecutionAudit, ExecutionWitness, FeePolicy, FeeVaults, KeccakCache,
+    LogFilter, LogsBloom, PrecompileSet, Selfdestruct, StateMisses,
+};
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ExecuteError {
     NotSupported,
-    InsufficientFunds,
+    InsufficientFunds {
+        tx_hash: SH256,
+        balance: SU256,
+        need: SU256,
+    },
     InsufficientBaseFee {
         tx_hash: SH256,
         block_base_fee_gwei: String,
@@ -18,18 +36,159 @@ pub enum ExecuteError {
     },
     ExecutePaymentTxFail(String),
     NonceTooLow {
+        tx_hash: SH256,
         expect: u64,
         got: u64,
     },
     NonceTooHigh {
+        tx_hash: SH256,
         expect: u64,
         got: u64,
     },
+    // the EOF initcode of a create tx doesn't validate
+    InvalidEof {
+        tx_hash: SH256,
+        err: EofError,
+    },
+    // a valid EOF container, which the evm can't run as legacy code
+    EofNotSupported {
+        tx_hash: SH256,
+        address: Option<SH160>,
+    },
+    GasOvercommit {
+        tx_hash: SH256,
+        gas_limit: u64,
+        used_gas: u64,
+    },
     StateError(statedb::Error),
 }
 
+impl core::fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotSupported => write!(f, "not supported"),
+            Self::InsufficientFunds {
+                tx_hash,
+                balance,
+                need,
+            } => write!(
+                f,
+                "insufficient funds for gas * price + value: tx {:?}, have {}, want {}",
+                tx_hash, balance, need
+            ),
+            Self::InsufficientBaseFee {
+                tx_hash,
+                block_base_fee_gwei,
+                base_fee_gwei,
+                block_number,
+            } => write!(
+                f,
+                "max fee per gas less than block base fee: tx {:?}, block #{}, base fee {} gwei, got {} gwei",
+                tx_hash, block_number, block_base_fee_gwei, base_fee_gwei
+            ),
+            Self::ExecutePaymentTxFail(reason) => write!(f, "execute payment tx fail: {}", reason),
+            Self::NonceTooLow {
+                tx_hash,
+                expect,
+                got,
+            } => write!(
+                f,
+                "nonce too low: tx {:?}, state nonce {}, tx nonce {}",
+                tx_hash, expect, got
+            ),
+            Self::NonceTooHigh {
+                tx_hash,
+                expect,
+                got,
+            } => write!(
+                f,
+                "nonce too high: tx {:?}, state nonce {}, tx nonce {}",
+                tx_hash, expect, got
+            ),
+            Self::InvalidEof { tx_hash, err } => {
+                write!(f, "invalid EOF initcode: tx {:?}, {:?}", tx_hash, err)
+            }
+            Self::EofNotSupported { tx_hash, address } => write!(
+                f,
+                "EOF code is not supported: tx {:?}, address {:?}",
+                tx_hash, address
+            ),
+            Self::GasOvercommit {
+                tx_hash,
+                gas_limit,
+                used_gas,
+            } => write!(
+                f,
+                "gas used over the fee policy allowance: tx {:?}, gas limit {}, used {}",
+                tx_hash, gas_limit, used_gas
+            ),
+            Self::StateError(err) => write!(f, "state error: {:?}", err),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+impl std::error::Error for ExecuteError {}
+
+// BLOCKHASH only sees the 256 most recent ancestors
+pub const BLOCK_HASH_WINDOW: u64 = 256;
+
 pub trait BlockHashGetter {
     fn get_hash(&self, current: u64, target: u64) -> SH256;
+
+    // resolve a range of ancestors at once, override it when the getter can
+    // batch the lookups, e.g. one rpc call for the whole window.
+    fn get_hashes(&self, current: u64, range: Range<u64>) -> BTreeMap<u64, SH256> {
+        range
+            .map(|target| (target, self.get_hash(current, target)))
+            .collect()
+    }
+}
+
+// the ancestors visible to BLOCKHASH in the block `current`
+pub fn block_hash_window(current: u64) -> Range<u64> {
+    current.saturating_sub(BLOCK_HASH_WINDOW)..current
+}
+
+// always returns the empty hash
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopBlockHashGetter;
+
+impl BlockHashGetter for NoopBlockHashGetter {
+    fn get_hash(&self, _: u64, _: u64) -> SH256 {
+        SH256::default()
+    }
+}
+
+impl BlockHashGetter for BTreeMap<u64, SH256> {
+    fn get_hash(&self, _: u64, target: u64) -> SH256 {
+        self.get(&target).cloned().unwrap_or_default()
+    }
+
+    fn get_hashes(&self, _: u64, range: Range<u64>) -> BTreeMap<u64, SH256> {
+        let mut hashes: BTreeMap<u64, SH256> =
+            range.clone().map(|n| (n, SH256::default())).collect();
+        for (number, hash) in self.range(range) {
+            hashes.insert(*number, *hash);
+        }
+        hashes
+    }
+}
+
+#[cfg(any(feature = "std", feature = "tstd"))]
+impl BlockHashGetter for HashMap<u64, SH256> {
+    fn get_hash(&self, _: u64, target: u64) -> SH256 {
+        self.get(&target).cloned().unwrap_or_default()
+    }
+}
+
+impl<F> BlockHashGetter for F
+where
+    F: Fn(u64, u64) -> SH256,
+{
+    fn get_hash(&self, current: u64, target: u64) -> SH256 {
+        self(current, target)
+    }
 }
 
 #[derive(Debug)]
@@ -40,16 +199,35 @@ pub struct TxContext<'a, T: TxTrait, B: BlockHeaderTrait, H: BlockHashGetter> {
     pub precompile: &'a PrecompileSet,
     pub tx: &'a T,
     pub header: &'a B,
-    pub no_gas_fee: bool,
-    pub extra_fee: Option<SU256>,
-    pub gas_overcommit: bool,
+    pub fee_policy: FeePolicy,
+    pub fee_vaults: FeeVaults,
     pub block_hash_getter: &'a H,
+    pub code_cache: Option<&'a CodeCache>,
+    pub keccak_cache: Option<&'a KeccakCache>,
+    pub witness: Option<&'a ExecutionWitness>,
+    pub audit: Option<&'a ExecutionAudit>,
+    // the speculative mode, the missing state is recorded and read as empty
+    pub state_misses: Option<&'a StateMisses>,
+    #[cfg(feature = "testing")]
+    pub cheatcodes: Option<&'a Cheatcodes>,
+    #[cfg(feature = "opcode-stats")]
+    pub opcode_stats: Option<&'a OpcodeGasStats>,
 
     // will no send the tx fee if it's None
     pub miner: Option<SH160>,
 
-    pub block_base_fee: SU256,
+    // None before london, the gas price is the tx gas price as is
+    pub block_base_fee: Option<SU256>,
     pub difficulty: SU256,
+    // returned by DIFFICULTY(PREVRANDAO) instead of the difficulty after the merge
+    pub prev_randao: Option<SH256>,
+
+    // the cancun inputs of BLOBHASH and BLOBBASEFEE, set by the engine
+    pub blob_hashes: Vec<SH256>,
+    pub blob_base_fee: Option<SU256>,
+    // the EOF fork, a tx creating or calling an EOF container is validated
+    // and refused, the evm only runs legacy code
+    pub eof: bool,
 }
 
 impl<'a, T, B, H> Clone for TxContext<'a, T, B, H>
@@ -66,24 +244,436 @@ where
             precompile: self.precompile,
             tx: self.tx,
             header: self.header,
-            no_gas_fee: self.no_gas_fee,
-            extra_fee: self.extra_fee.clone(),
-            gas_overcommit: self.gas_overcommit,
+            fee_policy: self.fee_policy.clone(),
+            fee_vaults: self.fee_vaults,
             block_hash_getter: self.block_hash_getter,
+            code_cache: self.code_cache,
+            keccak_cache: self.keccak_cache,
+            witness: self.witness,
+            audit: self.audit,
+            state_misses: self.state_misses,
+            #[cfg(feature = "testing")]
+            cheatcodes: self.cheatcodes,
+            #[cfg(feature = "opcode-stats")]
+            opcode_stats: self.opcode_stats,
             miner: self.miner.clone(),
             block_base_fee: self.block_base_fee.clone(),
             difficulty: self.difficulty.clone(),
+            prev_randao: self.prev_randao.clone(),
+            blob_hashes: self.blob_hashes.clone(),
+            blob_base_fee: self.blob_base_fee.clone(),
+            eof: self.eof,
         }
     }
 }
 
-#[derive(Debug, Default)]
+impl<'a, T, B, H> TxContext<'a, T, B, H>
+where
+    T: TxTrait,
+    B: BlockHeaderTrait,
+    H: BlockHashGetter,
+{
+    pub fn builder() -> TxContextBuilder<'a, T, B, H> {
+        TxContextBuilder::default()
+    }
+}
+
+#[derive(Debug)]
+pub enum TxContextError {
+    MissingField(&'static str),
+}
+
+// Builds the TxContext with FeePolicy::Normal, no miner, no base fee, zero
+// difficulty, no prev_randao, no blob inputs and before EOF by default.
+#[derive(Debug)]
+pub struct TxContextBuilder<'a, T: TxTrait, B: BlockHeaderTrait, H: BlockHashGetter> {
+    chain_id: Option<SU256>,
+    caller: Option<SH160>,
+    cfg: Option<&'a evm::Config>,
+    precompile: Option<&'a PrecompileSet>,
+    tx: Option<&'a T>,
+    header: Option<&'a B>,
+    block_hash_getter: Option<&'a H>,
+    fee_policy: FeePolicy,
+    fee_vaults: FeeVaults,
+    code_cache: Option<&'a CodeCache>,
+    keccak_cache: Option<&'a KeccakCache>,
+    witness: Option<&'a ExecutionWitness>,
+    audit: Option<&'a ExecutionAudit>,
+    state_misses: Option<&'a StateMisses>,
+    #[cfg(feature = "testing")]
+    cheatcodes: Option<&'a Cheatcodes>,
+    #[cfg(feature = "opcode-stats")]
+    opcode_stats: Option<&'a OpcodeGasStats>,
+    miner: Option<SH160>,
+    block_base_fee: Option<SU256>,
+    difficulty: SU256,
+    prev_randao: Option<SH256>,
+    blob_hashes: Vec<SH256>,
+    blob_base_fee: Option<SU256>,
+    eof: bool,
+}
+
+impl<'a, T, B, H> Default for TxContextBuilder<'a, T, B, H>
+where
+    T: TxTrait,
+    B: BlockHeaderTrait,
+    H: BlockHashGetter,
+{
+    fn default() -> Self {
+        Self {
+            chain_id: None,
+            caller: None,
+            cfg: None,
+            precompile: None,
+            tx: None,
+            header: None,
+            block_hash_getter: None,
+            fee_policy: FeePolicy::Normal,
+            fee_vaults: FeeVaults::default(),
+            code_cache: None,
+            keccak_cache: None,
+            witness: None,
+            audit: None,
+            state_misses: None,
+            #[cfg(feature = "testing")]
+            cheatcodes: None,
+            #[cfg(feature = "opcode-stats")]
+            opcode_stats: None,
+            miner: None,
+            block_base_fee: None,
+            difficulty: SU256::zero(),
+            prev_randao: None,
+            blob_hashes: Vec::new(),
+            blob_base_fee: None,
+            eof: false,
+        }
+    }
+}
+
+impl<'a, T, B, H> TxContextBuilder<'a, T, B, H>
+where
+    T: TxTrait,
+    B: BlockHeaderTrait,
+    H: BlockHashGetter,
+{
+    pub fn chain_id(mut self, chain_id: SU256) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn caller(mut self, caller: SH160) -> Self {
+        self.caller = Some(caller);
+        self
+    }
+
+    pub fn cfg(mut self, cfg: &'a evm::Config) -> Self {
+        self.cfg = Some(cfg);
+        self
+    }
+
+    pub fn precompile(mut self, precompile: &'a PrecompileSet) -> Self {
+        self.precompile = Some(precompile);
+        self
+    }
+
+    pub fn tx(mut self, tx: &'a T) -> Self {
+        self.tx = Some(tx);
+        self
+    }
+
+    pub fn header(mut self, header: &'a B) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    pub fn block_hash_getter(mut self, getter: &'a H) -> Self {
+        self.block_hash_getter = Some(getter);
+        self
+    }
+
+    pub fn fee_policy(mut self, fee_policy: FeePolicy) -> Self {
+        self.fee_policy = fee_policy;
+        self
+    }
+
+    pub fn fee_vaults(mut self, fee_vaults: FeeVaults) -> Self {
+        self.fee_vaults = fee_vaults;
+        self
+    }
+
+    pub fn code_cache(mut self, code_cache: Option<&'a CodeCache>) -> Self {
+        self.code_cache = code_cache;
+        self
+    }
+
+    pub fn keccak_cache(mut self, keccak_cache: Option<&'a KeccakCache>) -> Self {
+        self.keccak_cache = keccak_cache;
+        self
+    }
+
+    pub fn witness(mut self, witness: Option<&'a ExecutionWitness>) -> Self {
+        self.witness = witness;
+        self
+    }
+
+    pub fn audit(mut self, audit: Option<&'a ExecutionAudit>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn state_misses(mut self, state_misses: Option<&'a StateMisses>) -> Self {
+        self.state_misses = state_misses;
+        self
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn cheatcodes(mut self, cheatcodes: Option<&'a Cheatcodes>) -> Self {
+        self.cheatcodes = cheatcodes;
+        self
+    }
+
+    #[cfg(feature = "opcode-stats")]
+    pub fn opcode_stats(mut self, opcode_stats: Option<&'a OpcodeGasStats>) -> Self {
+        self.opcode_stats = opcode_stats;
+        self
+    }
+
+    pub fn miner(mut self, miner: Option<SH160>) -> Self {
+        self.miner = miner;
+        self
+    }
+
+    pub fn block_base_fee(mut self, block_base_fee: Option<SU256>) -> Self {
+        self.block_base_fee = block_base_fee;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: SU256) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    pub fn prev_randao(mut self, prev_randao: Option<SH256>) -> Self {
+        self.prev_randao = prev_randao;
+        self
+    }
+
+    pub fn blob_hashes(mut self, blob_hashes: Vec<SH256>) -> Self {
+        self.blob_hashes = blob_hashes;
+        self
+    }
+
+    pub fn blob_base_fee(mut self, blob_base_fee: Option<SU256>) -> Self {
+        self.blob_base_fee = blob_base_fee;
+        self
+    }
+
+    pub fn eof(mut self, eof: bool) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    pub fn build(self) -> Result<TxContext<'a, T, B, H>, TxContextError> {
+        fn required<V>(val: Option<V>, name: &'static str) -> Result<V, TxContextError> {
+            val.ok_or(TxContextError::MissingField(name))
+        }
+        Ok(TxContext {
+            chain_id: required(self.chain_id, "chain_id")?,
+            caller: required(self.caller, "caller")?,
+            cfg: required(self.cfg, "cfg")?,
+            precompile: required(self.precompile, "precompile")?,
+            tx: required(self.tx, "tx")?,
+            header: required(self.header, "header")?,
+            block_hash_getter: required(self.block_hash_getter, "block_hash_getter")?,
+            fee_policy: self.fee_policy,
+            fee_vaults: self.fee_vaults,
+            code_cache: self.code_cache,
+            keccak_cache: self.keccak_cache,
+            witness: self.witness,
+            audit: self.audit,
+            state_misses: self.state_misses,
+            #[cfg(feature = "testing")]
+            cheatcodes: self.cheatcodes,
+            #[cfg(feature = "opcode-stats")]
+            opcode_stats: self.opcode_stats,
+            miner: self.miner,
+            block_base_fee: self.block_base_fee,
+            difficulty: self.difficulty,
+            prev_randao: self.prev_randao,
+            blob_hashes: self.blob_hashes,
+            blob_base_fee: self.blob_base_fee,
+            eof: self.eof,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExecuteResult {
     pub success: bool,
     pub used_gas: u64, // Total used gas but include the refunded gas
     pub err: HexBytes, // Any error encountered during the execution(listed in core/vm/errors.go)
+    // The returned data, or the revert data if it fails
+    #[serde(default)]
+    pub return_data: HexBytes,
     pub logs: Vec<Log>,
+    #[serde(default)]
+    pub logs_bloom: LogsBloom, // derived from the logs
+    #[serde(with = "state_change_log")]
     pub states: StateChangeLog,
+    // the accounts and slots read during the tx, ascending by the address.
+    // feed them to BlockBuilder::prefetch to fetch exactly the needed state.
+    #[serde(default)]
+    pub touched: Vec<TransactionAccessTuple>,
+    // the addresses and slots warm at the end of the tx (EIP-2929), ascending
+    // by the address, without the precompiles, the sender, the recipient and
+    // the coinbase (since shanghai) which have no warm slots. the reverted frames don't leave
+    // theirs behind, unlike `touched`.
+    #[serde(default)]
+    pub warm: Vec<TransactionAccessTuple>,
+    // the deleted contracts and their beneficiaries, in the order of execution.
+    // only with the selfdestructs feature
+    #[serde(default)]
+    pub selfdestructs: Vec<Selfdestruct>,
+}
+
+impl ExecuteResult {
+    pub fn logs_matching<'a>(&'a self, filter: &'a LogFilter) -> impl Iterator<Item = &'a Log> {
+        // the bloom is empty if the result is decoded without it
+        let skip = !self.logs_bloom.is_empty() && !self.logs_bloom.might_match(filter);
+        self.logs
+            .iter()
+            .filter(move |log| !skip && filter.matches(log))
+    }
+
+    // the EIP-2930 access list which makes the same accesses warm from the
+    // start. the `excluded` addresses are warm anyway (the sender, the
+    // recipient) and are only kept for their slots.
+    pub fn access_list(&self, excluded: &[SH160]) -> Vec<TransactionAccessTuple> {
+        self.warm
+            .iter()
+            .filter(|item| !item.storage_keys.is_empty() || !excluded.contains(&item.address))
+            .cloned()
+            .collect()
+    }
+}
+
+pub(crate) type StateChangeLog = Vec<Apply<BTreeMap<H256, H256>>>;
+
+// The generated access list of a tx, same as the eth_createAccessList result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListResult {
+    pub access_list: Vec<TransactionAccessTuple>,
+    pub gas_used: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// gas_used = intrinsic + execution - refund
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasBreakdown {
+    pub intrinsic: u64,
+    pub execution: u64,
+    pub refund: u64,
+    pub effective_gas_price: SU256,
+    pub fee_paid: SU256,
+}
+
+impl GasBreakdown {
+    pub fn gas_used(&self) -> u64 {
+        (self.intrinsic + self.execution).saturating_sub(self.refund)
+    }
 }
 
-type StateChangeLog = Vec<Apply<BTreeMap<H256, H256>>>;
+// The serializable form of evm::backend::Apply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StateChange {
+    Modify {
+        address: SH160,
+        balance: SU256,
+        nonce: SU256,
+        code: Option<HexBytes>,
+        storage: BTreeMap<SH256, SH256>,
+        reset_storage: bool,
+    },
+    Delete {
+        address: SH160,
+    },
+}
+
+impl From<&Apply<BTreeMap<H256, H256>>> for StateChange {
+    fn from(apply: &Apply<BTreeMap<H256, H256>>) -> Self {
+        match apply {
+            Apply::Modify {
+                address,
+                basic,
+                code,
+                storage,
+                reset_storage,
+            } => StateChange::Modify {
+                address: (*address).into(),
+                balance: basic.balance.into(),
+                nonce: basic.nonce.into(),
+                code: code.clone().map(HexBytes::from),
+                storage: storage
+                    .iter()
+                    .map(|(k, v)| ((*k).into(), (*v).into()))
+                    .collect(),
+                reset_storage: *reset_storage,
+            },
+            Apply::Delete { address } => StateChange::Delete {
+                address: (*address).into(),
+            },
+        }
+    }
+}
+
+impl From<StateChange> for Apply<BTreeMap<H256, H256>> {
+    fn from(change: StateChange) -> Self {
+        match change {
+            StateChange::Modify {
+                address,
+                balance,
+                nonce,
+                code,
+                storage,
+                reset_storage,
+            } => Apply::Modify {
+                address: address.into(),
+                basic: Basic {
+                    balance: balance.into(),
+                    nonce: nonce.into(),
+                },
+                code: code.map(|code| code.to_vec()),
+                storage: storage
+                    .into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+                reset_storage,
+            },
+            StateChange::Delete { address } => Apply::Delete {
+                address: address.into(),
+            },
+        }
+    }
+}
+
+mod state_change_log {
+    use std::prelude::v1::*;
+
+    use super::{StateChange, StateChangeLog};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(states: &StateChangeLog, s: S) -> Result<S::Ok, S::Error> {
+        let changes: Vec<StateChange> = states.iter().map(StateChange::from).collect();
+        changes.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<StateChangeLog, D::Error> {
+        let changes = Vec::<StateChange>::deserialize(d)?;
+        Ok(changes.into_iter().map(Into::into).collect())
+    }
+}
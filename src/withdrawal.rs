@@ -0,0 +1,38 @@
+use std::prelude::v1::*;
+
+use eth_types::{SH256, SU256};
+use serde::{Deserialize, Serialize};
+
+// What Engine::process_withdrawal did with one withdrawal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WithdrawalOutcome {
+    // the amount credited, in the unit of the engine's withdrawal token
+    Credited(SU256),
+    // left out by the engine, e.g. a zero amount
+    Skipped,
+}
+
+// The withdrawals of a block as the engine processed them. `root` is what the
+// engine binds to the Poe's withdrawal_root, None keeps the one of the
+// BatchSource.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawalSummary {
+    pub processed: u64,
+    pub skipped: u64,
+    pub credited: SU256,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<SH256>,
+}
+
+impl WithdrawalSummary {
+    pub fn record(&mut self, outcome: WithdrawalOutcome) {
+        match outcome {
+            WithdrawalOutcome::Credited(amount) => {
+                self.processed += 1;
+                self.credited = self.credited + amount;
+            }
+            WithdrawalOutcome::Skipped => self.skipped += 1,
+        }
+    }
+}
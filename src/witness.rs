@@ -0,0 +1,252 @@
+use std::prelude::v1::*;
+
+use core::cell::RefCell;
+use eth_types::{HexBytes, SH160, SH256};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{keccak_hash, mpt, KeccakCache, PobData, PobError};
+
+// The state touched by the execution, recorded through the StateProxy.
+#[derive(Debug, Default)]
+pub struct ExecutionWitness {
+    accounts: RefCell<BTreeSet<SH160>>,
+    storages: RefCell<BTreeMap<SH160, BTreeSet<SH256>>>,
+    codes: RefCell<BTreeMap<SH256, HexBytes>>,
+    block_hashes: RefCell<BTreeMap<u64, SH256>>,
+    // the trie keys and nodes are hashed again on every from_witness/unused
+    hashes: KeccakCache,
+}
+
+impl ExecutionWitness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_account(&self, address: &SH160) {
+        self.accounts.borrow_mut().insert(*address);
+    }
+
+    pub fn add_storage(&self, address: &SH160, index: &SH256) {
+        self.add_account(address);
+        self.storages
+            .borrow_mut()
+            .entry(*address)
+            .or_insert_with(BTreeSet::new)
+            .insert(*index);
+    }
+
+    pub fn has_code(&self, hash: &SH256) -> bool {
+        self.codes.borrow().contains_key(hash)
+    }
+
+    pub fn add_code(&self, hash: SH256, code: HexBytes) {
+        self.codes.borrow_mut().entry(hash).or_insert(code);
+    }
+
+    pub fn add_block_hash(&self, number: u64, hash: SH256) {
+        self.block_hashes.borrow_mut().insert(number, hash);
+    }
+
+    pub fn accounts(&self) -> Vec<SH160> {
+        self.accounts.borrow().iter().cloned().collect()
+    }
+
+    pub fn storages(&self, address: &SH160) -> Vec<SH256> {
+        match self.storages.borrow().get(address) {
+            Some(slots) => slots.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn code_hashes(&self) -> Vec<SH256> {
+        self.codes.borrow().keys().cloned().collect()
+    }
+
+    pub fn codes(&self) -> Vec<HexBytes> {
+        self.codes.borrow().values().cloned().collect()
+    }
+
+    pub fn block_hashes(&self) -> BTreeMap<u64, SH256> {
+        self.block_hashes.borrow().clone()
+    }
+}
+
+// The mpt nodes and codes of a Pob which are never accessed by the execution.
+#[derive(Debug, Default)]
+pub struct PobUnused {
+    pub mpt_nodes: BTreeSet<SH256>,
+    pub codes: BTreeSet<SH256>,
+}
+
+impl PobUnused {
+    pub fn is_empty(&self) -> bool {
+        self.mpt_nodes.is_empty() && self.codes.is_empty()
+    }
+}
+
+fn node_pool<'a>(nodes: &'a [HexBytes], hashes: &KeccakCache) -> BTreeMap<SH256, &'a [u8]> {
+    let mut pool = BTreeMap::new();
+    for node in nodes {
+        let hash = hashes.hash(node);
+        pool.insert(hash, node.as_bytes());
+    }
+    pool
+}
+
+// collect the nodes on the paths of the touched accounts and slots
+fn touched_nodes(
+    pool: &BTreeMap<SH256, &[u8]>,
+    root: SH256,
+    witness: &ExecutionWitness,
+) -> Result<BTreeSet<SH256>, PobError> {
+    let mut touched = BTreeSet::new();
+    for address in witness.accounts() {
+        let key = witness.hashes.hash(address.0.as_bytes());
+        let acc = mpt::get(pool, root, key.0.as_bytes(), &mut touched).map_err(PobError::Decode)?;
+        let slots = witness.storages(&address);
+        let acc = match acc {
+            Some(acc) if !slots.is_empty() => acc,
+            _ => continue,
+        };
+        let storage_root = mpt::account_storage_root(&acc).map_err(PobError::Decode)?;
+        for slot in slots {
+            let key = witness.hashes.hash(slot.0.as_bytes());
+            mpt::get(pool, storage_root, key.0.as_bytes(), &mut touched)
+                .map_err(PobError::Decode)?;
+        }
+    }
+    // the accounts and the slots may be deleted
+    mpt::collapse_siblings(pool, &mut touched).map_err(PobError::Decode)?;
+    Ok(touched)
+}
+
+impl PobData {
+    // Build the minimal witness from the touched state. `nodes` is the pool of
+    // candidate mpt nodes (e.g. eth_getProof results or a prior Pob), only the
+    // nodes on the paths of the touched accounts and slots are kept.
+    pub fn from_witness(
+        chain_id: u64,
+        prev_state_root: SH256,
+        witness: &ExecutionWitness,
+        nodes: &[HexBytes],
+    ) -> Result<PobData, PobError> {
+        let pool = node_pool(nodes, &witness.hashes);
+        let touched = touched_nodes(&pool, prev_state_root, witness)?;
+        let mpt_nodes = touched
+            .iter()
+            .map(|hash| HexBytes::from(pool[hash].to_vec()))
+            .collect();
+        Ok(PobData {
+            chain_id,
+            prev_state_root,
+            block_hashes: witness.block_hashes(),
+            mpt_nodes,
+            codes: witness.codes(),
+            ..Default::default()
+        })
+    }
+
+    // report the nodes and codes which are not touched by the recorded execution
+    pub fn unused(&self, witness: &ExecutionWitness) -> Result<PobUnused, PobError> {
+        let pool = node_pool(&self.mpt_nodes, &witness.hashes);
+        let touched = touched_nodes(&pool, self.prev_state_root, witness)?;
+        let code_hashes: BTreeSet<SH256> = witness.code_hashes().into_iter().collect();
+
+        let mut unused = PobUnused::default();
+        for hash in pool.keys() {
+            if !touched.contains(hash) {
+                unused.mpt_nodes.insert(*hash);
+            }
+        }
+        for code in &self.codes {
+            let hash: SH256 = keccak_hash(code).into();
+            if !code_hashes.contains(&hash) {
+                unused.codes.insert(hash);
+            }
+        }
+        Ok(unused)
+    }
+
+    // returns the number of removed items
+    pub fn prune(&mut self, unused: &PobUnused) -> usize {
+        let before = self.mpt_nodes.len() + self.codes.len();
+        self.mpt_nodes
+            .retain(|node| !unused.mpt_nodes.contains(&SH256::from(keccak_hash(node))));
+        self.codes
+            .retain(|code| !unused.codes.contains(&SH256::from(keccak_hash(code))));
+        before - self.mpt_nodes.len() - self.codes.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PobStateDB;
+    use eth_types::H160;
+    use rlp::RlpStream;
+    use statedb::StateDB;
+
+    fn account(balance: u64) -> Vec<u8> {
+        let storage_root: &[u8] = &mpt::EMPTY_ROOT;
+        let code_hash = keccak_hash(&[]);
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&0_u64);
+        stream.append(&balance);
+        stream.append(&storage_root);
+        stream.append(&code_hash.as_slice());
+        stream.out().to_vec()
+    }
+
+    fn execute<D: StateDB>(statedb: &mut D, deleted: &SH160, credited: &SH160) -> SH256 {
+        statedb.suicide(deleted).unwrap();
+        statedb.add_balance(credited, &5_u64.into()).unwrap();
+        statedb.flush().unwrap()
+    }
+
+    #[test]
+    fn test_from_witness_reexecute() {
+        let addresses: Vec<SH160> = (1..=40_u8)
+            .map(|idx| H160::repeat_byte(idx).into())
+            .collect();
+        let keys: Vec<Vec<u8>> = addresses
+            .iter()
+            .map(|address| keccak_hash(address.0.as_bytes()).to_vec())
+            .collect();
+        let items = keys.iter().map(|key| (key.clone(), account(100))).collect();
+        let mut pool = BTreeSet::new();
+        let mut root = SH256::default();
+        for key in &keys {
+            let (proof_root, proof) = mpt::build_proof(&items, key);
+            root = proof_root;
+            pool.extend(proof);
+        }
+        let pool: Vec<HexBytes> = pool.into_iter().map(HexBytes::from).collect();
+
+        // the deleted account shares its branch with a single sibling, the
+        // branch collapses into the sibling
+        let nibble = |idx: usize| keys[idx][0] >> 4;
+        let deleted = (0..keys.len())
+            .find(|a| (0..keys.len()).filter(|b| nibble(*b) == nibble(*a)).count() == 2)
+            .expect("a branch with two children");
+        let credited = (0..keys.len())
+            .find(|idx| nibble(*idx) != nibble(deleted))
+            .unwrap();
+        let (deleted, credited) = (addresses[deleted], addresses[credited]);
+
+        let full = PobData {
+            prev_state_root: root,
+            mpt_nodes: pool.clone(),
+            ..Default::default()
+        };
+        let expect = execute(&mut PobStateDB::new(&full), &deleted, &credited);
+
+        let witness = ExecutionWitness::new();
+        witness.add_account(&deleted);
+        witness.add_account(&credited);
+        let data = PobData::from_witness(1, root, &witness, &pool).unwrap();
+        assert!(data.mpt_nodes.len() < pool.len());
+        let got = execute(&mut PobStateDB::new(&data), &deleted, &credited);
+        assert_eq!(got, expect);
+        assert!(data.unused(&witness).unwrap().is_empty());
+    }
+}